@@ -41,6 +41,8 @@ extern crate log;
 extern crate failure;
 extern crate toml_query;
 extern crate resiter;
+#[macro_use]
+extern crate serde_json;
 
 extern crate libimagentryannotation;
 extern crate libimagentryedit;
@@ -139,7 +141,12 @@ fn add(rt: &Runtime) -> Result<()> {
                 .get_header()
                 .read_string("annotation.name")?
             {
-                writeln!(rt.stdout(), "Name of the annotation: {}", annotation_id)?;
+                let record = json!({
+                    "id": annotation.get_location().to_str()?,
+                    "name": annotation_id,
+                });
+
+                rt.emit(record, |out| writeln!(out, "Name of the annotation: {}", annotation_id))?;
             } else {
                 Err(format_err!("Unnamed annotation: {:?}", annotation.get_location()))
                     .context("This is most likely a BUG, please report!")?;
@@ -230,14 +237,19 @@ fn list(rt: &Runtime) -> Result<()> {
 }
 
 fn list_annotation<'a>(rt: &Runtime, i: usize, a: &FileLockEntry<'a>, with_text: bool) -> Result<()> {
+    let mut record = json!({ "index": i, "id": a.get_location().to_str()? });
     if with_text {
-        writeln!(rt.stdout(),
+        record["text"] = json!(a.get_content());
+    }
+
+    rt.emit(record, |out| if with_text {
+        writeln!(out,
                  "--- {i: >5} | {id}\n{text}\n\n",
                  i = i,
                  id = a.get_location(),
                  text = a.get_content())
     } else {
-        writeln!(rt.stdout(), "{: >5} | {}", i, a.get_location())
-    }.map_err(Error::from)
+        writeln!(out, "{: >5} | {}", i, a.get_location())
+    })
 }
 
@@ -0,0 +1,244 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+#![forbid(unsafe_code)]
+
+#![deny(
+    non_camel_case_types,
+    non_snake_case,
+    path_statements,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_allocation,
+    unused_import_braces,
+    unused_imports,
+    unused_must_use,
+    unused_mut,
+    unused_qualifications,
+    while_true,
+)]
+
+extern crate clap;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate failure;
+extern crate mime_guess;
+#[macro_use]
+extern crate serde_json;
+
+extern crate libimagerror;
+extern crate libimagrt;
+extern crate libimagstore;
+
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+use failure::err_msg;
+use clap::App;
+use serde_json::Value as JsonValue;
+
+use libimagerror::errors::ErrorMsg as EM;
+use libimagrt::runtime::Runtime;
+use libimagrt::application::ImagApplication;
+use libimagstore::storeid::StoreId;
+use libimagstore::attachment::Attachment;
+
+mod ui;
+
+/// Marker enum for implementing ImagApplication on
+///
+/// This is used by binaries crates to execute business logic
+/// or to build a CLI completion.
+pub enum ImagAttach {}
+impl ImagApplication for ImagAttach {
+    fn run(rt: Runtime) -> Result<()> {
+        match rt.cli().subcommand_name().ok_or_else(|| err_msg("No command called"))? {
+            "add"     => add(&rt),
+            "list"    => list(&rt),
+            "extract" => extract(&rt),
+            "remove"  => remove(&rt),
+            other     => {
+                debug!("Unknown command");
+                if rt.handle_unknown_subcommand("imag-attach", other, rt.cli())?.success() {
+                    Ok(())
+                } else {
+                    Err(err_msg("Failed to handle unknown subcommand"))
+                }
+            },
+        }
+    }
+
+    fn build_cli<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+        ui::build_ui(app)
+    }
+
+    fn name() -> &'static str {
+        env!("CARGO_PKG_NAME")
+    }
+
+    fn description() -> &'static str {
+        "Attach files to entries"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+}
+
+fn ids(rt: &Runtime) -> Result<Vec<StoreId>> {
+    rt.ids::<ui::PathProvider>()
+        .context("No ids supplied")?
+        .ok_or_else(|| err_msg("No ids supplied"))
+}
+
+/// The single id the command was called with
+fn id(rt: &Runtime) -> Result<StoreId> {
+    let mut ids = ids(rt)?.into_iter();
+    match (ids.next(), ids.next()) {
+        (Some(id), None) => Ok(id),
+        (None, _)        => Err(err_msg("No id supplied")),
+        (Some(_), _)     => Err(err_msg("Only one id can be supplied")),
+    }
+}
+
+fn add(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("add").unwrap(); // safed by main()
+    let file = PathBuf::from(scmd.value_of("file").unwrap()); // safed by clap
+    let name = match scmd.value_of("name") {
+        Some(name) => String::from(name),
+        None       => file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(String::from)
+            .ok_or_else(|| format_err!("Cannot get a name for {}, pass one with --name", file.display()))?,
+    };
+    let mime = scmd
+        .value_of("mime")
+        .map(String::from)
+        .unwrap_or_else(|| ::mime_guess::from_path(&file).first_or_octet_stream().to_string());
+
+    let data = ::std::fs::read(&file).context(format_err!("Failed to read {}", file.display()))?;
+
+    let id        = id(rt)?;
+    let mut entry = rt.store()
+        .get(id.clone())?
+        .ok_or_else(|| EM::EntryNotFound(id.local_display_string()))?;
+
+    let attachment = rt.store().attach(&mut entry, &name, &mime, &data)?;
+    debug!("Attached {} to {}: {:?}", file.display(), id, attachment);
+    rt.store().update(&mut entry)?;
+
+    if rt.output_format().is_structured() {
+        rt.records().emit(&record(&id, &attachment)?)?;
+    }
+    rt.report_touched(&id).map_err(Error::from)
+}
+
+fn list(rt: &Runtime) -> Result<()> {
+    for id in ids(rt)? {
+        let entry = rt.store()
+            .get_header_only(id.clone())?
+            .ok_or_else(|| EM::EntryNotFound(id.local_display_string()))?;
+
+        for attachment in entry.attachments()? {
+            rt.emit(record(&id, &attachment)?, |out| {
+                writeln!(out,
+                         "{} | {} | {} | {} bytes",
+                         id,
+                         attachment.name,
+                         attachment.mime,
+                         attachment.size)
+            })?;
+        }
+
+        rt.report_touched(&id)?;
+    }
+    Ok(())
+}
+
+fn extract(rt: &Runtime) -> Result<()> {
+    let scmd  = rt.cli().subcommand_matches("extract").unwrap(); // safed by main()
+    let name  = scmd.value_of("name").unwrap(); // safed by clap
+    let force = scmd.is_present("force");
+
+    let id    = id(rt)?;
+    let entry = rt.store()
+        .get_header_only(id.clone())?
+        .ok_or_else(|| EM::EntryNotFound(id.local_display_string()))?;
+
+    let attachment = entry
+        .attachments()?
+        .into_iter()
+        .find(|a| a.name == name)
+        .ok_or_else(|| format_err!("{} has no attachment named '{}'", id, name))?;
+    let data = rt.store().attachment_data(&attachment)?;
+
+    let output = match scmd.value_of("output") {
+        Some("-") if rt.output_format().is_structured() => {
+            return Err(err_msg("Cannot extract to stdout, it is used for the output records"))
+        },
+        Some("-") => {
+            // the data is the output, so the id is not reported as touched
+            return rt.stdout().write_all(&data).map_err(Error::from)
+        },
+        Some(path) => PathBuf::from(path),
+        None       => Path::new(name)
+            .file_name()
+            .map(PathBuf::from)
+            .ok_or_else(|| format_err!("Cannot write attachment '{}' to a file of that name, pass --output", name))?,
+    };
+
+    if output.exists() && !force {
+        return Err(format_err!("{} exists, pass --force to overwrite it", output.display()))
+    }
+
+    ::std::fs::write(&output, &data).context(format_err!("Failed to write {}", output.display()))?;
+    info!("Wrote {} bytes to {}", data.len(), output.display());
+    rt.report_touched(&id).map_err(Error::from)
+}
+
+fn record(id: &StoreId, attachment: &Attachment) -> Result<JsonValue> {
+    Ok(json!({
+        "id": id.to_str()?,
+        "name": attachment.name,
+        "mime": attachment.mime,
+        "size": attachment.size,
+        "hash": attachment.hash,
+    }))
+}
+
+fn remove(rt: &Runtime) -> Result<()> {
+    let scmd      = rt.cli().subcommand_matches("remove").unwrap(); // safed by main()
+    let id        = id(rt)?;
+    let mut entry = rt.store()
+        .get(id.clone())?
+        .ok_or_else(|| EM::EntryNotFound(id.local_display_string()))?;
+
+    for name in scmd.values_of("name").unwrap() { // safed by clap
+        let _ = rt.store().detach(&mut entry, name)?;
+    }
+
+    rt.store().update(&mut entry)?;
+    rt.report_touched(&id).map_err(Error::from)
+}
@@ -0,0 +1,156 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, App, SubCommand};
+
+use libimagstore::storeid::StoreId;
+use libimagstore::storeid::IntoStoreId;
+use libimagrt::runtime::IdPathProvider;
+
+use failure::Fallible as Result;
+
+pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+    app
+        .subcommand(SubCommand::with_name("add")
+                    .about("Attach a file to an entry")
+                    .version("0.1")
+                    .arg(Arg::with_name("entry")
+                         .index(1)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The entry to attach the file to")
+                         .value_name("ENTRY"))
+                    .arg(Arg::with_name("file")
+                         .index(2)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The file to attach")
+                         .value_name("FILE"))
+                    .arg(Arg::with_name("name")
+                         .long("name")
+                         .short("n")
+                         .takes_value(true)
+                         .required(false)
+                         .multiple(false)
+                         .help("The name of the attachment (default: the name of the file)")
+                         .value_name("NAME"))
+                    .arg(Arg::with_name("mime")
+                         .long("mime")
+                         .short("m")
+                         .takes_value(true)
+                         .required(false)
+                         .multiple(false)
+                         .help("The mime type of the file (default: guessed from the file extension)")
+                         .value_name("MIME"))
+                   )
+
+        .subcommand(SubCommand::with_name("list")
+                    .about("List the attachments of entries")
+                    .version("0.1")
+                    .arg(Arg::with_name("entry")
+                         .index(1)
+                         .takes_value(true)
+                         .required(false)
+                         .multiple(true)
+                         .help("The entries to list the attachments of")
+                         .value_name("ENTRY"))
+                   )
+
+        .subcommand(SubCommand::with_name("extract")
+                    .about("Write an attachment of an entry to a file")
+                    .version("0.1")
+                    .arg(Arg::with_name("entry")
+                         .index(1)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The entry with the attachment")
+                         .value_name("ENTRY"))
+                    .arg(Arg::with_name("name")
+                         .index(2)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The name of the attachment")
+                         .value_name("NAME"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .takes_value(true)
+                         .required(false)
+                         .multiple(false)
+                         .help("The file to write to, '-' for stdout (default: a file named like the attachment)")
+                         .value_name("PATH"))
+                    .arg(Arg::with_name("force")
+                         .long("force")
+                         .short("f")
+                         .takes_value(false)
+                         .required(false)
+                         .multiple(false)
+                         .help("Overwrite the file if it exists"))
+                   )
+
+        .subcommand(SubCommand::with_name("remove")
+                    .about("Remove attachments from an entry")
+                    .version("0.1")
+                    .arg(Arg::with_name("entry")
+                         .index(1)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The entry to remove the attachments from")
+                         .value_name("ENTRY"))
+                    .arg(Arg::with_name("name")
+                         .index(2)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(true)
+                         .help("The names of the attachments to remove")
+                         .value_name("NAME"))
+                   )
+}
+
+pub struct PathProvider;
+impl IdPathProvider for PathProvider {
+    fn get_ids(matches: &ArgMatches) -> Result<Option<Vec<StoreId>>> {
+        fn get_id_paths(subm: &ArgMatches) -> Result<Option<Vec<StoreId>>> {
+            subm.values_of("entry")
+                .map(|v| v
+                     .map(PathBuf::from)
+                     .map(|pb| pb.into_storeid())
+                     .collect::<Result<Vec<_>>>()
+                )
+                .transpose()
+        }
+
+        match matches.subcommand() {
+            ("add", Some(subm))     => get_id_paths(subm),
+            ("list", Some(subm))    => get_id_paths(subm),
+            ("extract", Some(subm)) => get_id_paths(subm),
+            ("remove", Some(subm))  => get_id_paths(subm),
+            (other, _) => {
+                Err(format_err!("Not a known command: {}", other))
+            }
+        }
+    }
+}
@@ -40,6 +40,8 @@ extern crate log;
 #[macro_use]
 extern crate failure;
 extern crate resiter;
+#[macro_use]
+extern crate serde_json;
 
 extern crate libimagentrycategory;
 extern crate libimagerror;
@@ -128,17 +130,17 @@ fn set(rt: &Runtime) -> Result<()> {
 }
 
 fn get(rt: &Runtime) -> Result<()> {
-    let out = rt.stdout();
-    let mut outlock = out.lock();
     rt.ids::<crate::ui::PathProvider>()?
         .ok_or_else(|| err_msg("No ids supplied"))?
         .into_iter()
-        .map(Ok)
-        .into_get_iter(rt.store())
-        .map_inner_ok_or_else(|| err_msg("Did not find one entry"))
-        .map_report_touched(&rt)
-        .and_then_ok(|e| e.get_category())
-        .and_then_ok(|n| writeln!(outlock, "{}", n).map_err(Error::from))
+        .map(|id| rt.store().get_header_only(id)?.ok_or_else(|| err_msg("Did not find one entry")))
+        .and_then_ok(|e| e.into_entry())
+        .and_then_ok(|e| {
+            rt.report_touched(e.get_location())?;
+            let n = e.get_category()?;
+            let record = json!({ "id": e.get_location().to_str()?, "category": n });
+            rt.emit(record, |out| writeln!(out, "{}", n))
+        })
         .collect()
 }
 
@@ -147,13 +149,13 @@ fn list_category(rt: &Runtime) -> Result<()> {
     let name = scmd.value_of("list-category-name").map(String::from).unwrap(); // safed by clap
 
     if let Some(category) = rt.store().get_category_by_name(&name)? {
-        let out         = rt.stdout();
-        let mut outlock = out.lock();
-
         category
             .get_entries(rt.store())?
             .map_report_touched(&rt)
-            .map(|entry| writeln!(outlock, "{}", entry?.get_location()).map_err(Error::from))
+            .and_then_ok(|entry| {
+                let record = json!({ "id": entry.get_location().to_str()?, "category": name });
+                rt.emit(record, |out| writeln!(out, "{}", entry.get_location()))
+            })
             .collect()
     } else {
         Err(format_err!("No category named '{}'", name))
@@ -189,12 +191,9 @@ fn delete_category(rt: &Runtime) -> Result<()> {
 }
 
 fn list_categories(rt: &Runtime) -> Result<()> {
-    let out         = rt.stdout();
-    let mut outlock = out.lock();
-
     rt.store()
         .all_category_names()?
-        .and_then_ok(|n| writeln!(outlock, "{}", n).map_err(Error::from))
+        .and_then_ok(|n| rt.emit(json!({ "category": n }), |out| writeln!(out, "{}", n)))
         .collect()
 }
 
@@ -0,0 +1,375 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+#![forbid(unsafe_code)]
+
+#![deny(
+    non_camel_case_types,
+    non_snake_case,
+    path_statements,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_allocation,
+    unused_import_braces,
+    unused_imports,
+    unused_must_use,
+    unused_mut,
+    unused_qualifications,
+    while_true,
+)]
+
+extern crate clap;
+#[macro_use] extern crate log;
+#[macro_use] extern crate serde_json;
+extern crate toml;
+extern crate toml_query;
+extern crate toml_edit;
+#[macro_use] extern crate failure;
+
+extern crate libimagrt;
+extern crate libimagentryref;
+extern crate libimagdiary;
+extern crate libimagmail;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::App;
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use toml_query::read::TomlValueReadTypeExt;
+use toml_edit::Document;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::err_msg;
+
+use libimagrt::application::ImagApplication;
+use libimagrt::configuration::{config_leaves, fetch_user_config_path, is_allowed_in_store_config, parse_value};
+use libimagrt::configuration::{ConfigOrigin, STORE_CONFIG_NAME, SYSTEM_CONFIG_PATH};
+use libimagrt::io::toml_to_json;
+use libimagrt::runtime::Runtime;
+use libimagrt::schema::{ConfigSchema, ValueKind};
+
+mod ui;
+
+/// Marker enum for implementing ImagApplication on
+///
+/// This is used by binaries crates to execute business logic
+/// or to build a CLI completion.
+pub enum ImagConfig {}
+impl ImagApplication for ImagConfig {
+    fn run(rt: Runtime) -> Result<()> {
+        match rt.cli().subcommand_name().ok_or_else(|| err_msg("No command called"))? {
+            "show"     => show(&rt),
+            "get"      => get(&rt),
+            "set"      => set(&rt),
+            "unset"    => unset(&rt),
+            "validate" => validate(&rt),
+            other      => {
+                debug!("Unknown command");
+                if rt.handle_unknown_subcommand("imag-config", other, rt.cli())?.success() {
+                    Ok(())
+                } else {
+                    Err(err_msg("Failed to handle unknown subcommand"))
+                }
+            },
+        }
+    }
+
+    fn build_cli<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+        ui::build_ui(app)
+    }
+
+    fn name() -> &'static str {
+        env!("CARGO_PKG_NAME")
+    }
+
+    fn description() -> &'static str {
+        "Show, change and validate the imag configuration"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+}
+
+fn show(rt: &Runtime) -> Result<()> {
+    let scmd        = rt.cli().subcommand_matches("show").unwrap(); // safe by main()
+    let with_origin = scmd.is_present("origin");
+    let key         = scmd.value_of("key");
+    let config      = rt.config().ok_or_else(|| err_msg("No configuration"))?;
+
+    config_leaves(config)
+        .into_iter()
+        .filter(|(path, _)| key.map(|key| is_below(path, key)).unwrap_or(true))
+        .map(|(path, value)| {
+            let origin = rt.config_origin(&path).map(ToString::to_string);
+            let record = json!({
+                "key":    path,
+                "value":  toml_to_json(value),
+                "origin": origin,
+            });
+
+            rt.emit(record, |out| if with_origin {
+                let origin = origin.unwrap_or_else(|| String::from("unknown"));
+                writeln!(out, "{} = {} # {}", path, value, origin)
+            } else {
+                writeln!(out, "{} = {}", path, value)
+            })
+        })
+        .collect()
+}
+
+fn get(rt: &Runtime) -> Result<()> {
+    let scmd   = rt.cli().subcommand_matches("get").unwrap(); // safe by main()
+    let key    = scmd.value_of("key").unwrap(); // enforced by clap
+    let config = rt.config().ok_or_else(|| err_msg("No configuration"))?;
+
+    let value = config
+        .read(key)
+        .context(format_err!("Failed to read '{}'", key))?
+        .ok_or_else(|| match schema().key(key) {
+            Some(described) => format_err!("'{}' is not set: {}", key, described.description),
+            None            => format_err!("'{}' is not set", key),
+        })?;
+
+    let record = json!({
+        "key":    key,
+        "value":  toml_to_json(value),
+        "origin": rt.config_origin(key).map(ToString::to_string),
+    });
+
+    rt.emit(record, |out| match *value {
+        Value::String(ref s) => writeln!(out, "{}", s),
+        ref other            => writeln!(out, "{}", other),
+    })
+}
+
+fn set(rt: &Runtime) -> Result<()> {
+    let scmd   = rt.cli().subcommand_matches("set").unwrap(); // safe by main()
+    let key    = scmd.value_of("key").unwrap(); // enforced by clap
+    let value  = scmd.value_of("value").unwrap(); // enforced by clap
+    let file   = layer_file(rt, scmd.value_of("layer"))?;
+    let schema = schema();
+
+    let value = match schema.key(key) {
+        Some(described) => described.kind.parse(value).context(format_err!("Cannot set '{}'", key))?,
+        None            => {
+            warn!("'{}' is not a known setting", key);
+            parse_value(value)
+        },
+    };
+
+    if let Some(violation) = schema.check(key, &value) {
+        return Err(format_err!("{}", violation))
+    }
+
+    if ValueKind::of(&value) == ValueKind::Table {
+        return Err(format_err!("Cannot set '{}' to a table, set the values in the table instead", key))
+    }
+
+    let edit_value = value
+        .to_string()
+        .parse::<toml_edit::Value>()
+        .map_err(|e| format_err!("{}", e))
+        .context(format_err!("Cannot set '{}' to '{}'", key, value))?;
+
+    let mut document = read_document(&file)?;
+    let (name, table) = table_of(&mut document, key, true)?;
+    let item = table.entry(name);
+    let edit_value = match item.as_value().map(toml_edit::Value::decor) {
+        Some(decor) => toml_edit::decorated(edit_value, decor.prefix(), decor.suffix()),
+        None        => toml_edit::decorated(edit_value, " ", ""),
+    };
+    *item = toml_edit::Item::Value(edit_value);
+    write_document(&file, &document)?;
+
+    info!("Set '{}' = {} in {}", key, value, file.display());
+    warn_if_overridden(rt, key, &file);
+    if scmd.value_of("layer") == Some("store") && !is_allowed_in_store_config(key) && !trusts_store_config(rt) {
+        warn!("'{}' is ignored in the store configuration unless 'rt.trust-store-config' is set", key);
+    }
+    Ok(())
+}
+
+fn unset(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("unset").unwrap(); // safe by main()
+    let key  = scmd.value_of("key").unwrap(); // enforced by clap
+    let file = layer_file(rt, scmd.value_of("layer"))?;
+
+    let mut document = read_document(&file)?;
+    {
+        let (name, table) = table_of(&mut document, key, false)
+            .context(format_err!("'{}' is not set in {}", key, file.display()))?;
+
+        if table.remove(name).is_none() {
+            return Err(format_err!("'{}' is not set in {}", key, file.display()))
+        }
+    }
+    write_document(&file, &document)?;
+
+    if let Some(described) = schema().key(key).filter(|k| k.required) {
+        warn!("'{}' is required: {}", key, described.description);
+    }
+
+    info!("Removed '{}' from {}", key, file.display());
+    warn_if_overridden(rt, key, &file);
+    Ok(())
+}
+
+fn validate(rt: &Runtime) -> Result<()> {
+    let config     = rt.config().ok_or_else(|| err_msg("No configuration"))?;
+    let violations = schema().validate(config);
+
+    for violation in violations.iter() {
+        let origin = rt.config_origin(violation.path()).map(ToString::to_string);
+        let record = json!({
+            "key":     violation.path(),
+            "problem": violation.to_string(),
+            "origin":  origin,
+        });
+
+        rt.emit(record, |out| match origin {
+            Some(ref origin) => writeln!(out, "{} (from {})", violation, origin),
+            None             => writeln!(out, "{}", violation),
+        })?;
+    }
+
+    if violations.is_empty() {
+        info!("Configuration is valid");
+        Ok(())
+    } else {
+        Err(format_err!("Found {} problem(s) in the configuration", violations.len()))
+    }
+}
+
+/// The schema of the configuration, as described by the libraries reading it
+fn schema() -> ConfigSchema {
+    ConfigSchema::new()
+        .with_keys(libimagrt::schema::CONFIG_SCHEMA)
+        .with_keys(libimagrt::schema::TOOLS_CONFIG_SCHEMA)
+        .with_keys(libimagdiary::config::CONFIG_SCHEMA)
+        .with_keys(libimagentryref::reference::CONFIG_SCHEMA)
+        .with_keys(libimagmail::config::CONFIG_SCHEMA)
+}
+
+/// The configuration file of the layer `set` and `unset` work on
+fn layer_file(rt: &Runtime, layer: Option<&str>) -> Result<PathBuf> {
+    match layer {
+        Some("system")      => Ok(PathBuf::from(SYSTEM_CONFIG_PATH)),
+        Some("store")       => Ok(rt.store().path().join(STORE_CONFIG_NAME)),
+        Some("user") | None => {
+            let searchpath = rt
+                .cli()
+                .value_of("config")
+                .map_or_else(|| rt.rtp().clone(), PathBuf::from);
+
+            Ok(fetch_user_config_path(&searchpath).unwrap_or_else(|| if searchpath.is_dir() {
+                searchpath.join("imagrc.toml")
+            } else {
+                searchpath
+            }))
+        },
+        Some(other)         => Err(format_err!("Unknown configuration layer: {}", other)),
+    }
+}
+
+fn read_document(file: &Path) -> Result<Document> {
+    if !file.exists() {
+        return Ok(Document::new())
+    }
+
+    fs::read_to_string(file)
+        .context(format_err!("Cannot read {}", file.display()))?
+        .parse::<Document>()
+        .map_err(|e| format_err!("{}", e))
+        .context(format_err!("Cannot parse {}", file.display()))
+        .map_err(From::from)
+}
+
+fn write_document(file: &Path, document: &Document) -> Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(file, document.to_string())
+        .context(format_err!("Cannot write {}", file.display()))
+        .map_err(From::from)
+}
+
+/// Get the table the setting `key` is in, and the name of the setting in it
+///
+/// Missing tables are created if `create` is set.
+fn table_of<'a, 'k>(document: &'a mut Document, key: &'k str, create: bool)
+    -> Result<(&'k str, &'a mut toml_edit::Table)>
+{
+    let mut path = key.split('.').collect::<Vec<_>>();
+    let name     = path.pop().filter(|name| !name.is_empty()).ok_or_else(|| err_msg("Empty key"))?;
+    let mut table = document.as_table_mut();
+
+    for part in path {
+        let item = table.entry(part);
+        if item.is_none() && create {
+            let mut new_table = toml_edit::Table::new();
+            new_table.set_implicit(true);
+            *item = toml_edit::Item::Table(new_table);
+        }
+
+        if item.is_none() {
+            return Err(format_err!("There is no table '{}'", part))
+        }
+
+        table = item
+            .as_table_mut()
+            .ok_or_else(|| format_err!("'{}' in '{}' is not a table", part, key))?;
+    }
+
+    Ok((name, table))
+}
+
+/// Whether the store-local configuration may change all settings
+fn trusts_store_config(rt: &Runtime) -> bool {
+    rt.config()
+        .and_then(|c| c.read_bool("rt.trust-store-config").ok())
+        .and_then(|b| b)
+        .unwrap_or(false)
+}
+
+/// Warn if the setting in `file` has no effect because a higher configuration layer overrides it
+fn warn_if_overridden(rt: &Runtime, key: &str, file: &Path) {
+    let overridden_by = |origin: &ConfigOrigin| match *origin {
+        ConfigOrigin::System(_)       => false,
+        ConfigOrigin::User(_)         => file == Path::new(SYSTEM_CONFIG_PATH),
+        ConfigOrigin::Store(ref path) => path != file,
+        ConfigOrigin::Environment(_)  => true,
+        ConfigOrigin::CommandLine     => true,
+    };
+
+    if let Some(origin) = rt.config_origin(key).filter(|o| overridden_by(o)) {
+        warn!("'{}' is overridden by the {}", key, origin);
+    }
+}
+
+/// Whether the configuration value at `path` is `key` or inside the table at `key`
+fn is_below(path: &str, key: &str) -> bool {
+    path == key || (path.starts_with(key) && path[key.len()..].starts_with('.'))
+}
@@ -0,0 +1,90 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+use clap::{Arg, App, SubCommand};
+
+pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+    app
+        .subcommand(SubCommand::with_name("show")
+                    .about("Show the configuration values")
+                    .version("0.1")
+                    .arg(Arg::with_name("origin")
+                         .long("origin")
+                         .takes_value(false)
+                         .required(false)
+                         .multiple(false)
+                         .help("Show which configuration layer each value comes from"))
+                    .arg(Arg::with_name("key")
+                         .index(1)
+                         .takes_value(true)
+                         .required(false)
+                         .multiple(false)
+                         .help("Only show this value or the values in this table, e.g. 'imag.logging'")
+                         .value_name("KEY")))
+
+        .subcommand(SubCommand::with_name("get")
+                    .about("Print a configuration value")
+                    .version("0.1")
+                    .arg(key_arg("The setting to print, e.g. 'rt.editor'")))
+
+        .subcommand(SubCommand::with_name("set")
+                    .about("Set a configuration value in a configuration file")
+                    .version("0.1")
+                    .arg(key_arg("The setting to set, e.g. 'rt.editor'"))
+                    .arg(Arg::with_name("value")
+                         .index(2)
+                         .takes_value(true)
+                         .required(true)
+                         .multiple(false)
+                         .help("The value, converted to the type the setting has to be of. Arrays are written in TOML syntax, e.g. '[\"a\", \"b\"]'")
+                         .value_name("VALUE"))
+                    .arg(layer_arg()))
+
+        .subcommand(SubCommand::with_name("unset")
+                    .about("Remove a configuration value from a configuration file")
+                    .version("0.1")
+                    .arg(key_arg("The setting to remove, e.g. 'rt.editor'"))
+                    .arg(layer_arg()))
+
+        .subcommand(SubCommand::with_name("validate")
+                    .about("Check the configuration for missing settings and settings of the wrong type")
+                    .version("0.1"))
+}
+
+fn key_arg<'a>(help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name("key")
+        .index(1)
+        .takes_value(true)
+        .required(true)
+        .multiple(false)
+        .help(help)
+        .value_name("KEY")
+}
+
+fn layer_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("layer")
+        .long("layer")
+        .takes_value(true)
+        .required(false)
+        .multiple(false)
+        .possible_values(&["system", "user", "store"])
+        .help("The configuration file to change: the system configuration, the user configuration (default) or the store-local configuration")
+        .value_name("LAYER")
+}
@@ -42,12 +42,14 @@ extern crate libimagrt;
 extern crate libimagstore;
 
 use failure::Fallible as Result;
+use failure::Error;
 use failure::err_msg;
 use clap::App;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
 use libimagrt::iter::ReportTouchedResultEntry;
+use libimagrt::pipe::PipeAction;
 use libimagstore::iter::create::StoreIdCreateIteratorExtension;
 use libimagstore::iter::retrieve::StoreIdRetrieveIteratorExtension;
 
@@ -68,7 +70,11 @@ impl ImagApplication for ImagCreate {
         if force {
             ids.into_retrieve_iter(rt.store()).map_report_touched(&rt).collect::<Result<Vec<_>>>()
         } else {
-            ids.into_create_iter(rt.store()).map_report_touched(&rt).collect::<Result<Vec<_>>>()
+            ids.into_create_iter(rt.store())
+                .map(|r| r.and_then(|e| {
+                    rt.report_action(e.get_location(), PipeAction::Created).map_err(Error::from).map(|_| e)
+                }))
+                .collect::<Result<Vec<_>>>()
         }.map(|_| ())
     }
 
@@ -39,8 +39,8 @@ extern crate toml;
 extern crate toml_query;
 extern crate indicatif;
 extern crate failure;
-extern crate resiter;
 #[macro_use] extern crate log;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagrt;
 extern crate libimagerror;
@@ -61,8 +61,6 @@ use indicatif::{ProgressBar, ProgressStyle};
 use failure::Fallible as Result;
 use failure::err_msg;
 use clap::App;
-use resiter::AndThen;
-use resiter::IterInnerOkOrElse;
 
 use std::collections::BTreeMap;
 
@@ -127,17 +125,14 @@ impl ImagApplication for ImagDiagnostics {
         spinner.set_style(style);
         spinner.set_message("Accumulating data");
 
-        let diags = rt.store()
+        let mut diags = rt.store()
             .entries()?
-            .into_get_iter()
-            .map_inner_ok_or_else(|| err_msg("Unable to get entry"))
-            .and_then_ok(|e| {
+            .par_map(|e| {
                 let diag = Diagnostic::for_entry(&e);
                 debug!("Diagnostic for '{:?}' = {:?}", e.get_location(), diag);
-                drop(e);
                 diag
-            })
-            .collect::<Result<Vec<_>>>()?;
+            })?;
+        diags.sort_by(|a, b| a.id.cmp(&b.id));
 
         spinner.finish();
         let n                = diags.len();
@@ -197,6 +192,35 @@ impl ImagApplication for ImagDiagnostics {
 
         progress.finish();
 
+        let locks = rt.store().locks()?;
+        let stale = locks.iter().filter(|l| rt.store().is_stale_lock(l)).collect::<Vec<_>>();
+
+        if rt.output_format().is_structured() {
+            let record = json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "entries": n,
+                "store_versions": version_counts,
+                "header_sections_avg": if n != 0 { sum_header_sections / n } else { 0 },
+                "content_bytes_avg": if n != 0 { sum_bytecount_content / n } else { 0 },
+                "overall_bytes_avg": if n != 0 { sum_overall_byte_size / n } else { 0 },
+                "largest_entry": max_overall_byte_size.map(|(num, id)| json!({ "id": id.to_string(), "bytes": num })),
+                "links_avg": if n != 0 { num_links / n } else { 0 },
+                "most_links": max_links.map(|(num, id)| json!({ "id": id.to_string(), "links": num })),
+                "verified": verified_count,
+                "unverified": unverified_count,
+                "unverified_entries": unverified_entries.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                "locked_entries": locks.len(),
+                "stale_locks": stale.iter().map(|holder| json!({
+                    "id": holder.id.to_string(),
+                    "pid": holder.pid,
+                    "host": holder.host,
+                    "command": holder.command,
+                    "acquired": holder.acquired.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                })).collect::<Vec<_>>(),
+            });
+            return rt.records().emit(&record);
+        }
+
         let mut out = rt.stdout();
 
         write!(out, "imag version {}", { env!("CARGO_PKG_VERSION") })?;
@@ -230,6 +254,17 @@ impl ImagApplication for ImagDiagnostics {
                 }
             }
         }
+
+        writeln!(out, "{} locked entries", locks.len())?;
+        writeln!(out, "{} stale locks", stale.len())?;
+        for holder in stale {
+            writeln!(out, "Stale lock: {} (process {} on {} since {}: {})",
+                     holder.id,
+                     holder.pid,
+                     holder.host,
+                     holder.acquired.format("%Y-%m-%d %H:%M:%S"),
+                     holder.command)?;
+        }
         Ok(())
     }
 
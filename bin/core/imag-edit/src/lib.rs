@@ -80,12 +80,15 @@ impl ImagApplication for ImagEdit {
             .map_report_touched(&rt)
             .and_then_ok(|mut entry| {
                 if edit_header {
-                    entry.edit_header_and_content(&rt)
+                    entry.edit_header_and_content(&rt)?;
                 } else if edit_header_only {
-                    entry.edit_header(&rt)
+                    entry.edit_header(&rt)?;
                 } else {
-                    entry.edit_content(&rt)
+                    entry.edit_content(&rt)?;
                 }
+
+                // Write explicitly, so an edited header which violates the schema is reported
+                rt.store().update(&mut entry)
             })
             .collect()
     }
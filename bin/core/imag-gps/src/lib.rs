@@ -37,6 +37,7 @@
 extern crate clap;
 #[macro_use] extern crate log;
 #[macro_use] extern crate failure;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagentrygps;
 extern crate libimagrt;
@@ -167,7 +168,11 @@ fn remove(rt: &Runtime) -> Result<()> {
                 .ok_or_else(|| format_err!("Entry had no coordinates: {}", id))??;
 
             if print_removed {
-                writeln!(rt.stdout(), "{}", removed_value)?;
+                let record = json!({
+                    "id": id.to_str()?,
+                    "coordinates": ::serde_json::to_value(&removed_value)?,
+                });
+                rt.emit(record, |out| writeln!(out, "{}", removed_value))?;
             }
 
             rt.report_touched(&id).map_err(Error::from)
@@ -176,8 +181,6 @@ fn remove(rt: &Runtime) -> Result<()> {
 }
 
 fn get(rt: &Runtime) -> Result<()> {
-    let mut stdout = rt.stdout();
-
     rt_get_ids(&rt)?
         .into_iter()
         .map(|id| {
@@ -192,7 +195,11 @@ fn get(rt: &Runtime) -> Result<()> {
                     format_err!("Entry has no coordinates: {}", id)
                 })?;
 
-            writeln!(stdout, "{}", value)?;
+            let record = json!({
+                "id": id.to_str()?,
+                "coordinates": ::serde_json::to_value(&value)?,
+            });
+            rt.emit(record, |out| writeln!(out, "{}", value))?;
 
             rt.report_touched(&id).map_err(Error::from)
         })
@@ -38,7 +38,7 @@
 #[macro_use] extern crate failure;
 extern crate clap;
 extern crate regex;
-extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagstore;
 extern crate libimagrt;
@@ -50,9 +50,6 @@ use regex::Regex;
 use clap::App;
 use failure::Error;
 use failure::Fallible as Result;
-use failure::err_msg;
-use resiter::AndThen;
-use resiter::IterInnerOkOrElse;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
@@ -87,27 +84,33 @@ impl ImagApplication for ImagGrep {
             .unwrap() // ensured by clap
             .map_err(|e| format_err!("Regex building error: {:?}", e))?;
 
-        let overall_count = rt
+        // Matching is done in parallel, the output is written afterwards in a stable order
+        let matches = rt
             .store()
             .entries()?
-            .into_get_iter()
-            .map_inner_ok_or_else(|| err_msg("Entry from entries missing"))
-            .and_then_ok(|entry| {
-                if pattern.is_match(entry.get_content()) {
-                    debug!("Matched: {}", entry.get_location());
-                    rt.report_touched(entry.get_location())?;
-                    show(&rt, &entry, &pattern, &opts, &mut count)
-                } else {
-                    debug!("Not matched: {}", entry.get_location());
-                    Ok(())
-                }
-            })
-            .collect::<Result<Vec<_>>>()?
-            .len();
+            .par_map(|entry| if pattern.is_match(entry.get_content()) {
+                debug!("Matched: {}", entry.get_location());
+                Ok(Some(entry.get_location().clone()))
+            } else {
+                debug!("Not matched: {}", entry.get_location());
+                Ok(None)
+            })?;
+        let overall_count = matches.len();
+        let mut matches   = matches.into_iter().flatten().collect::<Vec<_>>();
+        matches.sort();
+
+        for id in matches {
+            let entry = rt
+                .store()
+                .get(id.clone())?
+                .ok_or_else(|| format_err!("Entry from entries missing: {}", id))?;
+            rt.report_touched(entry.get_location())?;
+            show(&rt, &entry, &pattern, &opts, &mut count)?;
+        }
 
         if opts.count {
-            writeln!(rt.stdout(), "{}", count)?;
-        } else if !opts.files_with_matches {
+            rt.emit(json!({ "count": count }), |out| writeln!(out, "{}", count))?;
+        } else if !opts.files_with_matches && !rt.output_format().is_structured() {
             writeln!(rt.stdout(), "Processed {} files, {} matches, {} nonmatches",
                      overall_count,
                      count,
@@ -136,7 +139,19 @@ impl ImagApplication for ImagGrep {
 }
 
 fn show(rt: &Runtime, e: &Entry, re: &Regex, opts: &Options, count: &mut usize) -> Result<()> {
-    if opts.files_with_matches {
+    if rt.output_format().is_structured() && !opts.count {
+        let mut record = json!({ "id": e.get_location().to_str()? });
+        if !opts.files_with_matches {
+            record["matches"] = re
+                .captures_iter(e.get_content())
+                .flat_map(|capture| {
+                    capture.iter().flatten().map(|m| json!(m.as_str())).collect::<Vec<_>>()
+                })
+                .collect();
+            *count += 1;
+        }
+        rt.records().emit(&record)?;
+    } else if opts.files_with_matches {
         writeln!(rt.stdout(), "{}", e.get_location())?;
     } else if opts.count {
         *count += 1;
@@ -39,6 +39,7 @@ extern crate toml;
 extern crate toml_query;
 extern crate filters;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagentryedit;
 extern crate libimagerror;
@@ -62,6 +63,7 @@ use resiter::IterInnerOkOrElse;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
+use libimagrt::io::toml_to_json;
 use libimagstore::iter::get::StoreIdGetIteratorExtension;
 use libimagstore::store::FileLockEntry;
 
@@ -137,9 +139,6 @@ fn read<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
 {
     debug!("Processing headers: reading value");
     let header_path = get_header_path(mtch, "header-value-path");
-    let mut output = rt.stdout();
-    trace!("Got output: {:?}", output);
-
     iter.and_then_ok(|entry| {
         trace!("Processing headers: working on {:?}", entry.get_location());
         entry.get_header()
@@ -156,12 +155,17 @@ fn read<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
                     _ => None,
                 };
 
-                if let Some(repr) = string_representation {
-                    writeln!(output, "{}", repr)?;
+                let record = json!({
+                    "id": entry.get_location().to_str()?,
+                    "path": header_path,
+                    "value": toml_to_json(value),
+                });
+
+                rt.emit(record, |output| if let Some(repr) = string_representation {
+                    writeln!(output, "{}", repr)
                 } else {
-                    writeln!(output, "{}", value)?;
-                }
-                Ok(())
+                    writeln!(output, "{}", value)
+                })
             })
     })
     .collect::<Result<()>>()
@@ -177,10 +181,10 @@ fn has<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
     iter.and_then_ok(|entry| {
         trace!("Processing headers: working on {:?}", entry.get_location());
         if let Some(_) = entry.get_header().read(header_path)?  {
-            if !rt.output_is_pipe() {
+            if !rt.output_is_pipe() && !rt.output_format().is_structured() {
                 writeln!(output, "{}", entry.get_location())?;
             }
-            rt.report_touched(entry.get_location()).map_err(Error::from)
+            report_match(rt, &entry)
         } else {
             Ok(())
         }
@@ -200,15 +204,24 @@ fn hasnt<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
         if let Some(_) = entry.get_header().read(header_path)? {
             Ok(())
         } else {
-            if !rt.output_is_pipe() {
+            if !rt.output_is_pipe() && !rt.output_format().is_structured() {
                 writeln!(output, "{}", entry.get_location())?;
             }
-            rt.report_touched(entry.get_location()).map_err(Error::from)
+            report_match(rt, &entry)
         }
     })
     .collect()
 }
 
+/// Report an entry which matched, as record with the structured output formats
+fn report_match(rt: &Runtime, entry: &FileLockEntry) -> Result<()> {
+    if rt.output_format().is_structured() {
+        rt.records().emit(&json!({ "id": entry.get_location().to_str()? }))
+    } else {
+        rt.report_touched(entry.get_location()).map_err(Error::from)
+    }
+}
+
 macro_rules! implement_compare {
     { $mtch: ident, $path: expr, $t: ty, $compare: expr } => {{
         trace!("Getting value at {}, comparing as {}", $path, stringify!($t));
@@ -256,7 +269,7 @@ fn int<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
         }
     })
     .filter_map_ok(|(b, e)| if b { Some(e) } else { None })
-    .and_then_ok(|entry| rt.report_touched(entry.get_location()).map_err(Error::from))
+    .and_then_ok(|entry| report_match(rt, &entry))
     .collect()
 }
 
@@ -294,7 +307,7 @@ fn float<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
         }
     })
     .filter_map_ok(|(b, e)| if b { Some(e) } else { None })
-    .and_then_ok(|entry| rt.report_touched(entry.get_location()).map_err(Error::from))
+    .and_then_ok(|entry| report_match(rt, &entry))
     .collect()
 }
 
@@ -320,7 +333,7 @@ fn string<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()>
         }
     })
     .filter_map_ok(|(b, e)| if b { Some(e) } else { None })
-    .and_then_ok(|entry| rt.report_touched(entry.get_location()).map_err(Error::from))
+    .and_then_ok(|entry| report_match(rt, &entry))
     .collect()
 }
 
@@ -342,7 +355,7 @@ fn boolean<'a, 'e, I>(rt: &Runtime, mtch: &ArgMatches<'a>, iter: I) -> Result<()
         }
     })
     .filter_map_ok(|(b, e)| if b { Some(e) } else { None })
-    .and_then_ok(|entry| rt.report_touched(entry.get_location()).map_err(Error::from))
+    .and_then_ok(|entry| report_match(rt, &entry))
     .collect()
 }
 
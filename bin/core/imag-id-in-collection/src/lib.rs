@@ -40,6 +40,7 @@ extern crate filters;
 extern crate toml;
 extern crate toml_query;
 extern crate failure;
+#[macro_use] extern crate serde_json;
 
 #[cfg(test)]
 extern crate env_logger;
@@ -98,19 +99,16 @@ impl ImagApplication for ImagIdInCollection {
             .map(|v| v.collect::<Vec<&str>>());
 
         let collection_filter = IsInCollectionsFilter::new(values);
-
-        let mut stdout = rt.stdout();
-        trace!("Got output: {:?}", stdout);
+        trace!("Got output format: {:?}", rt.output_format());
 
         rt.ids::<crate::ui::PathProvider>()?
             .ok_or_else(|| err_msg("No ids supplied"))?
             .iter()
             .filter(|id| collection_filter.filter(id))
             .map(|id| {
-                if !rt.output_is_pipe() {
+                if !rt.output_is_pipe() || rt.output_format().is_structured() {
                     let id = id.to_str()?;
-                    trace!("Writing to {:?}", stdout);
-                    writeln!(stdout, "{}", id)?;
+                    rt.emit(json!({ "id": id }), |out| writeln!(out, "{}", id))?;
                 }
 
                 rt.report_touched(&id).map_err(Error::from)
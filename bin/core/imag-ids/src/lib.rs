@@ -40,6 +40,7 @@ extern crate toml;
 extern crate toml_query;
 #[macro_use] extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 #[cfg(test)]
 extern crate env_logger;
@@ -72,24 +73,25 @@ impl ImagApplication for ImagIds {
     fn run(rt: Runtime) -> Result<()> {
         let print_storepath = rt.cli().is_present("print-storepath");
 
-        let mut stdout = rt.stdout();
-        trace!("Got output: {:?}", stdout);
-
-        let mut process = |iter: &mut dyn Iterator<Item = Result<StoreId>>| -> Result<()> {
+        let process = |iter: &mut dyn Iterator<Item = Result<StoreId>>| -> Result<()> {
             iter.map_ok(|id| if print_storepath {
                 (Some(rt.store().path()), id)
             } else {
                 (None, id)
             }).and_then_ok(|(storepath, id)| {
-                if !rt.output_is_pipe() {
-                    let id = id.to_str()?;
-                    trace!("Writing to {:?}", stdout);
-
-                    if let Some(store) = storepath {
-                        writeln!(stdout, "{}/{}", store.display(), id)?;
-                    } else {
-                        writeln!(stdout, "{}", id)?;
-                    }
+                if !rt.output_is_pipe() || rt.output_format().is_structured() {
+                    let id   = id.to_str()?;
+                    let mut record = json!({ "id": id });
+                    let text = match storepath {
+                        Some(store) => {
+                            let path = format!("{}/{}", store.display(), id);
+                            record["path"] = json!(path);
+                            path
+                        },
+                        None => id,
+                    };
+
+                    rt.emit(record, |out| writeln!(out, "{}", text))?;
                 }
 
                 rt.report_touched(&id).map_err(Error::from)
@@ -39,6 +39,7 @@ extern crate clap;
 extern crate url;
 #[macro_use] extern crate failure;
 #[macro_use] extern crate prettytable;
+#[macro_use] extern crate serde_json;
 #[cfg(test)] extern crate toml;
 #[cfg(test)] extern crate toml_query;
 #[cfg(test)] extern crate env_logger;
@@ -65,6 +66,8 @@ use failure::err_msg;
 use libimagentryurl::linker::UrlLinker;
 use libimagentrylink::linkable::Linkable;
 use libimagentrylink::storecheck::StoreLinkConsistentExt;
+use libimagrt::pipe::PipeAction;
+use libimagrt::pipe::PipeRecord;
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
 use libimagstore::store::FileLockEntry;
@@ -164,24 +167,29 @@ fn link_from_to<'a, I>(rt: &'a Runtime, from: &'a str, to: I) -> Result<()>
                 return Err(err_msg("Cannot link entry with itself. Exiting"))
             }
 
-            let mut to_entry = rt
-                .store()
-                .get(entr_id)?
-                .ok_or_else(|| format_err!("No 'to' entry: {}", entry))?;
+            // Linking alters both entries, so write them in one transaction
+            rt.store().transaction(|store| {
+                let mut to_entry = store
+                    .get(entr_id.clone())?
+                    .ok_or_else(|| format_err!("No 'to' entry: {}", entry))?;
 
-            if directional {
-                from_entry.add_link_to(&mut to_entry)?;
-            } else {
-                from_entry.add_link(&mut to_entry)?;
-            }
+                if directional {
+                    from_entry.add_link_to(&mut to_entry)?;
+                } else {
+                    from_entry.add_link(&mut to_entry)?;
+                }
+
+                store.update(&mut to_entry)?;
+                rt.store().update(&mut from_entry)
+            })?;
 
-            rt.report_touched(to_entry.get_location())?;
+            rt.report_record(&PipeRecord::new(entr_id, PipeAction::Updated).with_meta("linked", json!(from)))?;
         }
 
         info!("Ok: {} -> {}", from, entry);
     }
 
-    rt.report_touched(from_entry.get_location()).map_err(Error::from)
+    rt.report_action(from_entry.get_location(), PipeAction::Updated).map_err(Error::from)
 }
 
 fn remove_linking(rt: &Runtime) -> Result<()> {
@@ -200,7 +208,9 @@ fn remove_linking(rt: &Runtime) -> Result<()> {
         .map(|id| match rt.store().get(id.clone())? {
             Some(mut to_entry) => {
                 to_entry.remove_link(&mut from)?;
-                rt.report_touched(to_entry.get_location()).map_err(Error::from)
+                let record = PipeRecord::new(id, PipeAction::Updated)
+                    .with_meta("unlinked", json!(from.get_location().to_str()?));
+                rt.report_record(&record).map_err(Error::from)
             },
 
             None => {
@@ -219,7 +229,7 @@ fn remove_linking(rt: &Runtime) -> Result<()> {
         })
         .collect::<Result<Vec<_>>>()?;
 
-    rt.report_touched(from.get_location()).map_err(Error::from)
+    rt.report_action(from.get_location(), PipeAction::Updated).map_err(Error::from)
 }
 
 fn unlink(rt: &Runtime) -> Result<()> {
@@ -233,7 +243,7 @@ fn unlink(rt: &Runtime) -> Result<()> {
                 .ok_or_else(|| format_err!("No entry for {}", id))?
                 .unlink(rt.store())?;
 
-            rt.report_touched(&id).map_err(Error::from)
+            rt.report_action(&id, PipeAction::Updated).map_err(Error::from)
         })
         .collect()
 }
@@ -255,38 +265,36 @@ fn list_linkings(rt: &Runtime) -> Result<()> {
         .map(|id| {
             let entry = rt.store().get(id.clone())?.ok_or_else(|| format_err!("Not found: {}", id))?;
 
-            for (i, link) in entry.links()?.enumerate() {
-                let link = link.to_str()?;
-
-                if list_plain {
-                    writeln!(rt.stdout(), "{: <3}: {}", i, link)?;
-                } else {
-                    tab.add_row(row![i, link]);
-                }
-            }
-
-            if list_externals {
+            let links = entry.links()?.map(|link| link.to_str()).collect::<Result<Vec<_>>>()?;
+            let urls  = if list_externals {
                 entry.get_urls(rt.store())?
-                    .enumerate()
-                    .map(|(i, link)| {
-                        let link = link?.into_string();
-
-                        if list_plain {
-                            writeln!(rt.stdout(), "{: <3}: {}", i, link)?;
-                        } else {
-                            tab.add_row(row![i, link]);
-                        }
+                    .map(|link| link.map(|link| link.into_string()))
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                vec![]
+            };
 
-                        Ok(())
-                    })
-                    .collect::<Result<Vec<_>>>()?;
+            if rt.output_format().is_structured() {
+                let mut record = json!({ "id": entry.get_location().to_str()?, "links": links });
+                if list_externals {
+                    record["urls"] = json!(urls);
+                }
+                rt.records().emit(&record)?;
+            } else {
+                for (i, link) in links.iter().enumerate().chain(urls.iter().enumerate()) {
+                    if list_plain {
+                        writeln!(rt.stdout(), "{: <3}: {}", i, link)?;
+                    } else {
+                        tab.add_row(row![i, link]);
+                    }
+                }
             }
 
             rt.report_touched(entry.get_location()).map_err(Error::from)
         })
         .collect::<Result<Vec<_>>>()?;
 
-    if !list_plain {
+    if !list_plain && !rt.output_format().is_structured() {
         let out      = rt.stdout();
         let mut lock = out.lock();
         tab.print(&mut lock)?;
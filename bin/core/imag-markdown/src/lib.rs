@@ -38,6 +38,7 @@ extern crate clap;
 #[macro_use] extern crate log;
 extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagerror;
 extern crate libimagrt;
@@ -45,11 +46,9 @@ extern crate libimagstore;
 
 use std::io::Write;
 
-use failure::Error;
 use failure::err_msg;
 use failure::Fallible as Result;
 use resiter::AndThen;
-use resiter::Map;
 use resiter::IterInnerOkOrElse;
 use clap::App;
 
@@ -67,8 +66,6 @@ pub enum ImagMarkdown {}
 impl ImagApplication for ImagMarkdown {
     fn run(rt: Runtime) -> Result<()> {
         let only_links = rt.cli().is_present("links");
-        let out = rt.stdout();
-        let mut outlock = out.lock();
 
         let iter = rt
             .ids::<crate::ui::PathProvider>()?
@@ -80,20 +77,29 @@ impl ImagApplication for ImagMarkdown {
 
         if only_links {
             debug!("Printing only links");
-            iter.map_ok(|fle| libimagentrymarkdown::link::extract_links(fle.get_content()))
-                .and_then_ok(|links| {
-                    links.iter()
+            iter.and_then_ok(|fle| {
+                    libimagentrymarkdown::link::extract_links(fle.get_content())
+                        .iter()
                         .map(|link| {
-                            writeln!(outlock, "{title}: {link}", title = link.title, link = link.link).map_err(Error::from)
+                            let record = json!({
+                                "id": fle.get_location().to_str()?,
+                                "title": link.title,
+                                "link": link.link,
+                            });
+
+                            rt.emit(record, |out| {
+                                writeln!(out, "{title}: {link}", title = link.title, link = link.link)
+                            })
                         })
                         .collect()
                 })
                 .collect()
 
         } else {
-            iter.and_then_ok(|fle| libimagentrymarkdown::html::to_html(fle.get_content()))
-                .and_then_ok(|html| {
-                    writeln!(outlock, "{}", html).map_err(Error::from).map_err(Error::from)
+            iter.and_then_ok(|fle| {
+                    let html   = libimagentrymarkdown::html::to_html(fle.get_content())?;
+                    let record = json!({ "id": fle.get_location().to_str()?, "html": html });
+                    rt.emit(record, |out| writeln!(out, "{}", html))
                 })
                 .collect()
         }
@@ -83,40 +83,40 @@ impl ImagApplication for ImagMv {
             .map(StoreId::new)
             .unwrap()?; // unwrap safe by clap
 
-        // remove links to entry, and re-add them later
-        let mut linked_entries = rt.store()
-            .get(sourcename.clone())?
-            .ok_or_else(|| format_err!("Entry does not exist: {}", sourcename))?
-            .links()?
-            .map(|link| link.get_store_id().clone())
-            .map(Ok)
-            .into_get_iter(rt.store())
-            .map_inner_ok_or_else(|| err_msg("Linked entry does not exist"))
-            .collect::<Result<Vec<_>>>()?;
-
-        { // remove links to linked entries from source
-            let mut entry = rt
-                .store()
+        // Remove links to entry, move it and re-add the links afterwards. This happens in one
+        // transaction, so if anything fails, the store is left untouched.
+        rt.store().transaction(|store| {
+            let mut linked_entries = store
                 .get(sourcename.clone())?
-                .ok_or_else(|| err_msg("Source Entry does not exist"))?;
-
-            for link in linked_entries.iter_mut() {
-                entry.remove_link(link)?;
+                .ok_or_else(|| format_err!("Entry does not exist: {}", sourcename))?
+                .links()?
+                .map(|link| link.get_store_id().clone())
+                .map(Ok)
+                .into_get_iter(store)
+                .map_inner_ok_or_else(|| err_msg("Linked entry does not exist"))
+                .collect::<Result<Vec<_>>>()?;
+
+            { // remove links to linked entries from source
+                let mut entry = store
+                    .get(sourcename.clone())?
+                    .ok_or_else(|| err_msg("Source Entry does not exist"))?;
+
+                for link in linked_entries.iter_mut() {
+                    entry.remove_link(link)?;
+                    store.update(link)?;
+                }
+
+                store.update(&mut entry)?;
             }
-        }
 
-        if let Err(e) = rt.store().move_by_id(sourcename.clone(), destname.clone()) {
-            debug!("Re-adding links to source entry because moving failed");
-            relink(rt.store(), sourcename.clone(), &mut linked_entries)?;
+            store.move_by_id(sourcename.clone(), destname.clone())?;
 
-            return Err(e);
-        }
+            // re-add links to moved entry
+            relink(store, destname.clone(), &mut linked_entries)
+        })?;
 
         rt.report_touched(&destname)?;
 
-        // re-add links to moved entry
-        relink(rt.store(), destname, &mut linked_entries)?;
-
         info!("Ok.");
         Ok(())
     }
@@ -145,10 +145,10 @@ fn relink<'a>(store: &'a Store, target: StoreId, linked_entries: &mut Vec<FileLo
         .get(target)?
         .ok_or_else(|| err_msg("Funny things happened: Entry moved to destination did not fail, but entry does not exist"))?;
 
-    for mut link in linked_entries {
-        let _ = entry.add_link(&mut link)?;
+    for link in linked_entries {
+        entry.add_link(link)?;
+        store.update(link)?;
     }
 
-    Ok(())
+    store.update(&mut entry)
 }
-
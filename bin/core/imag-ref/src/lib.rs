@@ -37,6 +37,7 @@
 #[macro_use] extern crate log;
 extern crate clap;
 #[macro_use] extern crate failure;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagstore;
 extern crate libimagrt;
@@ -44,6 +45,7 @@ extern crate libimagentryref;
 extern crate libimagerror;
 extern crate libimaginteraction;
 extern crate libimagutil;
+extern crate rayon;
 
 mod ui;
 
@@ -53,6 +55,7 @@ use failure::Fallible as Result;
 use failure::Error;
 use failure::err_msg;
 use clap::App;
+use rayon::prelude::*;
 
 use libimagrt::application::ImagApplication;
 use libimagrt::runtime::Runtime;
@@ -111,8 +114,6 @@ fn deref(rt: &Runtime) -> Result<()> {
     let cmd         = rt.cli().subcommand_matches("deref").unwrap();
     let basepath    = cmd.value_of("override-basepath");
     let cfg         = get_ref_config(&rt, "imag-ref")?;
-    let out         = rt.stdout();
-    let mut outlock = out.lock();
 
     rt.ids::<::ui::PathProvider>()?
         .ok_or_else(|| err_msg("No ids supplied"))?
@@ -129,7 +130,10 @@ fn deref(rt: &Runtime) -> Result<()> {
                     }?
                     .to_str()
                     .ok_or_else(|| Error::from(::libimagerror::errors::ErrorMsg::UTF8Error))
-                    .and_then(|s| writeln!(outlock, "{}", s).map_err(Error::from))?;
+                    .and_then(|s| {
+                        let record = json!({ "id": id.to_str()?, "path": s });
+                        rt.emit(record, |out| writeln!(out, "{}", s))
+                    })?;
 
                     rt.report_touched(&id).map_err(Error::from)
                 },
@@ -171,13 +175,14 @@ fn list_dead(rt: &Runtime) -> Result<()> {
     let cmd        = rt.cli().subcommand_matches("list-dead").unwrap(); // safe by main()
     let list_path  = cmd.is_present("list-dead-pathes");
     let list_id    = cmd.is_present("list-dead-ids");
-    let mut output = rt.stdout();
+    let store      = rt.store();
 
-    rt.ids::<crate::ui::PathProvider>()?
+    // Checking the references is done in parallel, the output is written in the order of the ids
+    let dead = rt.ids::<crate::ui::PathProvider>()?
         .ok_or_else(|| err_msg("No ids supplied"))?
-        .into_iter()
+        .into_par_iter()
         .map(|id| {
-            match rt.store().get(id.clone())? {
+            match store.get(id.clone())? {
                 Some(entry) => {
                     let entry_ref = entry.as_ref_with_hasher::<DefaultHasher>();
 
@@ -185,27 +190,35 @@ fn list_dead(rt: &Runtime) -> Result<()> {
                         let entry_path = entry_ref.get_path(&cfg)?;
 
                         if !entry_path.exists() {
-                            if list_id {
-                                writeln!(output, "{}", entry.get_location().local().display())
-                            } else if list_path {
-                                writeln!(output, "{}", entry_path.display())
-                            } else {
-                                unimplemented!()
-                            }?;
-
-                            rt.report_touched(entry.get_location()).map_err(Error::from)
+                            Ok(Some((id, entry_path)))
                         } else {
-                            Ok(())
+                            Ok(None)
                         }
                     } else {
-                        Ok(())
+                        Ok(None)
                     }
                 }
 
                 None => Err(format_err!("Does not exist: {}", id.local().display())),
             }
         })
-        .collect()
+        .collect::<Result<Vec<_>>>()?;
+
+    dead.into_iter()
+        .flatten()
+        .try_for_each(|(id, entry_path)| {
+            let record = json!({ "id": id.to_str()?, "path": entry_path.display().to_string() });
+
+            rt.emit(record, |output| if list_id {
+                writeln!(output, "{}", id.local().display())
+            } else if list_path {
+                writeln!(output, "{}", entry_path.display())
+            } else {
+                unimplemented!()
+            })?;
+
+            rt.report_touched(&id).map_err(Error::from)
+        })
 }
 
 fn create(_rt: &Runtime) -> Result<()> {
@@ -0,0 +1,127 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+#![forbid(unsafe_code)]
+
+#![deny(
+    non_camel_case_types,
+    non_snake_case,
+    path_statements,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_allocation,
+    unused_import_braces,
+    unused_imports,
+    unused_must_use,
+    unused_mut,
+    unused_qualifications,
+    while_true,
+)]
+
+extern crate clap;
+#[macro_use] extern crate log;
+#[macro_use] extern crate failure;
+#[macro_use] extern crate serde_json;
+extern crate toml;
+extern crate toml_query;
+#[cfg(test)] extern crate tempdir;
+
+extern crate libimagrt;
+extern crate libimagerror;
+extern crate libimagstore;
+extern crate libimagentrylink;
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+use clap::App;
+use failure::Fallible as Result;
+use failure::ResultExt;
+
+use libimagrt::application::ImagApplication;
+use libimagrt::runtime::Runtime;
+use libimagstore::rpc;
+use libimagstore::rpc::Client;
+
+mod ui;
+pub mod server;
+
+use crate::server::Server;
+
+/// Marker enum for implementing ImagApplication on
+///
+/// This is used by binaries crates to execute business logic
+/// or to build a CLI completion.
+pub enum ImagServer {}
+impl ImagApplication for ImagServer {
+    fn run(rt: Runtime) -> Result<()> {
+        let socket = rpc::socket_path(rt.store().path(), &rt.config().cloned())?;
+
+        if rt.cli().is_present("stop") {
+            return stop(&socket)
+        }
+
+        if rt.store().is_remote() {
+            return Err(format_err!("imag-server is already running at {}", socket.display()))
+        }
+
+        if socket.exists() {
+            if Client::connect(&socket).is_ok() {
+                return Err(format_err!("imag-server is already running at {}", socket.display()))
+            }
+            debug!("Removing stale socket {}", socket.display());
+            ::std::fs::remove_file(&socket)
+                .context(format_err!("Cannot remove stale socket {}", socket.display()))?;
+        }
+
+        let listener = UnixListener::bind(&socket)
+            .context(format_err!("Cannot listen at {}", socket.display()))?;
+        ::std::fs::set_permissions(&socket, Permissions::from_mode(0o600))?;
+        info!("Listening at {}", socket.display());
+
+        let result = Server::new(rt.store(), socket.clone()).serve(listener);
+        let _ = ::std::fs::remove_file(&socket);
+        result
+    }
+
+    fn build_cli<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+        ui::build_ui(app)
+    }
+
+    fn name() -> &'static str {
+        env!("CARGO_PKG_NAME")
+    }
+
+    fn description() -> &'static str {
+        "Serve the store to other imag commands and programs"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+}
+
+/// Ask the server listening at `socket` to stop
+fn stop(socket: &Path) -> Result<()> {
+    let _ = Client::connect(socket)?.call("shutdown", json!({}))?;
+    info!("Stopped imag-server at {}", socket.display());
+    Ok(())
+}
@@ -0,0 +1,454 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! The JSON-RPC server, see `libimagstore::rpc` for the protocol
+
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use failure::Fallible as Result;
+use failure::Error;
+use serde_json::Value as JsonValue;
+use toml_query::read::TomlValueReadExt;
+
+use libimagentrylink::link::Link;
+use libimagentrylink::linkable::Linkable;
+use libimagstore::rpc;
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
+
+/// The error code and message of a failed call
+type CallResult = ::std::result::Result<JsonValue, (i64, String)>;
+
+fn store_error(e: Error) -> (i64, String) {
+    let message = e.iter_chain().map(|c| c.to_string()).collect::<Vec<_>>().join(": ");
+    (rpc::INTERNAL_ERROR, message)
+}
+
+fn param<'p>(params: &'p JsonValue, name: &str) -> ::std::result::Result<&'p str, (i64, String)> {
+    params
+        .get(name)
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| (rpc::INVALID_PARAMS, format!("Missing string parameter '{}'", name)))
+}
+
+fn id_param(params: &JsonValue, name: &str) -> ::std::result::Result<StoreId, (i64, String)> {
+    StoreId::new(PathBuf::from(param(params, name)?))
+        .map_err(|e| (rpc::INVALID_PARAMS, format!("Invalid id in '{}': {}", name, e)))
+}
+
+/// Serves the store to the clients connecting to the socket
+pub struct Server<'a> {
+    store:       &'a Store,
+    socket:      PathBuf,
+    stopping:    AtomicBool,
+    next_conn:   AtomicUsize,
+    connections: Mutex<BTreeMap<usize, UnixStream>>,
+}
+
+impl<'a> Server<'a> {
+
+    pub fn new(store: &'a Store, socket: PathBuf) -> Server<'a> {
+        Server {
+            store,
+            socket,
+            stopping:    AtomicBool::new(false),
+            next_conn:   AtomicUsize::new(0),
+            connections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Serve the connections to `listener` until a client calls `shutdown`
+    ///
+    /// Each connection is served in its own thread.
+    pub fn serve(&self, listener: UnixListener) -> Result<()> {
+        ::std::thread::scope(|scope| {
+            for stream in listener.incoming() {
+                if self.stopping.load(Ordering::SeqCst) {
+                    break
+                }
+
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e)     => {
+                        warn!("Failed to accept connection: {}", e);
+                        continue
+                    },
+                };
+
+                let conn = self.next_conn.fetch_add(1, Ordering::SeqCst);
+                match (stream.try_clone(), self.connections.lock()) {
+                    (Ok(clone), Ok(mut connections)) => { let _ = connections.insert(conn, clone); },
+                    _ => warn!("Cannot close connection {} on shutdown", conn),
+                }
+
+                let _ = scope.spawn(move || {
+                    debug!("Connection {} opened", conn);
+                    if let Err(e) = self.handle_connection(stream) {
+                        warn!("Connection {} failed: {}", conn, e);
+                    }
+                    if let Ok(mut connections) = self.connections.lock() {
+                        let _ = connections.remove(&conn);
+                    }
+                    debug!("Connection {} closed", conn);
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: UnixStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue
+            }
+
+            if let Some(response) = self.handle_line(&line) {
+                writeln!(writer, "{}", response)?;
+            }
+
+            if self.stopping.load(Ordering::SeqCst) {
+                self.close_all();
+                break
+            }
+        }
+        Ok(())
+    }
+
+    /// Close all connections and wake up the loop accepting connections, so it sees that the
+    /// server is stopping
+    fn close_all(&self) {
+        if let Ok(connections) = self.connections.lock() {
+            for stream in connections.values() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        let _ = UnixStream::connect(&self.socket);
+    }
+
+    /// Handle one line of input, the response if the request is not a notification
+    pub fn handle_line(&self, line: &str) -> Option<JsonValue> {
+        match ::serde_json::from_str(line) {
+            Ok(request) => self.handle_request(&request),
+            Err(e)      => Some(rpc::error(JsonValue::Null, rpc::PARSE_ERROR, &e.to_string())),
+        }
+    }
+
+    /// Handle a request, the response if the request is not a notification
+    pub fn handle_request(&self, request: &JsonValue) -> Option<JsonValue> {
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(JsonValue::as_str) {
+            None         => Err((rpc::INVALID_REQUEST, String::from("Missing method"))),
+            Some(method) => {
+                let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+                trace!("Calling {}: {}", method, params);
+                self.call(method, &params)
+            },
+        };
+
+        let id = id?;
+        Some(match result {
+            Ok(result)           => rpc::success(id, result),
+            Err((code, message)) => {
+                debug!("Request {} failed: {}", id, message);
+                rpc::error(id, code, &message)
+            },
+        })
+    }
+
+    fn call(&self, method: &str, params: &JsonValue) -> CallResult {
+        match method {
+            "create"           => self.create(params),
+            "get"              => self.get(params),
+            "exists"           => self.store.exists(id_param(params, "id")?).map(JsonValue::Bool).map_err(store_error),
+            "update"           => self.update(params),
+            "delete"           => self.store.delete(id_param(params, "id")?).map(|_| JsonValue::Null).map_err(store_error),
+            "move"             => self.store
+                .move_by_id(id_param(params, "from")?, id_param(params, "to")?)
+                .map(|_| JsonValue::Null)
+                .map_err(store_error),
+            "query"            => self.query(params),
+            "links"            => self.links(params),
+            "attachment.read"  => self.read_attachment(params),
+            "attachment.write" => self.write_attachment(params),
+            "shutdown"         => {
+                info!("Shutting down");
+                self.stopping.store(true, Ordering::SeqCst);
+                Ok(JsonValue::Null)
+            },
+            other              => Err((rpc::METHOD_NOT_FOUND, format!("Unknown method: {}", other))),
+        }
+    }
+
+    fn create(&self, params: &JsonValue) -> CallResult {
+        let id        = id_param(params, "id")?;
+        let mut entry = self.store.create(id).map_err(store_error)?;
+        if params.get("entry").is_some() {
+            entry.replace_from_buffer(param(params, "entry")?).map_err(store_error)?;
+        }
+        self.store.update(&mut entry).map_err(store_error)?;
+        entry.to_str().map(JsonValue::String).map_err(store_error)
+    }
+
+    fn get(&self, params: &JsonValue) -> CallResult {
+        let id = id_param(params, "id")?;
+        if !self.store.exists(id.clone()).map_err(store_error)? {
+            return Ok(JsonValue::Null)
+        }
+
+        self.store
+            .get_copy(id)
+            .and_then(|entry| entry.to_str())
+            .map(JsonValue::String)
+            .map_err(store_error)
+    }
+
+    fn update(&self, params: &JsonValue) -> CallResult {
+        let id        = id_param(params, "id")?;
+        let buffer    = param(params, "entry")?;
+        let mut entry = self.store.retrieve(id).map_err(store_error)?;
+        entry.replace_from_buffer(buffer).map_err(store_error)?;
+        self.store.update(&mut entry).map(|_| JsonValue::Null).map_err(store_error)
+    }
+
+    fn query(&self, params: &JsonValue) -> CallResult {
+        let collection = params.get("collection").and_then(JsonValue::as_str).map(PathBuf::from);
+        let header     = params.get("header").and_then(JsonValue::as_str);
+        let value      = params.get("value");
+
+        let ids = match (header, value) {
+            (Some(header), Some(JsonValue::String(value)))
+                if self.store.indexed_header_paths().iter().any(|p| p == header) =>
+            {
+                self.store.index_lookup(header, value)
+            },
+            _ => self.store.entries().map(|entries| entries.into_storeid_iter()),
+        };
+
+        let mut result = vec![];
+        for id in ids.map_err(store_error)? {
+            let id = id.map_err(store_error)?;
+            if let Some(ref collection) = collection {
+                if !id.local().starts_with(collection) {
+                    continue
+                }
+            }
+
+            if let Some(header) = header {
+                if !self.header_matches(&id, header, value).map_err(store_error)? {
+                    continue
+                }
+            }
+
+            result.push(id.to_str().map_err(store_error)?);
+        }
+
+        result.sort();
+        Ok(JsonValue::Array(result.into_iter().map(JsonValue::String).collect()))
+    }
+
+    /// Whether the entry has a value at `header`, which is or contains `value`, if given
+    fn header_matches(&self, id: &StoreId, header: &str, value: Option<&JsonValue>) -> Result<bool> {
+        let entry = match self.store.get_header_only(id.clone())? {
+            Some(entry) => entry,
+            None        => return Ok(false),
+        };
+
+        let found = match entry.get_header().read(header)? {
+            Some(found) => ::serde_json::to_value(found)?,
+            None        => return Ok(false),
+        };
+
+        Ok(match (value, found) {
+            (None, _)                          => true,
+            (Some(value), JsonValue::Array(a)) => a.contains(value),
+            (Some(value), found)               => *value == found,
+        })
+    }
+
+    fn links(&self, params: &JsonValue) -> CallResult {
+        let id = id_param(params, "id")?;
+        if !self.store.exists(id.clone()).map_err(store_error)? {
+            return Err((rpc::INTERNAL_ERROR, format!("Entry does not exist: {}", id)))
+        }
+
+        let entry = self.store.get_copy(id).map_err(store_error)?;
+        let mut links = vec![];
+        for link in entry.links().map_err(store_error)? {
+            let kind = match link {
+                Link::Id { .. }       => "link",
+                Link::LinkTo { .. }   => "to",
+                Link::LinkFrom { .. } => "from",
+            };
+            links.push(json!({ "id": link.to_str().map_err(store_error)?, "kind": kind }));
+        }
+
+        Ok(JsonValue::Array(links))
+    }
+
+    fn read_attachment(&self, params: &JsonValue) -> CallResult {
+        let data = self.store
+            .read_attachment_data(param(params, "hash")?)
+            .map_err(|e| (rpc::INVALID_PARAMS, e.to_string()))?;
+        Ok(data.map(|data| JsonValue::String(rpc::encode_data(&data))).unwrap_or(JsonValue::Null))
+    }
+
+    fn write_attachment(&self, params: &JsonValue) -> CallResult {
+        let data = rpc::decode_data(param(params, "data")?).map_err(|e| (rpc::INVALID_PARAMS, e.to_string()))?;
+        self.store.write_attachment_data(&data).map(JsonValue::String).map_err(store_error)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::Value as JsonValue;
+
+    use libimagentrylink::linkable::Linkable;
+    use libimagstore::rpc;
+    use libimagstore::rpc::Client;
+    use libimagstore::store::Store;
+    use libimagstore::storeid::StoreId;
+
+    use super::Server;
+
+    fn id(s: &str) -> StoreId {
+        StoreId::new(PathBuf::from(s)).unwrap()
+    }
+
+    fn call(server: &Server, method: &str, params: JsonValue) -> JsonValue {
+        server.handle_request(&rpc::request(1, method, params)).unwrap()
+    }
+
+    #[test]
+    fn test_methods() {
+        let store  = Store::new_inmemory(PathBuf::from("/"), &None).unwrap();
+        let server = Server::new(&store, PathBuf::from("/.imag-server.sock"));
+
+        let created = call(&server, "create", json!({ "id": "notes/a", "entry": "---\n[imag]\nversion = \"0.10.0\"\n\n[note]\nname = \"a\"\n---\nfoo" }));
+        assert!(created["result"].as_str().unwrap().ends_with("---\nfoo"));
+        let _ = call(&server, "create", json!({ "id": "todo/b" }));
+
+        let again = call(&server, "create", json!({ "id": "notes/a" }));
+        assert_eq!(again["error"]["code"], rpc::INTERNAL_ERROR);
+
+        let updated = call(&server, "update", json!({ "id": "todo/b", "entry": "---\n[imag]\nversion = \"0.10.0\"\n\n[todo]\ntags = [\"x\", \"y\"]\n---\nbar" }));
+        assert_eq!(updated["result"], JsonValue::Null);
+        assert_eq!(store.get_copy(PathBuf::from("todo/b")).unwrap().get_content(), "bar");
+
+        let got = call(&server, "get", json!({ "id": "todo/b" }));
+        assert!(got["result"].as_str().unwrap().contains("[todo]"));
+        assert!(got["result"].as_str().unwrap().ends_with("---\nbar"));
+        assert_eq!(call(&server, "get", json!({ "id": "todo/c" }))["result"], JsonValue::Null);
+        assert_eq!(call(&server, "exists", json!({ "id": "todo/c" }))["result"], false);
+
+        assert_eq!(call(&server, "query", json!({}))["result"], json!(["notes/a", "todo/b"]));
+        assert_eq!(call(&server, "query", json!({ "collection": "notes" }))["result"], json!(["notes/a"]));
+        assert_eq!(call(&server, "query", json!({ "header": "note.name" }))["result"], json!(["notes/a"]));
+        assert_eq!(call(&server, "query", json!({ "header": "todo.tags", "value": "y" }))["result"], json!(["todo/b"]));
+        assert_eq!(call(&server, "query", json!({ "header": "todo.tags", "value": "z" }))["result"], json!([]));
+
+        {
+            let mut a = store.get(PathBuf::from("notes/a")).unwrap().unwrap();
+            let mut b = store.get(PathBuf::from("todo/b")).unwrap().unwrap();
+            a.add_link(&mut b).unwrap();
+        }
+        assert_eq!(call(&server, "links", json!({ "id": "notes/a" }))["result"],
+                   json!([{ "id": "todo/b", "kind": "link" }]));
+
+        assert_eq!(call(&server, "move", json!({ "from": "todo/b", "to": "todo/c" }))["result"], JsonValue::Null);
+        assert_eq!(call(&server, "delete", json!({ "id": "todo/c" }))["result"], JsonValue::Null);
+        assert!(!store.exists(id("todo/c")).unwrap());
+
+        assert_eq!(call(&server, "get", json!({}))["error"]["code"], rpc::INVALID_PARAMS);
+        assert_eq!(call(&server, "frobnicate", json!({}))["error"]["code"], rpc::METHOD_NOT_FOUND);
+        assert_eq!(server.handle_line("{")
+                   .unwrap()["error"]["code"], rpc::PARSE_ERROR);
+        assert!(server.handle_line(r#"{"jsonrpc": "2.0", "method": "exists", "params": {"id": "notes/a"}}"#).is_none());
+    }
+
+    #[test]
+    fn test_remote_store() {
+        use std::os::unix::net::UnixListener;
+
+        let dir      = ::tempdir::TempDir::new("imag-server").unwrap();
+        let base     = dir.path().to_path_buf();
+        let socket   = rpc::socket_path(&base, &None).unwrap();
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let store  = Store::new_inmemory(base.clone(), &None).unwrap();
+        let server = Server::new(&store, socket.clone());
+
+        ::std::thread::scope(|scope| {
+            let serving = scope.spawn(|| server.serve(listener));
+
+            {
+                let client = Store::new_remote(base.clone(), &None, Client::connect(&socket).unwrap()).unwrap();
+                assert!(client.is_remote());
+
+                {
+                    let mut entry = client.create(PathBuf::from("notes/a")).unwrap();
+                    *entry.get_content_mut() = String::from("from the client");
+                }
+                let _ = client.create(PathBuf::from("notes/b")).unwrap();
+
+                let ids = client
+                    .entries()
+                    .unwrap()
+                    .into_storeid_iter()
+                    .map(|id| id.unwrap().to_str().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(ids, vec!["notes/a", "notes/b"]);
+
+                client.delete(PathBuf::from("notes/b")).unwrap();
+                client.move_by_id(id("notes/a"), id("notes/c")).unwrap();
+                assert!(!client.exists(id("notes/a")).unwrap());
+
+                let mut entry = client.create(PathBuf::from("notes/d")).unwrap();
+                let _ = client.attach(&mut entry, "scan.png", "image/png", &[0x89, 0x50, 0x00]).unwrap();
+                client.update(&mut entry).unwrap();
+                assert_eq!(client.attachment_data(&entry.attachments().unwrap()[0]).unwrap(), vec![0x89, 0x50, 0x00]);
+            }
+
+            let _ = Client::connect(&socket).unwrap().call("shutdown", json!({})).unwrap();
+            serving.join().unwrap().unwrap();
+        });
+
+        assert!(!store.exists(id("notes/b")).unwrap());
+        assert_eq!(store.get_copy(PathBuf::from("notes/c")).unwrap().get_content(), "from the client");
+        let entry = store.get_copy(PathBuf::from("notes/d")).unwrap();
+        assert_eq!(store.attachment_data(&entry.attachments().unwrap()[0]).unwrap(), vec![0x89, 0x50, 0x00]);
+    }
+
+}
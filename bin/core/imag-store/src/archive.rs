@@ -0,0 +1,95 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::str::FromStr;
+
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::archive::Collision;
+
+/// Export the store (or a collection of it) to an archive
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn export(rt: &Runtime) -> Result<()> {
+    let scmd       = rt.cli().subcommand_matches("export").unwrap(); // safe by main()
+    let collection = scmd.value_of("collection");
+
+    let manifest = match scmd.value_of("output") {
+        None | Some("-") => {
+            // The archive is the output, so it does not go through rt.stdout(), which would
+            // send it to stderr if the output is piped
+            let out      = ::std::io::stdout();
+            let mut lock = out.lock();
+            ::libimagstore::archive::export(rt.store(), collection, &mut lock)?
+        },
+        Some(path) => {
+            let file = File::create(path).context(format_err!("Failed to create {}", path))?;
+            ::libimagstore::archive::export(rt.store(), collection, &mut BufWriter::new(file))?
+        },
+    };
+
+    info!("{} entries exported", manifest.entries.len());
+    Ok(())
+}
+
+/// Import entries from an archive
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn import(rt: &Runtime) -> Result<()> {
+    let scmd         = rt.cli().subcommand_matches("import").unwrap(); // safe by main()
+    let on_collision = Collision::from_str(scmd.value_of("on-collision").unwrap())?; // safe by clap
+
+    let report = match scmd.value_of("input") {
+        None | Some("-") => {
+            let stdin = ::std::io::stdin();
+            let lock  = stdin.lock();
+            ::libimagstore::archive::import(rt.store(), lock, on_collision)?
+        },
+        Some(path) => {
+            let file = File::open(path).context(format_err!("Failed to open {}", path))?;
+            ::libimagstore::archive::import(rt.store(), BufReader::new(file), on_collision)?
+        },
+    };
+
+    for id in report.imported.iter().chain(report.overwritten.iter()) {
+        rt.report_touched(id).map_err(Error::from)?;
+    }
+
+    for (old, new) in report.renamed.iter() {
+        info!("Imported {} as {}", old, new);
+        rt.report_touched(new).map_err(Error::from)?;
+    }
+
+    for id in report.skipped.iter() {
+        info!("Skipped existing entry {}", id);
+    }
+
+    info!("{} entries imported, {} overwritten, {} renamed, {} skipped",
+          report.imported.len(),
+          report.overwritten.len(),
+          report.renamed.len(),
+          report.skipped.len());
+    Ok(())
+}
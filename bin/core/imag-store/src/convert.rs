@@ -0,0 +1,49 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+
+use failure::Fallible as Result;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::sqlite::convert_to_sqlite;
+use libimagstore::sqlite::convert_to_filesystem;
+
+/// Move the files of the store into its SQLite database or back to the filesystem
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn convert(rt: &Runtime) -> Result<()> {
+    let scmd          = rt.cli().subcommand_matches("convert").unwrap();
+    let remove_source = scmd.is_present("remove-source");
+    let path          = rt.store().path();
+
+    let (converted, backend) = match scmd.value_of("to").unwrap() { // safe by clap
+        "sqlite" => (convert_to_sqlite(path, remove_source)?, "sqlite"),
+        _        => (convert_to_filesystem(path, remove_source)?, "filesystem"),
+    };
+
+    for file in converted.iter() {
+        let file = file.strip_prefix(path).unwrap_or(file).display();
+        rt.emit(json!({ "path": file.to_string() }), |out| writeln!(out, "{}", file))?;
+    }
+
+    info!("{} files converted", converted.len());
+    info!("Set 'store.backend = \"{}\"' in the configuration to use the converted store", backend);
+    Ok(())
+}
@@ -29,6 +29,7 @@ use failure::Fallible as Result;
 use failure::Error;
 use failure::err_msg;
 
+use libimagrt::pipe::PipeAction;
 use libimagrt::runtime::Runtime;
 use libimagstore::store::Entry;
 use libimagstore::storeid::StoreId;
@@ -59,7 +60,7 @@ pub fn create(rt: &Runtime) -> Result<()> {
         create_with_content_and_header(rt, &path, String::new(), Entry::default_header())?;
     }
 
-    rt.report_touched(&path).map_err(Error::from)
+    rt.report_action(&path, PipeAction::Created).map_err(Error::from)
 }
 
 fn create_from_cli_spec(rt: &Runtime, matches: &ArgMatches, path: &StoreId) -> Result<()> {
@@ -96,11 +97,9 @@ fn create_from_source(rt: &Runtime, matches: &ArgMatches, path: &StoreId) -> Res
 
     Entry::from_str(path.clone(), &content[..])
         .and_then(|new_e| {
-            rt.store()
-                .create(path.clone())
-                .map(|mut old_e| {
-                    *old_e.deref_mut() = new_e;
-                })
+            let mut old_e = rt.store().create(path.clone())?;
+            *old_e.deref_mut() = new_e;
+            rt.store().update(&mut old_e)
         })
 }
 
@@ -110,20 +109,18 @@ fn create_with_content_and_header(rt: &Runtime,
                                   header: Value) -> Result<()>
 {
     debug!("Creating entry with content at {:?}", path);
-    rt.store()
-        .create(path.clone())
-        .map(|mut element| {
-            {
-                let e_content = element.get_content_mut();
-                *e_content = content;
-                debug!("New content set");
-            }
-            {
-                let e_header  = element.get_header_mut();
-                *e_header = header;
-                debug!("New header set");
-            }
-        })
+    let mut element = rt.store().create(path.clone())?;
+    {
+        let e_content = element.get_content_mut();
+        *e_content = content;
+        debug!("New content set");
+    }
+    {
+        let e_header  = element.get_header_mut();
+        *e_header = header;
+        debug!("New header set");
+    }
+    rt.store().update(&mut element)
 }
 
 fn string_from_raw_src(raw_src: &str) -> String {
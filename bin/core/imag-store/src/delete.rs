@@ -20,7 +20,9 @@
 use std::path::PathBuf;
 
 use failure::Fallible as Result;
+use failure::Error;
 
+use libimagrt::pipe::PipeAction;
 use libimagrt::runtime::Runtime;
 use libimagstore::storeid::StoreId;
 
@@ -31,7 +33,8 @@ pub fn delete(rt: &Runtime) -> Result<()> {
     let path  = StoreId::new(path)?;
     debug!("Deleting file at {:?}", id);
 
-    rt.store().delete(path)
+    rt.store().delete(path.clone())?;
+    rt.report_action(&path, PipeAction::Deleted).map_err(Error::from)
 }
 
 #[cfg(test)]
@@ -0,0 +1,57 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use failure::Fallible as Result;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::encryption::encrypt_store;
+use libimagstore::encryption::decrypt_store;
+
+/// Encrypt all entries of the store, with the key from the `store.encryption` configuration
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn encrypt(rt: &Runtime) -> Result<()> {
+    let converted = encrypt_store(rt.store().path(), &rt.config().cloned())?;
+    print_converted(rt, converted)?;
+    info!("Set 'store.backend = \"encrypted\"' in the configuration to use the encrypted store");
+    Ok(())
+}
+
+/// Decrypt all entries of the store, with the key from the `store.encryption` configuration
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn decrypt(rt: &Runtime) -> Result<()> {
+    let converted = decrypt_store(rt.store().path(), &rt.config().cloned())?;
+    print_converted(rt, converted)?;
+    info!("Remove 'store.backend = \"encrypted\"' from the configuration to use the decrypted store");
+    Ok(())
+}
+
+fn print_converted(rt: &Runtime, converted: Vec<PathBuf>) -> Result<()> {
+    for path in converted.iter() {
+        let path = path.strip_prefix(rt.store().path()).unwrap_or(path).display();
+        rt.emit(json!({ "path": path.to_string() }), |out| writeln!(out, "{}", path))?;
+    }
+
+    info!("{} files converted", converted.len());
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagrt::runtime::Runtime;
+use libimagrt::io::toml_to_json;
+use libimagstore::storeid::StoreId;
+
+/// List the earlier versions of an entry or print one of them
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn history(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("history").unwrap(); // safe by main()
+    let id   = get_id(scmd)?;
+
+    if let Some(number) = get_revision(scmd)? {
+        let entry  = rt.store().get_revision(id, number)?;
+        let raw    = entry.to_str()?;
+        let record = json!({
+            "id": entry.get_location().to_str()?,
+            "revision": number,
+            "header": toml_to_json(entry.get_header()),
+            "content": entry.get_content(),
+        });
+
+        rt.emit(record, |out| write!(out, "{}", raw))
+    } else {
+        let id_str = id.to_str()?;
+        for revision in rt.store().history(id)? {
+            let replaced_at = revision.replaced_at.format("%Y-%m-%d %H:%M:%S").to_string();
+            let record      = json!({
+                "id": id_str,
+                "revision": revision.number,
+                "replaced_at": replaced_at,
+            });
+
+            rt.emit(record, |out| writeln!(out, "{}  {}", revision.number, replaced_at))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Revert an entry to an earlier version, by default to the one before the last change
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn undo(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("undo").unwrap(); // safe by main()
+    let id   = get_id(scmd)?;
+
+    let number = match get_revision(scmd)? {
+        Some(number) => number,
+        None => rt.store()
+            .history(id.clone())?
+            .last()
+            .map(|revision| revision.number)
+            .ok_or_else(|| format_err!("No history for {}", id))?,
+    };
+
+    rt.store().revert(id.clone(), number)?;
+    info!("Reverted {} to revision {}", id, number);
+    rt.report_touched(&id).map_err(Error::from)
+}
+
+fn get_id(scmd: &ArgMatches) -> Result<StoreId> {
+    let id = scmd.value_of("id").map(PathBuf::from).unwrap(); // safe by clap
+    StoreId::new(id)
+}
+
+fn get_revision(scmd: &ArgMatches) -> Result<Option<usize>> {
+    scmd.value_of("revision")
+        .map(|r| {
+            r.parse::<usize>()
+                .context(format_err!("Not a revision number: {}", r))
+                .map_err(Error::from)
+        })
+        .transpose()
+}
@@ -0,0 +1,49 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+
+use failure::Fallible as Result;
+use failure::err_msg;
+
+use libimagrt::runtime::Runtime;
+
+/// Maintain the index of the store
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn index(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("index").unwrap(); // safe by main()
+
+    match scmd.subcommand_name() {
+        Some("rebuild") => {
+            rt.store().rebuild_index()?;
+            info!("Index rebuilt");
+            Ok(())
+        },
+
+        Some("paths") => {
+            for path in rt.store().indexed_header_paths() {
+                rt.emit(json!({ "path": path }), |out| writeln!(out, "{}", path))?;
+            }
+            Ok(())
+        },
+
+        _ => Err(err_msg("No index command")),
+    }
+}
@@ -37,13 +37,19 @@
 extern crate clap;
 #[macro_use] extern crate log;
 extern crate toml;
-extern crate resiter;
+extern crate chrono;
+#[macro_use] extern crate serde_json;
 #[cfg(test)] extern crate toml_query;
 #[macro_use] extern crate failure;
 
 extern crate libimagrt;
 extern crate libimagstore;
 extern crate libimagerror;
+extern crate libimagentrylink;
+extern crate libimagentryref;
+extern crate libimaginteraction;
+extern crate libimaghabit;
+extern crate libimagtodo;
 
 #[cfg(test)]
 #[macro_use]
@@ -58,10 +64,19 @@ use libimagrt::runtime::Runtime;
 use failure::Fallible as Result;
 use failure::err_msg;
 
+mod archive;
+#[cfg(feature = "sqlite")]
+mod convert;
 mod create;
 mod delete;
+mod encrypt;
 mod get;
+mod history;
+mod index;
+mod locks;
+mod migrate;
 mod retrieve;
+mod trash;
 mod ui;
 mod update;
 mod verify;
@@ -71,10 +86,22 @@ use std::ops::Deref;
 
 use clap::App;
 
+use crate::archive::export;
+use crate::archive::import;
+#[cfg(feature = "sqlite")]
+use crate::convert::convert;
 use crate::create::create;
 use crate::delete::delete;
+use crate::encrypt::encrypt;
+use crate::encrypt::decrypt;
 use crate::get::get;
+use crate::history::history;
+use crate::history::undo;
+use crate::index::index;
+use crate::locks::locks;
+use crate::migrate::migrate;
 use crate::retrieve::retrieve;
+use crate::trash::trash;
 use crate::update::update;
 use crate::verify::verify;
 
@@ -88,10 +115,22 @@ impl ImagApplication for ImagStore {
         if let Some(command) = rt.cli().subcommand_name() {
             debug!("Call: {}", command);
             match command.deref() {
+                #[cfg(feature = "sqlite")]
+                "convert"  => convert(&rt),
                 "create"   => create(&rt),
+                "decrypt"  => decrypt(&rt),
                 "delete"   => delete(&rt),
+                "encrypt"  => encrypt(&rt),
+                "export"   => export(&rt),
                 "get"      => get(&rt),
+                "history"  => history(&rt),
+                "import"   => import(&rt),
+                "index"    => index(&rt),
+                "locks"    => locks(&rt),
+                "migrate"  => migrate(&rt),
                 "retrieve" => retrieve(&rt),
+                "trash"    => trash(&rt),
+                "undo"     => undo(&rt),
                 "update"   => update(&rt),
                 "verify"   => verify(&rt),
                 other      => {
@@ -0,0 +1,61 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+
+use failure::Fallible as Result;
+
+use libimagrt::runtime::Runtime;
+
+/// List the entries which are locked by running imag processes
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn locks(rt: &Runtime) -> Result<()> {
+    let scmd       = rt.cli().subcommand_matches("locks").unwrap(); // safe by main()
+    let only_stale = scmd.is_present("stale");
+
+    for holder in rt.store().locks()? {
+        let stale = rt.store().is_stale_lock(&holder);
+        if only_stale && !stale {
+            continue
+        }
+
+        let acquired = holder.acquired.format("%Y-%m-%d %H:%M:%S").to_string();
+        let record   = json!({
+            "id": holder.id.to_str()?,
+            "pid": holder.pid,
+            "host": holder.host,
+            "command": holder.command,
+            "acquired": acquired,
+            "stale": stale,
+        });
+
+        rt.emit(record, |out| {
+            writeln!(out, "{}  {:>7}@{}  {}{}  {}",
+                     acquired,
+                     holder.pid,
+                     holder.host,
+                     holder.id,
+                     if stale { " (stale)" } else { "" },
+                     holder.command)
+        })?;
+    }
+
+    Ok(())
+}
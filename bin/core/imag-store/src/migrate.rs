@@ -0,0 +1,76 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+use std::io::Write;
+
+use failure::Fallible as Result;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::migration::MigrationRegistry;
+
+/// Migrate the entries of the store
+///
+/// All migrations provided by the libraries are applied to the entries which were written with an
+/// older version of imag. The migration can be interrupted and started again, it continues where
+/// it stopped.
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn migrate(rt: &Runtime) -> Result<()> {
+    let scmd    = rt.cli().subcommand_matches("migrate").unwrap(); // safe by main()
+    let dry_run = scmd.is_present("dry-run");
+
+    let mut registry = MigrationRegistry::default();
+    registry.register_all(::libimagentrylink::migration::migrations())?;
+    registry.register_all(::libimaghabit::migration::migrations())?;
+    registry.register_all(::libimagtodo::migration::migrations())?;
+    debug!("Migrations: {:?}", registry);
+
+    let report = registry.run(rt.store(), dry_run)?;
+
+    for migrated in report.migrated.iter() {
+        let record = json!({
+            "id": migrated.id.to_str()?,
+            "from": migrated.from.to_string(),
+            "to": migrated.to.to_string(),
+            "applied": migrated.applied,
+        });
+
+        rt.emit(record, |out| if migrated.applied.is_empty() {
+            writeln!(out, "{}: {} -> {}", migrated.id, migrated.from, migrated.to)
+        } else {
+            writeln!(out, "{}: {} -> {} ({})", migrated.id, migrated.from, migrated.to, migrated.applied.join(", "))
+        })?;
+        rt.report_touched(&migrated.id)?;
+    }
+
+    for (id, e) in report.failed.iter() {
+        error!("Failed to migrate {}: {}", id, e);
+    }
+
+    info!("{} entries {}migrated, {} up to date, {} failed",
+          report.migrated.len(),
+          if dry_run { "would be " } else { "" },
+          report.unchanged,
+          report.failed.len());
+
+    if report.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format_err!("Failed to migrate {} entries", report.failed.len()))
+    }
+}
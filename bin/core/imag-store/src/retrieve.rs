@@ -27,6 +27,7 @@ use clap::ArgMatches;
 use libimagstore::store::FileLockEntry;
 use libimagstore::storeid::StoreId;
 use libimagrt::runtime::Runtime;
+use libimagrt::io::toml_to_json;
 
 pub fn retrieve(rt: &Runtime) -> Result<()> {
     let scmd  = rt.cli().subcommand_matches("retrieve").unwrap();
@@ -43,6 +44,18 @@ pub fn retrieve(rt: &Runtime) -> Result<()> {
 }
 
 pub fn print_entry(rt: &Runtime, scmd: &ArgMatches, e: FileLockEntry) -> Result<()> {
+    if rt.output_format().is_structured() {
+        let raw        = do_print_raw(scmd);
+        let mut record = json!({ "id": e.get_location().to_str()? });
+        if raw || do_print_header(scmd) {
+            record["header"] = toml_to_json(e.get_header());
+        }
+        if raw || do_print_content(scmd) {
+            record["content"] = json!(e.get_content());
+        }
+        return rt.records().emit(&record)
+    }
+
     if do_print_raw(scmd) {
         debug!("Printing raw content...");
         writeln!(rt.stdout(), "{}", e.to_str()?)?;
@@ -57,9 +70,8 @@ pub fn print_entry(rt: &Runtime, scmd: &ArgMatches, e: FileLockEntry) -> Result<
             debug!("Printing header...");
             if do_print_header_as_json(rt.cli()) {
                 debug!("Printing header as json...");
-                warn!("Printing as JSON currently not supported.");
-                warn!("Will fail now!");
-                unimplemented!()
+                let header = ::serde_json::to_string(&toml_to_json(e.get_header()))?;
+                writeln!(rt.stdout(), "{}", header)?;
             } else {
                 debug!("Printing header as TOML...");
                 writeln!(rt.stdout(), "{}", e.get_header())?;
@@ -0,0 +1,78 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::Duration;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+use failure::err_msg;
+
+use libimagrt::runtime::Runtime;
+use libimagstore::storeid::StoreId;
+
+/// Maintain the trash of the store
+///
+/// This function is not intended to be called by normal programs but only by `imag-store`.
+pub fn trash(rt: &Runtime) -> Result<()> {
+    let scmd = rt.cli().subcommand_matches("trash").unwrap(); // safe by main()
+
+    match scmd.subcommand() {
+        ("list", _) => {
+            for trashed in rt.store().trash_list()? {
+                let deleted_at = trashed.deleted_at.format("%Y-%m-%d %H:%M:%S").to_string();
+                let record     = json!({ "id": trashed.id.to_str()?, "deleted_at": deleted_at });
+                rt.emit(record, |out| writeln!(out, "{}  {}", deleted_at, trashed.id))?;
+            }
+            Ok(())
+        },
+
+        ("restore", Some(mtch)) => {
+            let id = mtch.value_of("id").map(PathBuf::from).unwrap(); // safe by clap
+            let id = StoreId::new(id)?;
+            rt.store().trash_restore(id.clone())?;
+            rt.report_touched(&id).map_err(Error::from)
+        },
+
+        ("purge", Some(mtch)) => {
+            let older_than = if mtch.is_present("all") {
+                None
+            } else if let Some(days) = mtch.value_of("older-than") {
+                let days = days
+                    .parse::<i64>()
+                    .context(format_err!("Not a number of days: {}", days))
+                    .map_err(Error::from)?;
+                Some(Duration::days(days))
+            } else {
+                let age = rt.store()
+                    .trash_max_age()
+                    .ok_or_else(|| err_msg("No 'store.trash.max-age' configured, pass --all or --older-than"))?;
+                Some(age)
+            };
+
+            let purged = rt.store().trash_purge(older_than)?;
+            info!("{} entries purged from trash", purged.len());
+            Ok(())
+        },
+
+        _ => Err(err_msg("No trash command")),
+    }
+}
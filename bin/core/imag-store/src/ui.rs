@@ -20,7 +20,7 @@
 use clap::{Arg, App, ArgGroup, SubCommand};
 
 pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
-    app.subcommand(SubCommand::with_name("create")
+    let app = app.subcommand(SubCommand::with_name("create")
                    .about("Create an entry from the store")
                    .version("0.1")
                    .arg(Arg::with_name("path")
@@ -186,8 +186,197 @@ pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
                         .value_name("PATH"))
                    )
 
+       .subcommand(SubCommand::with_name("export")
+                   .about("Export the entries of the store to an archive")
+                   .version("0.1")
+                   .arg(Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Write the archive to this file instead of stdout ('-' for stdout)")
+                        .value_name("FILE"))
+                   .arg(Arg::with_name("collection")
+                        .long("collection")
+                        .short("c")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Only export the entries in this collection")
+                        .value_name("COLLECTION"))
+                   )
+
+       .subcommand(SubCommand::with_name("import")
+                   .about("Import the entries from an archive written by 'imag store export'")
+                   .version("0.1")
+                   .arg(Arg::with_name("input")
+                        .index(1)
+                        .takes_value(true)
+                        .required(false)
+                        .help("Read the archive from this file instead of stdin ('-' for stdin)")
+                        .value_name("FILE"))
+                   .arg(Arg::with_name("on-collision")
+                        .long("on-collision")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["skip", "overwrite", "rename"])
+                        .default_value("skip")
+                        .help("What to do with entries which exist in the store already. 'rename' imports them as '<id>-<n>'")
+                        .value_name("ACTION"))
+                   )
+
+       .subcommand(SubCommand::with_name("history")
+                   .about("List the earlier versions of an entry, or print one of them")
+                   .version("0.1")
+                   .arg(Arg::with_name("id")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true)
+                        .help("Show the history of the Store Entry with this path")
+                        .value_name("PATH"))
+                   .arg(Arg::with_name("revision")
+                        .long("revision")
+                        .short("r")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Print the entry as it was at this revision")
+                        .value_name("REVISION"))
+                   )
+
+       .subcommand(SubCommand::with_name("undo")
+                   .about("Revert an entry to an earlier version, by default to the version before the last change")
+                   .version("0.1")
+                   .arg(Arg::with_name("id")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true)
+                        .help("Revert the Store Entry with this path")
+                        .value_name("PATH"))
+                   .arg(Arg::with_name("revision")
+                        .long("revision")
+                        .short("r")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Revert to this revision (see 'imag store history')")
+                        .value_name("REVISION"))
+                   )
+
+       .subcommand(SubCommand::with_name("trash")
+                   .about("Maintain the trash of the store, where deleted entries are kept if 'store.trash.enabled' is set")
+                   .version("0.1")
+                   .subcommand(SubCommand::with_name("list")
+                               .about("List the entries in the trash, oldest first")
+                               .version("0.1")
+                               )
+                   .subcommand(SubCommand::with_name("restore")
+                               .about("Restore the most recently deleted entry with this id")
+                               .version("0.1")
+                               .arg(Arg::with_name("id")
+                                    .index(1)
+                                    .takes_value(true)
+                                    .required(true)
+                                    .help("Restore the Store Entry with this path")
+                                    .value_name("PATH"))
+                               )
+                   .subcommand(SubCommand::with_name("purge")
+                               .about("Remove entries from the trash for good. Without arguments, entries older than 'store.trash.max-age' days are removed")
+                               .version("0.1")
+                               .arg(Arg::with_name("all")
+                                    .long("all")
+                                    .short("a")
+                                    .takes_value(false)
+                                    .required(false)
+                                    .conflicts_with("older-than")
+                                    .help("Remove all entries from the trash"))
+                               .arg(Arg::with_name("older-than")
+                                    .long("older-than")
+                                    .takes_value(true)
+                                    .required(false)
+                                    .help("Remove entries which were deleted more than this many days ago")
+                                    .value_name("DAYS"))
+                               )
+                   )
+
        .subcommand(SubCommand::with_name("verify")
-                   .about("Verify the store")
+                   .about("Verify the store: check the headers of all entries, the links between them and refs")
                    .version("0.1")
+                   .arg(Arg::with_name("repair")
+                        .long("repair")
+                        .short("r")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Repair the problems which can be repaired safely, ask whether to repair the others"))
+                   .arg(Arg::with_name("report")
+                        .long("report")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Write a report of the problems and what was done about them to this file")
+                        .value_name("FILE"))
                    )
+
+       .subcommand(SubCommand::with_name("index")
+                   .about("Maintain the index of the store")
+                   .version("0.1")
+                   .subcommand(SubCommand::with_name("rebuild")
+                               .about("Rebuild the index, necessary if the store was altered without imag")
+                               .version("0.1")
+                               )
+                   .subcommand(SubCommand::with_name("paths")
+                               .about("List the header paths which are indexed")
+                               .version("0.1")
+                               )
+                   )
+
+       .subcommand(SubCommand::with_name("locks")
+                   .about("List the entries which are locked by running imag processes, with the process holding the lock")
+                   .version("0.1")
+                   .arg(Arg::with_name("stale")
+                        .long("stale")
+                        .short("s")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Only list stale locks, held by processes which are gone or longer than 'store.locks.stale-after' seconds"))
+                   )
+
+       .subcommand(SubCommand::with_name("migrate")
+                   .about("Migrate entries which were written by older versions of imag")
+                   .version("0.1")
+                   .arg(Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .short("n")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Only report which entries would be migrated, do not write anything"))
+                   )
+
+       .subcommand(SubCommand::with_name("encrypt")
+                   .about("Encrypt all entries of the store with the key from 'store.encryption' in the configuration")
+                   .version("0.1")
+                   )
+
+       .subcommand(SubCommand::with_name("decrypt")
+                   .about("Decrypt all entries of the store with the key from 'store.encryption' in the configuration")
+                   .version("0.1")
+                   );
+
+    #[cfg(feature = "sqlite")]
+    let app = app
+       .subcommand(SubCommand::with_name("convert")
+                   .about("Move all files of the store into a SQLite database or back to the filesystem")
+                   .version("0.1")
+                   .arg(Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple(false)
+                        .possible_values(&["sqlite", "filesystem"])
+                        .value_name("BACKEND")
+                        .help("The backend to convert the store to"))
+                   .arg(Arg::with_name("remove-source")
+                        .long("remove-source")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Remove the files (or the database) after they were converted"))
+                   );
+
+    app
 }
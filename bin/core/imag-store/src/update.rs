@@ -23,6 +23,7 @@ use std::path::PathBuf;
 use failure::Fallible as Result;
 use failure::Error;
 
+use libimagrt::pipe::PipeAction;
 use libimagrt::runtime::Runtime;
 use libimagstore::storeid::StoreId;
 
@@ -49,7 +50,8 @@ pub fn update(rt: &Runtime) -> Result<()> {
                 debug!("New header set");
             }
 
-            rt.report_touched(locked_e.get_location()).map_err(Error::from)
+            rt.store().update(&mut locked_e)?;
+            rt.report_action(locked_e.get_location(), PipeAction::Updated).map_err(Error::from)
         })
 }
 
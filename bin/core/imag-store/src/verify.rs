@@ -17,48 +17,232 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
-use std::ops::Deref;
+use std::io::Write;
+use std::path::PathBuf;
 
 use failure::Fallible as Result;
-use failure::err_msg;
-use resiter::AndThen;
-use resiter::IterInnerOkOrElse;
+use failure::ResultExt;
+use failure::Error;
 
 use libimagrt::runtime::Runtime;
+use libimagstore::fsck::Finding;
+use libimagstore::storeid::StoreId;
+use libimagstore::store::HeaderOnlyEntry;
+use libimagentrylink::storecheck::LinkProblem;
+use libimagentrylink::storecheck::StoreLinkConsistentExt;
+use libimagentryref::reference::Ref;
+use libimagentryref::reference::RefFassade;
+use libimagentryref::reference::MutRef;
+use libimagentryref::hasher::default::DefaultHasher;
+use libimagentryref::util::get_ref_config;
+use libimaginteraction::ask::ask_bool;
 
-/// Verify the store.
+/// A problem found by `imag store verify`
+enum Problem {
+    Entry(Finding),
+    Link(LinkProblem),
+
+    /// The entry is a ref to a file which does not exist
+    DeadRef { id: StoreId, path: PathBuf },
+}
+
+/// How a problem can be repaired
+enum Repair {
+    /// Without asking, as nothing is lost
+    Safe,
+
+    /// After asking the user this question
+    Ask(String),
+
+    Impossible,
+}
+
+impl Problem {
+    fn id(&self) -> &StoreId {
+        match *self {
+            Problem::Entry(ref finding)  => &finding.id,
+            Problem::Link(ref problem)   => problem.source(),
+            Problem::DeadRef { ref id, .. } => id,
+        }
+    }
+
+    fn description(&self) -> String {
+        match *self {
+            Problem::Entry(ref finding)       => finding.problem.to_string(),
+            Problem::Link(ref problem)        => problem.to_string(),
+            Problem::DeadRef { ref path, .. } => format!("Ref to missing file {}", path.display()),
+        }
+    }
+
+    fn repair_kind(&self) -> Repair {
+        match *self {
+            Problem::Entry(ref finding) => if finding.problem.is_safely_repairable() {
+                Repair::Safe
+            } else if finding.problem.is_repairable() {
+                Repair::Ask(format!("Replace the header of '{}', keeping the old text as content", finding.id))
+            } else {
+                Repair::Impossible
+            },
+
+            Problem::Link(LinkProblem::Dangling { .. }) => Repair::Safe,
+
+            Problem::Link(LinkProblem::OneDirectional { ref source, ref target, kind }) => {
+                Repair::Ask(format!("Add the missing link back from '{}' to '{}' in {}", target, source, kind.reverse()))
+            },
+
+            Problem::DeadRef { ref id, ref path } => {
+                Repair::Ask(format!("Remove the ref to missing file {} from '{}'", path.display(), id))
+            },
+        }
+    }
+
+    fn repair(&self, rt: &Runtime) -> Result<()> {
+        match *self {
+            Problem::Entry(ref finding) => rt.store().repair(finding),
+            Problem::Link(ref problem)  => rt.store().repair_link_problem(problem),
+            Problem::DeadRef { ref id, .. } => rt.store()
+                .get(id.clone())?
+                .ok_or_else(|| format_err!("Entry does not exist: {}", id))?
+                .as_ref_with_hasher_mut::<DefaultHasher>()
+                .remove_ref(),
+        }
+    }
+}
+
+/// Verify the store, and repair it if `--repair` is passed.
 ///
 /// This function is not intended to be called by normal programs but only by `imag-store`.
 pub fn verify(rt: &Runtime) -> Result<()> {
-    info!("Header | Content length | Path");
-    info!("-------+----------------+-----");
-    let result = rt
-        .store()
-        .entries()?
-        .into_get_iter()
-        .map_inner_ok_or_else(|| err_msg("Did not find one entry"))
-        .and_then_ok(|fle| {
-            let p           = fle.get_location();
-            let content_len = fle.get_content().len();
-            let (verify, status) = if fle.verify().is_ok() {
-                ("ok", true)
-            } else {
-                ("broken", false)
-            };
+    let scmd        = rt.cli().subcommand_matches("verify").unwrap(); // safe by main()
+    let repair      = scmd.is_present("repair");
+    let mut checker = Checker { rt, repair, report: vec![], unresolved: 0 };
+
+    // The checks are done one after another, so the later ones see the repairs of the earlier
+    // ones: links of an entry with a broken header can only be checked after it was repaired
+    let findings = rt.store().fsck()?.into_iter().map(Problem::Entry).collect();
+    checker.handle(findings)?;
 
-            info!("{: >6} | {: >14} | {:?}", verify, content_len, p.deref());
-            rt.report_touched(fle.get_location())?;
-            Ok(status)
-        })
-        .collect::<Result<Vec<_>>>()?
-        .iter()
-        .all(|x| *x);
+    let link_problems = rt.store().link_problems()?.into_iter().map(Problem::Link).collect();
+    checker.handle(link_problems)?;
+
+    checker.handle(dead_refs(rt)?)?;
+
+    if let Some(path) = scmd.value_of("report") {
+        let mut report = checker.report.join("\n");
+        report.push('\n');
+        ::std::fs::write(path, report)
+            .context(format_err!("Failed to write report to {}", path))?;
+    }
 
-    if result {
+    if checker.unresolved == 0 {
         info!("Store seems to be fine");
         Ok(())
     } else {
-        Err(err_msg("Store seems to be broken somehow"))
+        Err(format_err!("Store seems to be broken somehow, {} problems are left", checker.unresolved))
     }
 }
 
+struct Checker<'a> {
+    rt: &'a Runtime<'a>,
+    repair: bool,
+
+    /// The lines of the report
+    report: Vec<String>,
+
+    /// The number of problems which are not repaired
+    unresolved: usize,
+}
+
+impl<'a> Checker<'a> {
+
+    /// Report the problems, repairing them if requested
+    fn handle(&mut self, problems: Vec<Problem>) -> Result<()> {
+        let mut input  = self.rt.stdin();
+        let mut output = self.rt.stdout();
+
+        for problem in problems {
+            let record = json!({ "id": problem.id().to_str()?, "problem": problem.description() });
+            self.rt.emit(record, |out| writeln!(out, "{}: {}", problem.id(), problem.description()))?;
+
+            let repair = if !self.repair {
+                false
+            } else {
+                match problem.repair_kind() {
+                    Repair::Safe          => true,
+                    Repair::Impossible    => false,
+                    Repair::Ask(question) => match input.as_mut() {
+                        Some(input) => ask_bool(&question, Some(false), input, &mut output)?,
+                        None        => {
+                            warn!("No input stream, cannot ask whether to repair {}", problem.id());
+                            false
+                        },
+                    },
+                }
+            };
+
+            let status = if !repair {
+                self.unresolved += 1;
+                match problem.repair_kind() {
+                    Repair::Impossible     => String::from("cannot be repaired"),
+                    _ if self.repair       => String::from("not repaired"),
+                    _                      => String::from("found"),
+                }
+            } else {
+                match problem.repair(self.rt) {
+                    Ok(()) => {
+                        self.rt.report_touched(problem.id()).map_err(Error::from)?;
+                        String::from("repaired")
+                    },
+                    Err(e) => {
+                        self.unresolved += 1;
+                        format!("repair failed: {}", e)
+                    },
+                }
+            };
+
+            info!("{}: {}", problem.id(), status);
+            self.report.push(format!("{}: {} ({})", problem.id(), problem.description(), status));
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the refs to files which do not exist, if refs are configured
+fn dead_refs(rt: &Runtime) -> Result<Vec<Problem>> {
+    let cfg = match get_ref_config(rt, "imag-store") {
+        Ok(cfg) => cfg,
+        Err(e)  => {
+            info!("Not checking refs: {}", e);
+            return Ok(vec![])
+        },
+    };
+
+    let mut dead = vec![];
+    for id in rt.store().entries()?.into_storeid_iter() {
+        let id = id?;
+        let entry = rt.store()
+            .get_header_only(id.clone())
+            .and_then(|entry| entry.map(HeaderOnlyEntry::into_entry).transpose());
+
+        let entry = match entry {
+            Ok(Some(entry)) => entry,
+            Ok(None)        => continue,
+            Err(_)          => continue, // reported by the header check already
+        };
+
+        let reference = entry.as_ref_with_hasher::<DefaultHasher>();
+        if !reference.is_ref()? {
+            continue
+        }
+
+        match reference.get_path(&cfg) {
+            Ok(path) => if !path.exists() {
+                dead.push(Problem::DeadRef { id, path });
+            },
+            Err(e) => warn!("Cannot check ref {}: {}", id, e),
+        }
+    }
+
+    Ok(dead)
+}
@@ -0,0 +1,189 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+#![forbid(unsafe_code)]
+
+#![deny(
+    non_camel_case_types,
+    non_snake_case,
+    path_statements,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_allocation,
+    unused_import_braces,
+    unused_imports,
+    unused_must_use,
+    unused_mut,
+    unused_qualifications,
+    while_true,
+)]
+
+extern crate clap;
+#[macro_use] extern crate log;
+#[macro_use] extern crate failure;
+#[macro_use] extern crate serde_json;
+extern crate toml;
+extern crate toml_query;
+
+extern crate libimagrt;
+extern crate libimagerror;
+extern crate libimagstore;
+extern crate libimagsync;
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::App;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use serde_json::Value as JsonValue;
+
+use libimagrt::application::ImagApplication;
+use libimagrt::runtime::Runtime;
+use libimagstore::store::Store;
+use libimagsync::state::SyncState;
+use libimagsync::sync::Change;
+
+mod ui;
+
+/// Marker enum for implementing ImagApplication on
+///
+/// This is used by binaries crates to execute business logic
+/// or to build a CLI completion.
+pub enum ImagSync {}
+impl ImagApplication for ImagSync {
+    fn run(rt: Runtime) -> Result<()> {
+        let dry_run = rt.cli().is_present("dry-run");
+        let remote  = rt.cli().value_of("remote").unwrap(); // enforced by clap
+        let (name, path) = remote_location(&rt, remote)?;
+        let name = rt.cli().value_of("name").map(String::from).unwrap_or(name);
+
+        if !path.is_dir() {
+            return Err(format_err!("Store to synchronize with does not exist: {}, create the directory first", path.display()))
+        }
+        if path.canonicalize()? == rt.store().path().canonicalize()? {
+            return Err(format_err!("Cannot synchronize the store with itself"))
+        }
+
+        debug!("Synchronizing with {} ({})", name, path.display());
+        let remote = Store::new(path.clone(), &remote_config(&rt))
+            .context(format_err!("Failed to open store: {}", path.display()))?;
+
+        let state_path = SyncState::path(rt.store(), &name);
+        let mut state  = SyncState::load(&state_path)?;
+        let changes    = libimagsync::sync::sync(rt.store(), &remote, &mut state, dry_run)?;
+
+        for change in changes.iter() {
+            rt.emit(change_record(change)?, |out| writeln!(out, "{}", change))?;
+        }
+
+        let conflicts = changes.iter().filter(|c| c.is_conflict()).count();
+        if dry_run {
+            info!("Dry run, nothing was changed");
+        } else {
+            state.save(&state_path)?;
+
+            for change in changes.iter() {
+                match *change {
+                    Change::DeletedLocal(_) | Change::DeletedRemote(_) => {},
+                    Change::Conflict { ref sidecar, .. } => rt.report_touched(sidecar)?,
+                    ref other => rt.report_touched(other.id())?,
+                }
+            }
+        }
+
+        if conflicts > 0 {
+            warn!("{} conflicts, see the '.sync-conflict-' entries", conflicts);
+        }
+        info!("{} changes", changes.len());
+        Ok(())
+    }
+
+    fn build_cli<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+        ui::build_ui(app)
+    }
+
+    fn name() -> &'static str {
+        env!("CARGO_PKG_NAME")
+    }
+
+    fn description() -> &'static str {
+        "Synchronize the store with another store"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+}
+
+/// The name and path of the store to synchronize with
+///
+/// `remote` is either a name from `sync.remotes` or a path, the name of which is built from the
+/// path.
+fn remote_location(rt: &Runtime, remote: &str) -> Result<(String, PathBuf)> {
+    let configured = match rt.config() {
+        None         => None,
+        Some(config) => config
+            .read_string(&format!("sync.remotes.{}", remote))
+            .context(format_err!("Error reading 'sync.remotes.{}' in configuration", remote))?,
+    };
+
+    match configured {
+        Some(path) => Ok((String::from(remote), PathBuf::from(path))),
+        None       => {
+            let name = remote
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join("-");
+            Ok((name, PathBuf::from(remote)))
+        },
+    }
+}
+
+/// The configuration for the other store: the own one, without the mounted stores
+fn remote_config(rt: &Runtime) -> Option<Value> {
+    let mut config = rt.config().cloned();
+    if let Some(store) = config.as_mut().and_then(|c| c.get_mut("store")).and_then(Value::as_table_mut) {
+        let _ = store.remove("mounts");
+    }
+    config
+}
+
+/// The record of `change` for the structured output formats
+fn change_record(change: &Change) -> Result<JsonValue> {
+    let kind = match *change {
+        Change::ToLocal(_)       => "to-local",
+        Change::ToRemote(_)      => "to-remote",
+        Change::DeletedLocal(_)  => "deleted-local",
+        Change::DeletedRemote(_) => "deleted-remote",
+        Change::Merged(_)        => "merged",
+        Change::Restored(_)      => "restored",
+        Change::Conflict { .. }  => "conflict",
+    };
+
+    let mut record = json!({ "id": change.id().to_str()?, "change": kind });
+    if let Change::Conflict { ref sidecar, ref reason, .. } = *change {
+        record["sidecar"] = json!(sidecar.to_str()?);
+        record["reason"]  = json!(reason);
+    }
+    Ok(record)
+}
@@ -0,0 +1,47 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+use clap::{Arg, App};
+
+pub fn build_ui<'a>(app: App<'a, 'a>) -> App<'a, 'a> {
+    app
+        .arg(Arg::with_name("dry-run")
+             .long("dry-run")
+             .short("n")
+             .takes_value(false)
+             .required(false)
+             .multiple(false)
+             .help("Only print what would be changed, do not change anything"))
+
+        .arg(Arg::with_name("name")
+             .long("name")
+             .takes_value(true)
+             .required(false)
+             .multiple(false)
+             .value_name("NAME")
+             .help("The name the state of the synchronization is kept under. Defaults to the name of the remote in 'sync.remotes' or a name built from the path."))
+
+        .arg(Arg::with_name("remote")
+             .index(1)
+             .takes_value(true)
+             .required(true)
+             .multiple(false)
+             .value_name("REMOTE")
+             .help("The store to synchronize with: a name from 'sync.remotes' in the configuration or the path of a store"))
+}
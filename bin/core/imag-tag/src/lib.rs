@@ -37,6 +37,7 @@
 extern crate clap;
 extern crate resiter;
 #[macro_use] extern crate log;
+#[macro_use] extern crate serde_json;
 
 #[cfg(test)] extern crate toml;
 #[macro_use] extern crate failure;
@@ -68,9 +69,12 @@ use resiter::AndThen;
 use resiter::Map;
 use resiter::FilterMap;
 
+use libimagrt::pipe::PipeAction;
+use libimagrt::pipe::PipeRecord;
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
 use libimagentrytag::tagable::Tagable;
+use libimagentrytag::tagable::header_tags;
 use libimagentrytag::tag::is_tag_str;
 use libimagentrytag::tag::Tag;
 use libimagstore::storeid::StoreId;
@@ -118,11 +122,11 @@ impl ImagApplication for ImagTag {
                     must_be_present.iter().map(|t| is_tag_str(t)).collect::<Result<Vec<_>>>()?;
 
                     iter.filter_map_ok(|id| {
-                            match rt.store().get(id.clone()) {
+                            match rt.store().get_header_only(id.clone()) {
                                 Err(e) => Some(Err(e)),
                                 Ok(None) => Some(Err(format_err!("No entry for id {}", id))),
                                 Ok(Some(entry)) => {
-                                    let entry_tags = match entry.get_tags() {
+                                    let entry_tags = match header_tags(entry.get_header()) {
                                         Err(e) => return Some(Err(e)),
                                         Ok(e) => e,
                                     };
@@ -137,8 +141,9 @@ impl ImagApplication for ImagTag {
                         })
                         .flatten()
                         .and_then_ok(|e| {
-                            if !rt.output_is_pipe() {
-                                writeln!(rt.stdout(), "{}", e.get_location())?;
+                            if !rt.output_is_pipe() || rt.output_format().is_structured() {
+                                let record = json!({ "id": e.get_location().to_str()? });
+                                rt.emit(record, |out| writeln!(out, "{}", e.get_location()))?;
                             }
                             Ok(e)
                         })
@@ -157,11 +162,11 @@ impl ImagApplication for ImagTag {
                     must_be_missing.iter().map(|t| is_tag_str(t)).collect::<Result<Vec<_>>>()?;
 
                     iter.filter_map_ok(|id| {
-                            match rt.store().get(id.clone()) {
+                            match rt.store().get_header_only(id.clone()) {
                                 Err(e) => Some(Err(e)),
                                 Ok(None) => Some(Err(format_err!("No entry for id {}", id))),
                                 Ok(Some(entry)) => {
-                                    let entry_tags = match entry.get_tags() {
+                                    let entry_tags = match header_tags(entry.get_header()) {
                                         Err(e) => return Some(Err(e)),
                                         Ok(e) => e,
                                     };
@@ -176,8 +181,9 @@ impl ImagApplication for ImagTag {
                         })
                         .flatten()
                         .and_then_ok(|e| {
-                            if !rt.output_is_pipe() {
-                                writeln!(rt.stdout(), "{}", e.get_location())?;
+                            if !rt.output_is_pipe() || rt.output_format().is_structured() {
+                                let record = json!({ "id": e.get_location().to_str()? });
+                                rt.emit(record, |out| writeln!(out, "{}", e.get_location()))?;
                             }
                             Ok(e)
                         })
@@ -199,7 +205,16 @@ impl ImagApplication for ImagTag {
 
         match rt.ids::<crate::ui::PathProvider>()? {
             Some(ids) => process(&mut ids.into_iter().map(Ok)),
-            None => process(&mut rt.store().entries()?),
+
+            // Only entries with tags are of interest here, which we can get from the index
+            None => match rt.cli().subcommand() {
+                ("list", _) => process(&mut rt.store().index_ids_with("tag.values")?),
+                ("present", Some(scmd)) => {
+                    let tag = scmd.value_of("present-tag").unwrap(); // enforced by clap
+                    process(&mut rt.store().index_lookup("tag.values", tag)?)
+                },
+                _ => process(&mut rt.store().entries()?),
+            },
         }
     }
 
@@ -225,6 +240,10 @@ fn alter(rt: &Runtime, path: StoreId, add: Option<Vec<Tag>>, rem: Option<Vec<Tag
         Some(mut e) => {
             debug!("Entry header now = {:?}", e.get_header());
 
+            let record = PipeRecord::new(path, PipeAction::Updated)
+                .with_meta("tags_added", json!(add.as_ref().unwrap_or(&vec![])))
+                .with_meta("tags_removed", json!(rem.as_ref().unwrap_or(&vec![])));
+
             if let Some(tags) = add {
                 debug!("Adding tags = '{:?}'", tags);
                 tags.into_iter().map(|tag| {
@@ -244,14 +263,14 @@ fn alter(rt: &Runtime, path: StoreId, add: Option<Vec<Tag>>, rem: Option<Vec<Tag
             } // it is okay to ignore a None here
 
             debug!("Entry header now = {:?}", e.get_header());
+            rt.report_record(&record).map_err(Error::from)
         },
 
         None => {
             info!("No entry found.");
+            rt.report_touched(&path).map_err(Error::from)
         },
     }
-
-    rt.report_touched(&path).map_err(Error::from)
 }
 
 fn list(path: StoreId, rt: &Runtime) -> Result<()> {
@@ -269,8 +288,13 @@ fn list(path: StoreId, rt: &Runtime) -> Result<()> {
 
     let tags = entry.get_tags()?;
 
+    if rt.output_format().is_structured() {
+        rt.records().emit(&json!({ "id": path.to_str()?, "tags": tags }))?;
+        return Ok(())
+    }
+
     if json_out {
-        unimplemented!()
+        writeln!(rt.stdout(), "{}", ::serde_json::to_string(&tags)?)?;
     }
 
     if line_out {
@@ -42,6 +42,7 @@ extern crate toml;
 extern crate toml_query;
 #[macro_use] extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagentryview;
 extern crate libimagerror;
@@ -65,6 +66,7 @@ use clap::App;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
+use libimagrt::io::toml_to_json;
 use libimagentryview::builtin::stdout::StdoutViewer;
 use libimagentryview::builtin::md::MarkdownViewer;
 use libimagentryview::viewer::Viewer;
@@ -153,6 +155,19 @@ impl ImagApplication for ImagView {
 
             drop(files);
             Ok(())
+        } else if rt.output_format().is_structured() {
+            entries.and_then_ok(|entry| {
+                let mut record = json!({ "id": entry.get_location().to_str()? });
+                if view_header {
+                    record["header"] = toml_to_json(entry.get_header());
+                }
+                if !hide_content {
+                    record["content"] = json!(entry.get_content());
+                }
+
+                rt.records().emit(&record)
+            })
+            .collect()
         } else {
             let out         = rt.stdout();
             let mut outlock = out.lock();
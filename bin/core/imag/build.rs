@@ -30,6 +30,10 @@ use libimagrt::application::ImagApplication;
 
 #[cfg(feature = "cc-imag-annotate")]
 extern crate libimagannotatecmd;
+#[cfg(feature = "cc-imag-attach")]
+extern crate libimagattachcmd;
+#[cfg(feature = "cc-imag-config")]
+extern crate libimagconfigcmd;
 #[cfg(feature = "cc-imag-create")]
 extern crate libimagcreatecmd;
 #[cfg(feature = "cc-imag-diagnostics")]
@@ -52,8 +56,12 @@ extern crate libimaglinkcmd;
 extern crate libimagmvcmd;
 #[cfg(feature = "cc-imag-ref")]
 extern crate libimagrefcmd;
+#[cfg(feature = "cc-imag-server")]
+extern crate libimagservercmd;
 #[cfg(feature = "cc-imag-store")]
 extern crate libimagstorecmd;
+#[cfg(feature = "cc-imag-sync")]
+extern crate libimagsynccmd;
 #[cfg(feature = "cc-imag-tag")]
 extern crate libimagtagcmd;
 #[cfg(feature = "cc-imag-view")]
@@ -111,6 +119,10 @@ fn main() {
     // TODO: This feels tedious, can we automate this?
     #[cfg(feature = "cc-imag-annotate")]
     let app = app.subcommand(build_subcommand!("annotate",    libimagannotatecmd, ImagAnnotate));
+    #[cfg(feature = "cc-imag-attach")]
+    let app = app.subcommand(build_subcommand!("attach",      libimagattachcmd, ImagAttach));
+    #[cfg(feature = "cc-imag-config")]
+    let app = app.subcommand(build_subcommand!("config",      libimagconfigcmd, ImagConfig));
     #[cfg(feature = "cc-imag-create")]
     let app = app.subcommand(build_subcommand!("create",      libimagcreatecmd, ImagCreate));
     #[cfg(feature = "cc-imag-diagnostics")]
@@ -133,8 +145,12 @@ fn main() {
     let app = app.subcommand(build_subcommand!("mv",          libimagmvcmd, ImagMv));
     #[cfg(feature = "cc-imag-ref")]
     let app = app.subcommand(build_subcommand!("ref",         libimagrefcmd, ImagRef));
+    #[cfg(feature = "cc-imag-server")]
+    let app = app.subcommand(build_subcommand!("server",      libimagservercmd, ImagServer));
     #[cfg(feature = "cc-imag-store")]
     let app = app.subcommand(build_subcommand!("store",       libimagstorecmd, ImagStore));
+    #[cfg(feature = "cc-imag-sync")]
+    let app = app.subcommand(build_subcommand!("sync",        libimagsynccmd, ImagSync));
     #[cfg(feature = "cc-imag-tag")]
     let app = app.subcommand(build_subcommand!("tag",         libimagtagcmd, ImagTag));
     #[cfg(feature = "cc-imag-view")]
@@ -182,8 +182,10 @@ fn main() -> Result<()> {
         let configpath = matches
             .value_of("config")
             .map_or_else(|| rtp.clone(), PathBuf::from);
+        let storepath = ::libimagrt::runtime::get_storepath_match(&matches, &rtp);
         debug!("Config path = {:?}", configpath);
-        let config = ::libimagrt::configuration::fetch_config(&configpath)?;
+        let config = ::libimagrt::configuration::fetch_layered_config(&configpath, &storepath)?
+            .map(|config| config.into_parts().0);
 
         if enable_logging {
             Runtime::init_logger(&matches, config.as_ref())
@@ -358,5 +360,15 @@ fn forward_commandline_arguments(m: &ArgMatches, scmd: &mut Vec<String>) {
     push(Some("store"), "storepath", m , scmd);
     push(Some("editor"), "editor", m , scmd);
     push(Some("ignore-ids"), "ignore-ids", m , scmd);
+    push(Some("no-server"), "no-server", m , scmd);
+    push(Some("output-format"), "output-format", m , scmd);
+    push(Some("pipe-format"), "pipe-format", m , scmd);
+
+    if let Some(mounts) = m.values_of("mount") {
+        for mount in mounts {
+            scmd.insert(0, String::from(mount));
+            scmd.insert(0, String::from("--mount"));
+        }
+    }
 }
 
@@ -40,6 +40,7 @@ extern crate toml;
 extern crate toml_query;
 #[macro_use] extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagbookmark;
 extern crate libimagrt;
@@ -164,7 +165,8 @@ fn list(rt: &Runtime) -> Result<()> {
     collection
         .get_links(rt.store())?
         .and_then_ok(|link| {
-            let r = writeln!(rt.stdout(), "{: >3}: {}", i, link).map_err(Error::from);
+            let record = json!({ "collection": coll, "index": i, "url": link.as_str() });
+            let r      = rt.emit(record, |out| writeln!(out, "{: >3}: {}", i, link));
             i += 1;
             r
         })
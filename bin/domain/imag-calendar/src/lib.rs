@@ -43,6 +43,7 @@ extern crate handlebars;
 extern crate chrono;
 extern crate kairos;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagrt;
 extern crate libimagcalendar;
@@ -252,8 +253,9 @@ fn list(rt: &Runtime) -> Result<()> {
                     let data      = build_data_object_for_handlebars(listed_events, &event);
 
                     let rendered = list_format.render("format", &data)?;
+                    let record   = event_record(parsed_entry.get_entry().get_location(), &event)?;
 
-                    writeln!(rt.stdout(), "{}", rendered).map_err(Error::from)
+                    rt.emit(record, |out| writeln!(out, "{}", rendered))
                 })
                 .collect::<Result<Vec<_>>>()?;
 
@@ -300,8 +302,9 @@ fn show(rt: &Runtime) -> Result<()> {
                     let data     = util::build_data_object_for_handlebars(shown_events, &event);
 
                     let rendered = list_format.render("format", &data)?;
+                    let record   = util::event_record(parsed_entry.get_entry().get_location(), &event)?;
 
-                    writeln!(rt.stdout(), "{}", rendered).map_err(Error::from)
+                    rt.emit(record, |out| writeln!(out, "{}", rendered))
                 })
                 .collect::<Result<Vec<_>>>()?;
 
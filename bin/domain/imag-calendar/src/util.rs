@@ -28,10 +28,12 @@ use failure::Error;
 use failure::err_msg;
 use toml_query::read::TomlValueReadTypeExt;
 use chrono::NaiveDateTime;
+use serde_json::Value as JsonValue;
 
 use libimagrt::runtime::Runtime;
 use libimagstore::store::FileLockEntry;
 use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
 use libimagentryref::reference::fassade::RefFassade;
 use libimagentryref::reference::Ref;
 use libimagentryref::reference::Config;
@@ -123,6 +125,31 @@ pub fn build_data_object_for_handlebars<'a>(i: usize, event: &Event<'a>)
     data
 }
 
+/// The record of an event for the structured output formats
+pub fn event_record<'a>(id: &StoreId, event: &Event<'a>) -> Result<JsonValue> {
+    macro_rules! process_opt {
+        ($t:expr) => {
+            ($t).map(|obj| obj.into_raw())
+        }
+    }
+
+    Ok(json!({
+        "id": id.to_str()?,
+        "dtend": process_opt!(event.dtend()),
+        "dtstart": process_opt!(event.dtstart()),
+        "dtstamp": process_opt!(event.dtstamp()),
+        "uid": process_opt!(event.uid()),
+        "description": process_opt!(event.description()),
+        "summary": process_opt!(event.summary()),
+        "url": process_opt!(event.url()),
+        "location": process_opt!(event.location()),
+        "class": process_opt!(event.class()),
+        "categories": process_opt!(event.categories()),
+        "transp": process_opt!(event.transp()),
+        "rrule": process_opt!(event.rrule()),
+    }))
+}
+
 pub fn kairos_parse(spec: &str) -> Result<NaiveDateTime> {
     match ::kairos::parser::parse(spec).map_err_trace_exit_unwrap() {
         ::kairos::parser::Parsed::Iterator(_) => {
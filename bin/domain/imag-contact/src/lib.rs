@@ -42,7 +42,7 @@ extern crate toml_query;
 extern crate handlebars;
 extern crate walkdir;
 extern crate uuid;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate failure;
 
 extern crate libimagcontact;
@@ -67,6 +67,7 @@ use walkdir::WalkDir;
 use failure::Error;
 use failure::err_msg;
 use failure::Fallible as Result;
+use serde_json::Value as JsonValue;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
@@ -77,6 +78,7 @@ use libimagerror::iter::TraceIterator;
 use libimagcontact::store::ContactStore;
 use libimagcontact::contact::Contact;
 use libimagcontact::deser::DeserVcard;
+use libimagstore::storeid::StoreId;
 
 mod ui;
 mod util;
@@ -150,13 +152,19 @@ fn list(rt: &Runtime) {
             rt.report_touched(fle.get_location()).unwrap_or_exit();
             fle
         })
-        .map(|e| e.deser())
+        .map(|e| e.deser().map(|dvcard| (e.get_location().clone(), dvcard)))
         .trace_unwrap_exit()
         .enumerate();
 
-    if scmd.is_present("json") {
+    if rt.output_format().is_structured() {
+        iterator.for_each(|(_, (id, dvcard))| {
+            contact_record(&id, &dvcard)
+                .and_then(|record| rt.records().emit(&record))
+                .map_err_trace_exit_unwrap()
+        });
+    } else if scmd.is_present("json") {
         debug!("Listing as JSON");
-        let v : Vec<DeserVcard> = iterator.map(|tpl| tpl.1).collect();
+        let v : Vec<DeserVcard> = iterator.map(|(_, (_, dvcard))| dvcard).collect();
 
         match ::serde_json::to_string(&v) {
             Ok(s) => writeln!(rt.stdout(), "{}", s).to_exit_code().unwrap_or_exit(),
@@ -170,7 +178,7 @@ fn list(rt: &Runtime) {
         let output     = rt.stdout();
         let mut output = output.lock();
         iterator
-            .map(|(i, dvcard)| build_data_object_for_handlebars(i, &dvcard))
+            .map(|(i, (_, dvcard))| build_data_object_for_handlebars(i, &dvcard))
             .map(|data| list_format.render("format", &data).map_err(Error::from))
             .trace_unwrap_exit()
             .for_each(|s| {
@@ -243,8 +251,15 @@ fn show(rt: &Runtime) {
     util::find_contact_by_hash(rt, hash)
         .enumerate()
         .for_each(|(i, elem)| {
-            let elem = elem.deser().map_err_trace_exit_unwrap();
-            let data = build_data_object_for_handlebars(i, &elem);
+            let dvcard = elem.deser().map_err_trace_exit_unwrap();
+
+            if rt.output_format().is_structured() {
+                return contact_record(elem.get_location(), &dvcard)
+                    .and_then(|record| rt.records().emit(&record))
+                    .map_err_trace_exit_unwrap()
+            }
+
+            let data = build_data_object_for_handlebars(i, &dvcard);
 
             let s = show_format
                 .render("format", &data)
@@ -301,7 +316,13 @@ fn find(rt: &Runtime) {
         })
         .enumerate();
 
-    if !rt.output_is_pipe() || rt.ignore_ids() {
+    if rt.output_format().is_structured() {
+        iterator.for_each(|(_, (entry, card))| {
+            contact_record(entry.get_location(), &card)
+                .and_then(|record| rt.records().emit(&record))
+                .map_err_trace_exit_unwrap()
+        });
+    } else if !rt.output_is_pipe() || rt.ignore_ids() {
         if scmd.is_present("json") {
             let v : Vec<DeserVcard> = iterator.map(|(_, tlp)| tlp.1).collect();
 
@@ -352,6 +373,15 @@ fn find(rt: &Runtime) {
     }
 }
 
+/// The record of a contact for the structured output formats
+fn contact_record(id: &StoreId, dvcard: &DeserVcard) -> Result<JsonValue> {
+    let mut record = ::serde_json::to_value(dvcard)?;
+    if let Some(object) = record.as_object_mut() {
+        let _ = object.insert(String::from("id"), json!(id.to_str()?));
+    }
+    Ok(record)
+}
+
 fn get_contact_print_format(config_value_path: &'static str, rt: &Runtime, scmd: &ArgMatches) -> Handlebars {
     let fmt = scmd
         .value_of("format")
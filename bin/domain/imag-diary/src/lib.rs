@@ -41,6 +41,7 @@ extern crate chrono;
 extern crate toml;
 extern crate toml_query;
 extern crate itertools;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagdiary;
 extern crate libimagentryedit;
@@ -121,18 +122,16 @@ impl ImagApplication for ImagDiary {
 
 fn diaries(rt: &Runtime) {
     use libimagdiary::diary::Diary;
-    use libimagerror::io::ToExitCode;
-    use libimagerror::exit::ExitUnwrap;
     use libimagerror::iter::TraceIterator;
 
-    let out         = rt.stdout();
-    let mut outlock = out.lock();
-
     rt.store()
         .diary_names()
         .map_err_trace_exit_unwrap()
         .trace_unwrap_exit()
         .unique()
-        .for_each(|n| writeln!(outlock, "{}", n).to_exit_code().unwrap_or_exit())
+        .for_each(|n| {
+            rt.emit(json!({ "diary": n }), |out| writeln!(out, "{}", n))
+                .map_err_trace_exit_unwrap()
+        })
 }
 
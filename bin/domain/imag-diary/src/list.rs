@@ -24,7 +24,6 @@ use libimagrt::runtime::Runtime;
 use libimagutil::warn_exit::warn_exit;
 use libimagerror::trace::MapErrTrace;
 use libimagerror::iter::TraceIterator;
-use libimagerror::io::ToExitCode;
 use libimagerror::exit::ExitUnwrap;
 use libimagutil::debug_result::*;
 use libimagdiary::diaryid::DiaryId;
@@ -57,8 +56,13 @@ pub fn list(rt: &Runtime) {
         .for_each(|id| {
             rt.report_touched(&id).unwrap_or_exit();
 
-            if !rt.output_is_pipe() {
-                writeln!(rt.stdout(), "{}", id).to_exit_code().unwrap_or_exit()
+            if !rt.output_is_pipe() || rt.output_format().is_structured() {
+                let record = json!({
+                    "diary": diaryname,
+                    "id": id.to_str().map_err_trace_exit_unwrap(),
+                });
+
+                rt.emit(record, |out| writeln!(out, "{}", id)).map_err_trace_exit_unwrap()
             }
         });
 }
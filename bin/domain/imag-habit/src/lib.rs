@@ -42,6 +42,7 @@ extern crate kairos;
 extern crate chrono;
 extern crate prettytable;
 #[macro_use] extern crate failure;
+#[macro_use] extern crate serde_json;
 
 extern crate libimaghabit;
 extern crate libimagstore;
@@ -59,6 +60,7 @@ use prettytable::Row;
 use failure::Error;
 use failure::Fallible as Result;
 use clap::App;
+use serde_json::Value as JsonValue;
 
 use libimagrt::runtime::Runtime;
 use libimagrt::application::ImagApplication;
@@ -404,6 +406,13 @@ fn today(rt: &Runtime, future: bool) {
             })
             .enumerate()
             .for_each(|(i, e)| {
+                if rt.output_format().is_structured() {
+                    rt.report_touched(e.get_location()).unwrap_or_exit();
+                    return habit_record(&e)
+                        .and_then(|record| rt.records().emit(&record))
+                        .map_err_trace_exit_unwrap()
+                }
+
                 let mut v = vec![format!("{}", i)];
                 let mut list = lister_fn(&e);
 
@@ -474,6 +483,13 @@ fn list(rt: &Runtime) {
         })
         .enumerate()
         .for_each(|(i, e)| {
+            if rt.output_format().is_structured() {
+                rt.report_touched(e.get_location()).unwrap_or_exit();
+                return habit_record(&e)
+                    .and_then(|record| rt.records().emit(&record))
+                    .map_err_trace_exit_unwrap()
+            }
+
             let mut v = vec![format!("{}", i)];
             let mut list = lister_fn(&e);
 
@@ -644,6 +660,22 @@ fn get_from_store(store: &Store, id: StoreId) -> Option<FileLockEntry<'_>> {
     }
 }
 
+/// The record of a habit for the structured output formats
+fn habit_record(h: &FileLockEntry) -> Result<JsonValue> {
+    let next_due = h.next_instance_date()?;
+    let done     = next_due.map(|date| h.instance_exists_for_date(date)).transpose()?;
+
+    Ok(json!({
+        "id": h.get_location().to_str()?,
+        "name": h.habit_name()?,
+        "basedate": h.habit_basedate()?,
+        "recur": h.habit_recur_spec()?,
+        "comment": h.habit_comment()?,
+        "next_due": next_due.map(date_to_string_helper),
+        "done": done.unwrap_or(false),
+    }))
+}
+
 fn date_to_string_helper(d: chrono::NaiveDate) -> String {
     libimagutil::date::date_to_string(d)
 }
@@ -42,6 +42,7 @@ extern crate toml_query;
 extern crate itertools;
 extern crate failure;
 extern crate textwrap;
+#[macro_use] extern crate serde_json;
 
 extern crate libimaglog;
 extern crate libimagrt;
@@ -200,7 +201,16 @@ fn show(rt: &Runtime) {
         .sorted_by_key(|tpl| tpl.0.get_date_representation())
         .map(|tpl| { debug!("Found entry: {:?}", tpl.1); tpl })
         .map(|(id, entry)| {
-            if let Some(wrap_limit) = do_wrap {
+            if rt.output_format().is_structured() {
+                let record = json!({
+                    "id": entry.get_location().to_str().map_err_trace_exit_unwrap(),
+                    "diary": id.diary_name(),
+                    "datetime": format!("{:04}-{:02}-{:02}T{:02}:{:02}",
+                                        id.year(), id.month(), id.day(), id.hour(), id.minute()),
+                    "text": entry.get_content().trim_end(),
+                });
+                rt.records().emit(&record).map_err_trace_exit_unwrap();
+            } else if let Some(wrap_limit) = do_wrap {
                 // assume a capacity here:
                 // diaryname + year + month + day + hour + minute + delimiters + whitespace
                 // 10 + 4 + 2 + 2 + 2 + 2 + 6 + 4 = 32
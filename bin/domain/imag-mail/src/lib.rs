@@ -39,6 +39,7 @@ extern crate clap;
 #[macro_use] extern crate failure;
 extern crate toml_query;
 #[macro_use] extern crate indoc;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagrt;
 extern crate libimagmail;
@@ -57,7 +58,6 @@ use clap::App;
 use libimagerror::trace::{MapErrTrace, trace_error};
 use libimagerror::iter::TraceIterator;
 use libimagerror::exit::ExitUnwrap;
-use libimagerror::io::ToExitCode;
 use libimagmail::mail::Mail;
 use libimagmail::store::MailStore;
 use libimagmail::util;
@@ -204,31 +204,48 @@ fn list(rt: &Runtime) {
             },
         };
 
-        if print_content {
+        let content = if print_content {
             use libimagmail::hasher::MailHasher;
 
-            let content = m.as_ref_with_hasher::<MailHasher>()
+            m.as_ref_with_hasher::<MailHasher>()
                 .get_path(&refconfig)
                 .and_then(util::get_mail_text_content)
-                .map_err_trace_exit_unwrap();
+                .map(Some)
+                .map_err_trace_exit_unwrap()
+        } else {
+            None
+        };
+
+        let mut record = json!({
+            "id": m.get_location().to_str().map_err_trace_exit_unwrap(),
+            "message_id": id,
+            "from": from,
+            "to": to,
+            "subject": subject,
+        });
+        if let Some(ref content) = content {
+            record["content"] = json!(content);
+        }
 
-            writeln!(rt.stdout(),
+        rt.emit(record, |out| if let Some(content) = content {
+            writeln!(out,
                      "Mail: {id}\nFrom: {from}\nTo: {to}\n{subj}\n---\n{content}\n---\n",
                      from    = from,
                      id      = id,
                      subj    = subject,
                      to      = to,
                      content = content
-            ).to_exit_code().unwrap_or_exit();
+            )
         } else {
-            writeln!(rt.stdout(),
+            writeln!(out,
                      "Mail: {id}\nFrom: {from}\nTo: {to}\n{subj}\n",
                      from = from,
                      id   = id,
                      subj = subject,
                      to   = to
-            ).to_exit_code().unwrap_or_exit();
-        }
+            )
+        })
+        .map_err_trace_exit_unwrap();
 
         rt.report_touched(m.get_location()).unwrap_or_exit();
     }
@@ -39,6 +39,7 @@ extern crate clap;
 extern crate itertools;
 #[macro_use] extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagnotes;
 extern crate libimagrt;
@@ -153,8 +154,9 @@ fn list(rt: &Runtime) -> Result<()> {
             _ => Ordering::Greater,
         })
         .map(|note| {
-            let name = note.get_name().map_err(Error::from)?;
-            writeln!(rt.stdout(), "{}", name)?;
+            let name   = note.get_name().map_err(Error::from)?;
+            let record = json!({ "id": note.get_location().to_str()?, "name": name });
+            rt.emit(record, |out| writeln!(out, "{}", name))?;
             rt.report_touched(note.get_location()).map_err(Error::from)
         })
         .collect::<Result<Vec<_>>>()
@@ -44,6 +44,8 @@ extern crate itertools;
 extern crate prettytable;
 extern crate kairos;
 extern crate failure;
+#[macro_use]
+extern crate serde_json;
 
 extern crate libimagerror;
 extern crate libimagstore;
@@ -29,14 +29,17 @@ use failure::Fallible as Result;
 use failure::ResultExt;
 use failure::err_msg;
 use failure::Error;
+use serde_json::Value as JsonValue;
 
 use libimagerror::trace::trace_error;
 use libimagerror::trace::MapErrTrace;
 use libimagerror::iter::TraceIterator;
 use libimagerror::exit::ExitUnwrap;
 use libimagstore::store::FileLockEntry;
+use libimagstore::storeid::StoreId;
 use libimagtimetrack::store::TimeTrackStore;
 use libimagtimetrack::timetracking::TimeTracking;
+use libimagtimetrack::tag::TimeTrackingTag;
 
 use libimagrt::runtime::Runtime;
 
@@ -148,6 +151,10 @@ pub fn list_impl(rt: &Runtime,
                 let end   = e.get_end_datetime()?;
                 debug!(" -> end = {:?}", end);
 
+                if rt.output_format().is_structured() {
+                    rt.records().emit(&timetracking_record(e.get_location(), &tag, start, end)?)?;
+                }
+
                 let v = match (start, end) {
                     (None, _)          => {
                         let mut v = vec![String::from(tag.as_str()), String::from(""), String::from("")];
@@ -205,7 +212,7 @@ pub fn list_impl(rt: &Runtime,
         })
         .map_err_trace_exit_unwrap();
 
-    if !table_empty {
+    if !table_empty && !rt.output_format().is_structured() {
         table.print(&mut rt.stdout())
             .context(err_msg("Failed to print table"))
             .map_err(Error::from)
@@ -217,3 +224,21 @@ pub fn list_impl(rt: &Runtime,
     }
 }
 
+
+/// The record of a timetracking for the structured output formats
+///
+/// The duration is in seconds.
+pub fn timetracking_record(id: &StoreId,
+                           tag: &TimeTrackingTag,
+                           start: Option<NaiveDateTime>,
+                           end: Option<NaiveDateTime>)
+    -> Result<JsonValue>
+{
+    Ok(json!({
+        "id": id.to_str()?,
+        "tag": tag.as_str(),
+        "start": start.map(|s| s.to_string()),
+        "end": end.map(|e| e.to_string()),
+        "duration": start.and_then(|s| end.map(|e| (e - s).num_seconds())),
+    }))
+}
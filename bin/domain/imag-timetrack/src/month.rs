@@ -37,6 +37,8 @@ use libimagtimetrack::iter::filter::*;
 
 use libimagrt::runtime::Runtime;
 
+use crate::list::timetracking_record;
+
 pub fn month(rt: &Runtime) -> i32 {
     let cmd = rt.cli().subcommand().1.unwrap(); // checked in main
 
@@ -121,9 +123,14 @@ pub fn month(rt: &Runtime) -> i32 {
 
             rt.report_touched(e.get_location()).unwrap_or_exit();
 
+            if rt.output_format().is_structured() {
+                rt.records().emit(&timetracking_record(e.get_location(), &tag, start, end)?)?;
+            }
+
             Ok((tag, start, end))
         })
         .trace_unwrap_exit()
+        .filter(|_| !rt.output_format().is_structured())
         .map(|(tag, start, end)| {
             match (start, end) {
                 (None, _)          => writeln!(rt.stdout(), "{} has no start time.", tag),
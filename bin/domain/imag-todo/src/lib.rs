@@ -43,6 +43,7 @@ extern crate kairos;
 #[macro_use] extern crate log;
 #[macro_use] extern crate failure;
 extern crate resiter;
+#[macro_use] extern crate serde_json;
 
 #[cfg(feature = "import-taskwarrior")]
 extern crate task_hookrs;
@@ -75,6 +76,7 @@ use failure::err_msg;
 use clap::App;
 use resiter::AndThen;
 use resiter::IterInnerOkOrElse;
+use serde_json::Value as JsonValue;
 
 use libimagentryedit::edit::Edit;
 use libimagentryview::viewer::ViewFromIter;
@@ -323,7 +325,11 @@ fn list_todos(rt: &Runtime, matcher: &StatusMatcher, show_hidden: bool) -> Resul
             }
         })
         .and_then_ok(|entry| {
-            if !rt.output_is_pipe() && (show_hidden || filter_hidden.filter(&entry)?) {
+            if rt.output_format().is_structured() {
+                if show_hidden || filter_hidden.filter(&entry)? {
+                    rt.records().emit(&todo_record(&entry)?)?;
+                }
+            } else if !rt.output_is_pipe() && (show_hidden || filter_hidden.filter(&entry)?) {
                 viewer.view_entry(&entry, &mut rt.stdout())?;
             }
 
@@ -393,14 +399,20 @@ fn show(rt: &Runtime) -> Result<()> {
         }
     }
 
-    rt.ids::<crate::ui::PathProvider>()?
+    let entries = rt.ids::<crate::ui::PathProvider>()?
         .ok_or_else(|| err_msg("No ids supplied"))?
         .into_iter()
         .map(Ok)
         .into_get_iter(rt.store())
         .map_inner_ok_or_else(|| err_msg("Did not find one entry"))
         .and_then_ok(|e| rt.report_touched(e.get_location()).map_err(Error::from).map(|_| e))
-        .collect::<Result<Vec<_>>>()?
+        .collect::<Result<Vec<_>>>()?;
+
+    if rt.output_format().is_structured() {
+        return entries.iter().map(|e| rt.records().emit(&todo_record(e)?)).collect()
+    }
+
+    entries
         .into_iter()
         .view::<TodoShow, _>(&mut rt.stdout())
         .map_err(Error::from)
@@ -437,6 +449,25 @@ fn prio_from_str<S: AsRef<str>>(s: S) -> Result<Priority> {
     }
 }
 
+fn todo_record(entry: &Entry) -> Result<JsonValue> {
+    if !entry.is_todo()? {
+        return Err(format_err!("Not a Todo: {}", entry.get_location()))
+    }
+
+    let datetime = |d: Option<NaiveDateTime>| d.map(|d| datetime_to_string(&d));
+
+    Ok(json!({
+        "id": entry.get_location().to_str()?,
+        "uuid": entry.get_uuid()?.to_string(),
+        "status": entry.get_status()?.as_str(),
+        "priority": entry.get_priority()?.map(|p| p.as_str().to_string()),
+        "scheduled": datetime(entry.get_scheduled()?),
+        "hidden": datetime(entry.get_hidden()?),
+        "due": datetime(entry.get_due()?),
+        "text": entry.get_content(),
+    }))
+}
+
 fn get_dt_str(d: Result<Option<NaiveDateTime>>, s: &str) -> RResult<String, libimagentryview::error::Error> {
     Ok(d.map_err(libimagentryview::error::Error::from)?
        .map(|v| datetime_to_string(&v))
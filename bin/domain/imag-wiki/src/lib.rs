@@ -24,6 +24,7 @@ extern crate regex;
 extern crate filters;
 #[macro_use] extern crate log;
 extern crate failure;
+#[macro_use] extern crate serde_json;
 
 extern crate libimagrt;
 extern crate libimagerror;
@@ -105,9 +106,6 @@ fn list(rt: &Runtime, wiki_name: &str) {
         String::from("")
     };
 
-    let out         = rt.stdout();
-    let mut outlock = out.lock();
-
     rt.store()
         .get_wiki(wiki_name)
         .map_err_trace_exit_unwrap()
@@ -119,9 +117,13 @@ fn list(rt: &Runtime, wiki_name: &str) {
         .map_err_trace_exit_unwrap()
         .trace_unwrap_exit()
         .for_each(|id| {
-            writeln!(outlock, "{}{}", prefix, id)
-                .to_exit_code()
-                .unwrap_or_exit();
+            let record = json!({
+                "wiki": wiki_name,
+                "id": id.to_str().map_err_trace_exit_unwrap(),
+            });
+
+            rt.emit(record, |out| writeln!(out, "{}{}", prefix, id))
+                .map_err_trace_exit_unwrap();
         });
 }
 
@@ -251,9 +253,6 @@ fn show(rt: &Runtime, wiki_name: &str) {
             ::std::process::exit(1)
         });
 
-    let out         = rt.stdout();
-    let mut outlock = out.lock();
-
     for name in names {
         let entry = wiki
             .get_entry(&name)
@@ -263,13 +262,17 @@ fn show(rt: &Runtime, wiki_name: &str) {
                 ::std::process::exit(1)
             });
 
-        writeln!(outlock, "{}", entry.get_location())
-                .to_exit_code()
-                .unwrap_or_exit();
+        let record = json!({
+            "wiki": wiki_name,
+            "id": entry.get_location().to_str().map_err_trace_exit_unwrap(),
+            "content": entry.get_content(),
+        });
 
-        writeln!(outlock, "{}", entry.get_content())
-                .to_exit_code()
-                .unwrap_or_exit();
+        rt.emit(record, |out| {
+            writeln!(out, "{}", entry.get_location())?;
+            writeln!(out, "{}", entry.get_content())
+        })
+        .map_err_trace_exit_unwrap();
 
         rt.report_touched(entry.get_location()).unwrap_or_exit();
     }
@@ -40,7 +40,7 @@ macro_rules! simple_imag_application_binary {
         use failure::{Error, Fallible as Result};
         
         fn main() {
-            use libimagerror::trace::MapErrTrace;
+            use libimagerror::trace::trace_error;
             use libimagrt::application::ImagApplication;
             use libimagrt::setup::generate_runtime_setup;
             use $application_library::$application_implementor;
@@ -61,10 +61,21 @@ macro_rules! simple_imag_application_binary {
                 format!("Failed to run {}", $application_implementor::name())
                     .into_boxed_str()
             );
-            $application_implementor::run(rt)
+            // Errors are also printed as record with the structured output formats. The record
+            // writer is dropped after that, which finishes the output.
+            let records = rt.records();
+            let result  = $application_implementor::run(rt)
                 .map_err(|e| e.context(error_context))
-		.map_err(Error::from)
-		.map_err_trace_exit_unwrap();
+                .map_err(Error::from);
+
+            if let Err(e) = result {
+                if records.format().is_structured() {
+                    let _ = records.emit_error(&e);
+                }
+                drop(records);
+                trace_error(&e);
+                ::std::process::exit(1)
+            }
         }
     };
 }
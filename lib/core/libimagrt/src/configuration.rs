@@ -17,6 +17,9 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
 use std::path::PathBuf;
 
 use toml::Value;
@@ -28,20 +31,351 @@ use failure::err_msg;
 
 use libimagerror::errors::ErrorMsg as EM;
 
+/// The system wide configuration file, the lowest configuration layer
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/imag/imagrc.toml";
+
+/// The name of the store-local configuration file inside the store directory
+pub const STORE_CONFIG_NAME: &str = ".imagrc.toml";
+
+/// Prefix of the environment variables which override configuration values
+///
+/// `IMAG_CONFIG__rt__progressbar_style` overrides `rt.progressbar_style`.
+pub const ENV_CONFIG_PREFIX: &str = "IMAG_CONFIG__";
+
+/// The settings the store-local configuration may change
+///
+/// Anyone who can write to the store (for example through a synchronized directory) can write
+/// the store-local configuration, so it may not change settings which run commands, like
+/// `store.hooks`, or which access files outside of the store. The system or user configuration
+/// can lift this restriction with `rt.trust-store-config = true`.
+pub const STORE_CONFIG_ALLOWED: &[&str] = &[
+    "store.implicit-create",
+    "store.header-format",
+    "store.collections",
+    "store.index",
+    "store.trash",
+    "store.history",
+    "store.locks",
+    "store.schema",
+    "diary",
+    "log",
+];
+
+/// The layer of the configuration a value was read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    System(PathBuf),
+    User(PathBuf),
+    Store(PathBuf),
+    Environment(String),
+    CommandLine,
+}
+
+impl Display for ConfigOrigin {
+    fn fmt(&self, f: &mut Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        match *self {
+            ConfigOrigin::System(ref path)   => write!(f, "system config {}", path.display()),
+            ConfigOrigin::User(ref path)     => write!(f, "user config {}", path.display()),
+            ConfigOrigin::Store(ref path)    => write!(f, "store config {}", path.display()),
+            ConfigOrigin::Environment(ref v) => write!(f, "environment variable {}", v),
+            ConfigOrigin::CommandLine        => write!(f, "commandline"),
+        }
+    }
+}
+
+/// The origin of each value in the configuration, by the path of the value in the TOML tree
+pub type ConfigOrigins = BTreeMap<String, ConfigOrigin>;
+
+/// A configuration which is merged from several layers
+///
+/// Each layer overrides the values of the layers before it. Tables are merged key by key, all
+/// other values (including arrays) are replaced as a whole.
+#[derive(Clone, Debug)]
+pub struct LayeredConfig {
+    config: Value,
+    origins: ConfigOrigins,
+}
+
+impl Default for LayeredConfig {
+    fn default() -> Self {
+        LayeredConfig {
+            config: Value::Table(::toml::map::Map::new()),
+            origins: ConfigOrigins::new(),
+        }
+    }
+}
+
+impl LayeredConfig {
+
+    pub fn config(&self) -> &Value {
+        &self.config
+    }
+
+    pub fn origins(&self) -> &ConfigOrigins {
+        &self.origins
+    }
+
+    pub fn into_parts(self) -> (Value, ConfigOrigins) {
+        (self.config, self.origins)
+    }
+
+    /// Merge a complete configuration layer into this configuration
+    pub fn merge(&mut self, layer: Value, origin: ConfigOrigin) {
+        merge_value(&mut self.config, layer, "", &origin, &mut self.origins)
+    }
+
+    /// Set the value at `path` from an `IMAG_CONFIG__section__key` environment variable
+    ///
+    /// If the value is already configured, the new value is converted to the type of the present
+    /// one. Otherwise it is parsed with `parse_value()`.
+    pub fn set_from_env(&mut self, var: &str, value: &str) -> Result<()> {
+        let path = var
+            .trim_start_matches(ENV_CONFIG_PREFIX)
+            .split("__")
+            .map(|part| if part.is_empty() {
+                Err(format_err!("Empty key in environment variable '{}'", var))
+            } else {
+                Ok(part)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .join(".");
+
+        let value = {
+            use toml_query::read::TomlValueReadExt;
+
+            match self.config.read(&path).context(EM::TomlQueryError)? {
+                Some(present) => into_value(present, String::from(value))
+                    .ok_or_else(|| format_err!("Value of '{}' does not match the type of '{}'", var, path))?,
+                None => parse_value(value),
+            }
+        };
+
+        debug!("Configuration from environment: {} = {}", path, value);
+        let layer = path
+            .rsplit('.')
+            .fold(value, |value, key| {
+                let mut table = ::toml::map::Map::new();
+                let _ = table.insert(String::from(key), value);
+                Value::Table(table)
+            });
+
+        self.merge(layer, ConfigOrigin::Environment(String::from(var)));
+        Ok(())
+    }
+
+    /// Apply the `--override-config` specs, see `override_config()`
+    pub fn override_with(&mut self, specs: Vec<String>) -> Result<()> {
+        use libimagutil::key_value_split::*;
+
+        for spec in specs {
+            override_config(&mut self.config, vec![spec.clone()])?;
+
+            if let Some(kv) = spec.into_kv() {
+                let (key, _) : (String, String) = kv.into();
+                forget_origins(&mut self.origins, &key);
+                let _ = self.origins.insert(key, ConfigOrigin::CommandLine);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the layered configuration
+///
+/// The layers are, from lowest to highest priority:
+///
+/// * The system configuration in `SYSTEM_CONFIG_PATH`
+/// * The user configuration, searched as in `fetch_config()`
+/// * The store-local configuration `STORE_CONFIG_NAME` in the store directory, restricted to
+///   `STORE_CONFIG_ALLOWED` unless `rt.trust-store-config` is set
+/// * The `IMAG_CONFIG__section__key` environment variables
+///
+/// The commandline overrides are applied by the caller with `LayeredConfig::override_with()`.
+///
+/// Returns `None` if none of the configuration files exists and no environment variable is set.
+pub fn fetch_layered_config(searchpath: &PathBuf, storepath: &Path) -> Result<Option<LayeredConfig>> {
+    let env = ::std::env::vars().filter(|(k, _)| k.starts_with(ENV_CONFIG_PREFIX));
+    layered_config(&PathBuf::from(SYSTEM_CONFIG_PATH), searchpath, storepath, env)
+}
+
+fn layered_config<I>(systempath: &PathBuf, searchpath: &PathBuf, storepath: &Path, env: I)
+    -> Result<Option<LayeredConfig>>
+    where I: Iterator<Item = (String, String)>
+{
+    let storeconfig = storepath.join(STORE_CONFIG_NAME);
+    let files = vec![
+        read_config_file(systempath).map(|c| (c, ConfigOrigin::System(systempath.clone()))),
+        fetch_user_config(searchpath).map(|(path, c)| (c, ConfigOrigin::User(path))),
+    ];
+    let store = read_config_file(&storeconfig).map(|c| (c, storeconfig));
+
+    merge_layers(files, store, env)
+}
+
+/// Merge the configuration files with the environment variables, see `layered_config()`
+fn merge_layers<I>(files: Vec<Option<(Value, ConfigOrigin)>>, store: Option<(Value, PathBuf)>, env: I)
+    -> Result<Option<LayeredConfig>>
+    where I: Iterator<Item = (String, String)>
+{
+    let mut env = env.collect::<Vec<_>>();
+    env.sort();
+
+    if files.iter().all(Option::is_none) && store.is_none() && env.is_empty() {
+        return Ok(None)
+    }
+
+    let mut layered = LayeredConfig::default();
+    for (config, origin) in files.into_iter().flatten() {
+        debug!("Configuration layer: {}", origin);
+        layered.merge(config, origin);
+    }
+
+    if let Some((config, storeconfig)) = store {
+        let trusted = {
+            use toml_query::read::TomlValueReadTypeExt;
+            layered.config().read_bool("rt.trust-store-config").context(EM::TomlQueryError)?.unwrap_or(false)
+        };
+
+        let config = if trusted {
+            config
+        } else {
+            let mut ignored = vec![];
+            let config = restrict_to(config, STORE_CONFIG_ALLOWED, "", &mut ignored);
+            for key in ignored {
+                eprintln!("Ignoring '{}' in {}, set 'rt.trust-store-config' to allow it", key, storeconfig.display());
+            }
+            config
+        };
+
+        debug!("Configuration layer: {}", storeconfig.display());
+        layered.merge(config, ConfigOrigin::Store(storeconfig));
+    }
+    for (var, value) in env {
+        layered
+            .set_from_env(&var, &value)
+            .context(format_err!("Invalid configuration in environment variable '{}'", var))?;
+    }
+
+    Ok(Some(layered))
+}
+
+/// Parse `s` as TOML value, e.g. `true`, `1` or `[1, 2]`, or use it as string if that fails
+pub fn parse_value(s: &str) -> Value {
+    ::toml::de::from_str::<Value>(&format!("v = {}", s))
+        .ok()
+        .and_then(|mut t| t.as_table_mut().and_then(|t| t.remove("v")))
+        .unwrap_or_else(|| Value::String(String::from(s)))
+}
+
+/// Get all values which are not tables in `value`, with their path in the TOML tree
+pub fn config_leaves(value: &Value) -> Vec<(String, &Value)> {
+    fn collect<'a>(value: &'a Value, path: String, leaves: &mut Vec<(String, &'a Value)>) {
+        match *value {
+            Value::Table(ref table) => for (key, value) in table {
+                collect(value, join_path(&path, key), leaves)
+            },
+            _ => leaves.push((path, value)),
+        }
+    }
+
+    let mut leaves = vec![];
+    collect(value, String::new(), &mut leaves);
+    leaves
+}
+
+/// Whether the store-local configuration may set `key` without `rt.trust-store-config`
+pub fn is_allowed_in_store_config(key: &str) -> bool {
+    STORE_CONFIG_ALLOWED
+        .iter()
+        .any(|allowed| key == *allowed || (key.starts_with(allowed) && key[allowed.len()..].starts_with('.')))
+}
+
+/// Remove everything from the table `value` at `path` which is not in `allowed`
+///
+/// The paths of the removed values are pushed to `ignored`.
+fn restrict_to(value: Value, allowed: &[&str], path: &str, ignored: &mut Vec<String>) -> Value {
+    match value {
+        Value::Table(table) => Value::Table(table
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let path = join_path(path, &key);
+                let prefix = format!("{}.", path);
+                if allowed.contains(&path.as_str()) {
+                    Some((key, value))
+                } else if value.is_table() && allowed.iter().any(|a| a.starts_with(&prefix)) {
+                    Some((key, restrict_to(value, allowed, &path, ignored)))
+                } else {
+                    ignored.push(path);
+                    None
+                }
+            })
+            .collect()),
+        _ => {
+            ignored.push(String::from(path));
+            Value::Table(::toml::map::Map::new())
+        },
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        String::from(key)
+    } else if key.is_empty() {
+        String::from(path)
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn forget_origins(origins: &mut ConfigOrigins, path: &str) {
+    let prefix = format!("{}.", path);
+    origins.retain(|key, _| key != path && !key.starts_with(&prefix));
+}
+
+fn merge_value(base: &mut Value, layer: Value, path: &str, origin: &ConfigOrigin, origins: &mut ConfigOrigins) {
+    match (base, layer) {
+        (&mut Value::Table(ref mut base), Value::Table(layer)) => for (key, value) in layer {
+            let path = join_path(path, &key);
+            match base.get_mut(&key) {
+                Some(present) => merge_value(present, value, &path, origin, origins),
+                None => {
+                    for (leaf, _) in config_leaves(&value) {
+                        let _ = origins.insert(join_path(&path, &leaf), origin.clone());
+                    }
+                    let _ = base.insert(key, value);
+                },
+            }
+        },
+        (base, layer) => {
+            forget_origins(origins, path);
+            for (leaf, _) in config_leaves(&layer) {
+                let _ = origins.insert(join_path(path, &leaf), origin.clone());
+            }
+            *base = layer;
+        },
+    }
+}
+
 /// Get a new configuration object.
 ///
 /// The passed runtimepath is used for searching the configuration file, whereas several file
 /// names are tested. If that does not work, the home directory and the XDG basedir are tested
 /// with all variants.
 pub fn fetch_config(searchpath: &PathBuf) -> Result<Option<Value>> {
+    Ok(fetch_user_config(searchpath).map(|(_, config)| config))
+}
+
+/// Get the path of the user configuration file, searched as described in `fetch_config()`
+pub fn fetch_user_config_path(searchpath: &PathBuf) -> Option<PathBuf> {
+    fetch_user_config(searchpath).map(|(path, _)| path)
+}
+
+/// Search the user configuration file as described in `fetch_config()`, returning its path, too
+fn fetch_user_config(searchpath: &PathBuf) -> Option<(PathBuf, Value)> {
     use std::env;
-    use std::fs::File;
-    use std::io::Read;
-    use std::io::Write;
-    use std::io::stderr;
 
     use libimagutil::variants::generate_variants as gen_vars;
-    use libimagerror::trace::trace_error;
 
     let variants : Vec<&'static str> = vec!["config", "config.toml", "imagrc", "imagrc.toml"];
     let modifier = |base: &PathBuf, v: &&str| {
@@ -61,13 +395,33 @@ pub fn fetch_config(searchpath: &PathBuf) -> Result<Option<Value>> {
         xdg_basedir::get_data_home()
             .map(|data_dir| gen_vars(&data_dir, variants.iter(), &modifier))
             .unwrap_or_else(|_| vec![]),
+
+        xdg_basedir::get_config_home()
+            .map(|config_dir| gen_vars(&config_dir.join("imag"), variants.iter(), &modifier))
+            .unwrap_or_else(|_| vec![]),
     ];
 
-    let config = vals
-        .iter()
+    vals.into_iter()
         .flatten()
+        .filter_map(|path| read_config_file(&path).map(|config| (path, config)))
+        .nth(0)
+}
+
+/// Read and parse a configuration file
+///
+/// Returns `None` if the file does not exist or cannot be read or parsed, whereas the latter
+/// errors are reported on stderr.
+fn read_config_file(path: &PathBuf) -> Option<Value> {
+    use std::fs::File;
+    use std::io::Read;
+    use std::io::Write;
+    use std::io::stderr;
+
+    use libimagerror::trace::trace_error;
+
+    Some(path)
         .filter(|path| path.exists() && path.is_file())
-        .filter_map(|path| {
+        .and_then(|path| {
             let content = {
                 let f = File::open(path);
                 if f.is_err() {
@@ -95,9 +449,6 @@ pub fn fetch_config(searchpath: &PathBuf) -> Result<Option<Value>> {
                     None
                 })
         })
-        .nth(0);
-
-    Ok(config)
 }
 
 /// Override the configuration.
@@ -168,3 +519,102 @@ pub trait InternalConfiguration {
 
 impl<'a> InternalConfiguration for App<'a, 'a> {}
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toml(s: &str) -> Value {
+        ::toml::de::from_str(s).unwrap()
+    }
+
+    fn system() -> ConfigOrigin {
+        ConfigOrigin::System(PathBuf::from("/etc/imag/imagrc.toml"))
+    }
+
+    fn user() -> ConfigOrigin {
+        ConfigOrigin::User(PathBuf::from("/home/user/.imag/imagrc.toml"))
+    }
+
+    #[test]
+    fn test_merge_layers() {
+        let mut config = LayeredConfig::default();
+        config.merge(toml("[rt]\neditor = \"vi\"\nprogressbar_style = \"a\"\n[store]\nimplicit-create = false\n"), system());
+        config.merge(toml("[rt]\neditor = \"emacs\"\n[diary]\nname = \"default\"\n"), user());
+
+        assert_eq!(config.config(), &toml("[rt]\neditor = \"emacs\"\nprogressbar_style = \"a\"\n[store]\nimplicit-create = false\n[diary]\nname = \"default\"\n"));
+        assert_eq!(config.origins().get("rt.editor"), Some(&user()));
+        assert_eq!(config.origins().get("rt.progressbar_style"), Some(&system()));
+        assert_eq!(config.origins().get("store.implicit-create"), Some(&system()));
+        assert_eq!(config.origins().get("diary.name"), Some(&user()));
+        assert_eq!(config.origins().get("rt"), None);
+    }
+
+    #[test]
+    fn test_merge_replaces_arrays_and_tables() {
+        let mut config = LayeredConfig::default();
+        config.merge(toml("a = [1, 2]\n[b]\nc = 1\nd = 2\n"), system());
+        config.merge(toml("a = [3]\nb = \"replaced\"\n"), user());
+
+        assert_eq!(config.config(), &toml("a = [3]\nb = \"replaced\"\n"));
+        assert_eq!(config.origins().get("a"), Some(&user()));
+        assert_eq!(config.origins().get("b"), Some(&user()));
+        assert_eq!(config.origins().get("b.c"), None);
+    }
+
+    #[test]
+    fn test_set_from_env() {
+        let mut config = LayeredConfig::default();
+        config.merge(toml("[rt]\nprogressbar_style = \"a\"\n[git]\nexecute_in_store = false\n"), system());
+
+        config.set_from_env("IMAG_CONFIG__git__execute_in_store", "true").unwrap();
+        config.set_from_env("IMAG_CONFIG__rt__progressbar_style", "true").unwrap();
+        config.set_from_env("IMAG_CONFIG__log__level", "3").unwrap();
+        config.set_from_env("IMAG_CONFIG__log__name", "some name").unwrap();
+
+        assert_eq!(config.config(), &toml("[rt]\nprogressbar_style = \"true\"\n[git]\nexecute_in_store = true\n[log]\nlevel = 3\nname = \"some name\"\n"));
+        assert_eq!(config.origins().get("log.level"),
+                   Some(&ConfigOrigin::Environment(String::from("IMAG_CONFIG__log__level"))));
+
+        assert!(config.set_from_env("IMAG_CONFIG__git__execute_in_store", "maybe").is_err());
+        assert!(config.set_from_env("IMAG_CONFIG__git____foo", "1").is_err());
+    }
+
+    #[test]
+    fn test_override_with() {
+        let mut config = LayeredConfig::default();
+        config.merge(toml("[rt]\neditor = \"vi\"\n"), system());
+
+        config.override_with(vec![String::from("rt.editor=nano")]).unwrap();
+        assert_eq!(config.config(), &toml("[rt]\neditor = \"nano\"\n"));
+        assert_eq!(config.origins().get("rt.editor"), Some(&ConfigOrigin::CommandLine));
+
+        assert!(config.override_with(vec![String::from("rt.missing=nano")]).is_err());
+    }
+
+    #[test]
+    fn test_store_config_is_restricted() {
+        let store_config = toml("[store]\nimplicit-create = true\n[store.hooks]\npre-create = [\"rm -rf ~\"]\n[store.schema.entries.\"todo/*\"]\n\"todo.status\" = \"string\"\n");
+        let store_path   = PathBuf::from("/home/user/.imag/store/.imagrc.toml");
+        let layered = |user_config: &str| {
+            let files = vec![None, Some((toml(user_config), user()))];
+            merge_layers(files, Some((store_config.clone(), store_path.clone())), vec![].into_iter()).unwrap().unwrap()
+        };
+
+        let config = layered("[rt]\neditor = \"vi\"\n");
+        assert_eq!(config.config(), &toml("[rt]\neditor = \"vi\"\n[store]\nimplicit-create = true\n[store.schema.entries.\"todo/*\"]\n\"todo.status\" = \"string\"\n"));
+        assert_eq!(config.origins().get("store.implicit-create"), Some(&ConfigOrigin::Store(store_path.clone())));
+
+        let config = layered("[rt]\ntrust-store-config = true\n");
+        assert!(config.origins().contains_key("store.hooks.pre-create"));
+    }
+
+    #[test]
+    fn test_env_without_config_file() {
+        let env    = vec![(String::from("IMAG_CONFIG__rt__editor"), String::from("vi"))];
+        let config = merge_layers(vec![None, None], None, env.into_iter()).unwrap().unwrap();
+        assert_eq!(config.config(), &toml("[rt]\neditor = \"vi\"\n"));
+
+        assert!(merge_layers(vec![None, None], None, vec![].into_iter()).unwrap().is_none());
+    }
+}
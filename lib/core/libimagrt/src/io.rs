@@ -21,6 +21,14 @@
 
 use std::fmt::Debug;
 use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use failure::Error;
+use failure::Fallible as Result;
+use serde_json::Value as JsonValue;
+use toml::Value;
 
 /// Proxy object for output
 ///
@@ -56,7 +64,7 @@ impl Write for OutputProxy {
 }
 
 impl Debug for OutputProxy {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
         match *self {
             OutputProxy::Out(..) => write!(f, "OutputProxy(Stdout)"),
             OutputProxy::Err(..) => write!(f, "OutputProxy(Stderr)"),
@@ -101,7 +109,7 @@ impl<'a> Write for LockedOutputProxy<'a> {
 }
 
 impl<'a> Debug for LockedOutputProxy<'a> {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
         match *self {
             LockedOutputProxy::Out(..) => write!(f, "LockedOutputProxy(Stdout)"),
             LockedOutputProxy::Err(..) => write!(f, "LockedOutputProxy(Stderr)"),
@@ -110,3 +118,226 @@ impl<'a> Debug for LockedOutputProxy<'a> {
     }
 }
 
+/// The format the output of an imag command is printed in
+///
+/// Set with the `--output-format` flag. With `Json` and `JsonLines`, the commands print
+/// records instead of text to stdout, so that scripts do not have to parse the human readable
+/// output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text, the default
+    Text,
+
+    /// One JSON array containing all records
+    Json,
+
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl OutputFormat {
+    /// Whether records are printed instead of text
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Text
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text"  => Ok(OutputFormat::Text),
+            "json"  => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            other   => Err(format_err!("Unknown output format '{}', expected 'text', 'json' or 'jsonl'", other)),
+        }
+    }
+}
+
+/// Writer for the records of the structured output formats
+///
+/// All clones share one stream. With `OutputFormat::Json` the array is closed when the last clone
+/// is dropped, so the error of a failing command can still be added to it after the `Runtime` is
+/// gone.
+#[derive(Clone, Debug)]
+pub struct RecordWriter(Arc<Mutex<RecordStream>>);
+
+#[derive(Debug)]
+struct RecordStream {
+    format: OutputFormat,
+    count: usize,
+    out: Box<dyn RecordSink>,
+}
+
+pub(crate) trait RecordSink: Write + Debug + Send {}
+impl<W: Write + Debug + Send> RecordSink for W {}
+
+impl RecordWriter {
+    pub fn new(format: OutputFormat) -> Self {
+        RecordWriter::with_sink(format, Box::new(::std::io::stdout()))
+    }
+
+    fn with_sink(format: OutputFormat, out: Box<dyn RecordSink>) -> Self {
+        RecordWriter(Arc::new(Mutex::new(RecordStream { format, count: 0, out })))
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.lock().format
+    }
+
+    /// Print one record
+    ///
+    /// Does nothing with `OutputFormat::Text`.
+    pub fn emit(&self, record: &JsonValue) -> Result<()> {
+        self.lock().emit(record)
+    }
+
+    /// Print an error as record with the message of the error and all its causes
+    pub fn emit_error(&self, error: &Error) -> Result<()> {
+        let mut chain = error.iter_chain().map(|cause| JsonValue::String(cause.to_string()));
+        let message   = chain.next().unwrap_or(JsonValue::Null);
+
+        self.emit(&json!({
+            "error": message,
+            "causes": chain.collect::<Vec<_>>(),
+        }))
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, RecordStream> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl RecordStream {
+    fn emit(&mut self, record: &JsonValue) -> Result<()> {
+        let record = ::serde_json::to_string(record)?;
+
+        match self.format {
+            OutputFormat::Text      => return Ok(()),
+            OutputFormat::JsonLines => writeln!(self.out, "{}", record)?,
+            OutputFormat::Json      => {
+                let separator = if self.count == 0 { "[" } else { "," };
+                write!(self.out, "{}\n{}", separator, record)?;
+            },
+        }
+
+        self.count += 1;
+        self.out.flush().map_err(Error::from)
+    }
+}
+
+impl Drop for RecordStream {
+    fn drop(&mut self) {
+        if self.format == OutputFormat::Json {
+            let end = if self.count == 0 { "[]\n" } else { "\n]\n" };
+            let _   = self.out.write_all(end.as_bytes()).and_then(|_| self.out.flush());
+        }
+    }
+}
+
+/// Convert a TOML value, for example the header of an entry, to JSON
+///
+/// Datetimes are converted to their string representation.
+pub fn toml_to_json(value: &Value) -> JsonValue {
+    match *value {
+        Value::String(ref s)   => JsonValue::String(s.clone()),
+        Value::Integer(i)      => JsonValue::from(i),
+        Value::Float(f)        => JsonValue::from(f),
+        Value::Boolean(b)      => JsonValue::Bool(b),
+        Value::Datetime(ref d) => JsonValue::String(d.to_string()),
+        Value::Array(ref a)    => JsonValue::Array(a.iter().map(toml_to_json).collect()),
+        Value::Table(ref t)    => JsonValue::Object({
+            t.iter().map(|(k, v)| (k.clone(), toml_to_json(v))).collect()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Debug, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn write_records(format: OutputFormat, n: usize) -> String {
+        let buffer = Buffer::default();
+        {
+            let writer = RecordWriter::with_sink(format, Box::new(buffer.clone()));
+            let clone  = writer.clone();
+            for i in 0..n {
+                writer.emit(&json!({ "id": i })).unwrap();
+            }
+            drop(writer);
+            if n > 0 {
+                clone.emit_error(&format_err!("failed")).unwrap();
+            }
+        }
+        buffer.contents()
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!(OutputFormat::Text, "text".parse().unwrap());
+        assert_eq!(OutputFormat::Json, "json".parse().unwrap());
+        assert_eq!(OutputFormat::JsonLines, "jsonl".parse().unwrap());
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_json_array() {
+        let out = write_records(OutputFormat::Json, 2);
+        let v: JsonValue = ::serde_json::from_str(&out).unwrap();
+        assert_eq!(v, json!([{ "id": 0 }, { "id": 1 }, { "error": "failed", "causes": [] }]));
+
+        let v: JsonValue = ::serde_json::from_str(&write_records(OutputFormat::Json, 0)).unwrap();
+        assert_eq!(v, json!([]));
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let out   = write_records(OutputFormat::JsonLines, 2);
+        let lines = out
+            .lines()
+            .map(|line| ::serde_json::from_str(line).unwrap())
+            .collect::<Vec<JsonValue>>();
+        assert_eq!(lines, vec![json!({ "id": 0 }), json!({ "id": 1 }), json!({ "error": "failed", "causes": [] })]);
+        assert_eq!("", write_records(OutputFormat::Text, 2));
+    }
+
+    #[test]
+    fn test_toml_to_json() {
+        let header: Value = ::toml::from_str("[imag]\nversion = \"0.10.0\"\nlinks = [\"a\"]\n[a]\nn = 1").unwrap();
+        assert_eq!(toml_to_json(&header), json!({
+            "imag": { "version": "0.10.0", "links": ["a"] },
+            "a": { "n": 1 },
+        }));
+    }
+}
+
@@ -42,6 +42,7 @@ extern crate itertools;
 extern crate ansi_term;
 extern crate handlebars;
 extern crate serde;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate failure;
 #[macro_use] extern crate toml_query;
@@ -49,6 +50,10 @@ extern crate serde;
 extern crate clap;
 extern crate toml;
 extern crate atty;
+extern crate chrono;
+
+#[cfg(test)]
+extern crate tempdir;
 
 extern crate libimagstore;
 extern crate libimagutil;
@@ -58,9 +63,12 @@ extern crate libimaginteraction;
 pub mod application;
 pub mod configuration;
 pub mod logger;
+mod logfile;
 pub mod io;
 pub mod iter;
+pub mod pipe;
 pub mod runtime;
+pub mod schema;
 pub mod setup;
 pub mod spec;
 pub mod version;
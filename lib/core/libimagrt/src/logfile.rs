@@ -0,0 +1,366 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! Log files for the `ImagLogger`, with size- or date-based rotation
+
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::Local;
+
+/// When a log file is rotated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+
+    /// Rotate before the file grows larger than this many bytes
+    Size(u64),
+
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Rotation {
+    /// The name of the period `time` is in, for date-based rotation
+    fn period(self, time: &DateTime<Local>) -> Option<String> {
+        match self {
+            Rotation::Daily   => Some(time.format("%Y-%m-%d").to_string()),
+            Rotation::Weekly  => Some(time.format("%G-W%V").to_string()),
+            Rotation::Monthly => Some(time.format("%Y-%m").to_string()),
+            Rotation::Never | Rotation::Size(_) => None,
+        }
+    }
+}
+
+/// A log file which is appended to
+///
+/// With size-based rotation, the file is moved to `<file>.1` (and `<file>.1` to `<file>.2` and so
+/// on) when it is full. With date-based rotation, it is moved to `<file>.<period>`, e.g.
+/// `imag.log.2019-06-01`, when the first line of a new period is written.
+/// Only the newest `keep` rotated files are kept.
+///
+/// Several imag processes may write to the same file. Rotation happens while holding a lock file
+/// (`.<file>.lock`), and a process which finds that the file was rotated by another one reopens
+/// it instead of rotating it again.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    rotation: Rotation,
+    keep: usize,
+    period: Option<String>,
+}
+
+impl LogFile {
+
+    pub fn open(path: PathBuf, rotation: Rotation, keep: usize) -> io::Result<LogFile> {
+        let file   = open_append(&path)?;
+        let period = period_of(&file, rotation)?;
+
+        Ok(LogFile { path, file, rotation, keep, period })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_line_at(line, &Local::now())
+    }
+
+    fn write_line_at(&mut self, line: &str, now: &DateTime<Local>) -> io::Result<()> {
+        if self.is_replaced()? {
+            self.reopen()?;
+        }
+
+        // Other imag processes may append to the same file, so we cannot count the bytes we wrote
+        let size = self.file.metadata()?.len();
+
+        match self.rotation {
+            Rotation::Never => {},
+            Rotation::Size(max_size) => {
+                if size > 0 && size + line.len() as u64 + 1 > max_size {
+                    self.rotate(LogFile::rotate_numbered)?;
+                }
+            },
+            rotation => {
+                let period = rotation.period(now);
+                if period != self.period {
+                    if size > 0 {
+                        self.rotate(LogFile::rotate_dated)?;
+                    }
+                    self.period = period;
+                }
+            },
+        }
+
+        writeln!(self.file, "{}", line)
+    }
+
+    /// Rotate the file with `rotate` while holding the rotation lock
+    ///
+    /// If another process rotated the file while we waited for the lock, the file is only
+    /// reopened.
+    fn rotate<F>(&mut self, rotate: F) -> io::Result<()>
+        where F: FnOnce(&mut LogFile) -> io::Result<()>
+    {
+        let _lock = RotationLock::acquire(self.lock_path())?;
+
+        if self.is_replaced()? {
+            self.reopen()
+        } else {
+            rotate(self)
+        }
+    }
+
+    /// Whether `path` no longer is the file we have open, because another process rotated it
+    #[cfg(unix)]
+    fn is_replaced(&self) -> io::Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let open = self.file.metadata()?;
+        match fs::metadata(&self.path) {
+            Ok(current) => Ok(current.dev() != open.dev() || current.ino() != open.ino()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `path` no longer is the file we have open, because another process rotated it
+    ///
+    /// Without inodes we can only notice that the file was moved away.
+    #[cfg(not(unix))]
+    fn is_replaced(&self) -> io::Result<bool> {
+        Ok(!self.path.exists())
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file   = open_append(&self.path)?;
+        self.period = period_of(&self.file, self.rotation)?;
+        Ok(())
+    }
+
+    fn rotate_numbered(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                rename_if_exists(&self.rotated(&n.to_string()), &self.rotated(&(n + 1).to_string()))?;
+            }
+            fs::rename(&self.path, self.rotated("1"))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        Ok(())
+    }
+
+    fn rotate_dated(&mut self) -> io::Result<()> {
+        match self.period {
+            Some(ref period) if self.keep > 0 => fs::rename(&self.path, self.rotated(period))?,
+            _ => fs::remove_file(&self.path)?,
+        }
+
+        self.file = open_append(&self.path)?;
+        self.remove_old_dated()
+    }
+
+    /// Remove all but the newest `keep` files rotated by date
+    fn remove_old_dated(&self) -> io::Result<()> {
+        let prefix = match self.path.file_name().and_then(OsStr::to_str) {
+            Some(name) => format!("{}.", name),
+            None       => return Ok(()),
+        };
+        let dir = self
+            .path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+
+        // The lock file starts with a dot, so it never matches the prefix
+        let mut rotated = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(OsStr::to_str)
+                    .map(|name| name.starts_with(&prefix))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+
+        // The names end with the period, so sorting them sorts them by date
+        rotated.sort();
+        let old = rotated.len().saturating_sub(self.keep);
+        for path in rotated.into_iter().take(old) {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn rotated(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut name = OsString::from(".");
+        name.push(self.path.file_name().unwrap_or_default());
+        name.push(".lock");
+        self.path.with_file_name(name)
+    }
+}
+
+/// How long a lock file may exist before it is considered left behind by a crashed process
+const STALE_LOCK: Duration = Duration::from_secs(10);
+
+/// A lock file held while a log file is rotated, removed when dropped
+struct RotationLock(PathBuf);
+
+impl RotationLock {
+    fn acquire(path: PathBuf) -> io::Result<RotationLock> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(RotationLock(path)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+                Err(e) => return Err(e),
+            }
+
+            let stale = match fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(modified) => SystemTime::now()
+                    .duration_since(modified)
+                    .map(|age| age > STALE_LOCK)
+                    .unwrap_or(false),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => return Err(e),
+            };
+
+            if stale {
+                let _ = fs::remove_file(&path);
+            } else {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+impl Drop for RotationLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The period the last line in `file` was written in, for date-based rotation
+fn period_of(file: &File, rotation: Rotation) -> io::Result<Option<String>> {
+    Ok(file
+        .metadata()?
+        .modified()
+        .ok()
+        .map(DateTime::<Local>::from)
+        .and_then(|time| rotation.period(&time)))
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+    use tempdir::TempDir;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let dir  = TempDir::new("imag-logfile").unwrap();
+        let path = dir.path().join("imag.log");
+        let mut log = LogFile::open(path.clone(), Rotation::Size(10), 2).unwrap();
+
+        for line in &["aaaa", "bbbb", "cccc", "dddd", "eeee"] {
+            log.write_line(line).unwrap();
+        }
+
+        assert_eq!(read(&path), "eeee\n");
+        assert_eq!(read(&dir.path().join("imag.log.1")), "cccc\ndddd\n");
+        assert_eq!(read(&dir.path().join("imag.log.2")), "aaaa\nbbbb\n");
+        assert!(!dir.path().join("imag.log.3").exists());
+    }
+
+    #[test]
+    fn test_rotation_by_other_process() {
+        let dir  = TempDir::new("imag-logfile").unwrap();
+        let path = dir.path().join("imag.log");
+        let mut first  = LogFile::open(path.clone(), Rotation::Size(10), 2).unwrap();
+        let mut second = LogFile::open(path.clone(), Rotation::Size(10), 2).unwrap();
+
+        first.write_line("aaaa").unwrap();
+        second.write_line("bbbb").unwrap();
+        first.write_line("cccc").unwrap();
+
+        // The file was rotated by `first`, so `second` reopens it instead of rotating it again
+        second.write_line("dddd").unwrap();
+
+        assert_eq!(read(&path), "cccc\ndddd\n");
+        assert_eq!(read(&dir.path().join("imag.log.1")), "aaaa\nbbbb\n");
+        assert!(!dir.path().join("imag.log.2").exists());
+        assert!(!dir.path().join(".imag.log.lock").exists());
+    }
+
+    #[test]
+    fn test_date_rotation() {
+        let dir  = TempDir::new("imag-logfile").unwrap();
+        let path = dir.path().join("imag.log");
+        let mut log = LogFile::open(path.clone(), Rotation::Daily, 2).unwrap();
+
+        let day = |d| Local.ymd(2019, 6, d).and_hms(12, 0, 0);
+        log.write_line_at("one", &day(1)).unwrap();
+        log.write_line_at("two", &day(1)).unwrap();
+        log.write_line_at("three", &day(2)).unwrap();
+        log.write_line_at("four", &day(3)).unwrap();
+        log.write_line_at("five", &day(4)).unwrap();
+
+        assert_eq!(read(&path), "five\n");
+        assert!(!dir.path().join("imag.log.2019-06-01").exists());
+        assert_eq!(read(&dir.path().join("imag.log.2019-06-02")), "three\n");
+        assert_eq!(read(&dir.path().join("imag.log.2019-06-03")), "four\n");
+    }
+}
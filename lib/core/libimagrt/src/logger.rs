@@ -20,10 +20,11 @@
 use std::io::Write;
 use std::io::stderr;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::ops::Deref;
 
+use chrono::Local;
 use failure::ResultExt;
 use failure::Fallible as Result;
 use failure::Error;
@@ -37,26 +38,62 @@ use handlebars::Handlebars;
 
 use libimagerror::errors::ErrorMsg as EM;
 
+use crate::logfile::LogFile;
+use crate::logfile::Rotation;
+
 type ModuleName = String;
 
+/// The format of the lines written to a log destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Rendered with the templates in `imag.logging.format`
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
 #[derive(Debug)]
-enum LogDestination {
+enum LogSink {
     Stderr,
-    File(Arc<Mutex<::std::fs::File>>),
+    File(Arc<Mutex<LogFile>>),
 }
 
-impl Default for LogDestination {
-    fn default() -> LogDestination {
-        LogDestination::Stderr
+#[derive(Debug)]
+struct LogDestination {
+    sink: LogSink,
+    format: LogFormat,
+}
+
+impl LogDestination {
+    fn stderr() -> LogDestination {
+        LogDestination {
+            sink: LogSink::Stderr,
+            format: LogFormat::Text,
+        }
+    }
+
+    fn write_line(&self, line: &str) {
+        // If there's an error, we cannot do anything, can we?
+        match self.sink {
+            LogSink::Stderr => {
+                let _ = writeln!(stderr(), "{}", line);
+            },
+            LogSink::File(ref file) => {
+                let _ = file.lock().map(|mut file| file.write_line(line));
+            },
+        }
     }
 }
 
+/// The log files which are opened already, so a file which is configured as destination for
+/// several modules is only opened (and rotated) once
+type LogFiles = BTreeMap<PathBuf, Arc<Mutex<LogFile>>>;
+
 #[derive(Debug)]
 struct ModuleSettings {
     enabled:        bool,
     level:          Option<Level>,
-
-    #[allow(unused)]
     destinations:   Option<Vec<LogDestination>>,
 }
 
@@ -64,16 +101,10 @@ struct ModuleSettings {
 #[derive(Debug)]
 pub struct ImagLogger {
     global_loglevel     : Level,
-
-    #[allow(unused)]
     global_destinations : Vec<LogDestination>,
-    // global_format_trace : ,
-    // global_format_debug : ,
-    // global_format_info  : ,
-    // global_format_warn  : ,
-    // global_format_error : ,
     module_settings     : BTreeMap<ModuleName, ModuleSettings>,
 
+    program: String,
     handlebars: Handlebars,
 }
 
@@ -97,10 +128,23 @@ impl ImagLogger {
             aggregate::<Error>(&mut handlebars, config, "ERROR")?;
         }
 
+        let program = ::std::env::args()
+            .next()
+            .and_then(|arg0| {
+                PathBuf::from(arg0)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| String::from("imag"));
+
+        let mut files = LogFiles::new();
+
         Ok(ImagLogger {
             global_loglevel     : aggregate_global_loglevel(matches, config)?,
-            global_destinations : aggregate_global_destinations(config)?,
-            module_settings     : aggregate_module_settings(matches, config)?,
+            global_destinations : aggregate_global_destinations(config, &mut files)?,
+            module_settings     : aggregate_module_settings(matches, config, &mut files)?,
+            program,
             handlebars,
         })
     }
@@ -109,19 +153,31 @@ impl ImagLogger {
         self.global_loglevel
     }
 
-}
-
-impl Log for ImagLogger {
+    /// The most verbose level any module logs with
+    pub fn max_loglevel(&self) -> Level {
+        self.module_settings
+            .values()
+            .filter(|setting| setting.enabled)
+            .filter_map(|setting| setting.level)
+            .fold(self.global_loglevel, ::std::cmp::max)
+    }
 
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.global_loglevel
+    fn module_setting(&self, target: &str) -> Option<&ModuleSettings> {
+        // hack to get the right target configuration.
+        // If there is no element here, we use the empty string which automatically drops through
+        // to the global settings
+        let module = target.split("::").next().unwrap_or("");
+        self.module_settings.get(module)
     }
 
-    fn flush(&self) {
-        // nothing?
+    fn is_enabled(&self, setting: Option<&ModuleSettings>, level: Level) -> bool {
+        match setting {
+            Some(setting) => setting.enabled && setting.level.unwrap_or(self.global_loglevel) >= level,
+            None          => self.global_loglevel >= level,
+        }
     }
 
-    fn log(&self, record: &Record) {
+    fn render_text(&self, record: &Record, timestamp: &str) -> String {
         let mut data = BTreeMap::new();
 
         {
@@ -131,66 +187,66 @@ impl Log for ImagLogger {
             data.insert("line",         format!("{}", record.line().unwrap_or(0)));
             data.insert("target",       String::from(record.target()));
             data.insert("message",      format!("{}", record.args()));
+            data.insert("timestamp",    String::from(timestamp));
+            data.insert("program",      self.program.clone());
         }
 
-        let logtext = self
-            .handlebars
+        self.handlebars
             .render(&format!("{}", record.level()), &data)
-            .unwrap_or_else(|e| format!("Failed rendering logging data: {:?}\n", e));
+            .unwrap_or_else(|e| format!("Failed rendering logging data: {:?}\n", e))
+    }
 
-        let log_to_destination = |d: &LogDestination| match *d {
-            LogDestination::Stderr => {
-                let _ = writeln!(stderr(), "{}", logtext);
-            },
-            LogDestination::File(ref arc_mutex_logdest) => {
-                // if there is an error in the lock, we cannot do anything. So we ignore it here.
-                let _ = arc_mutex_logdest
-                    .deref()
-                    .lock()
-                    .map(|mut logdest| {
-                        writeln!(logdest, "{}", logtext)
-                    });
-            }
-        };
+    fn render_json(&self, record: &Record, timestamp: &str) -> String {
+        json!({
+            "timestamp":   timestamp,
+            "level":       record.level().to_string(),
+            "target":      record.target(),
+            "module_path": record.module_path(),
+            "file":        record.file(),
+            "line":        record.line(),
+            "message":     record.args().to_string(),
+            "program":     self.program,
+            "pid":         ::std::process::id(),
+        }).to_string()
+    }
 
-        // hack to get the right target configuration.
-        // If there is no element here, we use the empty string which automatically drops through
-        // to the unwrap_or_else() case
-        let record_target = record
-            .target()
-            .split("::")
-            .next()
-            .unwrap_or("");
+}
 
-        self.module_settings
-            .get(record_target)
-            .map(|module_setting| {
-                let set = module_setting.enabled &&
-                    module_setting.level.unwrap_or(self.global_loglevel) >= record.level();
-
-                if set {
-                    if let Some(destinations) = &module_setting.destinations {
-                        for d in destinations {
-                            // If there's an error, we cannot do anything, can we?
-                            log_to_destination(&d);
-                        }
-                    }
-
-                    for d in self.global_destinations.iter() {
-                        // If there's an error, we cannot do anything, can we?
-                        log_to_destination(&d);
-                    }
-                }
-            })
-        .unwrap_or_else(|| {
-            if self.global_loglevel >= record.level() {
-                // Yes, we log
-                for d in self.global_destinations.iter() {
-                    // If there's an error, we cannot do anything, can we?
-                    log_to_destination(&d);
-                }
-            }
-        });
+impl Log for ImagLogger {
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.is_enabled(self.module_setting(metadata.target()), metadata.level())
+    }
+
+    fn flush(&self) {
+        // nothing?
+    }
+
+    fn log(&self, record: &Record) {
+        let setting = self.module_setting(record.target());
+        if !self.is_enabled(setting, record.level()) {
+            return
+        }
+
+        let timestamp = Local::now().to_rfc3339();
+        let mut text  = None;
+        let mut json  = None;
+
+        // The destinations of a module are used in addition to the global ones
+        let destinations = setting
+            .and_then(|setting| setting.destinations.as_ref())
+            .into_iter()
+            .flatten()
+            .chain(self.global_destinations.iter());
+
+        for destination in destinations {
+            let line = match destination.format {
+                LogFormat::Text => text.get_or_insert_with(|| self.render_text(record, &timestamp)),
+                LogFormat::Json => json.get_or_insert_with(|| self.render_json(record, &timestamp)),
+            };
+
+            destination.write_line(line);
+        }
     }
 }
 
@@ -243,44 +299,87 @@ fn aggregate_global_loglevel(matches: &ArgMatches, config: Option<&Value>) -> Re
     }
 }
 
-fn translate_destination(raw: &str) -> Result<LogDestination> {
-    use std::fs::OpenOptions;
-
-    match raw {
-        "-" => Ok(LogDestination::Stderr),
-        other => {
-            OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(other)
-                .map(Mutex::new)
-                .map(Arc::new)
-                .map(LogDestination::File)
+/// Translate a log destination from the configuration
+///
+/// A destination is either a path (`-` for stderr) or a table with the keys
+///
+/// * `path`: the path of the file (`-` for stderr)
+/// * `format`: `text` (default) for the templates in `imag.logging.format`, `json` for JSON lines
+/// * `rotate`: `size`, `daily`, `weekly` or `monthly`, files are not rotated if missing
+/// * `max_size`: the size in bytes a file is rotated at with `rotate = "size"`, 10 MiB by default
+/// * `keep`: the number of rotated files to keep, 5 by default
+fn translate_destination(raw: &Value, files: &mut LogFiles) -> Result<LogDestination> {
+    const KEYS: &[&str] = &["path", "format", "rotate", "max_size", "keep"];
+
+    let (path, format, rotation, keep) = match *raw {
+        Value::String(ref path) => (path.clone(), LogFormat::Text, Rotation::Never, 0),
+        Value::Table(ref table) => {
+            if let Some(key) = table.keys().find(|key| !KEYS.contains(&key.as_str())) {
+                return Err(format_err!("Unknown key '{}' in log destination", key))
+            }
+
+            let path = raw
+                .read_string("path")?
+                .ok_or_else(|| err_msg("Log destination without 'path'"))?;
+
+            let format = match raw.read_string("format")?.as_deref() {
+                None | Some("text") => LogFormat::Text,
+                Some("json")        => LogFormat::Json,
+                Some(other)         => return Err(format_err!("Invalid log format '{}', expected 'text' or 'json'", other)),
+            };
+
+            let max_size = raw.read_int("max_size")?.unwrap_or(10 * 1024 * 1024);
+            let rotation = match raw.read_string("rotate")?.as_deref() {
+                None            => Rotation::Never,
+                Some("size")    => Rotation::Size(max_size as u64),
+                Some("daily")   => Rotation::Daily,
+                Some("weekly")  => Rotation::Weekly,
+                Some("monthly") => Rotation::Monthly,
+                Some(other)     => return Err(format_err!("Invalid log rotation '{}', expected 'size', 'daily', 'weekly' or 'monthly'", other)),
+            };
+
+            let keep = raw.read_int("keep")?.unwrap_or(5);
+            if keep < 0 || max_size <= 0 {
+                return Err(err_msg("'keep' and 'max_size' of a log destination must not be negative"))
+            }
+
+            (path, format, rotation, keep as usize)
+        },
+        _ => return Err(err_msg("Type error at log destination, expected String or Table")),
+    };
+
+    if path == "-" {
+        return Ok(LogDestination { sink: LogSink::Stderr, format })
+    }
+
+    let path = PathBuf::from(path);
+    let file = match files.get(&path) {
+        Some(file) => file.clone(),
+        None       => {
+            let file = LogFile::open(path.clone(), rotation, keep)
                 .map_err(Error::from)
                 .context(EM::IO)
-                .map_err(Error::from)
-        }
-    }
+                .context(format_err!("Cannot open log file {}", path.display()))?;
+            let file = Arc::new(Mutex::new(file));
+            files.insert(path, file.clone());
+            file
+        },
+    };
+
+    Ok(LogDestination { sink: LogSink::File(file), format })
 }
 
-
-fn translate_destinations(raw: &[Value]) -> Result<Vec<LogDestination>> {
+fn translate_destinations(raw: &[Value], files: &mut LogFiles) -> Result<Vec<LogDestination>> {
     raw.iter()
-        .map(|val| {
-            val.as_str()
-                .ok_or_else(|| "Type error at 'imag.logging.modules.<mod>.destinations', expected Array<String>")
-                .map_err(err_msg)
-                .map_err(Error::from)
-                .and_then(|s| translate_destination(s))
-        })
+        .map(|val| translate_destination(val, files))
         .collect()
 }
 
-fn aggregate_global_destinations(config: Option<&Value>)
+fn aggregate_global_destinations(config: Option<&Value>, files: &mut LogFiles)
     -> Result<Vec<LogDestination>>
 {
     match config {
-        None      => Ok(vec![LogDestination::default()]),
+        None      => Ok(vec![LogDestination::stderr()]),
         Some(cfg) => cfg
             .read("imag.logging.destinations")
             .map_err(Error::from)
@@ -291,7 +390,9 @@ fn aggregate_global_destinations(config: Option<&Value>)
                 let msg = "Type error at 'imag.logging.destinations', expected 'Array'";
                 err_msg(msg)
             })
-            .and_then(|val| translate_destinations(val)),
+            .and_then(|val| translate_destinations(val, files))
+            .context(err_msg("Invalid log destination in 'imag.logging.destinations'"))
+            .map_err(Error::from),
     }
 }
 
@@ -346,16 +447,14 @@ mod log_lvl_aggregate {
 
 }
 
-fn aggregate_module_settings(_matches: &ArgMatches, config: Option<&Value>)
+fn aggregate_module_settings(_matches: &ArgMatches, config: Option<&Value>, files: &mut LogFiles)
     -> Result<BTreeMap<ModuleName, ModuleSettings>>
 {
-    use std::convert::TryInto;
-
     //
     // We define helper types here for deserializing easily using typed toml-query functionality.
     //
     // We need the helper types because we cannot deserialize in the target types directly, because
-    // of the `File(Arc<Mutex<::std::fs::File>>)` variant in `LogDestination`, which would
+    // of the `File(Arc<Mutex<LogFile>>)` variant in `LogSink`, which would
     // technically possible to deserialize the toml into the type, but it might be a bad idea.
     //
     // This code is idomatic enough for the conversions, so it is not a big painpoint.
@@ -363,7 +462,7 @@ fn aggregate_module_settings(_matches: &ArgMatches, config: Option<&Value>)
 
     #[derive(Serialize, Deserialize, Debug)]
     struct LoggingModuleConfig {
-        pub destinations: Option<Vec<String>>,
+        pub destinations: Option<Vec<Value>>,
         pub level: Option<Level>,
         pub enabled: bool,
     }
@@ -372,36 +471,28 @@ fn aggregate_module_settings(_matches: &ArgMatches, config: Option<&Value>)
     #[location = "imag.logging.modules"]
     struct LoggingModuleConfigMap(BTreeMap<String, LoggingModuleConfig>);
 
-    impl TryInto<BTreeMap<String, ModuleSettings>> for LoggingModuleConfigMap {
-        type Error = Error;
+    match config {
+        Some(cfg) => {
+            let modules = cfg.read_partial::<LoggingModuleConfigMap>()?
+                .ok_or_else(|| err_msg("Logging configuration missing"))?;
 
-        fn try_into(self) -> Result<BTreeMap<String, ModuleSettings>> {
             let mut map = BTreeMap::new();
+            for (key, value) in modules.0.into_iter() {
+                let destinations = match value.destinations {
+                    None     => None,
+                    Some(ds) => Some(translate_destinations(&ds, files) // This is why we do this whole thing
+                        .context(format_err!("Invalid log destination in 'imag.logging.modules.{}'", key))?),
+                };
 
-            for (key, value) in self.0.into_iter() {
                 map.insert(key, ModuleSettings {
                     enabled:      value.enabled,
                     level:        value.level.map(Into::into),
-                    destinations: match value.destinations {
-                        None     => None,
-                        Some(ds) => Some(ds
-                            .iter()
-                            .map(Deref::deref)
-                            .map(translate_destination) // This is why we do this whole thing
-                            .collect::<Result<Vec<LogDestination>>>()?)
-                    },
+                    destinations,
                 });
             }
 
             Ok(map)
-        }
-    }
-
-    match config {
-        Some(cfg) => cfg.read_partial::<LoggingModuleConfigMap>()?
-            .ok_or_else(|| err_msg("Logging configuration missing"))?
-            .try_into()
-            .map_err(Error::from),
+        },
         None => {
             write!(stderr(), "No Configuration.").ok();
             write!(stderr(), "cannot find module-settings for logging.").ok();
@@ -411,4 +502,3 @@ fn aggregate_module_settings(_matches: &ArgMatches, config: Option<&Value>)
         }
     }
 }
-
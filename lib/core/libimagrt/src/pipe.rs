@@ -0,0 +1,420 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! The protocol for passing the ids of entries between piped imag commands
+//!
+//! If stdout is a pipe, imag commands print the ids of the entries they touched, and if stdin is
+//! a pipe, they read the ids to work on from it. The ids are passed in one of these formats:
+//!
+//! * `lines`: one id per line. This is the default, but it cannot pass ids which contain a
+//!   newline.
+//! * `nul`: each id is terminated by a NUL byte, like the output of `find -print0`.
+//! * `jsonl`: a header line `{"imag-pipe":1}` with the version of the protocol, followed by one
+//!   JSON object per entry, for example `{"id":"notes/a","action":"created","meta":{}}`.
+//!
+//! Only the `jsonl` format carries the action and metadata of a record and reports deleted
+//! entries; the other formats only contain the ids of the entries which still exist.
+//!
+//! The format of the input is detected automatically. The output format is set with
+//! `--pipe-format` or the `IMAG_PIPE_FORMAT` environment variable, and otherwise follows the
+//! format of the input, so it is only chosen once at the start of a pipeline.
+
+use std::fmt::Debug;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use failure::Error;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use serde_json::Map;
+use serde_json::Value as JsonValue;
+
+use libimagstore::storeid::StoreId;
+
+use crate::io::RecordSink;
+
+/// The version of the `jsonl` pipe format this library reads and writes
+pub const PIPE_PROTOCOL_VERSION: u64 = 1;
+
+/// The environment variable the output format can be set with
+pub const PIPE_FORMAT_ENV: &str = "IMAG_PIPE_FORMAT";
+
+/// The key of the header line of the `jsonl` pipe format
+const HEADER_KEY: &str = "imag-pipe";
+
+/// The format of the ids passed between imag commands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipeFormat {
+    /// One id per line
+    Lines,
+
+    /// Ids terminated by NUL bytes
+    Nul,
+
+    /// A versioned header line, followed by one JSON record per line
+    JsonLines,
+}
+
+impl PipeFormat {
+    /// Detect the format of piped input
+    ///
+    /// Input starting with the `jsonl` header is `JsonLines`, input containing NUL bytes is
+    /// `Nul`, everything else is `Lines`.
+    pub fn detect(input: &[u8]) -> PipeFormat {
+        let first_line = input.split(|b| *b == b'\n').next().unwrap_or(&[]);
+
+        match ::serde_json::from_slice::<JsonValue>(first_line) {
+            Ok(JsonValue::Object(ref o)) if o.contains_key(HEADER_KEY) => PipeFormat::JsonLines,
+            _ if input.contains(&b'\0') => PipeFormat::Nul,
+            _ => PipeFormat::Lines,
+        }
+    }
+}
+
+impl Default for PipeFormat {
+    fn default() -> Self {
+        PipeFormat::Lines
+    }
+}
+
+impl FromStr for PipeFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lines" => Ok(PipeFormat::Lines),
+            "nul"   => Ok(PipeFormat::Nul),
+            "jsonl" => Ok(PipeFormat::JsonLines),
+            other   => Err(format_err!("Unknown pipe format '{}', expected 'lines', 'nul' or 'jsonl'", other)),
+        }
+    }
+}
+
+/// What a command did to an entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipeAction {
+    /// The entry was read or otherwise processed without being changed
+    Touched,
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Default for PipeAction {
+    fn default() -> Self {
+        PipeAction::Touched
+    }
+}
+
+/// One entry passed between imag commands
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipeRecord {
+    id: StoreId,
+    action: PipeAction,
+    meta: Map<String, JsonValue>,
+}
+
+/// The representation of a `PipeRecord` in the `jsonl` format
+#[derive(Serialize, Deserialize)]
+struct RawRecord {
+    id: String,
+
+    #[serde(default)]
+    action: PipeAction,
+
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    meta: Map<String, JsonValue>,
+}
+
+impl PipeRecord {
+    pub fn new(id: StoreId, action: PipeAction) -> Self {
+        PipeRecord { id, action, meta: Map::new() }
+    }
+
+    /// Add a metadata value to the record, for example the tags a command added to the entry
+    pub fn with_meta<K: Into<String>>(mut self, key: K, value: JsonValue) -> Self {
+        self.meta.insert(key.into(), value);
+        self
+    }
+
+    pub fn id(&self) -> &StoreId {
+        &self.id
+    }
+
+    pub fn into_id(self) -> StoreId {
+        self.id
+    }
+
+    pub fn action(&self) -> PipeAction {
+        self.action
+    }
+
+    pub fn meta(&self) -> &Map<String, JsonValue> {
+        &self.meta
+    }
+
+    fn from_raw(raw: RawRecord) -> Result<Self> {
+        Ok(PipeRecord {
+            id: StoreId::new(PathBuf::from(raw.id))?,
+            action: raw.action,
+            meta: raw.meta,
+        })
+    }
+
+    fn to_raw(&self) -> RawRecord {
+        RawRecord {
+            id: self.id.local_display_string(),
+            action: self.action,
+            meta: self.meta.clone(),
+        }
+    }
+}
+
+/// Parse piped input, detecting its format
+pub fn parse(input: &[u8]) -> Result<(PipeFormat, Vec<PipeRecord>)> {
+    let format = PipeFormat::detect(input);
+    let input  = ::std::str::from_utf8(input).context("Piped ids are not valid UTF-8")?;
+
+    let records = match format {
+        PipeFormat::Lines     => parse_ids(input.lines())?,
+        PipeFormat::Nul       => parse_ids(input.split('\0'))?,
+        PipeFormat::JsonLines => parse_json_lines(input)?,
+    };
+
+    Ok((format, records))
+}
+
+fn parse_ids<'a, I: Iterator<Item = &'a str>>(ids: I) -> Result<Vec<PipeRecord>> {
+    ids.filter(|id| !id.is_empty())
+        .map(|id| StoreId::new(PathBuf::from(id)).map(|id| PipeRecord::new(id, PipeAction::Touched)))
+        .collect()
+}
+
+fn parse_json_lines(input: &str) -> Result<Vec<PipeRecord>> {
+    let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+
+    let header: JsonValue = ::serde_json::from_str(lines.next().unwrap_or_default())?;
+    let version = header
+        .get(HEADER_KEY)
+        .and_then(JsonValue::as_u64)
+        .ok_or_else(|| format_err!("Invalid pipe header, '{}' is not a version number", HEADER_KEY))?;
+
+    if version > PIPE_PROTOCOL_VERSION {
+        return Err(format_err!("Unsupported pipe protocol version {}, the highest supported version is {}",
+                               version, PIPE_PROTOCOL_VERSION))
+    }
+
+    lines
+        .enumerate()
+        .map(|(n, line)| {
+            ::serde_json::from_str(line)
+                .map_err(Error::from)
+                .and_then(PipeRecord::from_raw)
+                .with_context(|_| format!("Invalid pipe record {}", n + 1))
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+/// Writer for the ids of the touched entries
+///
+/// All clones share one stream, so the `jsonl` header is printed only once.
+#[derive(Clone, Debug)]
+pub struct PipeWriter(Arc<Mutex<PipeStream>>);
+
+#[derive(Debug)]
+struct PipeStream {
+    format: Option<PipeFormat>,
+    written: bool,
+    out: Box<dyn RecordSink>,
+}
+
+impl PipeWriter {
+    /// Create a writer to stdout
+    ///
+    /// Without a `format`, the format of the input is used, see `PipeWriter::inherit()`.
+    pub fn new(format: Option<PipeFormat>) -> Self {
+        PipeWriter::with_sink(format, Box::new(::std::io::stdout()))
+    }
+
+    fn with_sink(format: Option<PipeFormat>, out: Box<dyn RecordSink>) -> Self {
+        PipeWriter(Arc::new(Mutex::new(PipeStream { format, written: false, out })))
+    }
+
+    pub fn format(&self) -> PipeFormat {
+        self.lock().format.unwrap_or_default()
+    }
+
+    /// Use the format of the input for the output, unless a format was set or something was
+    /// written already
+    pub fn inherit(&self, format: PipeFormat) {
+        let mut stream = self.lock();
+        if stream.format.is_none() && !stream.written {
+            stream.format = Some(format);
+        }
+    }
+
+    pub fn write(&self, record: &PipeRecord) -> ::std::io::Result<()> {
+        self.lock().write(record)
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, PipeStream> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PipeStream {
+    fn write(&mut self, record: &PipeRecord) -> ::std::io::Result<()> {
+        let format = self.format.unwrap_or_default();
+
+        if record.action == PipeAction::Deleted && format != PipeFormat::JsonLines {
+            trace!("Not passing deleted entry {} in {:?} format", record.id, format);
+            return Ok(())
+        }
+
+        match format {
+            PipeFormat::Lines => {
+                let id = record.id.local_display_string();
+                if id.contains('\n') {
+                    let msg = format!("Cannot pass id {:?} in the 'lines' pipe format, use '--pipe-format nul' or '--pipe-format jsonl'", id);
+                    return Err(::std::io::Error::new(ErrorKind::InvalidData, msg))
+                }
+
+                writeln!(self.out, "{}", id)?;
+            },
+
+            PipeFormat::Nul => write!(self.out, "{}\0", record.id)?,
+
+            PipeFormat::JsonLines => {
+                if !self.written {
+                    writeln!(self.out, "{}", json!({ HEADER_KEY: PIPE_PROTOCOL_VERSION }))?;
+                }
+
+                let record = ::serde_json::to_string(&record.to_raw())?;
+                writeln!(self.out, "{}", record)?;
+            },
+        }
+
+        self.written = true;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Debug, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn id(s: &str) -> StoreId {
+        StoreId::new(PathBuf::from(s)).unwrap()
+    }
+
+    fn records() -> Vec<PipeRecord> {
+        vec![
+            PipeRecord::new(id("notes/a"), PipeAction::Created).with_meta("tags", json!(["x"])),
+            PipeRecord::new(id("notes/with\nnewline"), PipeAction::Updated),
+            PipeRecord::new(id("notes/b"), PipeAction::Deleted),
+        ]
+    }
+
+    fn write_records(format: Option<PipeFormat>, records: &[PipeRecord]) -> ::std::io::Result<Vec<u8>> {
+        let buffer = Buffer::default();
+        let writer = PipeWriter::with_sink(format, Box::new(buffer.clone()));
+        for record in records {
+            writer.write(record)?;
+        }
+        let out = buffer.0.lock().unwrap().clone();
+        Ok(out)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(PipeFormat::Lines, PipeFormat::detect(b"a\nb\n"));
+        assert_eq!(PipeFormat::Lines, PipeFormat::detect(b"{\"id\":\"a\"}\n"));
+        assert_eq!(PipeFormat::Lines, PipeFormat::detect(b""));
+        assert_eq!(PipeFormat::Nul, PipeFormat::detect(b"a\0b\nc\0"));
+        assert_eq!(PipeFormat::JsonLines, PipeFormat::detect(b"{\"imag-pipe\":1}\n"));
+    }
+
+    #[test]
+    fn test_roundtrip_json_lines() {
+        let out = write_records(Some(PipeFormat::JsonLines), &records()).unwrap();
+        assert!(out.starts_with(b"{\"imag-pipe\":1}\n"));
+
+        let (format, parsed) = parse(&out).unwrap();
+        assert_eq!(PipeFormat::JsonLines, format);
+        assert_eq!(records(), parsed);
+    }
+
+    #[test]
+    fn test_roundtrip_nul() {
+        let out = write_records(Some(PipeFormat::Nul), &records()).unwrap();
+        assert_eq!(&b"notes/a\0notes/with\nnewline\0"[..], &out[..]);
+
+        let (format, parsed) = parse(&out).unwrap();
+        assert_eq!(PipeFormat::Nul, format);
+        assert_eq!(vec![id("notes/a"), id("notes/with\nnewline")],
+                   parsed.into_iter().map(PipeRecord::into_id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_lines_rejects_newlines() {
+        assert!(write_records(None, &records()).is_err());
+        assert_eq!(&b"notes/a\n"[..], &write_records(None, &records()[..1]).unwrap()[..]);
+    }
+
+    #[test]
+    fn test_inherit() {
+        let writer = PipeWriter::with_sink(None, Box::new(Buffer::default()));
+        writer.inherit(PipeFormat::Nul);
+        assert_eq!(PipeFormat::Nul, writer.format());
+
+        let writer = PipeWriter::with_sink(Some(PipeFormat::JsonLines), Box::new(Buffer::default()));
+        writer.inherit(PipeFormat::Nul);
+        assert_eq!(PipeFormat::JsonLines, writer.format());
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        assert!(parse(b"{\"imag-pipe\":2}\n{\"id\":\"a\"}\n").is_err());
+        let (_, parsed) = parse(b"{\"imag-pipe\":1}\n{\"id\":\"a\"}\n").unwrap();
+        assert_eq!(vec![PipeRecord::new(id("a"), PipeAction::Touched)], parsed);
+    }
+}
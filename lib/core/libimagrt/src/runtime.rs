@@ -17,12 +17,12 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::env;
 use std::process::exit;
 use std::io::Stdin;
-use std::io::StdoutLock;
 use std::borrow::Borrow;
 use std::result::Result as RResult;
 
@@ -30,6 +30,7 @@ pub use clap::App;
 use clap::AppSettings;
 use toml::Value;
 use toml_query::read::TomlValueReadExt;
+use serde_json::Value as JsonValue;
 
 use clap::{Arg, ArgMatches};
 use failure::ResultExt;
@@ -37,14 +38,22 @@ use failure::Fallible as Result;
 use failure::Error;
 use failure::err_msg;
 
-use crate::configuration::{fetch_config, override_config, InternalConfiguration};
+use crate::configuration::{fetch_layered_config, ConfigOrigin, ConfigOrigins, InternalConfiguration};
 use crate::logger::ImagLogger;
 use crate::io::OutputProxy;
+use crate::io::OutputFormat;
+use crate::io::RecordWriter;
+use crate::pipe::PipeAction;
+use crate::pipe::PipeFormat;
+use crate::pipe::PipeRecord;
+use crate::pipe::PipeWriter;
+use crate::pipe::PIPE_FORMAT_ENV;
 
 use libimagerror::exit::ExitCode;
 use libimagerror::errors::ErrorMsg as EM;
 use libimagerror::trace::*;
 use libimagerror::io::ToExitCode;
+use libimagstore::rpc::Client;
 use libimagstore::store::Store;
 use libimagstore::storeid::StoreId;
 use libimagutil::debug_result::DebugResult;
@@ -58,13 +67,17 @@ use atty;
 pub struct Runtime<'a> {
     rtp: PathBuf,
     configuration: Option<Value>,
+    config_origins: ConfigOrigins,
     cli_matches: ArgMatches<'a>,
     store: Store,
 
     has_output_pipe: bool,
     has_input_pipe: bool,
 
-    ignore_ids: bool
+    ignore_ids: bool,
+
+    records: RecordWriter,
+    pipe: PipeWriter,
 }
 
 impl<'a> Runtime<'a> {
@@ -73,6 +86,9 @@ impl<'a> Runtime<'a> {
     /// in $HOME/.imag/config, $XDG_CONFIG_DIR/imag/config or from env("$IMAG_CONFIG")
     /// and builds the Runtime object with it.
     ///
+    /// The configuration is layered, see `configuration::fetch_layered_config()`, with the
+    /// `--override-config` arguments applied last.
+    ///
     /// The cli_app object should be initially build with the ::get_default_cli_builder() function.
     pub fn new<C>(cli_app: C) -> Result<Runtime<'a>>
         where C: Clone + CliSpec<'a> + InternalConfiguration
@@ -84,9 +100,11 @@ impl<'a> Runtime<'a> {
         let configpath = matches.value_of("config")
                                 .map_or_else(|| rtp.clone(), PathBuf::from);
 
+        let storepath = get_storepath_match(&matches, &rtp);
+
         debug!("Config path = {:?}", configpath);
 
-        let config = match fetch_config(&configpath)? {
+        let config = match fetch_layered_config(&configpath, &storepath)? {
             None => {
                 return Err(err_msg("No configuration file found"))
                     .context(err_msg("Maybe try to use 'imag-init' to initialize imag?"))
@@ -95,16 +113,17 @@ impl<'a> Runtime<'a> {
                     .map_err(Error::from);
             },
             Some(mut config) => {
-                if let Err(e) = override_config(&mut config, get_override_specs(&matches)) {
+                if let Err(e) = config.override_with(get_override_specs(&matches)) {
                     error!("Could not apply config overrides");
                     trace_error(&e);
                 }
 
-                Some(config)
+                config
             }
         };
 
-        Runtime::_new(cli_app, matches, config)
+        let (config, origins) = config.into_parts();
+        Runtime::_new(cli_app, matches, Some(config), origins)
     }
 
     /// Builds the Runtime object using the given `config`.
@@ -112,10 +131,11 @@ impl<'a> Runtime<'a> {
         where C: Clone + CliSpec<'a> + InternalConfiguration
     {
         let matches = cli_app.clone().matches();
-        Runtime::_new(cli_app, matches, config)
+        Runtime::_new(cli_app, matches, config, ConfigOrigins::new())
     }
 
-    fn _new<C>(cli_app: C, matches: ArgMatches<'a>, config: Option<Value>) -> Result<Runtime<'a>>
+    fn _new<C>(cli_app: C, matches: ArgMatches<'a>, mut config: Option<Value>, mut config_origins: ConfigOrigins)
+        -> Result<Runtime<'a>>
     where C: Clone + CliSpec<'a> + InternalConfiguration
     {
         if cli_app.enable_logging() {
@@ -124,20 +144,19 @@ impl<'a> Runtime<'a> {
 
         let rtp = get_rtp_match(&matches)?;
 
-        let storepath = matches.value_of("storepath")
-                                .map_or_else(|| {
-                                    let mut spath = rtp.clone();
-                                    spath.push("store");
-                                    spath
-                                }, PathBuf::from);
+        let storepath = get_storepath_match(&matches, &rtp);
 
         debug!("RTP path    = {:?}", rtp);
         debug!("Store path  = {:?}", storepath);
         debug!("CLI         = {:?}", matches);
         trace!("Config      = {:#?}", config);
 
+        apply_mount_specs(&mut config, &mut config_origins, &matches)?;
+
         let store_result = if cli_app.use_inmemory_fs() {
             Store::new_inmemory(storepath, &config)
+        } else if let Some(client) = server_client(&storepath, &config, &matches)? {
+            Store::new_remote(storepath, &config, client)
         } else {
             Store::new(storepath, &config)
         };
@@ -145,20 +164,32 @@ impl<'a> Runtime<'a> {
         let has_output_pipe = !atty::is(atty::Stream::Stdout);
         let has_input_pipe  = !atty::is(atty::Stream::Stdin);
         let ignore_ids      = matches.is_present("ignore-ids");
+        let output_format   = matches
+            .value_of("output-format")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        let pipe_format     = get_pipe_format(&matches)?;
 
         debug!("has output pipe = {}", has_output_pipe);
         debug!("has input pipe  = {}", has_input_pipe);
         debug!("ignore ids      = {}", ignore_ids);
+        debug!("output format   = {:?}", output_format);
+        debug!("pipe format     = {:?}", pipe_format);
 
         store_result.map(|store| Runtime {
             cli_matches: matches,
             configuration: config,
+            config_origins,
             rtp,
             store,
 
             has_output_pipe,
             has_input_pipe,
             ignore_ids,
+
+            records: RecordWriter::new(output_format),
+            pipe: PipeWriter::new(pipe_format),
         })
         .context(err_msg("Cannot instantiate runtime"))
         .map_err(Error::from)
@@ -173,6 +204,10 @@ impl<'a> Runtime<'a> {
     ///   * -c <file> | --config <file> for alternative configuration file
     ///   * -r <path> | --rtp <path> for alternative runtimepath
     ///   * --store <path> for alternative store path
+    ///   * --mount <prefix=path> for mounting another store read-only
+    ///   * --no-server for not accessing the store through imag-server
+    ///   * --output-format <text|json|jsonl> for machine-readable output
+    ///   * --pipe-format <lines|nul|jsonl> for the format of the ids passed to the next command
     /// Each has the appropriate help text included.
     ///
     /// The `appname` shall be "imag-<command>".
@@ -236,6 +271,21 @@ impl<'a> Runtime<'a> {
                 .validator(::libimagutil::cli_validators::is_directory)
                 .takes_value(true))
 
+            .arg(Arg::with_name("mount")
+                .long("mount")
+                .help("Mount another store read-only, its entries are seen with the prefix in front of their ids. Use 'prefix=path' pairs. Adds to the mounts in 'store.mounts' of the configuration.")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PREFIX=PATH")
+                .takes_value(true))
+
+            .arg(Arg::with_name("no-server")
+                .long("no-server")
+                .help("Do not access the store through imag-server, even if it is running. The server is not used with --mount either.")
+                .required(false)
+                .takes_value(false))
+
             .arg(Arg::with_name("editor")
                 .long("editor")
                 .help("Set editor")
@@ -249,6 +299,24 @@ impl<'a> Runtime<'a> {
                 .required(false)
                 .takes_value(false))
 
+            .arg(Arg::with_name("output-format")
+                .long("output-format")
+                .help("Print the output as text, as one JSON array ('json') or as one JSON object per line ('jsonl')")
+                .long_help("Print the output as text, as one JSON array ('json') or as one JSON object per line ('jsonl'). With 'json' and 'jsonl', stdout only contains the records of the command: the touched ids are not printed and other messages go to stderr. Errors are printed as records with an 'error' key.")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["text", "json", "jsonl"])
+                .value_name("FORMAT"))
+
+            .arg(Arg::with_name("pipe-format")
+                .long("pipe-format")
+                .help("Pass the ids of the touched entries to the next command one per line ('lines'), NUL-terminated ('nul') or as JSON records ('jsonl')")
+                .long_help("Pass the ids of the touched entries to the next command one per line ('lines'), NUL-terminated ('nul') or as JSON records with the action and metadata of each entry ('jsonl'). Ids with a newline can only be passed with 'nul' and 'jsonl'. Defaults to the IMAG_PIPE_FORMAT environment variable, or to the format of the ids piped in, or to 'lines'. The format of the input is always detected automatically.")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["lines", "nul", "jsonl"])
+                .value_name("FORMAT"))
+
     }
 
     /// Extract the Store object from the Runtime object, destroying the Runtime object
@@ -301,7 +369,7 @@ impl<'a> Runtime<'a> {
                 .map_err_trace()
                 .unwrap_or_else(|_| exit(1));
 
-            set_max_level(logger.global_loglevel().to_level_filter());
+            set_max_level(logger.max_loglevel().to_level_filter());
 
             // safe debug output for later, after the instance itself
             // is moved away
@@ -336,29 +404,54 @@ impl<'a> Runtime<'a> {
         &self.cli_matches
     }
 
+    /// Get the ids to work on, from stdin if it is a pipe or from the commandline otherwise
+    ///
+    /// Deleted entries reported by the previous command are skipped, see
+    /// `Runtime::pipe_records()`.
     pub fn ids<T: IdPathProvider>(&self) -> Result<Option<Vec<StoreId>>> {
+        match self.pipe_records()? {
+            Some(records) => Ok(Some({
+                records
+                    .into_iter()
+                    .filter(|record| {
+                        let deleted = record.action() == PipeAction::Deleted;
+                        if deleted {
+                            debug!("Skipping deleted entry {}", record.id());
+                        }
+                        !deleted
+                    })
+                    .map(PipeRecord::into_id)
+                    .collect()
+            })),
+            None => Ok(T::get_ids(self.cli())?),
+        }
+    }
+
+    /// Get the records piped to stdin, if stdin is a pipe
+    ///
+    /// The format of the input is detected, see the `pipe` module, and used for the ids printed
+    /// by this command unless `--pipe-format` is passed.
+    /// This consumes stdin, so it returns no records when called a second time.
+    pub fn pipe_records(&self) -> Result<Option<Vec<PipeRecord>>> {
         use std::io::Read;
 
-        if self.has_input_pipe {
-            trace!("Getting IDs from stdin...");
-            let stdin    = ::std::io::stdin();
-            let mut lock = stdin.lock();
-
-            let mut buf = String::new();
-            lock.read_to_string(&mut buf)
-                .context("Failed to read stdin to buffer")
-                .map_err(Error::from)
-                .and_then(|_| {
-                    trace!("Got IDs = {}", buf);
-                    buf.lines()
-                        .map(PathBuf::from)
-                        .map(|id| StoreId::new(id).map_err(Error::from))
-                        .collect()
-                })
-                .map(Some)
-        } else {
-            Ok(T::get_ids(self.cli())?)
+        if !self.has_input_pipe {
+            return Ok(None)
         }
+
+        trace!("Getting IDs from stdin...");
+        let mut buf = Vec::new();
+        ::std::io::stdin()
+            .lock()
+            .read_to_end(&mut buf)
+            .context("Failed to read stdin to buffer")?;
+
+        let (format, records) = crate::pipe::parse(&buf)
+            .context("Failed to parse the ids from stdin")?;
+
+        trace!("Got {:?} records = {:?}", format, records);
+        self.pipe.inherit(format);
+        Ok(Some(records))
     }
 
     /// Get the configuration object
@@ -366,6 +459,18 @@ impl<'a> Runtime<'a> {
         self.configuration.as_ref()
     }
 
+    /// Get the configuration layer each configuration value was read from
+    ///
+    /// Empty if the configuration was passed via `Runtime::with_configuration()`.
+    pub fn config_origins(&self) -> &ConfigOrigins {
+        &self.config_origins
+    }
+
+    /// Get the configuration layer the value at `path` was read from
+    pub fn config_origin(&self, path: &str) -> Option<&ConfigOrigin> {
+        self.config_origins.get(path)
+    }
+
     /// Get the store object
     pub fn store(&self) -> &Store {
         &self.store
@@ -423,8 +528,44 @@ impl<'a> Runtime<'a> {
         self.ignore_ids
     }
 
+    /// Get the format the output is printed in
+    pub fn output_format(&self) -> OutputFormat {
+        self.records.format()
+    }
+
+    /// Get the format the ids of the touched entries are passed to the next command in
+    pub fn pipe_format(&self) -> PipeFormat {
+        self.pipe.format()
+    }
+
+    /// Get the writer for the records of the structured output formats
+    ///
+    /// The writer outlives the `Runtime`, `simple_imag_application_binary!` uses it to print the
+    /// error of a failing command.
+    pub fn records(&self) -> RecordWriter {
+        self.records.clone()
+    }
+
+    /// Print one piece of output of the command
+    ///
+    /// With the text output format, `text` is called to write to `Runtime::stdout()`. Otherwise
+    /// the `record` is printed.
+    pub fn emit<F>(&self, record: JsonValue, text: F) -> Result<()>
+        where F: FnOnce(&mut OutputProxy) -> ::std::io::Result<()>
+    {
+        if self.output_format().is_structured() {
+            self.records.emit(&record)
+        } else {
+            text(&mut self.stdout()).context(EM::IO).map_err(Error::from)
+        }
+    }
+
+    /// Get the output proxy for the output of the command
+    ///
+    /// This is stderr if the ids of touched entries go to stdout, or if stdout is reserved for the
+    /// records of a structured output format.
     pub fn stdout(&self) -> OutputProxy {
-        if self.output_is_pipe() && !self.ignore_ids {
+        if self.output_format().is_structured() || (self.output_is_pipe() && !self.ignore_ids) {
             OutputProxy::Err(::std::io::stderr())
         } else {
             OutputProxy::Out(::std::io::stdout())
@@ -523,33 +664,42 @@ impl<'a> Runtime<'a> {
     }
 
     pub fn report_touched(&self, id: &StoreId) -> RResult<(), ExitCode> {
-        let out      = ::std::io::stdout();
-        let mut lock = out.lock();
-
-        self.report_touched_id(id, &mut lock)
+        self.report_action(id, PipeAction::Touched)
     }
 
     pub fn report_all_touched<ID, I>(&self, ids: I) -> RResult<(), ExitCode>
         where ID: Borrow<StoreId> + Sized,
               I: Iterator<Item = ID>
     {
-        let out      = ::std::io::stdout();
-        let mut lock = out.lock();
-
         for id in ids {
-            self.report_touched_id(id.borrow(), &mut lock)?;
+            self.report_touched(id.borrow())?;
         }
 
         Ok(())
     }
 
-    #[inline]
-    fn report_touched_id(&self, id: &StoreId, output: &mut StdoutLock) -> RResult<(), ExitCode> {
-        use std::io::Write;
+    /// Report what the command did to the entry with the id `id`
+    pub fn report_action(&self, id: &StoreId, action: PipeAction) -> RResult<(), ExitCode> {
+        self.report_record(&PipeRecord::new(id.clone(), action))
+    }
 
-        if self.output_is_pipe() && !self.ignore_ids {
-            trace!("Reporting: {} to {:?}", id, output);
-            writeln!(output, "{}", id).to_exit_code()
+    /// Pass a record to the next command, if stdout is a pipe
+    ///
+    /// The action and metadata of the record are only passed with the `jsonl` pipe format.
+    pub fn report_record(&self, record: &PipeRecord) -> RResult<(), ExitCode> {
+        if self.output_is_pipe() && !self.ignore_ids && !self.output_format().is_structured() {
+            use std::io::Write;
+
+            trace!("Reporting: {:?}", record);
+            self.pipe
+                .write(record)
+                .map_err(|e| {
+                    if e.kind() != ::std::io::ErrorKind::BrokenPipe {
+                        let _ = writeln!(self.stderr(), "{}", e);
+                    }
+                    e
+                })
+                .to_exit_code()
         } else {
             Ok(())
         }
@@ -618,6 +768,90 @@ pub fn get_rtp_match<'a>(matches: &ArgMatches<'a>) -> Result<PathBuf> {
         })
 }
 
+/// Get the store path from the commandline, which defaults to `<rtp>/store`
+pub fn get_storepath_match<'a>(matches: &ArgMatches<'a>, rtp: &PathBuf) -> PathBuf {
+    matches
+        .value_of("storepath")
+        .map_or_else(|| rtp.join("store"), PathBuf::from)
+}
+
+/// Get the format for passing ids to the next command from `--pipe-format` or `$IMAG_PIPE_FORMAT`
+///
+/// `None` if neither is set, so the format of the input is used.
+fn get_pipe_format(matches: &ArgMatches) -> Result<Option<PipeFormat>> {
+    if let Some(format) = matches.value_of("pipe-format") {
+        return format.parse().map(Some)
+    }
+
+    match env::var(PIPE_FORMAT_ENV) {
+        Ok(format) => format
+            .parse::<PipeFormat>()
+            .map(Some)
+            .with_context(|_| format!("Invalid value in ${}", PIPE_FORMAT_ENV))
+            .map_err(Error::from),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Connect to the `imag-server` of the store, if it is running and may be used
+fn server_client(storepath: &Path, config: &Option<Value>, matches: &ArgMatches) -> Result<Option<Client>> {
+    if matches.is_present("no-server") || matches.is_present("mount") {
+        return Ok(None)
+    }
+
+    let socket = libimagstore::rpc::socket_path(storepath, config)?;
+    if !socket.exists() {
+        return Ok(None)
+    }
+
+    match Client::connect(&socket) {
+        Ok(client) => {
+            debug!("Using imag-server at {}", socket.display());
+            Ok(Some(client))
+        },
+        Err(_) => {
+            debug!("Not using imag-server, cannot connect to {}", socket.display());
+            Ok(None)
+        },
+    }
+}
+
+/// Put the stores from the `--mount` arguments into `store.mounts` of the configuration
+fn apply_mount_specs(config: &mut Option<Value>, origins: &mut ConfigOrigins, matches: &ArgMatches) -> Result<()> {
+    let specs = match matches.values_of("mount") {
+        None        => return Ok(()),
+        Some(specs) => specs,
+    };
+
+    let mut mounts = ::toml::map::Map::new();
+    for spec in specs {
+        let mut parts = spec.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(prefix), Some(path)) if !prefix.is_empty() && !path.is_empty() => {
+                let _ = mounts.insert(String::from(prefix), Value::String(String::from(path)));
+                let _ = origins.insert(format!("store.mounts.{}", prefix), ConfigOrigin::CommandLine);
+            },
+            _ => return Err(format_err!("Invalid mount '{}', expected 'prefix=path'", spec)),
+        }
+    }
+
+    let config = config.get_or_insert_with(|| Value::Table(::toml::map::Map::new()));
+    let store  = config
+        .as_table_mut()
+        .ok_or_else(|| err_msg("Configuration is not a table"))?
+        .entry(String::from("store"))
+        .or_insert_with(|| Value::Table(::toml::map::Map::new()))
+        .as_table_mut()
+        .ok_or_else(|| err_msg("'store' in configuration is not a table"))?;
+
+    match store.entry(String::from("mounts")).or_insert_with(|| Value::Table(::toml::map::Map::new())) {
+        Value::Table(ref mut configured) => configured.extend(mounts),
+        _ => return Err(err_msg("'store.mounts' in configuration is not a table")),
+    }
+
+    Ok(())
+}
+
 fn get_override_specs(matches: &ArgMatches) -> Vec<String> {
     matches
         .values_of("config-override")
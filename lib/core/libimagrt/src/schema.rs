@@ -0,0 +1,371 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! Schema of the configuration
+//!
+//! Every library which reads settings from the configuration describes them in a `CONFIG_SCHEMA`
+//! constant, so tools like `imag-config` can check a configuration before a command fails on a
+//! missing or mistyped setting.
+
+use std::fmt::{Display, Formatter};
+
+use toml::Value;
+use failure::Fallible as Result;
+
+/// The type of a configuration value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Datetime,
+    Array,
+    Table,
+}
+
+impl ValueKind {
+    pub fn of(value: &Value) -> ValueKind {
+        match *value {
+            Value::String(_)   => ValueKind::String,
+            Value::Integer(_)  => ValueKind::Integer,
+            Value::Float(_)    => ValueKind::Float,
+            Value::Boolean(_)  => ValueKind::Boolean,
+            Value::Datetime(_) => ValueKind::Datetime,
+            Value::Array(_)    => ValueKind::Array,
+            Value::Table(_)    => ValueKind::Table,
+        }
+    }
+
+    /// Parse `s` as a value of this kind
+    ///
+    /// Arrays and tables have to be written in TOML syntax, e.g. `[1, 2]`.
+    pub fn parse(self, s: &str) -> Result<Value> {
+        let value = match self {
+            ValueKind::String   => Some(Value::String(String::from(s))),
+            ValueKind::Integer  => s.parse().ok().map(Value::Integer),
+            ValueKind::Float    => s.parse().ok().map(Value::Float),
+            ValueKind::Boolean  => s.parse().ok().map(Value::Boolean),
+            ValueKind::Datetime => s.parse().ok().map(Value::Datetime),
+            ValueKind::Array | ValueKind::Table => Some(crate::configuration::parse_value(s))
+                .filter(|v| ValueKind::of(v) == self),
+        };
+
+        value.ok_or_else(|| format_err!("'{}' is not a {}", s, self))
+    }
+}
+
+impl Display for ValueKind {
+    fn fmt(&self, f: &mut Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        let name = match *self {
+            ValueKind::String   => "string",
+            ValueKind::Integer  => "integer",
+            ValueKind::Float    => "float",
+            ValueKind::Boolean  => "boolean",
+            ValueKind::Datetime => "datetime",
+            ValueKind::Array    => "array",
+            ValueKind::Table    => "table",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Description of a configuration setting
+///
+/// A `*` in the path matches every key of a table, e.g. `diary.diaries.*.timed`.
+#[derive(Clone, Debug)]
+pub struct ConfigKey {
+    pub path: &'static str,
+    pub kind: ValueKind,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+impl ConfigKey {
+    /// Whether the setting at `path` is described by this key
+    pub fn matches(&self, path: &str) -> bool {
+        let pattern = self.path.split('.');
+        let path    = path.split('.');
+
+        pattern.clone().count() == path.clone().count() &&
+            pattern.zip(path).all(|(p, s)| p == "*" || p == s)
+    }
+
+    /// All values in `config` which are described by this key, with their path
+    fn find<'a>(&self, config: &'a Value) -> Vec<(String, &'a Value)> {
+        fn find<'a>(value: &'a Value, pattern: &[&str], path: String, found: &mut Vec<(String, &'a Value)>) {
+            let (head, tail) = match pattern.split_first() {
+                None            => return found.push((path, value)),
+                Some(split)     => split,
+            };
+
+            let table = match *value {
+                Value::Table(ref table) => table,
+                _                       => return,
+            };
+
+            let join = |key: &str| if path.is_empty() { String::from(key) } else { format!("{}.{}", path, key) };
+
+            if *head == "*" {
+                for (key, value) in table {
+                    find(value, tail, join(key), found)
+                }
+            } else if let Some(value) = table.get(*head) {
+                find(value, tail, join(head), found)
+            }
+        }
+
+        let pattern = self.path.split('.').collect::<Vec<_>>();
+        let mut found = vec![];
+        find(config, &pattern, String::new(), &mut found);
+        found
+    }
+}
+
+/// A problem with the configuration, as found by `ConfigSchema::validate()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    Missing { path: String, description: &'static str },
+    WrongType { path: String, expected: ValueKind, found: ValueKind },
+}
+
+impl Violation {
+    pub fn path(&self) -> &str {
+        match *self {
+            Violation::Missing { ref path, .. }   => path,
+            Violation::WrongType { ref path, .. } => path,
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        match *self {
+            Violation::Missing { ref path, description } =>
+                write!(f, "Missing setting '{}': {}", path, description),
+            Violation::WrongType { ref path, expected, found } =>
+                write!(f, "Setting '{}' should be a {}, but is a {}", path, expected, found),
+        }
+    }
+}
+
+/// The schema of the configuration, made of the `CONFIG_SCHEMA`s of the libraries
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSchema(Vec<&'static ConfigKey>);
+
+impl ConfigSchema {
+
+    pub fn new() -> Self {
+        ConfigSchema::default()
+    }
+
+    pub fn with_keys(mut self, keys: &'static [ConfigKey]) -> Self {
+        self.0.extend(keys.iter());
+        self
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &'static ConfigKey> + '_ {
+        self.0.iter().cloned()
+    }
+
+    /// Get the description of the setting at `path`
+    pub fn key(&self, path: &str) -> Option<&'static ConfigKey> {
+        self.keys().find(|key| key.matches(path))
+    }
+
+    /// Check whether `value` may be used as the setting at `path`
+    pub fn check(&self, path: &str, value: &Value) -> Option<Violation> {
+        self.key(path)
+            .filter(|key| key.kind != ValueKind::of(value))
+            .map(|key| Violation::WrongType {
+                path: String::from(path),
+                expected: key.kind,
+                found: ValueKind::of(value),
+            })
+    }
+
+    /// Check `config` for missing required settings and settings of the wrong type
+    pub fn validate(&self, config: &Value) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        for key in self.keys() {
+            let found = key.find(config);
+
+            if found.is_empty() && key.required && !key.path.contains('*') {
+                violations.push(Violation::Missing {
+                    path: String::from(key.path),
+                    description: key.description,
+                });
+            }
+
+            violations.extend(found.into_iter().filter_map(|(path, value)| self.check(&path, value)));
+        }
+
+        violations.dedup();
+        violations
+    }
+}
+
+/// The settings read by libimagrt
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "imag.logging.level",
+        kind: ValueKind::String,
+        required: true,
+        description: "The log level: 'trace', 'debug', 'info', 'warn' or 'error'",
+    },
+    ConfigKey {
+        path: "imag.logging.destinations",
+        kind: ValueKind::Array,
+        required: true,
+        description: "Where to log to: paths ('-' for stderr) or tables with path, format, rotate, max_size and keep",
+    },
+    ConfigKey {
+        path: "imag.logging.format.trace",
+        kind: ValueKind::String,
+        required: true,
+        description: "The template for trace log lines",
+    },
+    ConfigKey {
+        path: "imag.logging.format.debug",
+        kind: ValueKind::String,
+        required: true,
+        description: "The template for debug log lines",
+    },
+    ConfigKey {
+        path: "imag.logging.format.info",
+        kind: ValueKind::String,
+        required: true,
+        description: "The template for info log lines",
+    },
+    ConfigKey {
+        path: "imag.logging.format.warn",
+        kind: ValueKind::String,
+        required: true,
+        description: "The template for warn log lines",
+    },
+    ConfigKey {
+        path: "imag.logging.format.error",
+        kind: ValueKind::String,
+        required: true,
+        description: "The template for error log lines",
+    },
+    ConfigKey {
+        path: "imag.logging.modules.*.enabled",
+        kind: ValueKind::Boolean,
+        required: false,
+        description: "Whether the module logs at all",
+    },
+    ConfigKey {
+        path: "imag.logging.modules.*.level",
+        kind: ValueKind::String,
+        required: false,
+        description: "The log level of the module, overriding 'imag.logging.level'",
+    },
+    ConfigKey {
+        path: "imag.logging.modules.*.destinations",
+        kind: ValueKind::Array,
+        required: false,
+        description: "Where the module logs to, in addition to 'imag.logging.destinations'",
+    },
+    ConfigKey {
+        path: "rt.editor",
+        kind: ValueKind::String,
+        required: false,
+        description: "The editor to use if neither --editor nor $EDITOR is set",
+    },
+    ConfigKey {
+        path: "rt.progressbar_style",
+        kind: ValueKind::String,
+        required: false,
+        description: "The template for progress bars, see the indicatif documentation",
+    },
+    ConfigKey {
+        path: "rt.progressticker_chars",
+        kind: ValueKind::String,
+        required: false,
+        description: "The characters used for progress spinners",
+    },
+    ConfigKey {
+        path: "rt.trust-store-config",
+        kind: ValueKind::Boolean,
+        required: false,
+        description: "Whether the store-local configuration may change all settings, not only the safe ones",
+    },
+];
+
+/// The settings read by the imag tools which have no library of their own
+pub const TOOLS_CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "git.execute_in_store",
+        kind: ValueKind::Boolean,
+        required: true,
+        description: "Whether imag-git runs git in the store (true) or in the runtimepath (false)",
+    },
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCHEMA: &[ConfigKey] = &[
+        ConfigKey { path: "git.execute_in_store", kind: ValueKind::Boolean, required: true, description: "git" },
+        ConfigKey { path: "diary.diaries.*.timed", kind: ValueKind::String, required: false, description: "timed" },
+        ConfigKey { path: "rt.editor", kind: ValueKind::String, required: false, description: "editor" },
+    ];
+
+    fn toml(s: &str) -> Value {
+        ::toml::de::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_key_matches() {
+        let schema = ConfigSchema::new().with_keys(SCHEMA);
+        assert_eq!(schema.key("diary.diaries.work.timed").map(|k| k.path), Some("diary.diaries.*.timed"));
+        assert!(schema.key("diary.diaries.work").is_none());
+        assert!(schema.key("git.execute_in_store.foo").is_none());
+        assert!(schema.key("git.execute_in_store").is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = ConfigSchema::new().with_keys(SCHEMA);
+
+        let config = toml("[git]\nexecute_in_store = false\n[diary.diaries.work]\ntimed = \"hourly\"\n");
+        assert_eq!(schema.validate(&config), vec![]);
+
+        let config = toml("[rt]\neditor = 1\n[diary.diaries.work]\ntimed = true\n");
+        assert_eq!(schema.validate(&config), vec![
+            Violation::Missing { path: String::from("git.execute_in_store"), description: "git" },
+            Violation::WrongType { path: String::from("diary.diaries.work.timed"), expected: ValueKind::String, found: ValueKind::Boolean },
+            Violation::WrongType { path: String::from("rt.editor"), expected: ValueKind::String, found: ValueKind::Integer },
+        ]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ValueKind::Boolean.parse("true").unwrap(), Value::Boolean(true));
+        assert_eq!(ValueKind::Integer.parse("12").unwrap(), Value::Integer(12));
+        assert_eq!(ValueKind::String.parse("12").unwrap(), Value::String(String::from("12")));
+        assert_eq!(ValueKind::Array.parse("[1, 2]").unwrap(), toml("a = [1, 2]")["a"]);
+        assert!(ValueKind::Boolean.parse("yes").is_err());
+        assert!(ValueKind::Array.parse("1").is_err());
+    }
+}
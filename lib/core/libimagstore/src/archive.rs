@@ -0,0 +1,476 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Export and import of the store to and from a portable archive
+//!
+//! The archive is a JSON Lines file. The first line is the manifest:
+//!
+//! ```json
+//! {"manifest":{"format":1,"store-version":"0.10.0","created":"2019-10-17T12:00:00","entries":[{"id":"notes/foo","checksum":"..."}]}}
+//! ```
+//!
+//! Each of the following lines holds one entry, with its header (as TOML) and its content:
+//!
+//! ```json
+//! {"id":"notes/foo","header":"[imag]\nversion = \"0.10.0\"\n","content":"Hello"}
+//! ```
+//!
+//! The checksum of an entry is the BLAKE2s-256 hash of the entry as it is written to disk
+//! (`---\n<header>---\n<content>`). All checksums are verified before anything is imported.
+
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use blake2::Blake2s256;
+use blake2::Digest;
+use chrono::NaiveDateTime;
+use semver::Version;
+use serde_json::Value as JsonValue;
+use toml::Value;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+use failure::err_msg;
+
+use crate::encryption::to_hex;
+use crate::store::Entry;
+use crate::store::Store;
+use crate::storeid::StoreId;
+
+/// The version of the archive format written by `export()`
+pub const ARCHIVE_FORMAT : u64 = 1;
+
+const DATETIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S";
+
+/// The manifest of an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// The version of imag which wrote the archive
+    pub store_version: String,
+
+    pub created: NaiveDateTime,
+
+    /// The ids of the entries in the archive, with their checksums
+    pub entries: BTreeMap<StoreId, String>,
+}
+
+/// What to do when an entry from an archive already exists in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Keep the entry in the store, do not import the one from the archive
+    Skip,
+
+    /// Replace the entry in the store with the one from the archive
+    Overwrite,
+
+    /// Import the entry from the archive with a new id (`<id>-<n>`)
+    Rename,
+}
+
+impl FromStr for Collision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Collision> {
+        match s {
+            "skip"      => Ok(Collision::Skip),
+            "overwrite" => Ok(Collision::Overwrite),
+            "rename"    => Ok(Collision::Rename),
+            other       => Err(format_err!("Unknown collision handling: {}", other)),
+        }
+    }
+}
+
+/// What `import()` did
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Entries which did not exist in the store
+    pub imported: Vec<StoreId>,
+
+    /// Entries which existed in the store and were not imported
+    pub skipped: Vec<StoreId>,
+
+    /// Entries which existed in the store and were replaced
+    pub overwritten: Vec<StoreId>,
+
+    /// Entries which existed in the store and were imported with a new id (old id, new id)
+    pub renamed: Vec<(StoreId, StoreId)>,
+}
+
+/// One entry in the archive
+struct ArchivedEntry {
+    id: StoreId,
+    header: String,
+    content: String,
+}
+
+impl ArchivedEntry {
+    fn checksum(&self) -> String {
+        let mut hasher = Blake2s256::new();
+        hasher.update(b"---\n");
+        hasher.update(self.header.as_bytes());
+        hasher.update(b"---\n");
+        hasher.update(self.content.as_bytes());
+        to_hex(&hasher.finalize())
+    }
+
+    fn to_json(&self) -> Result<JsonValue> {
+        Ok(json!({
+            "id": self.id.to_str()?,
+            "header": self.header,
+            "content": self.content,
+        }))
+    }
+
+    fn from_json(json: &JsonValue) -> Result<ArchivedEntry> {
+        let field = |name: &str| {
+            json.get(name)
+                .and_then(JsonValue::as_str)
+                .map(String::from)
+                .ok_or_else(|| format_err!("Archived entry without '{}'", name))
+        };
+
+        let id = StoreId::new(PathBuf::from(field("id")?))?;
+        Ok(ArchivedEntry { id, header: field("header")?, content: field("content")? })
+    }
+
+    fn to_entry(&self, id: StoreId) -> Result<Entry> {
+        let header : Value = ::toml::de::from_str(&self.header)
+            .context(format_err!("Invalid header of archived entry {}", self.id))?;
+        let mut entry = Entry::new(id);
+        *entry.get_header_mut() = header;
+        entry.set_content(self.content.clone());
+        Ok(entry)
+    }
+}
+
+impl Manifest {
+    fn to_json(&self) -> Result<JsonValue> {
+        let entries = self.entries
+            .iter()
+            .map(|(id, checksum)| Ok(json!({ "id": id.to_str()?, "checksum": checksum })))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(json!({
+            "manifest": {
+                "format": ARCHIVE_FORMAT,
+                "store-version": self.store_version,
+                "created": self.created.format(DATETIME_FORMAT).to_string(),
+                "entries": entries,
+            }
+        }))
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Manifest> {
+        let manifest = json.get("manifest").ok_or_else(|| err_msg("Archive does not start with a manifest"))?;
+
+        match manifest.get("format").and_then(JsonValue::as_u64) {
+            Some(ARCHIVE_FORMAT) => {},
+            Some(v) => return Err(format_err!("Unsupported archive format: {}", v)),
+            None    => return Err(err_msg("Manifest without 'format'")),
+        }
+
+        let store_version = manifest.get("store-version")
+            .and_then(JsonValue::as_str)
+            .map(String::from)
+            .ok_or_else(|| err_msg("Manifest without 'store-version'"))?;
+
+        let created = manifest.get("created")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| err_msg("Manifest without 'created'"))
+            .and_then(|s| NaiveDateTime::parse_from_str(s, DATETIME_FORMAT).map_err(Error::from))?;
+
+        let entries = manifest.get("entries")
+            .and_then(JsonValue::as_array)
+            .ok_or_else(|| err_msg("Manifest without 'entries'"))?
+            .iter()
+            .map(|e| {
+                let id = e.get("id")
+                    .and_then(JsonValue::as_str)
+                    .ok_or_else(|| err_msg("Manifest entry without 'id'"))
+                    .and_then(|id| StoreId::new(PathBuf::from(id)))?;
+                let checksum = e.get("checksum")
+                    .and_then(JsonValue::as_str)
+                    .map(String::from)
+                    .ok_or_else(|| format_err!("Manifest entry without 'checksum': {}", id))?;
+                Ok((id, checksum))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        Ok(Manifest { store_version, created, entries })
+    }
+}
+
+/// Export the entries of `store` to `out`
+///
+/// If `collection` is given, only the entries in this collection are exported (see
+/// `Entries::in_collection()`).
+pub fn export<W: Write>(store: &Store, collection: Option<&str>, out: &mut W) -> Result<Manifest> {
+    let entries = match collection {
+        Some(c) => store.entries()?.in_collection(c)?,
+        None    => store.entries()?,
+    };
+
+    let mut archived = entries
+        .into_storeid_iter()
+        .map(|id| {
+            let id    = id?;
+            let entry = store.get_copy(id.clone())?;
+            let header = ::toml::ser::to_string_pretty(entry.get_header())
+                .context(format_err!("Failed to serialize header of {}", id))?;
+            Ok(ArchivedEntry { id, header, content: entry.get_content().clone() })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    archived.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let manifest = Manifest {
+        store_version: String::from(env!("CARGO_PKG_VERSION")),
+        created: ::chrono::Local::now().naive_local(),
+        entries: archived.iter().map(|e| (e.id.clone(), e.checksum())).collect(),
+    };
+    debug!("Exporting {} entries", manifest.entries.len());
+
+    writeln!(out, "{}", manifest.to_json()?)?;
+    for entry in archived.iter() {
+        writeln!(out, "{}", entry.to_json()?)?;
+    }
+    out.flush()?;
+
+    Ok(manifest)
+}
+
+/// Read and verify an archive
+fn read_archive<R: BufRead>(input: R) -> Result<(Manifest, Vec<ArchivedEntry>)> {
+    let mut lines = input.lines();
+
+    let manifest = lines.next()
+        .ok_or_else(|| err_msg("Archive is empty"))?
+        .map_err(Error::from)
+        .and_then(|line| ::serde_json::from_str(&line).map_err(Error::from))
+        .and_then(|json| Manifest::from_json(&json))
+        .context(err_msg("Failed to read manifest of archive"))?;
+
+    let archived = lines
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+        .map(|(n, line)| {
+            line.map_err(Error::from)
+                .and_then(|line| ::serde_json::from_str(&line).map_err(Error::from))
+                .and_then(|json| ArchivedEntry::from_json(&json))
+                .context(format_err!("Failed to read line {} of archive", n + 2))
+                .map_err(Error::from)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for entry in archived.iter() {
+        match manifest.entries.get(&entry.id) {
+            None => return Err(format_err!("Entry not listed in manifest: {}", entry.id)),
+            Some(checksum) if *checksum != entry.checksum() => {
+                return Err(format_err!("Checksum mismatch for entry: {}", entry.id))
+            },
+            Some(_) => {},
+        }
+    }
+
+    if archived.len() != manifest.entries.len() {
+        return Err(format_err!("Archive is incomplete: {} entries in manifest, {} in archive",
+                               manifest.entries.len(),
+                               archived.len()))
+    }
+
+    Ok((manifest, archived))
+}
+
+/// Import the entries from the archive `input` into `store`
+///
+/// The archive is verified completely before anything is imported. The import is done in one
+/// transaction, so if importing one entry fails, no entry is imported.
+pub fn import<R: BufRead>(store: &Store, input: R, on_collision: Collision) -> Result<ImportReport> {
+    let (manifest, archived) = read_archive(input)?;
+    debug!("Importing {} entries, written by imag {} at {}",
+           archived.len(), manifest.store_version, manifest.created);
+
+    let archive_version = Version::parse(&manifest.store_version)
+        .context(format_err!("Invalid store version in manifest: {}", manifest.store_version))?;
+    if archive_version > Version::parse(env!("CARGO_PKG_VERSION"))? {
+        warn!("Archive was written by a newer version of imag ({}), entries might not be usable",
+              manifest.store_version);
+    }
+
+    store.transaction(|store| {
+        let mut report = ImportReport::default();
+
+        for archived in archived.iter() {
+            let id = archived.id.clone();
+
+            if !store.exists(id.clone())? {
+                import_entry(store, archived, id.clone())?;
+                report.imported.push(id);
+                continue
+            }
+
+            match on_collision {
+                Collision::Skip => {
+                    debug!("Skipping existing entry: {}", id);
+                    report.skipped.push(id);
+                },
+
+                Collision::Overwrite => {
+                    debug!("Overwriting existing entry: {}", id);
+                    import_entry(store, archived, id.clone())?;
+                    report.overwritten.push(id);
+                },
+
+                Collision::Rename => {
+                    let new_id = free_id(store, &id)?;
+                    debug!("Importing existing entry {} as {}", id, new_id);
+                    import_entry(store, archived, new_id.clone())?;
+                    report.renamed.push((id, new_id));
+                },
+            }
+        }
+
+        Ok(report)
+    })
+}
+
+fn import_entry(store: &Store, archived: &ArchivedEntry, id: StoreId) -> Result<()> {
+    let imported  = archived.to_entry(id.clone())?;
+    let mut entry = store.retrieve(id)?;
+    *entry.get_header_mut() = imported.get_header().clone();
+    entry.set_content(imported.get_content().clone());
+    store.update(&mut entry)
+}
+
+/// Find an id `<id>-<n>` which does not exist in the store
+fn free_id(store: &Store, id: &StoreId) -> Result<StoreId> {
+    let base = id.local().to_str().ok_or_else(|| format_err!("Id is not valid UTF-8: {:?}", id))?;
+    for n in 1.. {
+        let candidate = StoreId::new(PathBuf::from(format!("{}-{}", base, n)))?;
+        if !store.exists(candidate.clone())? {
+            return Ok(candidate)
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml_query::insert::TomlValueInsertExt;
+
+    use super::*;
+    use crate::store::Store;
+
+    fn get_store() -> Store {
+        let config = ::toml::de::from_str("[store]\nimplicit-create = true\n").unwrap();
+        Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap()
+    }
+
+    fn add(store: &Store, id: &str, content: &str) {
+        let mut entry = store.retrieve(PathBuf::from(id)).unwrap();
+        entry.set_content(String::from(content));
+        let _ = entry.get_header_mut().insert("test.id", Value::String(String::from(id))).unwrap();
+        store.update(&mut entry).unwrap();
+    }
+
+    fn content(store: &Store, id: &str) -> String {
+        store.get_copy(PathBuf::from(id)).unwrap().get_content().clone()
+    }
+
+    fn exported(store: &Store, collection: Option<&str>) -> Vec<u8> {
+        let mut buf = vec![];
+        let _ = export(store, collection, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let store = get_store();
+        add(&store, "notes/a", "Content of a\nwith two lines");
+        add(&store, "notes/b", "");
+        add(&store, "todo/c", "Content of c");
+
+        let archive = exported(&store, None);
+        let other   = get_store();
+        let report  = import(&other, &archive[..], Collision::Skip).unwrap();
+        assert_eq!(report.imported.len(), 3);
+
+        assert_eq!(content(&other, "notes/a"), "Content of a\nwith two lines");
+        assert_eq!(content(&other, "todo/c"), "Content of c");
+        assert_eq!(other.get_copy(PathBuf::from("notes/a")).unwrap().get_header(),
+                   store.get_copy(PathBuf::from("notes/a")).unwrap().get_header());
+    }
+
+    #[test]
+    fn test_export_collection() {
+        let store = get_store();
+        add(&store, "notes/a", "a");
+        add(&store, "todo/c", "c");
+
+        let manifest = export(&store, Some("notes"), &mut vec![]).unwrap();
+        let ids = manifest.entries.keys().map(|id| id.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["notes/a"]);
+    }
+
+    #[test]
+    fn test_import_collisions() {
+        let store = get_store();
+        add(&store, "notes/a", "archived");
+        let archive = exported(&store, None);
+
+        let other = get_store();
+        add(&other, "notes/a", "existing");
+
+        let report = import(&other, &archive[..], Collision::Skip).unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(content(&other, "notes/a"), "existing");
+
+        let report = import(&other, &archive[..], Collision::Rename).unwrap();
+        assert_eq!(report.renamed[0].1.to_str().unwrap(), "notes/a-1");
+        assert_eq!(content(&other, "notes/a"), "existing");
+        assert_eq!(content(&other, "notes/a-1"), "archived");
+
+        let report = import(&other, &archive[..], Collision::Overwrite).unwrap();
+        assert_eq!(report.overwritten.len(), 1);
+        assert_eq!(content(&other, "notes/a"), "archived");
+    }
+
+    #[test]
+    fn test_import_verifies_checksums() {
+        let store = get_store();
+        add(&store, "notes/a", "archived");
+        add(&store, "notes/b", "archived");
+
+        let archive  = String::from_utf8(exported(&store, None)).unwrap();
+        let tampered = archive.replacen("\"content\":\"archived\"", "\"content\":\"tampered\"", 1);
+        assert_ne!(archive, tampered);
+
+        let other = get_store();
+        assert!(import(&other, tampered.as_bytes(), Collision::Skip).is_err());
+        assert!(!other.exists(StoreId::new(PathBuf::from("notes/a")).unwrap()).unwrap());
+        assert!(!other.exists(StoreId::new(PathBuf::from("notes/b")).unwrap()).unwrap());
+
+        let truncated = archive.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(import(&other, truncated.as_bytes(), Collision::Skip).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Binary attachments of entries
+//!
+//! The content of an entry is text, binary files like scanned receipts or PDFs are attached to
+//! entries instead. The data of an attachment is stored in `.attachments/` in the store, named
+//! after its BLAKE2s hash, so the same file attached to several entries is stored once. As the
+//! directory is hidden, `Store::entries()` does not yield attachments.
+//!
+//! The attachments of an entry are listed in the `imag.attachments` array of its header:
+//!
+//! ```toml
+//! [[imag.attachments]]
+//! name = "receipt.pdf"
+//! mime = "application/pdf"
+//! size = 48213
+//! hash = "6d0bb00954ceb7fbee436bb55a8397a9..."
+//! ```
+//!
+//! `Store::attach()` and `Store::detach()` add and remove attachments, `Store::attachment_data()`
+//! reads them. The data of an attachment is removed when no entry refers to it anymore, that is
+//! when the last entry with it is deleted (or purged from the trash, if the trash is enabled) or
+//! when it is detached from the last entry. Earlier revisions in the history of an entry do not
+//! keep attachments, reverting to a revision with a removed attachment is reported by
+//! `Store::fsck()`.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use blake2::Blake2s256;
+use blake2::Digest;
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use toml_query::insert::TomlValueInsertExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::encryption::to_hex;
+
+/// The reserved directory in the store where the data of attachments lives
+pub(crate) const ATTACHMENT_DIR : &str = ".attachments";
+
+/// The header path of the list of attachments
+pub(crate) const HEADER_PATH : &str = "imag.attachments";
+
+/// Data which no entry refers to is only removed if it was written longer ago than this, as
+/// another process might have attached it to an entry which it did not write yet
+pub(crate) const GRACE_PERIOD : Duration = Duration::from_secs(60 * 60);
+
+/// An attachment of an entry, as listed in its header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// The file name of the attachment, unique within the entry
+    pub name: String,
+
+    /// The mime type of the data, like `application/pdf`
+    pub mime: String,
+
+    /// The size of the data in bytes
+    pub size: u64,
+
+    /// The BLAKE2s hash of the data, hex encoded
+    pub hash: String,
+}
+
+impl Attachment {
+
+    /// Build the attachment for `data`
+    pub fn new(name: String, mime: String, data: &[u8]) -> Attachment {
+        Attachment { name, mime, size: data.len() as u64, hash: hash(data) }
+    }
+
+    fn from_value(value: &Value) -> Result<Attachment> {
+        let string = |key: &str| value
+            .get(key)
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| format_err!("Attachment without '{}' in header", key));
+
+        let size = value
+            .get("size")
+            .and_then(Value::as_integer)
+            .filter(|size| *size >= 0)
+            .ok_or_else(|| format_err!("Attachment without valid 'size' in header"))?;
+
+        let hash = string("hash")?;
+        if !is_hash(&hash) {
+            return Err(format_err!("Invalid attachment hash in header: {}", hash))
+        }
+
+        Ok(Attachment { name: string("name")?, mime: string("mime")?, size: size as u64, hash })
+    }
+
+    fn to_value(&self) -> Value {
+        let mut table = ::toml::map::Map::new();
+        let _ = table.insert(String::from("name"), Value::String(self.name.clone()));
+        let _ = table.insert(String::from("mime"), Value::String(self.mime.clone()));
+        let _ = table.insert(String::from("size"), Value::Integer(self.size as i64));
+        let _ = table.insert(String::from("hash"), Value::String(self.hash.clone()));
+        Value::Table(table)
+    }
+}
+
+/// The hash the data of an attachment is stored under
+pub(crate) fn hash(data: &[u8]) -> String {
+    let mut hasher = Blake2s256::new();
+    hasher.update(data);
+    to_hex(&hasher.finalize())
+}
+
+fn is_hash(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// The path of the data with the hash `hash` in the store at `store_base`
+///
+/// The data is spread over subdirectories named after the first two characters of the hash, so no
+/// directory gets too big.
+pub(crate) fn data_path(store_base: &Path, hash: &str) -> Result<PathBuf> {
+    if !is_hash(hash) {
+        return Err(format_err!("Invalid attachment hash: {}", hash))
+    }
+    Ok(store_base.join(ATTACHMENT_DIR).join(&hash[..2]).join(hash))
+}
+
+/// The hash of the data at `path`, if it is the path of attachment data in the store at
+/// `store_base`
+pub(crate) fn hash_of_path(store_base: &Path, path: &Path) -> Option<String> {
+    let rest = path.strip_prefix(store_base.join(ATTACHMENT_DIR)).ok()?;
+    rest.file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| is_hash(name))
+        .filter(|name| data_path(store_base, name).map(|p| p == path).unwrap_or(false))
+        .map(String::from)
+}
+
+/// Get the attachments listed in `header`
+pub(crate) fn read(header: &Value) -> Result<Vec<Attachment>> {
+    match header.read(HEADER_PATH).context(format_err!("Error reading '{}' in header", HEADER_PATH))? {
+        None                      => Ok(vec![]),
+        Some(Value::Array(array)) => array.iter().map(Attachment::from_value).collect(),
+        Some(_)                   => Err(format_err!("'{}' in header is not an array", HEADER_PATH)),
+    }
+}
+
+/// Get the hashes of the attachments listed in `header`, ignoring invalid ones
+pub(crate) fn hashes(header: &Value) -> Vec<String> {
+    match header.read(HEADER_PATH) {
+        Ok(Some(Value::Array(array))) => array
+            .iter()
+            .filter_map(|a| a.get("hash").and_then(Value::as_str))
+            .filter(|hash| is_hash(hash))
+            .map(String::from)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Set the attachments listed in `header`, removing the list if `attachments` is empty
+pub(crate) fn write(header: &mut Value, attachments: &[Attachment]) -> Result<()> {
+    if attachments.is_empty() {
+        if let Some(imag) = header.get_mut("imag").and_then(Value::as_table_mut) {
+            let _ = imag.remove("attachments");
+        }
+        return Ok(())
+    }
+
+    let array = attachments.iter().map(Attachment::to_value).collect();
+    header.insert(HEADER_PATH, Value::Array(array))
+        .context(format_err!("Error writing '{}' in header", HEADER_PATH))
+        .map(|_| ())
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::store::Entry;
+    use crate::storeid::StoreId;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let mut header  = Entry::new(StoreId::new(PathBuf::from("test")).unwrap()).get_header().clone();
+        let attachments = vec![
+            Attachment::new(String::from("a.pdf"), String::from("application/pdf"), b"%PDF"),
+            Attachment::new(String::from("b.png"), String::from("image/png"), &[0, 1, 2]),
+        ];
+
+        write(&mut header, &attachments).unwrap();
+        assert_eq!(read(&header).unwrap(), attachments);
+        assert_eq!(hashes(&header), vec![hash(b"%PDF"), hash(&[0, 1, 2])]);
+        assert_eq!(attachments[1].size, 3);
+
+        write(&mut header, &[]).unwrap();
+        assert!(read(&header).unwrap().is_empty());
+        assert!(header.get("imag").unwrap().get("version").is_some());
+    }
+
+    #[test]
+    fn test_data_path() {
+        let base = PathBuf::from("/store");
+        let hash = hash(b"data");
+        let path = data_path(&base, &hash).unwrap();
+
+        assert_eq!(path, base.join(ATTACHMENT_DIR).join(&hash[..2]).join(&hash));
+        assert_eq!(hash_of_path(&base, &path), Some(hash));
+        assert_eq!(hash_of_path(&base, &base.join("notes/a")), None);
+        assert!(data_path(&base, "../../etc/passwd").is_err());
+    }
+}
@@ -43,6 +43,22 @@ pub fn config_implicit_store_create_allowed(config: &Option<Value>) -> Result<bo
     }
 }
 
+/// Get the name of the backend from the "store.backend" key, if any
+pub fn config_store_backend(config: &Option<Value>) -> Result<Option<String>> {
+    use toml_query::read::TomlValueReadTypeExt;
+
+    let key = "store.backend";
+
+    match *config {
+        None => Ok(None),
+        Some(ref t) => t.read_string(key)
+            .context(format_err!("Error reading header '{}' in configuration", key))
+            .map_err(Error::from)
+            .context(EM::TomlQueryError)
+            .map_err(Error::from),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use toml::de::from_str as toml_from_str;
@@ -0,0 +1,140 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! The content of an entry, which might be loaded lazily
+//!
+//! Entries which are read with `Store::get_header_only()` only have their header parsed. The
+//! content is read from the backend when it is accessed for the first time.
+
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Error as FMTError;
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use failure::Fallible as Result;
+use failure::Error;
+use failure::ResultExt;
+
+use crate::store::EntryContent;
+
+/// A function which reads the content of an entry from the backend
+pub(crate) type ContentLoader = Arc<dyn Fn() -> Result<EntryContent> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct LazyContent {
+    /// The content, or the message of the error which happened while loading it
+    content: OnceCell<::std::result::Result<EntryContent, String>>,
+
+    /// Where to get the content from, `None` if it was passed on construction
+    loader: Option<ContentLoader>,
+}
+
+impl LazyContent {
+
+    pub(crate) fn loaded(content: EntryContent) -> LazyContent {
+        LazyContent { content: OnceCell::from(Ok(content)), loader: None }
+    }
+
+    pub(crate) fn deferred(loader: ContentLoader) -> LazyContent {
+        LazyContent { content: OnceCell::new(), loader: Some(loader) }
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.content.get().is_some()
+    }
+
+    fn load(&self) -> &::std::result::Result<EntryContent, String> {
+        self.content.get_or_init(|| match self.loader {
+            None             => Ok(EntryContent::new()),
+            Some(ref loader) => loader().map_err(|e| e.to_string()),
+        })
+    }
+
+    /// Get the content, loading it if necessary
+    pub(crate) fn try_get(&self) -> Result<&EntryContent> {
+        self.load().as_ref().map_err(|e| format_err!("Failed to load content: {}", e))
+    }
+
+    /// Take the content, loading it if necessary
+    pub(crate) fn into_content(self) -> Result<EntryContent> {
+        match self.content.into_inner() {
+            Some(Ok(content)) => Ok(content),
+            Some(Err(e))      => Err(format_err!("Failed to load content: {}", e)),
+            None              => match self.loader {
+                None         => Ok(EntryContent::new()),
+                Some(loader) => loader().context("Failed to load content").map_err(Error::from),
+            },
+        }
+    }
+}
+
+impl Debug for LazyContent {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FMTError> {
+        match self.content.get() {
+            Some(Ok(content)) => write!(fmt, "{:?}", content),
+            Some(Err(e))      => write!(fmt, "<failed to load: {}>", e),
+            None              => write!(fmt, "<not loaded>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    #[test]
+    fn test_loaded_once() {
+        let calls   = Arc::new(AtomicUsize::new(0));
+        let c       = calls.clone();
+        let content = LazyContent::deferred(Arc::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+            Ok(String::from("content"))
+        }));
+
+        assert!(!content.is_loaded());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(content.try_get().unwrap(), "content");
+        assert_eq!(content.try_get().unwrap(), "content");
+        assert!(content.is_loaded());
+        assert_eq!(content.into_content().unwrap(), "content");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_failed_load() {
+        let content = LazyContent::deferred(Arc::new(|| Err(format_err!("gone"))));
+        assert!(content.try_get().is_err());
+        assert!(content.is_loaded());
+    }
+
+    #[test]
+    fn test_failed_load_into_content() {
+        let content = LazyContent::deferred(Arc::new(|| Err(format_err!("gone"))));
+        assert!(content.clone().into_content().is_err());
+
+        let _ = content.try_get();
+        assert!(content.into_content().is_err());
+    }
+}
@@ -0,0 +1,424 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Encryption of store files
+//!
+//! With `store.backend = "encrypted"`, every file in the store is encrypted with
+//! XChaCha20-Poly1305. The key is derived with Argon2id from either `store.encryption.passphrase`
+//! or the content of the file at `store.encryption.keyfile`, and a random salt which is kept,
+//! together with a value for checking the key, in the `.encryption` file of the store.
+//!
+//! Only the content of the files is encrypted. The file names (and thus the ids of the entries)
+//! are left in the clear, so that the entries can be iterated without the key.
+//!
+//! `encrypt_store()` and `decrypt_store()` convert existing stores, including the trash, the
+//! history and the attachments.
+
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Error as FmtError;
+use std::fs::read;
+use std::fs::remove_dir_all;
+use std::fs::remove_file;
+use std::path::Path;
+use std::path::PathBuf;
+
+use argon2::Argon2;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::XNonce;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::AeadCore;
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use walkdir::WalkDir;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use crate::file_abstraction::fs::write_atomically;
+use crate::file_abstraction::fs::is_internal;
+use crate::file_abstraction::fs::is_staging_file;
+use crate::attachment::ATTACHMENT_DIR;
+use crate::history::HISTORY_DIR;
+use crate::index::INDEX_DIR;
+use crate::transaction::JOURNAL_FILE;
+use crate::trash::TRASH_DIR;
+
+/// Every encrypted file starts with these bytes
+const MAGIC : &[u8] = b"IMAGENC1";
+
+const NONCE_LEN : usize = 24;
+
+/// The file in the store which holds the salt and the check value
+pub(crate) const PARAMS_FILE : &str = ".encryption";
+
+/// The files and directories of the store, besides the entries, which the backend encrypts
+///
+/// The index is encrypted as well, but it is removed when converting the store.
+const ENCRYPTED_DATA : &[&str] = &[ATTACHMENT_DIR, TRASH_DIR, HISTORY_DIR, JOURNAL_FILE];
+
+/// The plaintext of the check value
+const CHECK_PLAINTEXT : &[u8] = b"imag";
+
+/// Whether `data` is encrypted
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+impl Debug for Cipher {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        write!(fmt, "Cipher")
+    }
+}
+
+impl Cipher {
+
+    /// Get the cipher for the store at `store_path`, with the secret from the configuration
+    ///
+    /// If the store has no `.encryption` file yet, a new salt is generated and written. Otherwise,
+    /// the key is checked against the check value from that file, so that a wrong passphrase is
+    /// detected before anything is written.
+    pub(crate) fn from_config(store_path: &Path, config: &Option<Value>) -> Result<Cipher> {
+        use crate::configuration::config_implicit_store_create_allowed;
+
+        let secret = config_secret(config)?;
+        let params = store_path.join(PARAMS_FILE);
+
+        if params.exists() {
+            let content = ::std::fs::read_to_string(&params)
+                .context(format_err!("Failed to read {}", params.display()))?;
+            let params  = content
+                .parse::<Value>()
+                .context(format_err!("Failed to parse {}", params.display()))?;
+
+            let read_hex = |key: &str| -> Result<Vec<u8>> {
+                params.read_string(key)?
+                    .ok_or_else(|| format_err!("Missing '{}' in {}", key, PARAMS_FILE))
+                    .and_then(|s| from_hex(&s))
+            };
+
+            let cipher = Cipher::derive(&secret, &read_hex("salt")?)?;
+            match cipher.decrypt(&read_hex("check")?) {
+                Ok(ref check) if check.as_slice() == CHECK_PLAINTEXT => Ok(cipher),
+                _ => Err(format_err!("Wrong passphrase or keyfile for encrypted store at {}", store_path.display())),
+            }
+        } else {
+            if !store_path.exists() {
+                if !config_implicit_store_create_allowed(config)? {
+                    return Err(format_err!("CreateStoreDirDenied"))
+                        .context(EM::FileError)
+                        .context(EM::IO)
+                        .map_err(Error::from)
+                }
+
+                ::std::fs::create_dir_all(store_path).context(EM::DirNotCreated)?;
+            }
+
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+
+            let cipher = Cipher::derive(&secret, &salt)?;
+            let check  = cipher.encrypt(CHECK_PLAINTEXT)?;
+
+            info!("Initializing encryption of store at {}", store_path.display());
+            let content = format!("version = 1\nsalt = \"{}\"\ncheck = \"{}\"\n", to_hex(&salt), to_hex(&check));
+            write_atomically(&params, content.as_bytes())?;
+            Ok(cipher)
+        }
+    }
+
+    fn derive(secret: &[u8], salt: &[u8]) -> Result<Cipher> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(secret, salt, &mut key)
+            .map_err(|e| format_err!("Failed to derive key: {}", e))?;
+
+        XChaCha20Poly1305::new_from_slice(&key)
+            .map(Cipher)
+            .map_err(|_| format_err!("Invalid key length"))
+    }
+
+    pub(crate) fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce      = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.0
+            .encrypt(&nonce, plain)
+            .map_err(|_| format_err!("Encryption failed"))?;
+
+        let mut buf = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+        Ok(buf)
+    }
+
+    /// Decrypt data which was encrypted with `Cipher::encrypt()`
+    ///
+    /// Fails if the data is not encrypted, was encrypted with another key or was altered.
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(data) {
+            return Err(format_err!("Data is not encrypted"))
+        }
+        if data.len() < MAGIC.len() + NONCE_LEN {
+            return Err(format_err!("Encrypted data is truncated"))
+        }
+
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        self.0
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| format_err!("Decryption failed, the data was altered or encrypted with another key"))
+    }
+}
+
+/// Get the secret from `store.encryption.passphrase` or `store.encryption.keyfile`
+fn config_secret(config: &Option<Value>) -> Result<Vec<u8>> {
+    let config = config
+        .as_ref()
+        .ok_or_else(|| format_err!("No configuration, cannot get the key for the encrypted store"))?;
+
+    let passphrase = config
+        .read_string("store.encryption.passphrase")
+        .context(format_err!("Error reading 'store.encryption.passphrase' in configuration"))?;
+    let keyfile = config
+        .read_string("store.encryption.keyfile")
+        .context(format_err!("Error reading 'store.encryption.keyfile' in configuration"))?;
+
+    match (passphrase, keyfile) {
+        (Some(pw), None)   => Ok(pw.into_bytes()),
+        (None, Some(path)) => read(&path)
+            .context(format_err!("Failed to read keyfile: {}", path))
+            .map_err(Error::from),
+        (Some(_), Some(_)) => Err(format_err!("Only one of 'store.encryption.passphrase' and 'store.encryption.keyfile' may be set")),
+        (None, None)       => Err(format_err!("Neither 'store.encryption.passphrase' nor 'store.encryption.keyfile' is set")),
+    }
+}
+
+/// Encrypt all files of the store at `store_path` which are not encrypted yet
+///
+/// The index of the store is removed, it is rebuilt on the next lookup. Returns the converted
+/// files.
+pub fn encrypt_store(store_path: &Path, config: &Option<Value>) -> Result<Vec<PathBuf>> {
+    let cipher = Cipher::from_config(store_path, config)?;
+    convert_store(store_path, |data| if is_encrypted(data) {
+        Ok(None)
+    } else {
+        cipher.encrypt(data).map(Some)
+    })
+}
+
+/// Decrypt all encrypted files of the store at `store_path`
+///
+/// The index of the store and the `.encryption` file are removed afterwards. Returns the
+/// converted files.
+pub fn decrypt_store(store_path: &Path, config: &Option<Value>) -> Result<Vec<PathBuf>> {
+    let cipher    = Cipher::from_config(store_path, config)?;
+    let converted = convert_store(store_path, |data| if is_encrypted(data) {
+        cipher.decrypt(data).map(Some)
+    } else {
+        Ok(None)
+    })?;
+
+    debug!("Removing encryption parameters");
+    remove_file(store_path.join(PARAMS_FILE)).context(EM::FileNotRemoved)?;
+    Ok(converted)
+}
+
+/// Rewrite each entry file, each file in the trash and the history and the data of each
+/// attachment with the result of `convert`, if it returns something
+///
+/// Each file is written atomically, so an interrupted conversion can be started again.
+fn convert_store<F>(store_path: &Path, convert: F) -> Result<Vec<PathBuf>>
+    where F: Fn(&[u8]) -> Result<Option<Vec<u8>>>
+{
+    let index = store_path.join(INDEX_DIR);
+    if index.exists() {
+        debug!("Removing index: {}", index.display());
+        remove_dir_all(&index).context(EM::FileNotRemoved)?;
+    }
+
+    let mut converted = vec![];
+    let files = WalkDir::new(store_path)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| {
+            let path      = e.path().strip_prefix(store_path).unwrap_or_else(|_| e.path());
+            let encrypted = path
+                .components()
+                .next()
+                .and_then(|c| c.as_os_str().to_str())
+                .map(|name| ENCRYPTED_DATA.contains(&name))
+                .unwrap_or(false);
+
+            !is_internal(path) || (encrypted && !is_staging_file(path))
+        });
+
+    for file in files {
+        let file = file.context(format_err!("Error in Walkdir"))?;
+        if !file.file_type().is_file() {
+            continue
+        }
+
+        let data = read(file.path()).context(format_err!("Failed to read {}", file.path().display()))?;
+        if let Some(data) = convert(&data).context(format_err!("Failed to convert {}", file.path().display()))? {
+            trace!("Writing converted file {}", file.path().display());
+            write_atomically(file.path(), &data)?;
+            converted.push(file.path().to_path_buf());
+        }
+    }
+
+    Ok(converted)
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(format_err!("Invalid hex string: {}", s))
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| format_err!("Invalid hex string: {}", s))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn config(secret: &str) -> Option<Value> {
+        ::toml::de::from_str(&format!(r#"
+        [store]
+            implicit-create = true
+            backend = "encrypted"
+
+        [store.encryption]
+            passphrase = "{}"
+        "#, secret)).ok()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let dir    = TempDir::new("imag-store-encryption").unwrap();
+        let cipher = Cipher::from_config(dir.path(), &config("secret")).unwrap();
+
+        let encrypted = cipher.encrypt(b"Hello World").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"Hello World");
+
+        let mut altered = encrypted.clone();
+        let last = altered.len() - 1;
+        altered[last] ^= 1;
+        assert!(cipher.decrypt(&altered).is_err());
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let dir = TempDir::new("imag-store-encryption").unwrap();
+        assert!(Cipher::from_config(dir.path(), &config("secret")).is_ok());
+        assert!(Cipher::from_config(dir.path(), &config("secret")).is_ok());
+        assert!(Cipher::from_config(dir.path(), &config("wrong")).is_err());
+        assert!(Cipher::from_config(dir.path(), &None).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_store() {
+        let dir  = TempDir::new("imag-store-encryption").unwrap();
+        let file = dir.path().join("diary").join("2019");
+        ::std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        ::std::fs::write(&file, "---\n[imag]\nversion = \"0.10.0\"\n---\nDear diary").unwrap();
+
+        let converted = encrypt_store(dir.path(), &config("secret")).unwrap();
+        assert_eq!(converted, vec![file.clone()]);
+        assert!(is_encrypted(&read(&file).unwrap()));
+
+        // nothing left to do
+        assert!(encrypt_store(dir.path(), &config("secret")).unwrap().is_empty());
+        assert!(decrypt_store(dir.path(), &config("wrong")).is_err());
+
+        let converted = decrypt_store(dir.path(), &config("secret")).unwrap();
+        assert_eq!(converted, vec![file.clone()]);
+        assert!(::std::fs::read_to_string(&file).unwrap().ends_with("Dear diary"));
+        assert!(!dir.path().join(PARAMS_FILE).exists());
+    }
+
+    #[test]
+    fn test_encrypt_trash_and_history() {
+        use crate::store::Store;
+        use crate::storeid::StoreId;
+
+        let dir = TempDir::new("imag-store-encryption").unwrap();
+        let a   = StoreId::new(PathBuf::from("diary/a")).unwrap();
+        let b   = StoreId::new(PathBuf::from("diary/b")).unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            for id in [&a, &b].iter() {
+                let mut entry = store.create((*id).clone()).unwrap();
+                entry.set_content(String::from("Dear diary"));
+                store.update(&mut entry).unwrap();
+                entry.set_content(String::from("Dear diary, again"));
+                store.update(&mut entry).unwrap();
+            }
+            store.delete(b.clone()).unwrap();
+        }
+
+        let converted = encrypt_store(dir.path(), &config("secret")).unwrap();
+        for internal in [TRASH_DIR, HISTORY_DIR].iter() {
+            let files = WalkDir::new(dir.path().join(internal))
+                .into_iter()
+                .map(|e| e.unwrap())
+                .filter(|e| e.file_type().is_file())
+                .collect::<Vec<_>>();
+
+            assert!(!files.is_empty());
+            for file in files {
+                assert!(converted.contains(&file.path().to_path_buf()));
+                assert!(is_encrypted(&read(file.path()).unwrap()));
+            }
+        }
+
+        let store = Store::new(dir.path().to_path_buf(), &config("secret")).unwrap();
+        assert!(!store.history(a).unwrap().is_empty());
+        store.trash_restore(b.clone()).unwrap();
+        assert_eq!(store.get(b).unwrap().unwrap().get_content(), "Dear diary, again");
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(from_hex(&to_hex(&[0, 1, 254, 255])).unwrap(), vec![0, 1, 254, 255]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+}
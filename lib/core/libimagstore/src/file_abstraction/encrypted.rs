@@ -0,0 +1,244 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! A filesystem backend which encrypts the content of all files
+//!
+//! See the `encryption` module for the details.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use toml::Value;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use super::FileAbstraction;
+use super::FileAbstractionInstance;
+use super::Drain;
+use super::fs::FSFileAbstraction;
+use super::fs::modified;
+use super::fs::read_bytes;
+use super::fs::write_atomically;
+use crate::encryption::Cipher;
+use crate::store::Entry;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::iter::PathIterator;
+
+#[derive(Debug)]
+pub struct EncryptedFileAbstractionInstance {
+    path: PathBuf,
+    cipher: Arc<Cipher>,
+}
+
+impl FileAbstractionInstance for EncryptedFileAbstractionInstance {
+
+    fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>> {
+        debug!("Getting encrypted file: {:?}", self.path);
+        match read_decrypted(&self.cipher, &self.path)? {
+            None    => Ok(None),
+            Some(s) => Entry::from_str(id, &s).map(Some),
+        }
+    }
+
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        let data = self.cipher.encrypt(buf.to_str()?.as_bytes())?;
+        write_atomically(&self.path, &data)
+    }
+}
+
+#[derive(Debug)]
+pub struct EncryptedFileAbstraction {
+    fs: FSFileAbstraction,
+    cipher: Arc<Cipher>,
+}
+
+impl EncryptedFileAbstraction {
+
+    /// Create the backend for the store at `store_path`, with the key from the `store.encryption`
+    /// section of the configuration
+    pub fn from_config(store_path: PathBuf, config: &Option<Value>) -> Result<EncryptedFileAbstraction> {
+        let cipher = Cipher::from_config(&store_path, config)?;
+        Ok(EncryptedFileAbstraction {
+            fs: FSFileAbstraction::default(),
+            cipher: Arc::new(cipher),
+        })
+    }
+}
+
+impl FileAbstraction for EncryptedFileAbstraction {
+
+    fn remove_file(&self, path: &PathBuf) -> Result<()> {
+        self.fs.remove_file(path)
+    }
+
+    fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.fs.copy(from, to)
+    }
+
+    fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.fs.rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &PathBuf) -> Result<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn exists(&self, path: &PathBuf) -> Result<bool> {
+        self.fs.exists(path)
+    }
+
+    fn is_file(&self, path: &PathBuf) -> Result<bool> {
+        self.fs.is_file(path)
+    }
+
+    fn new_instance(&self, p: PathBuf) -> Box<dyn FileAbstractionInstance> {
+        Box::new(EncryptedFileAbstractionInstance { path: p, cipher: self.cipher.clone() })
+    }
+
+    /// We return nothing from the FS here.
+    fn drain(&self) -> Result<Drain> {
+        Ok(Drain::empty())
+    }
+
+    fn fill(&mut self, mut d: Drain) -> Result<()> {
+        d.iter().try_for_each(|(path, element)| self.new_instance(path).write_file_content(&element))
+    }
+
+    fn pathes_recursively<'a>(&self,
+                          basepath: PathBuf,
+                          storepath: &'a PathBuf,
+                          backend: Arc<dyn FileAbstraction>)
+        -> Result<PathIterator<'a>>
+    {
+        self.fs.pathes_recursively(basepath, storepath, backend)
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        read_decrypted(&self.cipher, path)
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        let data = self.cipher.encrypt(content.as_bytes())?;
+        write_atomically(path, &data)
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        self.fs.remove_raw(path)
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        match read_bytes(path)? {
+            None       => Ok(None),
+            Some(data) => self.cipher
+                .decrypt(&data)
+                .context(format_err!("Failed to decrypt {}", path.display()))
+                .map(Some)
+                .map_err(Error::from),
+        }
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        let data = self.cipher.encrypt(content)?;
+        write_atomically(path, &data)
+    }
+
+    fn modified(&self, path: &PathBuf) -> Result<Option<SystemTime>> {
+        modified(path)
+    }
+}
+
+fn read_decrypted(cipher: &Cipher, path: &Path) -> Result<Option<String>> {
+    let data = match read_bytes(path)? {
+        None       => return Ok(None),
+        Some(data) => data,
+    };
+
+    let plain = cipher
+        .decrypt(&data)
+        .context(format_err!("Failed to decrypt {}", path.display()))?;
+
+    String::from_utf8(plain)
+        .context(format_err!("Decrypted content of {} is not UTF-8", path.display()))
+        .map(Some)
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use crate::store::Store;
+
+    fn config(secret: &str) -> Option<::toml::Value> {
+        ::toml::de::from_str(&format!(r#"
+        [store]
+            implicit-create = true
+            backend = "encrypted"
+
+        [store.encryption]
+            passphrase = "{}"
+        "#, secret)).ok()
+    }
+
+    #[test]
+    fn test_entries_are_encrypted() {
+        use toml::Value;
+        use toml_query::insert::TomlValueInsertExt;
+
+        let dir = TempDir::new("imag-store-encrypted").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &config("secret")).unwrap();
+            let mut entry = store.create(PathBuf::from("diary/secret")).unwrap();
+            *entry.get_content_mut() = String::from("Dear diary");
+            let tags = Value::Array(vec![Value::String(String::from("private"))]);
+            let _ = entry.get_header_mut().insert("tag.values", tags).unwrap();
+            store.update(&mut entry).unwrap();
+            assert_eq!(store.index_lookup("tag.values", "private").unwrap().count(), 1);
+        }
+
+        let raw = ::std::fs::read(dir.path().join("diary/secret")).unwrap();
+        assert!(crate::encryption::is_encrypted(&raw));
+        assert!(!String::from_utf8_lossy(&raw).contains("Dear diary"));
+
+        let index = ::std::fs::read(dir.path().join(".index/v2.json")).unwrap();
+        assert!(!String::from_utf8_lossy(&index).contains("private"));
+
+        let store = Store::new(dir.path().to_path_buf(), &config("secret")).unwrap();
+        let ids = store.entries().unwrap().into_storeid_iter().collect::<::failure::Fallible<Vec<_>>>().unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(store.get_copy(PathBuf::from("diary/secret")).unwrap().get_content(), "Dear diary");
+
+        let tagged = store.index_lookup("tag.values", "private").unwrap().count();
+        assert_eq!(tagged, 1);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let dir = TempDir::new("imag-store-encrypted").unwrap();
+        let _   = Store::new(dir.path().to_path_buf(), &config("secret")).unwrap();
+        assert!(Store::new(dir.path().to_path_buf(), &config("wrong")).is_err());
+    }
+
+}
@@ -17,10 +17,14 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
-use std::fs::{File, OpenOptions, create_dir_all, remove_file, copy, rename};
-use std::io::{Seek, SeekFrom, Read};
+use std::ffi::OsStr;
+use std::fs::{File, Metadata, OpenOptions, create_dir_all, remove_file, copy, rename};
+use std::io::{BufReader, Seek, SeekFrom, Read};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
 
 use libimagerror::errors::ErrorMsg as EM;
 
@@ -28,9 +32,17 @@ use super::FileAbstraction;
 use super::FileAbstractionInstance;
 use super::Drain;
 use crate::store::Entry;
+use crate::store::HeaderOnlyEntry;
+use crate::util::read_header;
 use crate::storeid::StoreIdWithBase;
 use crate::file_abstraction::iter::PathIterator;
 use crate::file_abstraction::iter::PathIterBuilder;
+use crate::attachment::ATTACHMENT_DIR;
+use crate::history::HISTORY_DIR;
+use crate::index::INDEX_DIR;
+use crate::lock::LOCKS_DIR;
+use crate::transaction::JOURNAL_FILE;
+use crate::trash::TRASH_DIR;
 
 use walkdir::WalkDir;
 use failure::ResultExt;
@@ -67,19 +79,37 @@ impl FileAbstractionInstance for FSFileAbstractionInstance {
     }
 
     /**
-     * Write the content of this file
+     * Get the header behind this file, the content is read when it is accessed
+     *
+     * If the file was replaced or changed in the meantime, reading the content fails, as it would
+     * not match the header anymore.
      */
-    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
-        use std::io::Write;
+    fn get_file_header<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<HeaderOnlyEntry>> {
+        debug!("Getting header of file: {:?}", self);
 
-        let buf      = buf.to_str()?.into_bytes();
-        let mut file = create_file(&self.0).context(EM::FileNotCreated)?;
+        let file = match File::open(&self.0) {
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e)   => return Err(Error::from(e)),
+            Ok(file) => file,
+        };
 
-        file.seek(SeekFrom::Start(0)).context(EM::FileNotCreated)?;
-        file.set_len(buf.len() as u64).context(EM::FileNotWritten)?;
-        file.write_all(&buf)
-            .context(EM::FileNotWritten)
-            .map_err(Error::from)
+        let version                  = FileVersion::of(&file.metadata().context(EM::IO)?);
+        let (header, format, offset) = read_header(&mut BufReader::new(file))?;
+        let path                     = self.0.clone();
+        let loader                   = Arc::new(move || read_content(&path, &version, offset));
+
+        HeaderOnlyEntry::new(id, header, format, loader).map(Some)
+    }
+
+    /**
+     * Write the content of this file
+     *
+     * The content is written to a staging file next to the actual file first, which is then
+     * renamed over the actual file. This way, the file is either completely written or not at
+     * all.
+     */
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        write_atomically(&self.0, buf.to_str()?.as_bytes())
     }
 }
 
@@ -160,22 +190,69 @@ impl FileAbstraction for FSFileAbstraction {
         -> Result<PathIterator<'a>>
     {
         trace!("Building PathIterator object");
-        Ok(PathIterator::new(Box::new(WalkDirPathIterBuilder { basepath }), storepath, backend))
+        Ok(PathIterator::new(Box::new(WalkDirPathIterBuilder::new(basepath.clone(), basepath)), storepath, backend))
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        let mut file = match open_file(path).context(EM::IO)? {
+            None       => return Ok(None),
+            Some(file) => file,
+        };
+
+        let mut s = String::new();
+        file.read_to_string(&mut s).context(EM::IO)?;
+        Ok(Some(s))
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        write_atomically(path, content.as_bytes())
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        self.remove_file(path)
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        read_bytes(path)
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        write_atomically(path, content)
+    }
+
+    fn modified(&self, path: &PathBuf) -> Result<Option<SystemTime>> {
+        modified(path)
     }
 }
 
 #[derive(Debug)]
 pub struct WalkDirPathIterBuilder {
+    /// The store (or the trash) the iterated files belong to
+    root: PathBuf,
+
     basepath: PathBuf
 }
 
+impl WalkDirPathIterBuilder {
+    pub(crate) fn new(root: PathBuf, basepath: PathBuf) -> Self {
+        WalkDirPathIterBuilder { root, basepath }
+    }
+}
+
 impl PathIterBuilder for WalkDirPathIterBuilder {
     fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
         trace!("Building iterator for {}", self.basepath.display());
+        if !self.basepath.exists() {
+            // For example the trash, which is created with the first deleted entry
+            return Box::new(::std::iter::empty())
+        }
+
+        let root = self.root.clone();
         Box::new(WalkDir::new(self.basepath.clone())
             .min_depth(1)
             .max_open(100)
             .into_iter()
+            .filter_entry(move |e| e.depth() == 0 || !is_internal(e.path().strip_prefix(&root).unwrap_or_else(|_| e.path())))
             .filter(|r| match r {
                 Err(_) => true,
                 Ok(path) => path.file_type().is_file(),
@@ -201,6 +278,99 @@ impl PathIterBuilder for WalkDirPathIterBuilder {
     }
 }
 
+/// Write `buf` to a staging file next to `path` and rename it over `path` afterwards
+///
+/// This way, the file is either completely written or not at all.
+pub(crate) fn write_atomically(path: &Path, buf: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let staging = staging_path(path)?;
+    let written = create_staging_file(&staging)
+        .context(EM::FileNotCreated)
+        .map_err(Error::from)
+        .and_then(|mut file| {
+            file.write_all(buf).context(EM::FileNotWritten)?;
+            file.sync_all().context(EM::FileNotWritten)?;
+            Ok(())
+        })
+        .and_then(|_| {
+            trace!("Moving staging file {:?} to {:?}", staging, path);
+            rename(&staging, path)
+                .context(EM::FileNotRenamed)
+                .map_err(Error::from)
+        });
+
+    if written.is_err() && staging.exists() {
+        let _ = remove_file(&staging);
+    }
+
+    written
+}
+
+/// Read the file at `path`, `None` if it does not exist
+pub(crate) fn read_bytes(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = match open_file(path).context(EM::IO)? {
+        None       => return Ok(None),
+        Some(file) => file,
+    };
+
+    let mut buf = vec![];
+    file.read_to_end(&mut buf).context(EM::IO)?;
+    Ok(Some(buf))
+}
+
+/// When the file at `path` was modified, `None` if it does not exist
+pub(crate) fn modified(path: &Path) -> Result<Option<SystemTime>> {
+    match path.metadata() {
+        Ok(metadata) => metadata.modified().map(Some).context(EM::IO).map_err(Error::from),
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(EM::IO).map_err(Error::from),
+    }
+}
+
+/// Read the content of the entry at `path`, which starts at `offset`
+/// What identifies the state of a file: the file itself (on unix) as well as its size and
+/// modification time
+///
+/// As files are replaced by renaming a staging file over them, a new version of a file is a new
+/// file.
+#[derive(Debug, PartialEq, Eq)]
+struct FileVersion {
+    #[cfg(unix)]
+    inode: (u64, u64),
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileVersion {
+    fn of(metadata: &Metadata) -> FileVersion {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+
+        FileVersion {
+            #[cfg(unix)]
+            inode: (metadata.dev(), metadata.ino()),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// Read the content of the file at `path` from `offset`, if the file is still at `version`
+fn read_content(path: &Path, version: &FileVersion, offset: u64) -> Result<String> {
+    debug!("Reading content of file: {:?}", path);
+    let mut file = File::open(path).context(EM::FileNotFound)?;
+    if FileVersion::of(&file.metadata().context(EM::IO)?) != *version {
+        return Err(format_err!("{} changed since its header was read", path.display()))
+    }
+
+    file.seek(SeekFrom::Start(offset)).context(EM::FileNotSeeked)?;
+
+    let mut s = String::new();
+    file.read_to_string(&mut s).context(EM::IO)?;
+    Ok(s)
+}
+
 fn open_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<Option<File>> {
     match OpenOptions::new().write(true).read(true).open(p) {
         Err(e) => match e.kind() {
@@ -211,7 +381,7 @@ fn open_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<Option<File>> {
     }
 }
 
-fn create_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<File> {
+fn create_staging_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<File> {
     if let Some(parent) = p.as_ref().parent() {
         trace!("'{}' is directory = {}", parent.display(), parent.is_dir());
         if !parent.is_dir() {
@@ -219,6 +389,67 @@ fn create_file<A: AsRef<Path>>(p: A) -> ::std::io::Result<File> {
             create_dir_all(parent)?;
         }
     }
-    OpenOptions::new().write(true).read(true).create(true).open(p)
+    OpenOptions::new().write(true).create_new(true).open(p)
+}
+
+/// Counter to make the names of the staging files of one process unique
+static STAGING_COUNTER : AtomicUsize = AtomicUsize::new(0);
+
+/// Get the path of a new staging file for `p`
+///
+/// The staging file lives in the same directory as the file itself (so that renaming it is
+/// atomic) and is hidden, so iterating over the store does not yield it. The name contains the
+/// process id and a counter, so processes and threads writing the same file concurrently do not
+/// write to the same staging file.
+fn staging_path(p: &Path) -> Result<PathBuf> {
+    let name = p
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(|| format_err!("Cannot build staging file name for: {}", p.display()))?;
+
+    let n = STAGING_COUNTER.fetch_add(1, Ordering::SeqCst);
+    Ok(p.with_file_name(format!(".{}.{}-{}{}", name, ::std::process::id(), n, STAGING_SUFFIX)))
+}
+
+/// The suffix of the names of staging files
+const STAGING_SUFFIX : &str = ".imag-staging";
+
+/// The files and directories directly in the store which the store (or git) keeps its own data in
+const INTERNAL_NAMES : &[&str] = &[
+    JOURNAL_FILE,
+    INDEX_DIR,
+    TRASH_DIR,
+    HISTORY_DIR,
+    LOCKS_DIR,
+    ATTACHMENT_DIR,
+    crate::encryption::PARAMS_FILE,
+    crate::rpc::DEFAULT_SOCKET,
+    ".store.sqlite", // see `sqlite::DATABASE_FILE`
+    ".git",
+    ".gitignore",
+];
+
+/// Whether the file or directory at `path`, relative to the store, is no entry but data of the
+/// store itself
+///
+/// These are the files and directories in `INTERNAL_NAMES` and staging files. Other files with a
+/// leading dot are entries.
+pub(crate) fn is_internal(path: &Path) -> bool {
+    let top_level = path
+        .components()
+        .next()
+        .and_then(|c| c.as_os_str().to_str())
+        .map(|name| INTERNAL_NAMES.contains(&name))
+        .unwrap_or(false);
+
+    top_level || is_staging_file(path)
+}
+
+/// Whether `path` is a staging file, which is only left behind if writing a file was interrupted
+pub(crate) fn is_staging_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .map(|name| name.ends_with(STAGING_SUFFIX))
+        .unwrap_or(false)
 }
 
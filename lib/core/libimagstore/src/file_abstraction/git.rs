@@ -0,0 +1,511 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! A filesystem backend which commits all changes to a git repository in the store path
+//!
+//! All file operations are done by the `FSFileAbstraction`, this backend only remembers which
+//! files were touched. These files are committed either after each store operation (or
+//! transaction) or once when the store is dropped, depending on `store.git.commit`.
+//!
+//! The `git` executable is used for talking to the repository.
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use super::FileAbstraction;
+use super::FileAbstractionInstance;
+use super::Drain;
+use super::fs::FSFileAbstraction;
+use crate::store::Entry;
+use crate::store::HeaderOnlyEntry;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::iter::PathIterator;
+
+/// When the touched files are committed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitMode {
+    /// After each `Store::update()`/`delete()`/`move_by_id()` and after each transaction
+    Operation,
+
+    /// Once, when the store is dropped
+    Session,
+}
+
+type Touched = Arc<Mutex<BTreeSet<PathBuf>>>;
+
+#[derive(Debug)]
+pub struct GitFileAbstractionInstance {
+    path: PathBuf,
+    fs: Box<dyn FileAbstractionInstance>,
+    touched: Touched,
+}
+
+impl FileAbstractionInstance for GitFileAbstractionInstance {
+
+    fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>> {
+        self.fs.get_file_content(id)
+    }
+
+    fn get_file_header<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<HeaderOnlyEntry>> {
+        self.fs.get_file_header(id)
+    }
+
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        self.fs.write_file_content(buf)?;
+        touch(&self.touched, &self.path)
+    }
+}
+
+#[derive(Debug)]
+pub struct GitFileAbstraction {
+    repo: PathBuf,
+    fs: FSFileAbstraction,
+    mode: CommitMode,
+
+    /// `-c key=value` options passed to each git call
+    git_config: Vec<String>,
+
+    /// Files which were altered since the last commit
+    touched: Touched,
+}
+
+impl GitFileAbstraction {
+
+    /// Create the backend for the store at `repo`
+    ///
+    /// Reads the `store.git` section of the configuration:
+    ///
+    /// * `commit`: `"session"` (default) or `"operation"`
+    /// * `user-name`, `user-email`: The identity to commit with, if not the one from the git
+    ///   configuration
+    pub fn from_config(repo: PathBuf, config: &Option<Value>) -> Result<GitFileAbstraction> {
+        let read = |key: &str| -> Result<Option<String>> {
+            match *config {
+                None => Ok(None),
+                Some(ref config) => config
+                    .read_string(key)
+                    .context(format_err!("Error reading '{}' in configuration", key))
+                    .map_err(Error::from),
+            }
+        };
+
+        let mode = match read("store.git.commit")?.as_deref() {
+            None | Some("session") => CommitMode::Session,
+            Some("operation")      => CommitMode::Operation,
+            Some(other)            => return Err(format_err!("Unknown value for 'store.git.commit': {}", other)),
+        };
+
+        let mut git_config = vec![];
+        if let Some(name) = read("store.git.user-name")? {
+            git_config.push(format!("user.name={}", name));
+        }
+        if let Some(email) = read("store.git.user-email")? {
+            git_config.push(format!("user.email={}", email));
+        }
+
+        debug!("Git backend at {}, committing per {:?}", repo.display(), mode);
+        Ok(GitFileAbstraction {
+            repo,
+            fs: FSFileAbstraction::default(),
+            mode,
+            git_config,
+            touched: Arc::new(Mutex::new(BTreeSet::new())),
+        })
+    }
+
+    /// A git command in the repository, with the configured `git_config` options
+    fn git_command<I, S>(&self, args: I) -> Command
+        where I: IntoIterator<Item = S>,
+              S: AsRef<OsStr>
+    {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.repo);
+        for kv in self.git_config.iter() {
+            command.arg("-c").arg(kv);
+        }
+        command.args(args);
+        trace!("Calling {:?}", command);
+        command
+    }
+
+    fn git<I, S>(&self, args: I) -> Result<Output>
+        where I: IntoIterator<Item = S>,
+              S: AsRef<OsStr>
+    {
+        let mut command = self.git_command(args);
+        let output = command
+            .output()
+            .context(format_err!("Failed to call git in {}", self.repo.display()))?;
+
+        if output.status.success() {
+            Ok(output)
+        } else {
+            Err(format_err!("{:?} failed: {}", command, String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    /// Initialize the repository, if the store is no repository yet
+    ///
+    /// Returns the files which have to be committed because of that.
+    fn ensure_repository(&self) -> Result<Vec<PathBuf>> {
+        if self.repo.join(".git").exists() {
+            return Ok(vec![])
+        }
+
+        info!("Initializing git repository in {}", self.repo.display());
+        let _ = self.git(&["init", "-q"])?;
+
+        // The index is a cache, locks belong to running processes and staging files are only
+        // left behind by crashes
+        let gitignore = self.repo.join(".gitignore");
+        if !gitignore.exists() {
+            self.fs.write_raw(&gitignore, ".index/\n.locks/\n.journal.json\n.*.imag-staging\n")?;
+        }
+        Ok(vec![gitignore])
+    }
+
+    /// Commit all touched files
+    fn commit(&self) -> Result<()> {
+        let touched = {
+            let mut touched = self.touched.lock().map_err(|_| Error::from(EM::LockError))?;
+            if touched.is_empty() {
+                trace!("Nothing touched, not committing");
+                return Ok(())
+            }
+            ::std::mem::replace(&mut *touched, BTreeSet::new())
+        };
+
+        self.commit_paths(&touched).map_err(|e| {
+            // try again with the next commit
+            if let Ok(mut t) = self.touched.lock() {
+                t.extend(touched);
+            }
+            e
+        })
+    }
+
+    fn commit_paths(&self, touched: &BTreeSet<PathBuf>) -> Result<()> {
+        let mut paths = self.ensure_repository()?;
+        paths.extend(touched.iter().cloned());
+
+        let paths = paths
+            .into_iter()
+            .filter_map(|p| p.strip_prefix(&self.repo).ok().map(PathBuf::from))
+            .collect::<Vec<PathBuf>>();
+
+        // Files which were created and removed again since the last commit are unknown to git
+        let tracked = self.git(vec![OsStr::new("ls-files"), OsStr::new("-z"), OsStr::new("--")]
+                               .into_iter()
+                               .chain(paths.iter().map(|p| p.as_os_str())))?
+            .stdout;
+        let tracked = String::from_utf8_lossy(&tracked);
+        let tracked = tracked.split('\0').filter(|s| !s.is_empty()).map(PathBuf::from).collect::<BTreeSet<_>>();
+
+        let paths = paths
+            .into_iter()
+            .filter(|p| tracked.contains(p) || self.repo.join(p).exists())
+            .collect::<Vec<PathBuf>>();
+
+        if paths.is_empty() {
+            debug!("No files to commit");
+            return Ok(())
+        }
+
+        let with_paths = |args: &[&str]| {
+            args.iter()
+                .map(OsStr::new)
+                .chain(::std::iter::once(OsStr::new("--")))
+                .chain(paths.iter().map(|p| p.as_os_str()))
+                .map(|s| s.to_os_string())
+                .collect::<Vec<_>>()
+        };
+
+        let _ = self.git(with_paths(&["add", "-A"]))?;
+
+        let unchanged = self
+            .git_command(with_paths(&["diff", "--cached", "--quiet"]))
+            .status()
+            .context(format_err!("Failed to call git in {}", self.repo.display()))?
+            .success();
+
+        if unchanged {
+            debug!("Touched files are unchanged, not committing");
+            return Ok(())
+        }
+
+        let message = commit_message(&paths);
+        debug!("Committing: {}", message);
+        let _ = self.git(with_paths(&["commit", "-q", "-m", &message]))?;
+        Ok(())
+    }
+}
+
+impl FileAbstraction for GitFileAbstraction {
+
+    fn remove_file(&self, path: &PathBuf) -> Result<()> {
+        self.fs.remove_file(path)?;
+        touch(&self.touched, path)
+    }
+
+    fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.fs.copy(from, to)?;
+        touch(&self.touched, to)
+    }
+
+    fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.fs.rename(from, to)?;
+        touch(&self.touched, from)?;
+        touch(&self.touched, to)
+    }
+
+    fn create_dir_all(&self, path: &PathBuf) -> Result<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn exists(&self, path: &PathBuf) -> Result<bool> {
+        self.fs.exists(path)
+    }
+
+    fn is_file(&self, path: &PathBuf) -> Result<bool> {
+        self.fs.is_file(path)
+    }
+
+    fn new_instance(&self, p: PathBuf) -> Box<dyn FileAbstractionInstance> {
+        Box::new(GitFileAbstractionInstance {
+            fs: self.fs.new_instance(p.clone()),
+            path: p,
+            touched: self.touched.clone(),
+        })
+    }
+
+    /// We return nothing from the FS here.
+    fn drain(&self) -> Result<Drain> {
+        Ok(Drain::empty())
+    }
+
+    fn fill(&mut self, mut d: Drain) -> Result<()> {
+        d.iter().try_for_each(|(path, element)| self.new_instance(path).write_file_content(&element))
+    }
+
+    fn pathes_recursively<'a>(&self,
+                          basepath: PathBuf,
+                          storepath: &'a PathBuf,
+                          backend: Arc<dyn FileAbstraction>)
+        -> Result<PathIterator<'a>>
+    {
+        self.fs.pathes_recursively(basepath, storepath, backend)
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        self.fs.read_raw(path)
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        self.fs.write_raw(path, content)
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        self.fs.remove_raw(path)?;
+        touch(&self.touched, path) // only committed if it was tracked, like an attachment
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        self.fs.read_bytes(path)
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        self.fs.write_bytes(path, content)?;
+        touch(&self.touched, path)
+    }
+
+    fn modified(&self, path: &PathBuf) -> Result<Option<SystemTime>> {
+        self.fs.modified(path)
+    }
+
+    fn batch_finished(&self) -> Result<()> {
+        if self.mode == CommitMode::Operation {
+            self.commit()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn session_finished(&self) -> Result<()> {
+        self.commit()
+    }
+}
+
+fn touch(touched: &Touched, path: &Path) -> Result<()> {
+    trace!("Touched: {}", path.display());
+    let _ = touched
+        .lock()
+        .map_err(|_| Error::from(EM::LockError))?
+        .insert(path.to_path_buf());
+    Ok(())
+}
+
+/// Build the commit message, naming the touched ids
+fn commit_message(paths: &[PathBuf]) -> String {
+    let ids = paths
+        .iter()
+        .filter(|p| p.as_os_str() != ".gitignore")
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>();
+
+    match ids.len() {
+        0 => String::from("imag: Initialize store"),
+        1 => format!("imag: Update {}", ids[0]),
+        n => format!("imag: Update {} entries\n\n{}", n, ids.join("\n")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use tempdir::TempDir;
+
+    use crate::store::Store;
+
+    fn store_config(mode: &str) -> ::toml::Value {
+        ::toml::de::from_str(&format!(r#"
+        [store]
+            implicit-create = true
+            backend = "git"
+
+        [store.git]
+            commit = "{}"
+            user-name = "imag"
+            user-email = "imag@localhost"
+
+        [store.trash]
+            enabled = false
+        "#, mode)).unwrap()
+    }
+
+    fn log(dir: &TempDir) -> Vec<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["log", "--format=%B%x00"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .split('\0')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    fn status(dir: &TempDir) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["status", "--porcelain"])
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_commit_per_session() {
+        let dir = TempDir::new("imag-store-git").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &Some(store_config("session"))).unwrap();
+            let _ = store.create(PathBuf::from("a")).unwrap();
+            let _ = store.create(PathBuf::from("b")).unwrap();
+            assert!(!dir.path().join(".git").exists());
+        }
+
+        let log = log(&dir);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].starts_with("imag: Update 2 entries"));
+        assert!(log[0].contains("\na\nb"));
+        assert_eq!(status(&dir), "");
+    }
+
+    #[test]
+    fn test_commit_per_operation() {
+        let dir = TempDir::new("imag-store-git").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &Some(store_config("operation"))).unwrap();
+
+        {
+            let mut a = store.create(PathBuf::from("a")).unwrap();
+            store.update(&mut a).unwrap();
+        }
+        assert_eq!(log(&dir), vec!["imag: Update a"]);
+
+        let a = store.get_copy(PathBuf::from("a")).unwrap().get_location().clone();
+        let b = crate::storeid::StoreId::new(PathBuf::from("b")).unwrap();
+        store.move_by_id(a, b.clone()).unwrap();
+        assert_eq!(log(&dir)[0], "imag: Update 2 entries\n\na\nb");
+
+        store.delete(b).unwrap();
+        assert_eq!(log(&dir)[0], "imag: Update b");
+        assert_eq!(log(&dir).len(), 3);
+        assert_eq!(status(&dir), "");
+    }
+
+    #[test]
+    fn test_transaction_is_one_commit() {
+        use failure::err_msg;
+
+        let dir = TempDir::new("imag-store-git").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &Some(store_config("operation"))).unwrap();
+
+        store.transaction(|store| {
+            for name in &["a", "b", "c"] {
+                let mut entry = store.create(PathBuf::from(*name))?;
+                store.update(&mut entry)?;
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(log(&dir).len(), 1);
+
+        // a rolled back transaction does not result in a commit
+        let res : ::failure::Fallible<()> = store.transaction(|store| {
+            let mut entry = store.create(PathBuf::from("d"))?;
+            store.update(&mut entry)?;
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+        assert_eq!(log(&dir).len(), 1);
+    }
+
+}
@@ -37,6 +37,7 @@ use crate::store::Entry;
 use crate::storeid::StoreIdWithBase;
 use crate::file_abstraction::iter::PathIterator;
 use crate::file_abstraction::iter::PathIterBuilder;
+use crate::file_abstraction::iter::is_visible_below;
 
 type Backend = Arc<Mutex<RefCell<HashMap<PathBuf, Entry>>>>;
 
@@ -91,6 +92,12 @@ impl FileAbstractionInstance for InMemoryFileAbstractionInstance {
 #[derive(Debug, Default)]
 pub struct InMemoryFileAbstraction {
     virtual_filesystem: Backend,
+
+    /// Files which are not store entries
+    raw_files: Arc<Mutex<HashMap<PathBuf, String>>>,
+
+    /// Binary files which are not store entries
+    raw_bytes: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
 }
 
 impl InMemoryFileAbstraction {
@@ -195,7 +202,7 @@ impl FileAbstraction for InMemoryFileAbstraction {
         Ok(())
     }
 
-    fn pathes_recursively<'a>(&self, _basepath: PathBuf, storepath: &'a PathBuf, backend: Arc<dyn FileAbstraction>) -> Result<PathIterator<'a>> {
+    fn pathes_recursively<'a>(&self, basepath: PathBuf, storepath: &'a PathBuf, backend: Arc<dyn FileAbstraction>) -> Result<PathIterator<'a>> {
         trace!("Building PathIterator object (inmemory implementation)");
         let keys : Vec<PathBuf> = self
             .backend()
@@ -203,26 +210,91 @@ impl FileAbstraction for InMemoryFileAbstraction {
             .map_err(|_| EM::LockError)?
             .get_mut()
             .keys()
+            .filter(|p| is_visible_below(p, &basepath))
             .map(PathBuf::from)
             .map(Ok)
             .collect::<Result<_>>()?; // we have to collect() because of the lock() above.
 
-        Ok(PathIterator::new(Box::new(InMemPathIterBuilder(keys)), storepath, backend))
+        let builder = InMemPathIterBuilder { paths: keys, basepath };
+        Ok(PathIterator::new(Box::new(builder), storepath, backend))
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        self.raw_files
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))
+            .map(|files| files.get(path).cloned())
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        debug!("Writing raw file: {:?}", path);
+        let _ = self.raw_files
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?
+            .insert(path.clone(), String::from(content));
+        Ok(())
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        debug!("Removing raw file: {:?}", path);
+        let removed = self.raw_files
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?
+            .remove(path)
+            .is_some();
+
+        let removed = removed || self.raw_bytes
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?
+            .remove(path)
+            .is_some();
+
+        if removed {
+            Ok(())
+        } else {
+            Err(EM::FileNotFound.into())
+        }
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        self.raw_bytes
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))
+            .map(|files| files.get(path).cloned())
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        debug!("Writing binary file: {:?}", path);
+        let _ = self.raw_bytes
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?
+            .insert(path.clone(), content.to_vec());
+        Ok(())
+    }
+
+    /// No other process can access the files
+    fn is_shared(&self) -> bool {
+        false
     }
 }
 
 #[derive(Debug)]
-pub struct InMemPathIterBuilder(Vec<PathBuf>);
+pub struct InMemPathIterBuilder {
+    paths: Vec<PathBuf>,
+    basepath: PathBuf,
+}
 
 impl PathIterBuilder for InMemPathIterBuilder {
     fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
-        Box::new(self.0.clone().into_iter().map(Ok))
+        Box::new(self.paths.clone().into_iter().map(Ok))
     }
 
     fn in_collection(&mut self, c: &str) -> Result<()> {
         debug!("Altering PathIterBuilder path with: {:?}", c);
-        self.0.retain(|p| p.starts_with(c));
-        debug!(" -> path : {:?}", self.0);
+        self.basepath.push(c);
+        let basepath = &self.basepath;
+        self.paths.retain(|p| p.starts_with(basepath));
+        debug!(" -> path : {:?}", self.paths);
         Ok(())
     }
 }
@@ -17,6 +17,7 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::fmt::Debug;
@@ -25,6 +26,15 @@ use failure::Fallible as Result;
 
 use crate::storeid::StoreIdWithBase;
 use crate::file_abstraction::FileAbstraction;
+use crate::file_abstraction::fs::is_internal;
+
+/// Whether `path` is below `root` (the store or the trash) and no internal file of the store,
+/// like the `FSFileAbstraction` does it
+pub(crate) fn is_visible_below(path: &Path, root: &Path) -> bool {
+    path.strip_prefix(root)
+        .map(|rel| !is_internal(rel))
+        .unwrap_or(false)
+}
 
 /// See documentation for PathIterator
 pub(crate) trait PathIterBuilder : Debug {
@@ -85,6 +95,13 @@ impl<'a> PathIterator<'a> {
         self.iter
     }
 
+    /// Turn iterator into the `PathIterBuilder` it was built with
+    ///
+    /// Used by backends which wrap other backends, to build the iterator from several builders.
+    pub(crate) fn into_builder(self) -> Box<dyn PathIterBuilder> {
+        self.iter_builder
+    }
+
 }
 
 impl<'a> Iterator for PathIterator<'a> {
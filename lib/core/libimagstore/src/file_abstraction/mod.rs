@@ -21,20 +21,28 @@ use std::path::PathBuf;
 use std::fmt::Debug;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use failure::Fallible as Result;
 
 use crate::store::Entry;
+use crate::store::HeaderOnlyEntry;
 use crate::storeid::StoreIdWithBase;
 
+pub mod encrypted;
 pub mod fs;
+pub mod git;
 pub mod inmemory;
+pub mod overlay;
+pub mod remote;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod iter;
 
 use self::iter::PathIterator;
 
 /// An abstraction trait over filesystem actions
-pub(crate) trait FileAbstraction : Debug {
+pub(crate) trait FileAbstraction : Debug + Send + Sync {
     fn remove_file(&self, path: &PathBuf) -> Result<()>;
     fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()>;
     fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()>;
@@ -49,16 +57,68 @@ pub(crate) trait FileAbstraction : Debug {
     fn fill(&mut self, d: Drain) -> Result<()>;
 
     fn pathes_recursively<'a>(&self, basepath: PathBuf, storepath: &'a PathBuf, backend: Arc<dyn FileAbstraction>) -> Result<PathIterator<'a>>;
+
+    /// Read a file which is not a store entry (for example the index), `None` if it does not exist
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>>;
+
+    /// Write a file which is not a store entry
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()>;
+
+    /// Remove a file which is not a store entry
+    fn remove_raw(&self, path: &PathBuf) -> Result<()>;
+
+    /// Read a binary file which is not a store entry (an attachment), `None` if it does not exist
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>>;
+
+    /// Write a binary file which is not a store entry, it is removed with `remove_raw()`
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()>;
+
+    /// When the binary file at `path` was written, `None` if it does not exist or the backend
+    /// does not know
+    fn modified(&self, _path: &PathBuf) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    /// Called by the store when a batch of changes (one operation or one transaction) is complete
+    ///
+    /// Backends which keep a history of the store can record the changes here.
+    fn batch_finished(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called by the store when it is dropped
+    fn session_finished(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether other processes can access the files, so entries have to be locked while borrowed
+    fn is_shared(&self) -> bool {
+        true
+    }
+
+    /// Whether the file at `path` cannot be removed or renamed, for example because it is in a
+    /// read-only mounted store
+    fn is_read_only(&self, _path: &PathBuf) -> Result<bool> {
+        Ok(false)
+    }
 }
 
 /// An abstraction trait over actions on files
-pub(crate) trait FileAbstractionInstance : Debug {
+pub(crate) trait FileAbstractionInstance : Debug + Send + Sync {
 
     /// Get the contents of the FileAbstractionInstance, as Entry object.
     ///
     /// The `StoreIdWithBase` is passed because the backend does not know where the Entry lives, but the
     /// Entry type itself must be constructed with the id.
     fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>>;
+
+    /// Get the Entry with only its header read, the content is read when it is accessed
+    ///
+    /// Backends which cannot read the header on its own return the complete entry.
+    fn get_file_header<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<HeaderOnlyEntry>> {
+        self.get_file_content(id).map(|entry| entry.map(HeaderOnlyEntry::from))
+    }
+
     fn write_file_content(&mut self, buf: &Entry) -> Result<()>;
 }
 
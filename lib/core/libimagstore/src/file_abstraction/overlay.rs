@@ -0,0 +1,499 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! A backend which adds read-only mounted stores to another backend
+//!
+//! See the `mount` module for the details.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use failure::Fallible as Result;
+use failure::Error;
+
+use super::FileAbstraction;
+use super::FileAbstractionInstance;
+use super::Drain;
+use super::fs::FSFileAbstraction;
+use super::fs::WalkDirPathIterBuilder;
+use crate::mount::Mount;
+use crate::store::Entry;
+use crate::store::HeaderOnlyEntry;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::iter::PathIterator;
+use crate::file_abstraction::iter::PathIterBuilder;
+
+#[derive(Debug)]
+pub struct OverlayFileAbstractionInstance {
+    path: PathBuf,
+    primary: Box<dyn FileAbstractionInstance>,
+    primary_backend: Arc<dyn FileAbstraction>,
+
+    /// The path of the file in a mounted store, if the path is below the prefix of a mount
+    mounted_path: Option<PathBuf>,
+    mounted: Option<Box<dyn FileAbstractionInstance>>,
+}
+
+impl OverlayFileAbstractionInstance {
+
+    /// Whether `entry` is the unchanged entry from the mounted store
+    fn is_unchanged_mounted(&self, entry: &Entry) -> Result<bool> {
+        let mounted_path = match self.mounted_path {
+            Some(ref path) => path,
+            None           => return Ok(false),
+        };
+
+        if self.primary_backend.exists(&self.path)? {
+            return Ok(false)
+        }
+
+        match FSFileAbstraction::default().read_raw(mounted_path)? {
+            None      => Ok(false),
+            Some(raw) => Ok(Entry::from_str(entry.get_location().clone(), &raw)
+                .map(|mounted| mounted == *entry)
+                .unwrap_or(false)),
+        }
+    }
+}
+
+impl FileAbstractionInstance for OverlayFileAbstractionInstance {
+
+    fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>> {
+        match (self.primary.get_file_content(id.clone())?, self.mounted.as_mut()) {
+            (Some(entry), _)      => Ok(Some(entry)),
+            (None, Some(mounted)) => mounted.get_file_content(id),
+            (None, None)          => Ok(None),
+        }
+    }
+
+    fn get_file_header<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<HeaderOnlyEntry>> {
+        match (self.primary.get_file_header(id.clone())?, self.mounted.as_mut()) {
+            (Some(entry), _)      => Ok(Some(entry)),
+            (None, Some(mounted)) => mounted.get_file_header(id),
+            (None, None)          => Ok(None),
+        }
+    }
+
+    /// Entries are always written to the primary backend, also mounted ones
+    ///
+    /// Mounted entries which were not changed are not written, so that reading them does not
+    /// copy them.
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        if self.is_unchanged_mounted(buf)? {
+            trace!("Not writing unchanged mounted entry: {}", self.path.display());
+            return Ok(())
+        }
+        self.primary.write_file_content(buf)
+    }
+}
+
+#[derive(Debug)]
+pub struct OverlayFileAbstraction {
+    store_base: PathBuf,
+    primary: Arc<dyn FileAbstraction>,
+    mounts: Vec<Mount>,
+    fs: FSFileAbstraction,
+}
+
+impl OverlayFileAbstraction {
+
+    pub(crate) fn new(store_base: PathBuf, primary: Arc<dyn FileAbstraction>, mounts: Vec<Mount>) -> Self {
+        OverlayFileAbstraction { store_base, primary, mounts, fs: FSFileAbstraction::default() }
+    }
+
+    /// The path in a mounted store for `path`, if it is below the prefix of a mount
+    fn mounted(&self, path: &Path) -> Option<PathBuf> {
+        self.mounts.iter().filter_map(|m| m.resolve(&self.store_base, path)).next()
+    }
+
+    /// The path in a mounted store for `path`, if the file is not in the primary backend but in a
+    /// mounted store
+    fn only_mounted(&self, path: &PathBuf) -> Result<Option<PathBuf>> {
+        if self.primary.exists(path)? {
+            return Ok(None)
+        }
+
+        match self.mounted(path) {
+            Some(mounted) => Ok(Some(mounted).filter(|m| m.is_file())),
+            None          => Ok(None),
+        }
+    }
+
+    fn read_only_error(path: &Path) -> Error {
+        format_err!("{} is in a read-only mounted store", path.display())
+    }
+}
+
+impl FileAbstraction for OverlayFileAbstraction {
+
+    fn remove_file(&self, path: &PathBuf) -> Result<()> {
+        if self.only_mounted(path)?.is_some() {
+            return Err(Self::read_only_error(path))
+        }
+        self.primary.remove_file(path)
+    }
+
+    /// Copying a mounted file copies it into the primary backend
+    fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        match self.only_mounted(from)? {
+            None          => self.primary.copy(from, to),
+            Some(mounted) => {
+                let content = self.fs
+                    .read_raw(&mounted)?
+                    .ok_or_else(|| format_err!("File does not exist: {}", mounted.display()))?;
+                self.primary.write_raw(to, &content)
+            },
+        }
+    }
+
+    fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        if self.only_mounted(from)?.is_some() {
+            return Err(Self::read_only_error(from))
+        }
+        self.primary.rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &PathBuf) -> Result<()> {
+        self.primary.create_dir_all(path)
+    }
+
+    fn exists(&self, path: &PathBuf) -> Result<bool> {
+        Ok(self.primary.exists(path)? || self.mounted(path).map(|m| m.exists()).unwrap_or(false))
+    }
+
+    fn is_file(&self, path: &PathBuf) -> Result<bool> {
+        Ok(self.primary.is_file(path)? || self.mounted(path).map(|m| m.is_file()).unwrap_or(false))
+    }
+
+    fn new_instance(&self, p: PathBuf) -> Box<dyn FileAbstractionInstance> {
+        let mounted_path = self.mounted(&p);
+        Box::new(OverlayFileAbstractionInstance {
+            primary: self.primary.new_instance(p.clone()),
+            primary_backend: self.primary.clone(),
+            mounted: mounted_path.clone().map(|m| self.fs.new_instance(m)),
+            mounted_path,
+            path: p,
+        })
+    }
+
+    fn drain(&self) -> Result<Drain> {
+        self.primary.drain()
+    }
+
+    fn fill(&mut self, _: Drain) -> Result<()> {
+        Err(format_err!("Cannot fill a store with mounted stores"))
+    }
+
+    fn pathes_recursively<'a>(&self,
+                          basepath: PathBuf,
+                          storepath: &'a PathBuf,
+                          backend: Arc<dyn FileAbstraction>)
+        -> Result<PathIterator<'a>>
+    {
+        let primary = self.primary
+            .pathes_recursively(basepath.clone(), storepath, backend.clone())?
+            .into_builder();
+
+        let mounts = self.mounts
+            .iter()
+            .map(|m| MountPathIterBuilder {
+                basepath: basepath.clone(),
+                virtual_base: self.store_base.join(&m.prefix),
+                mounted_base: m.path.clone(),
+            })
+            .collect();
+
+        let builder = OverlayPathIterBuilder {
+            primary: Some(primary),
+            primary_backend: self.primary.clone(),
+            mounts,
+        };
+        Ok(PathIterator::new(Box::new(builder), storepath, backend))
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        match self.primary.read_raw(path)? {
+            Some(content) => Ok(Some(content)),
+            None          => match self.mounted(path) {
+                Some(mounted) => self.fs.read_raw(&mounted),
+                None          => Ok(None),
+            },
+        }
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        self.primary.write_raw(path, content)
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        self.primary.remove_raw(path)
+    }
+
+    /// The data of attachments is looked up in all mounted stores, as mounted entries refer to the
+    /// attachments of their store
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        if let Some(content) = self.primary.read_bytes(path)? {
+            return Ok(Some(content))
+        }
+
+        let rest = match path.strip_prefix(&self.store_base) {
+            Ok(rest) => rest,
+            Err(_)   => return Ok(None),
+        };
+
+        for mount in self.mounts.iter() {
+            if let Some(content) = self.fs.read_bytes(&mount.path.join(rest))? {
+                return Ok(Some(content))
+            }
+        }
+        Ok(None)
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        self.primary.write_bytes(path, content)
+    }
+
+    fn modified(&self, path: &PathBuf) -> Result<Option<SystemTime>> {
+        self.primary.modified(path)
+    }
+
+    fn batch_finished(&self) -> Result<()> {
+        self.primary.batch_finished()
+    }
+
+    fn session_finished(&self) -> Result<()> {
+        self.primary.session_finished()
+    }
+
+    fn is_shared(&self) -> bool {
+        self.primary.is_shared()
+    }
+
+    fn is_read_only(&self, path: &PathBuf) -> Result<bool> {
+        self.only_mounted(path).map(|m| m.is_some())
+    }
+}
+
+/// Iterates over the files of the primary backend and of all mounted stores
+///
+/// Files in mounted stores which are shadowed by a file in the primary backend are skipped.
+#[derive(Debug)]
+struct OverlayPathIterBuilder {
+    /// `None` if the primary backend does not have the collection
+    primary: Option<Box<dyn PathIterBuilder>>,
+    primary_backend: Arc<dyn FileAbstraction>,
+    mounts: Vec<MountPathIterBuilder>,
+}
+
+impl PathIterBuilder for OverlayPathIterBuilder {
+    fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
+        let primary = self.primary
+            .as_ref()
+            .map(|p| p.build_iter())
+            .unwrap_or_else(|| Box::new(::std::iter::empty()));
+
+        let mounts = self.mounts
+            .iter()
+            .map(|m| {
+                let primary_backend = self.primary_backend.clone();
+                m.build_iter().filter(move |r| match *r {
+                    Err(_)       => true,
+                    Ok(ref path) => !primary_backend.exists(path).unwrap_or(false),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Box::new(primary.chain(mounts.into_iter().flatten()))
+    }
+
+    fn in_collection(&mut self, c: &str) -> Result<()> {
+        let primary_err = match self.primary.as_mut().map(|p| p.in_collection(c)) {
+            Some(Err(e)) => {
+                self.primary = None;
+                Some(e)
+            },
+            _ => None,
+        };
+
+        let mounts = self.mounts
+            .drain(..)
+            .filter_map(|mut m| if m.in_collection(c) { Some(m) } else { None })
+            .collect::<Vec<_>>();
+        self.mounts = mounts;
+
+        match primary_err {
+            Some(e) if self.mounts.is_empty() => Err(e),
+            _                                 => Ok(()),
+        }
+    }
+}
+
+/// Iterates over the files of one mounted store, as paths in the store it is mounted in
+#[derive(Debug)]
+struct MountPathIterBuilder {
+    /// The directory to iterate over, in the store the mounted store is mounted in
+    basepath: PathBuf,
+
+    /// Where the mounted store appears in the store it is mounted in
+    virtual_base: PathBuf,
+
+    /// Where the mounted store is
+    mounted_base: PathBuf,
+}
+
+impl MountPathIterBuilder {
+
+    /// The directory to iterate over in the mounted store, `None` if `basepath` is not in the
+    /// mounted store and the mounted store is not below `basepath`
+    fn mounted_basepath(&self) -> Option<PathBuf> {
+        if let Ok(rest) = self.basepath.strip_prefix(&self.virtual_base) {
+            Some(self.mounted_base.join(rest))
+        } else if self.virtual_base.starts_with(&self.basepath) {
+            Some(self.mounted_base.clone())
+        } else {
+            None
+        }
+    }
+
+    fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
+        let mounted_basepath = match self.mounted_basepath() {
+            Some(path) => path,
+            None       => return Box::new(::std::iter::empty()),
+        };
+
+        let mounted_base = self.mounted_base.clone();
+        let virtual_base = self.virtual_base.clone();
+        let iter = WalkDirPathIterBuilder::new(mounted_base.clone(), mounted_basepath)
+            .build_iter()
+            .map(move |r| r.and_then(|path| {
+                path.strip_prefix(&mounted_base)
+                    .map(|rest| virtual_base.join(rest))
+                    .map_err(Error::from)
+            }));
+        Box::new(iter)
+    }
+
+    /// Restrict the iterator to the collection `c`, returns whether the mounted store has files in
+    /// that collection
+    fn in_collection(&mut self, c: &str) -> bool {
+        self.basepath.push(c);
+        self.mounted_basepath().map(|p| p.exists()).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use crate::store::Store;
+
+    fn mounting_store(team: &TempDir) -> Store {
+        let config = ::toml::de::from_str(&format!(r#"
+        [store.mounts]
+            team = "{}"
+        "#, team.path().display())).ok();
+        Store::new_inmemory(PathBuf::from("/"), &config).unwrap()
+    }
+
+    fn write_team_entry(team: &TempDir, id: &str, content: &str) {
+        let path = team.path().join(id);
+        ::std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        ::std::fs::write(path, format!("---\n[imag]\nversion = \"{}\"\n---\n{}", env!("CARGO_PKG_VERSION"), content)).unwrap();
+    }
+
+    fn ids(store: &Store, collection: Option<&str>) -> Vec<String> {
+        let entries = store.entries().unwrap();
+        let entries = match collection {
+            Some(c) => entries.in_collection(c).unwrap(),
+            None    => entries,
+        };
+        let mut ids = entries
+            .into_storeid_iter()
+            .map(|id| id.unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_union() {
+        let team  = TempDir::new("imag-store-team").unwrap();
+        write_team_entry(&team, "wiki/foo", "team foo");
+        write_team_entry(&team, "wiki/bar", "team bar");
+
+        let store = mounting_store(&team);
+        let _ = store.create(PathBuf::from("diary/today")).unwrap();
+        let _ = store.get(PathBuf::from("team/wiki/bar")).unwrap().unwrap(); // not written back
+
+        assert_eq!(ids(&store, None), vec!["diary/today", "team/wiki/bar", "team/wiki/foo"]);
+        assert_eq!(ids(&store, Some("team")), vec!["team/wiki/bar", "team/wiki/foo"]);
+        assert_eq!(ids(&store, Some("team/wiki")), vec!["team/wiki/bar", "team/wiki/foo"]);
+        assert_eq!(ids(&store, Some("diary")), vec!["diary/today"]);
+
+        let foo = store.get(PathBuf::from("team/wiki/foo")).unwrap().unwrap();
+        assert_eq!(foo.get_content(), "team foo");
+        assert!(store.get(PathBuf::from("team/wiki/baz")).unwrap().is_none());
+        assert!(store.create(PathBuf::from("team/wiki/bar")).is_err());
+
+        // only mounted, so it cannot be deleted
+        assert!(store.delete(PathBuf::from("team/wiki/bar")).is_err());
+    }
+
+    #[test]
+    fn test_index_sees_mounted_entries() {
+        let team = TempDir::new("imag-store-team").unwrap();
+        let path = team.path().join("wiki/tagged");
+        ::std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let text = format!("---\n[imag]\nversion = \"{}\"\n[category]\nvalue = \"work\"\n---\n", env!("CARGO_PKG_VERSION"));
+        ::std::fs::write(path, text).unwrap();
+
+        let store = mounting_store(&team);
+        let found = store.index_lookup("category.value", "work").unwrap().collect::<::failure::Fallible<Vec<_>>>().unwrap();
+        assert_eq!(found, vec![crate::storeid::StoreId::new(PathBuf::from("team/wiki/tagged")).unwrap()]);
+    }
+
+    #[test]
+    fn test_writes_go_to_primary() {
+        let team  = TempDir::new("imag-store-team").unwrap();
+        write_team_entry(&team, "wiki/foo", "team foo");
+
+        let store = mounting_store(&team);
+        {
+            let mut foo = store.get(PathBuf::from("team/wiki/foo")).unwrap().unwrap();
+            *foo.get_content_mut() = String::from("my foo");
+            store.update(&mut foo).unwrap();
+        }
+
+        assert_eq!(store.get_copy(PathBuf::from("team/wiki/foo")).unwrap().get_content(), "my foo");
+        assert!(::std::fs::read_to_string(team.path().join("wiki/foo")).unwrap().ends_with("team foo"));
+        assert_eq!(ids(&store, None), vec!["team/wiki/foo"]);
+
+        // deleting the changed entry reveals the mounted one, which cannot be deleted
+        store.delete(PathBuf::from("team/wiki/foo")).unwrap();
+        assert_eq!(store.get_copy(PathBuf::from("team/wiki/foo")).unwrap().get_content(), "team foo");
+        assert!(store.delete(PathBuf::from("team/wiki/foo")).is_err());
+        assert!(store.move_by_id(crate::storeid::StoreId::new(PathBuf::from("team/wiki/foo")).unwrap(),
+                                 crate::storeid::StoreId::new(PathBuf::from("wiki/foo")).unwrap()).is_err());
+        assert!(team.path().join("wiki/foo").exists());
+    }
+}
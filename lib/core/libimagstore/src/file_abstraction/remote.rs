@@ -0,0 +1,275 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! A backend which accesses the store through `imag-server`
+//!
+//! All entries and the data of attachments are read from and written to the server, see the `rpc`
+//! module for the protocol. Other files which are not entries (the index, the history) are not
+//! available through the server, they are maintained by the server itself.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use failure::Fallible as Result;
+use failure::Error;
+use serde_json::Value as JsonValue;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use super::FileAbstraction;
+use super::FileAbstractionInstance;
+use super::Drain;
+use super::iter::is_visible_below;
+use crate::rpc::Client;
+use crate::attachment::hash_of_path;
+use crate::rpc::decode_data;
+use crate::rpc::encode_data;
+use crate::store::Entry;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::iter::PathIterator;
+use crate::file_abstraction::iter::PathIterBuilder;
+
+fn call(client: &Mutex<Client>, method: &str, params: JsonValue) -> Result<JsonValue> {
+    client
+        .lock()
+        .map_err(|_| Error::from(EM::LockError))?
+        .call(method, params)
+}
+
+/// The id of the entry at `path`, as the server expects it
+fn id_of(store_base: &Path, path: &Path) -> Result<String> {
+    path.strip_prefix(store_base)
+        .ok()
+        .and_then(Path::to_str)
+        .map(String::from)
+        .ok_or_else(|| format_err!("Not an entry in the store: {}", path.display()))
+}
+
+/// Get the text of the entry at `path` from the server
+fn get(client: &Mutex<Client>, store_base: &Path, path: &Path) -> Result<Option<String>> {
+    match call(client, "get", json!({ "id": id_of(store_base, path)? }))? {
+        JsonValue::Null          => Ok(None),
+        JsonValue::String(entry) => Ok(Some(entry)),
+        other                    => Err(format_err!("Unexpected response from imag-server: {}", other)),
+    }
+}
+
+#[derive(Debug)]
+pub struct RemoteFileAbstractionInstance {
+    store_base: PathBuf,
+    path:       PathBuf,
+    client:     Arc<Mutex<Client>>,
+}
+
+impl FileAbstractionInstance for RemoteFileAbstractionInstance {
+
+    fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>> {
+        match get(&self.client, &self.store_base, &self.path)? {
+            None        => Ok(None),
+            Some(entry) => Entry::from_str(id, &entry).map(Some),
+        }
+    }
+
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        let params = json!({ "id": id_of(&self.store_base, &self.path)?, "entry": buf.to_str()? });
+        call(&self.client, "update", params).map(|_| ())
+    }
+}
+
+/// A backend which forwards all operations on entries to `imag-server`
+///
+/// The entries are not locked by this backend, the server locks them while it changes them.
+#[derive(Debug)]
+pub struct RemoteFileAbstraction {
+    store_base: PathBuf,
+    client:     Arc<Mutex<Client>>,
+}
+
+impl RemoteFileAbstraction {
+    pub fn new(store_base: PathBuf, client: Client) -> RemoteFileAbstraction {
+        RemoteFileAbstraction { store_base, client: Arc::new(Mutex::new(client)) }
+    }
+
+    /// Whether `path` is an entry, the other files are not available through the server
+    fn is_entry(&self, path: &Path) -> bool {
+        is_visible_below(path, &self.store_base)
+    }
+}
+
+impl FileAbstraction for RemoteFileAbstraction {
+
+    fn remove_file(&self, path: &PathBuf) -> Result<()> {
+        call(&self.client, "delete", json!({ "id": id_of(&self.store_base, path)? })).map(|_| ())
+    }
+
+    fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        let entry = get(&self.client, &self.store_base, from)?
+            .ok_or_else(|| format_err!("Entry does not exist: {}", from.display()))?;
+        let params = json!({ "id": id_of(&self.store_base, to)?, "entry": entry });
+        call(&self.client, "update", params).map(|_| ())
+    }
+
+    fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        let params = json!({ "from": id_of(&self.store_base, from)?, "to": id_of(&self.store_base, to)? });
+        call(&self.client, "move", params).map(|_| ())
+    }
+
+    fn create_dir_all(&self, _: &PathBuf) -> Result<()> {
+        Ok(()) // the server creates the directories it needs
+    }
+
+    fn exists(&self, path: &PathBuf) -> Result<bool> {
+        if !self.is_entry(path) {
+            return Ok(false)
+        }
+
+        match call(&self.client, "exists", json!({ "id": id_of(&self.store_base, path)? }))? {
+            JsonValue::Bool(b) => Ok(b),
+            other              => Err(format_err!("Unexpected response from imag-server: {}", other)),
+        }
+    }
+
+    fn is_file(&self, path: &PathBuf) -> Result<bool> {
+        self.exists(path)
+    }
+
+    fn new_instance(&self, p: PathBuf) -> Box<dyn FileAbstractionInstance> {
+        Box::new(RemoteFileAbstractionInstance {
+            store_base: self.store_base.clone(),
+            path:       p,
+            client:     self.client.clone(),
+        })
+    }
+
+    fn drain(&self) -> Result<Drain> {
+        Err(format_err!("Cannot drain a store accessed through imag-server"))
+    }
+
+    fn fill(&mut self, _: Drain) -> Result<()> {
+        Err(format_err!("Cannot fill a store accessed through imag-server"))
+    }
+
+    fn pathes_recursively<'a>(&self,
+                          _basepath: PathBuf,
+                          storepath: &'a PathBuf,
+                          backend: Arc<dyn FileAbstraction>)
+        -> Result<PathIterator<'a>>
+    {
+        let builder = RemotePathIterBuilder {
+            store_base: self.store_base.clone(),
+            collection: None,
+            client:     self.client.clone(),
+        };
+        Ok(PathIterator::new(Box::new(builder), storepath, backend))
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        if self.is_entry(path) {
+            get(&self.client, &self.store_base, path)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        if !self.is_entry(path) {
+            return Err(format_err!("Cannot write {} through imag-server", path.display()))
+        }
+        let params = json!({ "id": id_of(&self.store_base, path)?, "entry": content });
+        call(&self.client, "update", params).map(|_| ())
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        if self.is_entry(path) {
+            self.remove_file(path)
+        } else {
+            Ok(()) // does not exist or, for attachments, removed by the server
+        }
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        let hash = match hash_of_path(&self.store_base, path) {
+            Some(hash) => hash,
+            None       => return Ok(None),
+        };
+
+        match call(&self.client, "attachment.read", json!({ "hash": hash }))? {
+            JsonValue::Null         => Ok(None),
+            JsonValue::String(data) => decode_data(&data).map(Some),
+            other                   => Err(format_err!("Unexpected response from imag-server: {}", other)),
+        }
+    }
+
+    /// The server stores the data under its hash, so only the data of attachments can be written
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        let hash = hash_of_path(&self.store_base, path)
+            .ok_or_else(|| format_err!("Cannot write {} through imag-server", path.display()))?;
+
+        match call(&self.client, "attachment.write", json!({ "data": encode_data(content) }))? {
+            JsonValue::String(ref written) if *written == hash => Ok(()),
+            other => Err(format_err!("Unexpected response from imag-server: {}", other)),
+        }
+    }
+
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+/// Builds iterators over the ids the server returns for a `query`
+#[derive(Debug)]
+pub(crate) struct RemotePathIterBuilder {
+    store_base: PathBuf,
+    collection: Option<String>,
+    client:     Arc<Mutex<Client>>,
+}
+
+impl PathIterBuilder for RemotePathIterBuilder {
+    fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
+        let params = match self.collection {
+            Some(ref c) => json!({ "collection": c }),
+            None        => json!({}),
+        };
+
+        let ids = match call(&self.client, "query", params) {
+            Ok(JsonValue::Array(ids)) => ids,
+            Ok(other)                 => {
+                let e = format_err!("Unexpected response from imag-server: {}", other);
+                return Box::new(::std::iter::once(Err(e)))
+            },
+            Err(e)                    => return Box::new(::std::iter::once(Err(e))),
+        };
+
+        let base = self.store_base.clone();
+        Box::new(ids.into_iter().map(move |id| match id {
+            JsonValue::String(id) => Ok(base.join(id)),
+            other                 => Err(format_err!("Unexpected id from imag-server: {}", other)),
+        }))
+    }
+
+    fn in_collection(&mut self, c: &str) -> Result<()> {
+        self.collection = Some(match self.collection.take() {
+            Some(outer) => format!("{}/{}", outer, c),
+            None        => String::from(c),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! A backend which stores all files in a single SQLite database
+//!
+//! See the `sqlite` module for the details.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use toml::Value;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use super::FileAbstraction;
+use super::FileAbstractionInstance;
+use super::Drain;
+use crate::sqlite::Database;
+use crate::store::Entry;
+use crate::storeid::StoreId;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::iter::PathIterator;
+use crate::file_abstraction::iter::PathIterBuilder;
+
+#[derive(Debug)]
+pub struct SqliteFileAbstractionInstance {
+    db: Database,
+    path: PathBuf,
+}
+
+impl FileAbstractionInstance for SqliteFileAbstractionInstance {
+
+    fn get_file_content<'a>(&mut self, id: StoreIdWithBase<'a>) -> Result<Option<Entry>> {
+        debug!("Getting file from database: {:?}", self.path);
+        match self.db.read(&self.path)? {
+            None    => Ok(None),
+            Some(s) => Entry::from_str(id, &s).map(Some),
+        }
+    }
+
+    fn write_file_content(&mut self, buf: &Entry) -> Result<()> {
+        self.db.write(&self.path, &buf.to_str()?)
+    }
+}
+
+#[derive(Debug)]
+pub struct SqliteFileAbstraction {
+    store_path: PathBuf,
+    db: Database,
+}
+
+impl SqliteFileAbstraction {
+
+    /// Open the database of the store at `store_path`, creating the store directory if the
+    /// configuration allows it
+    pub fn from_config(store_path: PathBuf, config: &Option<Value>) -> Result<SqliteFileAbstraction> {
+        use crate::configuration::config_implicit_store_create_allowed;
+
+        if !store_path.exists() {
+            if !config_implicit_store_create_allowed(config)? {
+                return Err(format_err!("CreateStoreDirDenied"))
+                    .context(EM::FileError)
+                    .context(EM::IO)
+                    .map_err(Error::from)
+            }
+
+            ::std::fs::create_dir_all(&store_path).context(EM::DirNotCreated)?;
+        }
+
+        let db = Database::open(&store_path)?;
+        Ok(SqliteFileAbstraction { store_path, db })
+    }
+}
+
+impl FileAbstraction for SqliteFileAbstraction {
+
+    fn remove_file(&self, path: &PathBuf) -> Result<()> {
+        self.db.remove(path)
+    }
+
+    fn copy(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.db.copy(from, to)
+    }
+
+    fn rename(&self, from: &PathBuf, to: &PathBuf) -> Result<()> {
+        self.db.rename(from, to)
+    }
+
+    /// There are no directories in the database
+    fn create_dir_all(&self, _: &PathBuf) -> Result<()> {
+        Ok(())
+    }
+
+    fn exists(&self, path: &PathBuf) -> Result<bool> {
+        self.db.exists(path)
+    }
+
+    fn is_file(&self, path: &PathBuf) -> Result<bool> {
+        self.db.is_file(path)
+    }
+
+    fn new_instance(&self, p: PathBuf) -> Box<dyn FileAbstractionInstance> {
+        Box::new(SqliteFileAbstractionInstance { db: self.db.clone(), path: p })
+    }
+
+    fn drain(&self) -> Result<Drain> {
+        self.db
+            .paths_below(&self.store_path, &self.store_path)?
+            .into_iter()
+            .map(|path| {
+                let id      = StoreId::new(path.strip_prefix(&self.store_path)?.to_path_buf())?;
+                let content = self.db.read(&path)?.ok_or(EM::FileNotFound)?;
+                Entry::from_str(id, &content).map(|entry| (path, entry))
+            })
+            .collect::<Result<_>>()
+            .map(Drain::new)
+    }
+
+    fn fill(&mut self, mut d: Drain) -> Result<()> {
+        d.iter().try_for_each(|(path, element)| self.new_instance(path).write_file_content(&element))
+    }
+
+    fn pathes_recursively<'a>(&self,
+                          basepath: PathBuf,
+                          storepath: &'a PathBuf,
+                          backend: Arc<dyn FileAbstraction>)
+        -> Result<PathIterator<'a>>
+    {
+        trace!("Building PathIterator object (sqlite implementation)");
+        let builder = SqlitePathIterBuilder { db: self.db.clone(), root: basepath.clone(), basepath };
+        Ok(PathIterator::new(Box::new(builder), storepath, backend))
+    }
+
+    fn read_raw(&self, path: &PathBuf) -> Result<Option<String>> {
+        self.db.read(path)
+    }
+
+    fn write_raw(&self, path: &PathBuf, content: &str) -> Result<()> {
+        self.db.write(path, content)
+    }
+
+    fn remove_raw(&self, path: &PathBuf) -> Result<()> {
+        self.db.remove(path)
+    }
+
+    fn read_bytes(&self, path: &PathBuf) -> Result<Option<Vec<u8>>> {
+        self.db.read_bytes(path)
+    }
+
+    fn write_bytes(&self, path: &PathBuf, content: &[u8]) -> Result<()> {
+        self.db.write_bytes(path, content)
+    }
+
+    fn modified(&self, path: &PathBuf) -> Result<Option<SystemTime>> {
+        self.db.modified(path)
+    }
+}
+
+/// Queries the database for the files below `basepath`
+#[derive(Debug)]
+pub struct SqlitePathIterBuilder {
+    db: Database,
+
+    /// The store (or the trash) the iterated files belong to
+    root: PathBuf,
+
+    basepath: PathBuf,
+}
+
+impl PathIterBuilder for SqlitePathIterBuilder {
+    fn build_iter(&self) -> Box<dyn Iterator<Item = Result<PathBuf>>> {
+        match self.db.paths_below(&self.basepath, &self.root) {
+            Ok(paths) => Box::new(paths.into_iter().map(Ok)),
+            Err(e)    => Box::new(::std::iter::once(Err(e))),
+        }
+    }
+
+    fn in_collection(&mut self, c: &str) -> Result<()> {
+        debug!("Altering PathIterBuilder path with: {:?}", c);
+        self.basepath.push(c);
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Checking the store for broken entries
+//!
+//! `Store::fsck()` reads every file in the store and reports the problems it finds:
+//! files which cannot be parsed as an entry, headers without `imag.version` or with values
+//! outside of a section, entries which are not in one of the known collections (see
+//! `store.collections`) and attachments whose data is missing or damaged. `Store::repair()` fixes the problems which can be fixed.
+//!
+//! Problems of the links between entries are checked by `libimagentrylink`.
+
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+
+use crate::storeid::StoreId;
+
+/// The collections the imag modules put their entries in
+const DEFAULT_COLLECTIONS : &[&str] = &[
+    "annotations",
+    "bookmark",
+    "calendar",
+    "category",
+    "contact",
+    "diary",
+    "habit",
+    "links",
+    "mail",
+    "notes",
+    "timetrack",
+    "todo",
+    "url",
+    "wiki",
+];
+
+/// A problem with an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The file cannot be parsed as an entry
+    Unparseable(String),
+
+    /// The header has no `imag` section
+    MissingMainSection,
+
+    /// The `imag` section of the header has no valid `imag.version`
+    MissingVersion,
+
+    /// The header has values at the top level which are not sections
+    NonTableInHeader(Vec<String>),
+
+    /// The entry is not in one of the known collections
+    UnknownCollection,
+
+    /// The header does not match the schema from `store.schema`
+    SchemaViolation(String),
+
+    /// The list of attachments is invalid or the data of an attachment is missing or damaged
+    BrokenAttachment(String),
+}
+
+impl Problem {
+
+    /// Whether `Store::repair()` can fix the problem without losing anything
+    pub fn is_safely_repairable(&self) -> bool {
+        is_match!(*self, Problem::MissingMainSection | Problem::MissingVersion)
+    }
+
+    /// Whether `Store::repair()` can fix the problem at all
+    ///
+    /// An unparseable file is repaired by replacing its header, which moves the old header into
+    /// the content.
+    pub fn is_repairable(&self) -> bool {
+        self.is_safely_repairable() || is_match!(*self, Problem::Unparseable(_))
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Problem::Unparseable(ref e)       => write!(fmt, "Cannot be parsed: {}", e),
+            Problem::MissingMainSection       => write!(fmt, "No 'imag' section in header"),
+            Problem::MissingVersion           => write!(fmt, "No valid 'imag.version' in header"),
+            Problem::NonTableInHeader(ref ks) => write!(fmt, "Values outside of a section in header: {}", ks.join(", ")),
+            Problem::UnknownCollection        => write!(fmt, "Not in a known collection"),
+            Problem::SchemaViolation(ref v)   => write!(fmt, "Header does not match the schema: {}", v),
+            Problem::BrokenAttachment(ref e)  => write!(fmt, "Broken attachment: {}", e),
+        }
+    }
+}
+
+/// A problem found by `Store::fsck()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub id: StoreId,
+    pub problem: Problem,
+}
+
+/// Find the problems in the header of an entry
+pub(crate) fn header_problems(header: &Value) -> Result<Vec<Problem>> {
+    let table = header
+        .as_table()
+        .ok_or_else(|| format_err!("HeaderTypeFailure"))?;
+
+    let mut problems = vec![];
+    match header.read("imag")? {
+        Some(&Value::Table(_)) => {
+            let version_valid = match header.read("imag.version")? {
+                Some(Value::String(s)) => ::semver::Version::parse(s).is_ok(),
+                _                      => false,
+            };
+
+            if !version_valid {
+                problems.push(Problem::MissingVersion);
+            }
+        },
+        _ => problems.push(Problem::MissingMainSection),
+    }
+
+    let non_tables = table
+        .iter()
+        .filter(|&(_, v)| !v.is_table())
+        .map(|(k, _)| k.clone())
+        .collect::<Vec<_>>();
+
+    if !non_tables.is_empty() {
+        problems.push(Problem::NonTableInHeader(non_tables));
+    }
+
+    Ok(problems)
+}
+
+/// Put the version of this imag into the `imag` section of `header`
+pub(crate) fn repair_version(header: &mut Value) -> Result<()> {
+    let table = header
+        .as_table_mut()
+        .ok_or_else(|| format_err!("HeaderTypeFailure"))?;
+
+    let imag = table
+        .entry(String::from("imag"))
+        .or_insert_with(|| Value::Table(::toml::map::Map::new()));
+    if !imag.is_table() {
+        *imag = Value::Table(::toml::map::Map::new());
+    }
+
+    if let Value::Table(ref mut imag) = *imag {
+        let _ = imag.insert(String::from("version"), Value::String(String::from(env!("CARGO_PKG_VERSION"))));
+    }
+    Ok(())
+}
+
+/// Get the known collections: the ones of the imag modules and the ones in `store.collections`
+pub(crate) fn config_collections(config: &Option<Value>) -> Result<Vec<String>> {
+    let mut collections = DEFAULT_COLLECTIONS.iter().map(|s| String::from(*s)).collect::<Vec<_>>();
+
+    if let Some(ref config) = *config {
+        match config.read("store.collections").context(format_err!("Error reading 'store.collections' in configuration"))? {
+            None => {},
+            Some(Value::Array(names)) => for name in names {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| format_err!("'store.collections' must be an array of strings"))?;
+                collections.push(String::from(name));
+            },
+            Some(_) => return Err(format_err!("'store.collections' must be an array of strings")),
+        }
+    }
+
+    Ok(collections)
+}
+
+/// Whether `id` is in one of `collections`
+pub(crate) fn in_collection(id: &StoreId, collections: &[String]) -> bool {
+    let mut components = id.local().components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first
+            .as_os_str()
+            .to_str()
+            .map(|first| collections.iter().any(|c| c == first))
+            .unwrap_or(false),
+        _ => false, // not in any collection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_header_problems() {
+        let header = ::toml::de::from_str::<Value>(r#"
+        foo = 1
+        [imag]
+        version = "not a version"
+        "#).unwrap();
+
+        let problems = header_problems(&header).unwrap();
+        assert_eq!(problems, vec![Problem::MissingVersion, Problem::NonTableInHeader(vec![String::from("foo")])]);
+
+        let mut header = ::toml::de::from_str::<Value>("[links]\ninternal = []").unwrap();
+        assert_eq!(header_problems(&header).unwrap(), vec![Problem::MissingMainSection]);
+        repair_version(&mut header).unwrap();
+        assert!(header_problems(&header).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collections() {
+        let config = ::toml::de::from_str(r#"
+        [store]
+            collections = [ "scripts" ]
+        "#).unwrap();
+        let collections = config_collections(&Some(config)).unwrap();
+
+        let id = |s: &str| StoreId::new(PathBuf::from(s)).unwrap();
+        assert!(in_collection(&id("notes/foo"), &collections));
+        assert!(in_collection(&id("scripts/foo"), &collections));
+        assert!(!in_collection(&id("scripts"), &collections));
+        assert!(!in_collection(&id("unknown/foo"), &collections));
+    }
+}
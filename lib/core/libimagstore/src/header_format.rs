@@ -0,0 +1,327 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! Formats of the header of an entry
+//!
+//! The header of an entry is written between two `---` lines at the start of the file. It is TOML
+//! by default, but can also be YAML or JSON, so that other tools which expect YAML front matter can
+//! read the entries. In memory, the header is always a `toml::Value`.
+//!
+//! When reading an entry, the format of its header is detected. When writing it, the format it was
+//! read in is kept. New entries use the format from `store.header-format`.
+//!
+//! YAML and JSON have no datetime type. TOML datetimes are written as strings in these formats, so
+//! they are strings when the header is read again.
+
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Error as FmtError;
+use std::str::FromStr;
+
+use toml::Value;
+use toml::map::Map;
+use serde_json::Value as JsonValue;
+use serde_json::Number;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+/// The format of the header of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Default for HeaderFormat {
+    fn default() -> HeaderFormat {
+        HeaderFormat::Toml
+    }
+}
+
+impl FromStr for HeaderFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HeaderFormat> {
+        match s {
+            "toml" => Ok(HeaderFormat::Toml),
+            "yaml" => Ok(HeaderFormat::Yaml),
+            "json" => Ok(HeaderFormat::Json),
+            other  => Err(format_err!("Unknown header format: '{}', expected 'toml', 'yaml' or 'json'", other)),
+        }
+    }
+}
+
+impl Display for HeaderFormat {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        match *self {
+            HeaderFormat::Toml => write!(fmt, "toml"),
+            HeaderFormat::Yaml => write!(fmt, "yaml"),
+            HeaderFormat::Json => write!(fmt, "json"),
+        }
+    }
+}
+
+impl HeaderFormat {
+
+    /// Parse the header text `s`, detecting its format
+    ///
+    /// A header starting with `{` is JSON. Otherwise it is TOML, or YAML if it cannot be parsed as
+    /// TOML and looks like YAML (it starts with a `key: value` line). In all other cases, the TOML
+    /// error is returned, so broken TOML headers are not taken for YAML.
+    pub fn detect_and_parse(s: &str) -> Result<(Value, HeaderFormat)> {
+        if s.trim_start().starts_with('{') {
+            return HeaderFormat::Json.parse(s).map(|v| (v, HeaderFormat::Json))
+        }
+
+        match HeaderFormat::Toml.parse(s) {
+            Ok(v)  => Ok((v, HeaderFormat::Toml)),
+            Err(e) => if looks_like_yaml(s) {
+                debug!("Header is not TOML, trying YAML");
+                HeaderFormat::Yaml.parse(s).map(|v| (v, HeaderFormat::Yaml))
+            } else {
+                Err(e)
+            },
+        }
+    }
+
+    /// Parse the header text `s` in this format
+    pub fn parse(self, s: &str) -> Result<Value> {
+        let value = match self {
+            HeaderFormat::Toml => return ::toml::de::from_str(s)
+                .context(EM::TomlDeserError)
+                .map_err(Error::from),
+
+            HeaderFormat::Yaml => if s.trim().is_empty() {
+                JsonValue::Object(Default::default())
+            } else {
+                ::serde_yaml::from_str(s).context(format_err!("YAML header cannot be parsed"))?
+            },
+
+            HeaderFormat::Json => ::serde_json::from_str(s)
+                .context(format_err!("JSON header cannot be parsed"))?,
+        };
+
+        match json_to_toml(value)? {
+            v @ Value::Table(_) => Ok(v),
+            _                   => Err(format_err!("Header is not a table")),
+        }
+    }
+
+    /// Serialize the header in this format
+    ///
+    /// The result ends with a newline, so it can be written between the `---` lines directly.
+    /// Datetimes are written as strings in YAML and JSON.
+    pub fn serialize(self, header: &Value) -> Result<String> {
+        match self {
+            HeaderFormat::Toml => ::toml::ser::to_string_pretty(header)
+                .map_err(Error::from)
+                .context(format_err!("TOML Error"))
+                .map_err(Error::from),
+
+            HeaderFormat::Yaml => {
+                let yaml = ::serde_yaml::to_string(&toml_to_json(header))
+                    .context(format_err!("YAML Error"))?;
+                Ok(if yaml.trim() == "{}" { String::new() } else { yaml })
+            },
+
+            HeaderFormat::Json => ::serde_json::to_string_pretty(&toml_to_json(header))
+                .context(format_err!("JSON Error"))
+                .map_err(Error::from)
+                .map(|json| json + "\n"),
+        }
+    }
+}
+
+/// Whether the header text `s` starts like a YAML mapping
+///
+/// This is the case if the first line which is neither empty nor a comment is a `%YAML` directive
+/// or a `key: value` (or `key:`) line without a `=` before the colon, which TOML would have.
+fn looks_like_yaml(s: &str) -> bool {
+    s.lines()
+        .map(str::trim_end)
+        .find(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|line| {
+            if line.starts_with("%YAML") {
+                return true
+            }
+
+            if line.starts_with(char::is_whitespace) || line.starts_with('[') {
+                return false
+            }
+
+            match line.find(':') {
+                None      => false,
+                Some(pos) => !line[..pos].contains('=')
+                    && line[pos + 1..].chars().next().map(char::is_whitespace).unwrap_or(true),
+            }
+        })
+        .unwrap_or(false)
+}
+
+/// Convert a TOML value to JSON
+///
+/// JSON has no datetime type, datetimes become strings. Thus, they are strings after the JSON (or
+/// YAML) is converted back with `json_to_toml()`.
+fn toml_to_json(value: &Value) -> JsonValue {
+    match *value {
+        Value::String(ref s)   => JsonValue::String(s.clone()),
+        Value::Integer(i)      => JsonValue::Number(Number::from(i)),
+        Value::Float(f)        => Number::from_f64(f).map(JsonValue::Number).unwrap_or(JsonValue::Null),
+        Value::Boolean(b)      => JsonValue::Bool(b),
+        Value::Datetime(ref d) => JsonValue::String(d.to_string()),
+        Value::Array(ref a)    => JsonValue::Array(a.iter().map(toml_to_json).collect()),
+        Value::Table(ref t)    => JsonValue::Object(t.iter().map(|(k, v)| (k.clone(), toml_to_json(v))).collect()),
+    }
+}
+
+/// Convert a JSON value to TOML, which fails for `null`
+fn json_to_toml(value: JsonValue) -> Result<Value> {
+    match value {
+        JsonValue::Null      => Err(format_err!("Header contains a null value, which is not supported")),
+        JsonValue::Bool(b)   => Ok(Value::Boolean(b)),
+        JsonValue::String(s) => Ok(Value::String(s)),
+        JsonValue::Number(n) => n.as_i64()
+            .map(Value::Integer)
+            .or_else(|| n.as_f64().map(Value::Float))
+            .ok_or_else(|| format_err!("Number in header out of range: {}", n)),
+        JsonValue::Array(a)  => a.into_iter().map(json_to_toml).collect::<Result<Vec<_>>>().map(Value::Array),
+        JsonValue::Object(o) => o.into_iter()
+            .map(|(k, v)| json_to_toml(v).map(|v| (k, v)))
+            .collect::<Result<Map<_, _>>>()
+            .map(Value::Table),
+    }
+}
+
+/// Get the header format for new entries from the "store.header-format" key
+pub(crate) fn config_header_format(config: &Option<Value>) -> Result<HeaderFormat> {
+    use toml_query::read::TomlValueReadTypeExt;
+
+    match *config {
+        None        => Ok(HeaderFormat::default()),
+        Some(ref t) => t.read_string("store.header-format")
+            .context(format_err!("Error reading 'store.header-format' in configuration"))?
+            .map(|s| s.parse())
+            .transpose()
+            .map(Option::unwrap_or_default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Value {
+        ::toml::de::from_str(r#"
+        [imag]
+        version = "0.10.0"
+
+        [tag]
+        values = ["a", "b"]
+
+        [todo]
+        priority = 2
+        progress = 0.5
+        done = false
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for format in &[HeaderFormat::Toml, HeaderFormat::Yaml, HeaderFormat::Json] {
+            let text = format.serialize(&header()).unwrap();
+            assert!(text.ends_with('\n'), "{} header does not end with newline", format);
+
+            let (parsed, detected) = HeaderFormat::detect_and_parse(&text).unwrap();
+            assert_eq!(parsed, header(), "{} header changed in roundtrip", format);
+            assert_eq!(detected, *format);
+        }
+    }
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let (header, format) = HeaderFormat::detect_and_parse("title: Note\ntags:\n  - a\n  - b\n").unwrap();
+
+        assert_eq!(format, HeaderFormat::Yaml);
+        assert_eq!(header.get("title"), Some(&Value::String(String::from("Note"))));
+        assert_eq!(header.get("tags").and_then(Value::as_array).map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(HeaderFormat::detect_and_parse("[imag\nversion = ").is_err());
+        assert!(HeaderFormat::detect_and_parse("- a list\n- not a table\n").is_err());
+        assert!(HeaderFormat::detect_and_parse("{\"a\": null}").is_err());
+    }
+
+    #[test]
+    fn test_broken_toml_is_not_yaml() {
+        // valid YAML (a string), but not a YAML mapping
+        let err = HeaderFormat::detect_and_parse("[imag]\nversion = \"0.10.0\"\nbroken\n").unwrap_err();
+        assert!(err.iter_chain().any(|c| c.to_string().to_lowercase().contains("toml")));
+
+        let err = HeaderFormat::detect_and_parse("imag.version = 0.10.0: 1\n").unwrap_err();
+        assert!(err.iter_chain().any(|c| c.to_string().to_lowercase().contains("toml")));
+
+        // broken YAML, the YAML error is returned
+        let err = HeaderFormat::detect_and_parse("title: [Note\n").unwrap_err();
+        assert!(err.iter_chain().any(|c| c.to_string().contains("YAML")));
+    }
+
+    #[test]
+    fn test_looks_like_yaml() {
+        assert!(looks_like_yaml("# comment\n\ntitle: Note\n"));
+        assert!(looks_like_yaml("tags:\n  - a\n"));
+        assert!(looks_like_yaml("%YAML 1.2\n---\na: 1\n"));
+        assert!(!looks_like_yaml("[imag]\nversion = \"0.10.0\"\n"));
+        assert!(!looks_like_yaml("url = \"http://example.com\"\n"));
+        assert!(!looks_like_yaml("\"a:b\" = 1\n"));
+        assert!(!looks_like_yaml(""));
+    }
+
+    #[test]
+    fn test_datetime_becomes_string() {
+        let header = ::toml::de::from_str::<Value>("date = 2019-01-01T12:00:00Z\n").unwrap();
+        for format in &[HeaderFormat::Yaml, HeaderFormat::Json] {
+            let text = format.serialize(&header).unwrap();
+            let (parsed, _) = HeaderFormat::detect_and_parse(&text).unwrap();
+            assert_eq!(parsed.get("date"), Some(&Value::String(String::from("2019-01-01T12:00:00Z"))));
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config = ::toml::de::from_str(r#"
+        [store]
+            header-format = "yaml"
+        "#).unwrap();
+        assert_eq!(config_header_format(&Some(config)).unwrap(), HeaderFormat::Yaml);
+        assert_eq!(config_header_format(&None).unwrap(), HeaderFormat::Toml);
+
+        let config = ::toml::de::from_str(r#"
+        [store]
+            header-format = "xml"
+        "#).unwrap();
+        assert!(config_header_format(&Some(config)).is_err());
+    }
+}
@@ -0,0 +1,551 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Revision history of entries
+//!
+//! Each time `Store::update()` changes an entry, the version which is replaced is recorded in
+//! `.history/<id>` in the store. The history file contains the latest version of the entry and,
+//! for each earlier version, a line based diff which turns the next newer version into it. Thus,
+//! restoring a version does not depend on git or any other tool and history files stay small.
+//!
+//! The history of an entry is moved with the entry by `Store::move_by_id()`, and into the trash
+//! and back with it. It is removed when the entry is removed for good.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use serde_json::Value as JsonValue;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::file_abstraction::FileAbstraction;
+use crate::storeid::StoreId;
+
+/// The reserved directory in the store where the history files live
+pub(crate) const HISTORY_DIR : &str = ".history";
+
+const HISTORY_VERSION : u64 = 1;
+
+/// The number of revisions kept per entry if `store.history.max-revisions` is not set
+const DEFAULT_MAX_REVISIONS : usize = 100;
+
+const DATETIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// If the changed part of an entry has more lines than this (old times new), the change is stored
+/// as a replacement of the whole part instead of computing a minimal diff
+const MAX_DIFF_COMPLEXITY : usize = 4_000_000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct HistoryConfig {
+    pub(crate) enabled: bool,
+
+    /// The number of revisions kept per entry, `None` for all
+    pub(crate) max_revisions: Option<usize>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { enabled: true, max_revisions: Some(DEFAULT_MAX_REVISIONS) }
+    }
+}
+
+/// Get the history settings from the `store.history` section of the configuration
+pub(crate) fn config_history(config: &Option<Value>) -> Result<HistoryConfig> {
+    let config = match *config {
+        None           => return Ok(HistoryConfig::default()),
+        Some(ref conf) => conf,
+    };
+
+    let enabled = config
+        .read_bool("store.history.enabled")
+        .context(format_err!("Error reading 'store.history.enabled' in configuration"))?
+        .unwrap_or(true);
+
+    let max_revisions = config
+        .read_int("store.history.max-revisions")
+        .context(format_err!("Error reading 'store.history.max-revisions' in configuration"))?
+        .map(|n| if n < 0 {
+            Err(format_err!("'store.history.max-revisions' must not be negative"))
+        } else {
+            Ok(n as usize)
+        })
+        .transpose()?
+        .unwrap_or(DEFAULT_MAX_REVISIONS);
+    let max_revisions = Some(max_revisions).filter(|n| *n > 0); // 0 means "keep all"
+
+    Ok(HistoryConfig { enabled, max_revisions })
+}
+
+/// One earlier version of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    /// The number of the revision, counting from 1 for the first version which was replaced
+    pub number: usize,
+
+    /// When this version was replaced by a newer one
+    pub replaced_at: NaiveDateTime,
+
+    /// Turns the next newer version into this one
+    patch: Patch,
+}
+
+/// The history of one entry
+#[derive(Debug)]
+pub(crate) struct History {
+    /// The latest version of the entry, as written by the store
+    head: String,
+
+    /// The earlier versions, oldest first
+    revisions: Vec<Revision>,
+}
+
+impl History {
+
+    pub(crate) fn path(store_base: &Path, id: &StoreId) -> PathBuf {
+        store_base.join(HISTORY_DIR).join(id.local())
+    }
+
+    /// Load the history of `id`, if there is one
+    pub(crate) fn load(store_base: &Path, id: &StoreId, backend: &Arc<dyn FileAbstraction>)
+        -> Result<Option<History>>
+    {
+        let path = History::path(store_base, id);
+        match backend.read_raw(&path)? {
+            None    => Ok(None),
+            Some(s) => History::from_json(&s)
+                .context(format_err!("Failed to read history of {} at {}", id, path.display()))
+                .map(Some)
+                .map_err(Error::from),
+        }
+    }
+
+    pub(crate) fn save(&self, store_base: &Path, id: &StoreId, backend: &Arc<dyn FileAbstraction>)
+        -> Result<()>
+    {
+        let path = History::path(store_base, id);
+        debug!("Writing history of {}: {} revisions", id, self.revisions.len());
+        let json = ::serde_json::to_string(&self.to_json()).map_err(Error::from)?;
+        backend.write_raw(&path, &json)
+    }
+
+    /// Remove the history of `id`, if there is one
+    pub(crate) fn remove(store_base: &Path, id: &StoreId, backend: &Arc<dyn FileAbstraction>)
+        -> Result<()>
+    {
+        let path = History::path(store_base, id);
+        if backend.read_raw(&path)?.is_some() {
+            debug!("Removing history of {}", id);
+            backend.remove_raw(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Move the history of `old_id` to `new_id`, if there is one
+    pub(crate) fn rename(store_base: &Path,
+                         old_id: &StoreId,
+                         new_id: &StoreId,
+                         backend: &Arc<dyn FileAbstraction>)
+        -> Result<()>
+    {
+        let old_path = History::path(store_base, old_id);
+        if let Some(content) = backend.read_raw(&old_path)? {
+            debug!("Moving history of {} to {}", old_id, new_id);
+            backend.write_raw(&History::path(store_base, new_id), &content)?;
+            backend.remove_raw(&old_path)?;
+        }
+        Ok(())
+    }
+
+    /// A new history, where `content` is the latest version
+    pub(crate) fn new(content: String) -> History {
+        History { head: content, revisions: vec![] }
+    }
+
+    /// Record that `old` was replaced by `new` at `now`
+    ///
+    /// If `old` is not the latest version in the history, the entry was altered without the store.
+    /// The latest version from the history is kept as a revision then, so nothing is lost.
+    pub(crate) fn record(&mut self, old: &str, new: &str, now: NaiveDateTime, max_revisions: Option<usize>) {
+        if self.head != old {
+            debug!("Entry was altered outside of the store, keeping the last known version");
+            let patch = Patch::diff(old, &self.head);
+            self.push(patch, now);
+        }
+
+        let patch = Patch::diff(new, old);
+        self.push(patch, now);
+        self.head = String::from(new);
+
+        if let Some(max) = max_revisions {
+            if self.revisions.len() > max {
+                let too_many = self.revisions.len() - max;
+                trace!("Dropping {} old revisions", too_many);
+                let _ = self.revisions.drain(..too_many);
+            }
+        }
+    }
+
+    fn push(&mut self, patch: Patch, replaced_at: NaiveDateTime) {
+        let number = self.revisions.last().map(|r| r.number + 1).unwrap_or(1);
+        self.revisions.push(Revision { number, replaced_at, patch });
+    }
+
+    /// The earlier versions, oldest first
+    pub(crate) fn revisions(&self) -> &[Revision] {
+        &self.revisions
+    }
+
+    /// Get the content of the entry at revision `number`
+    pub(crate) fn content_at(&self, number: usize) -> Result<String> {
+        let pos = self.revisions
+            .iter()
+            .position(|r| r.number == number)
+            .ok_or_else(|| format_err!("No such revision: {}", number))?;
+
+        self.revisions[pos..]
+            .iter()
+            .rev()
+            .try_fold(self.head.clone(), |content, rev| {
+                rev.patch.apply(&content).context(format_err!("Failed to restore revision {}", rev.number))
+            })
+            .map_err(Error::from)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let revisions = self.revisions
+            .iter()
+            .map(|r| json!({
+                "number": r.number,
+                "replaced": r.replaced_at.format(DATETIME_FORMAT).to_string(),
+                "patch": r.patch.to_json(),
+            }))
+            .collect::<Vec<_>>();
+
+        json!({
+            "version": HISTORY_VERSION,
+            "head": self.head,
+            "revisions": revisions,
+        })
+    }
+
+    fn from_json(s: &str) -> Result<History> {
+        let json : JsonValue = ::serde_json::from_str(s).map_err(Error::from)?;
+
+        match json.get("version").and_then(JsonValue::as_u64) {
+            Some(HISTORY_VERSION) => {},
+            Some(v) => return Err(format_err!("Unsupported history version: {}", v)),
+            None    => return Err(format_err!("History without version")),
+        }
+
+        let head = json
+            .get("head")
+            .and_then(JsonValue::as_str)
+            .map(String::from)
+            .ok_or_else(|| format_err!("History without 'head'"))?;
+
+        let revisions = json
+            .get("revisions")
+            .and_then(JsonValue::as_array)
+            .ok_or_else(|| format_err!("History without 'revisions'"))?
+            .iter()
+            .map(|r| {
+                let number = r.get("number")
+                    .and_then(JsonValue::as_u64)
+                    .ok_or_else(|| format_err!("Revision without 'number'"))? as usize;
+                let replaced_at = r.get("replaced")
+                    .and_then(JsonValue::as_str)
+                    .ok_or_else(|| format_err!("Revision without 'replaced'"))
+                    .and_then(|s| NaiveDateTime::parse_from_str(s, DATETIME_FORMAT).map_err(Error::from))?;
+                let patch = r.get("patch")
+                    .ok_or_else(|| format_err!("Revision without 'patch'"))
+                    .and_then(Patch::from_json)?;
+
+                Ok(Revision { number, replaced_at, patch })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(History { head, revisions })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatchOp {
+    /// Take this many lines from the old text
+    Keep(usize),
+
+    /// Skip this many lines of the old text
+    Delete(usize),
+
+    /// Insert these lines
+    Insert(Vec<String>),
+}
+
+/// A line based diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Patch(Vec<PatchOp>);
+
+impl Patch {
+
+    /// Compute the patch which turns `from` into `to`
+    fn diff(from: &str, to: &str) -> Patch {
+        let a = from.split_inclusive('\n').collect::<Vec<_>>();
+        let b = to.split_inclusive('\n').collect::<Vec<_>>();
+
+        let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+        let suffix = a[prefix..].iter().rev()
+            .zip(b[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+
+        let mut patch = Patch(vec![]);
+        patch.keep(prefix);
+        Patch::diff_lines(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix], &mut patch);
+        patch.keep(suffix);
+        patch
+    }
+
+    /// Longest common subsequence diff of `a` and `b`, appended to `patch`
+    fn diff_lines(a: &[&str], b: &[&str], patch: &mut Patch) {
+        if a.len().saturating_mul(b.len()) > MAX_DIFF_COMPLEXITY {
+            patch.delete(a.len());
+            patch.insert(b);
+            return
+        }
+
+        // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let width   = b.len() + 1;
+        let mut lcs = vec![0usize; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    ::std::cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                patch.keep(1);
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                patch.delete(1);
+                i += 1;
+            } else {
+                patch.insert(&b[j..=j]);
+                j += 1;
+            }
+        }
+    }
+
+    fn keep(&mut self, n: usize) {
+        if n == 0 {
+            return
+        }
+        match self.0.last_mut() {
+            Some(PatchOp::Keep(ref mut k)) => *k += n,
+            _ => self.0.push(PatchOp::Keep(n)),
+        }
+    }
+
+    fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return
+        }
+        match self.0.last_mut() {
+            Some(PatchOp::Delete(ref mut d)) => *d += n,
+            _ => self.0.push(PatchOp::Delete(n)),
+        }
+    }
+
+    fn insert(&mut self, lines: &[&str]) {
+        if lines.is_empty() {
+            return
+        }
+        let lines = lines.iter().map(|l| String::from(*l));
+        match self.0.last_mut() {
+            Some(PatchOp::Insert(ref mut v)) => v.extend(lines),
+            _ => self.0.push(PatchOp::Insert(lines.collect())),
+        }
+    }
+
+    /// Apply the patch to `text`
+    fn apply(&self, text: &str) -> Result<String> {
+        let mut lines  = text.split_inclusive('\n');
+        let mut result = String::with_capacity(text.len());
+
+        for op in self.0.iter() {
+            match *op {
+                PatchOp::Keep(n) => for _ in 0..n {
+                    result.push_str(lines.next().ok_or_else(|| format_err!("Patch does not match text"))?);
+                },
+                PatchOp::Delete(n) => for _ in 0..n {
+                    let _ = lines.next().ok_or_else(|| format_err!("Patch does not match text"))?;
+                },
+                PatchOp::Insert(ref new) => for line in new.iter() {
+                    result.push_str(line);
+                },
+            }
+        }
+
+        if lines.next().is_some() {
+            return Err(format_err!("Patch does not match text"))
+        }
+
+        Ok(result)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.0.iter().map(|op| match *op {
+            PatchOp::Keep(n)         => json!(["=", n]),
+            PatchOp::Delete(n)       => json!(["-", n]),
+            PatchOp::Insert(ref new) => json!(["+", new]),
+        }).collect())
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Patch> {
+        json.as_array()
+            .ok_or_else(|| format_err!("Patch is not an array"))?
+            .iter()
+            .map(|op| {
+                let invalid = || format_err!("Invalid patch operation: {}", op);
+                let kind = op.get(0).and_then(JsonValue::as_str).ok_or_else(invalid)?;
+                let arg  = op.get(1).ok_or_else(invalid)?;
+
+                match kind {
+                    "=" => arg.as_u64().map(|n| PatchOp::Keep(n as usize)).ok_or_else(invalid),
+                    "-" => arg.as_u64().map(|n| PatchOp::Delete(n as usize)).ok_or_else(invalid),
+                    "+" => arg.as_array()
+                        .and_then(|a| a.iter().map(|l| l.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+                        .map(PatchOp::Insert)
+                        .ok_or_else(invalid),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2019, 10, 17).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_patch_roundtrip() {
+        let texts = [
+            "",
+            "a\nb\nc\n",
+            "a\nc\n",
+            "a\nx\ny\nc",
+            "x\n",
+            "a\nb\nc\nd\ne\nf\n",
+            "f\ne\nd\nc\nb\na\n",
+        ];
+
+        for from in texts.iter() {
+            for to in texts.iter() {
+                let patch = Patch::diff(from, to);
+                assert_eq!(&patch.apply(from).unwrap(), to, "diff {:?} -> {:?}: {:?}", from, to, patch);
+
+                let json = patch.to_json();
+                assert_eq!(Patch::from_json(&json).unwrap(), patch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_patch_is_compact() {
+        let from  = (0..100).map(|i| format!("line {}\n", i)).collect::<String>();
+        let to    = from.replace("line 50\n", "changed\n");
+        let patch = Patch::diff(&from, &to);
+        assert_eq!(patch.0, vec![
+            PatchOp::Keep(50),
+            PatchOp::Delete(1),
+            PatchOp::Insert(vec![String::from("changed\n")]),
+            PatchOp::Keep(49),
+        ]);
+    }
+
+    #[test]
+    fn test_history_revisions() {
+        let mut history = History::new(String::from("one\n"));
+        history.record("one\n", "two\n", now(), None);
+        history.record("two\n", "three\n", now(), None);
+
+        // altered outside of the store
+        history.record("four\n", "five\n", now(), None);
+
+        let numbers = history.revisions().iter().map(|r| r.number).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1, 2, 3, 4]);
+        assert_eq!(history.content_at(1).unwrap(), "one\n");
+        assert_eq!(history.content_at(2).unwrap(), "two\n");
+        assert_eq!(history.content_at(3).unwrap(), "three\n");
+        assert_eq!(history.content_at(4).unwrap(), "four\n");
+        assert!(history.content_at(5).is_err());
+
+        let reloaded = History::from_json(&history.to_json().to_string()).unwrap();
+        assert_eq!(reloaded.revisions(), history.revisions());
+        assert_eq!(reloaded.content_at(1).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn test_max_revisions() {
+        let mut history = History::new(String::from("0\n"));
+        for i in 0..5 {
+            history.record(&format!("{}\n", i), &format!("{}\n", i + 1), now(), Some(2));
+        }
+
+        let numbers = history.revisions().iter().map(|r| r.number).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![4, 5]);
+        assert_eq!(history.content_at(4).unwrap(), "3\n");
+        assert!(history.content_at(1).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(config_history(&None).unwrap().max_revisions, Some(DEFAULT_MAX_REVISIONS));
+
+        let config = ::toml::de::from_str(r#"
+        [store.history]
+            enabled = true
+        "#).unwrap();
+        assert_eq!(config_history(&Some(config)).unwrap().max_revisions, Some(DEFAULT_MAX_REVISIONS));
+
+        let config = ::toml::de::from_str(r#"
+        [store.history]
+            max-revisions = 0
+        "#).unwrap();
+        assert_eq!(config_history(&Some(config)).unwrap().max_revisions, None);
+    }
+}
@@ -0,0 +1,290 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Hooks which are run before and after store operations
+//!
+//! Hooks are either registered in Rust code with `Store::register_hook()` or configured as
+//! external commands in the `[store.hooks]` section of the configuration:
+//!
+//! ```toml
+//! [store.hooks]
+//! pre-update  = [ "imag-validate-entry --strict" ]
+//! post-create = [ "notify-send 'imag: entry created'" ]
+//! ```
+//!
+//! If a hook fails, the store operation fails. If a "pre" hook fails, the operation is not
+//! executed at all, so "pre" hooks can be used to veto operations.
+
+use std::fmt::Display;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Error as FmtError;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::thread;
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::store::Entry;
+use crate::storeid::StoreId;
+
+/// The point in a store operation at which a hook is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookPosition {
+    PreCreate,
+    PostCreate,
+    PreRetrieve,
+    PostRetrieve,
+    PreUpdate,
+    PostUpdate,
+    PreDelete,
+    PostDelete,
+    PreMove,
+    PostMove,
+}
+
+impl HookPosition {
+
+    /// All hook positions
+    pub fn all() -> &'static [HookPosition] {
+        &[
+            HookPosition::PreCreate,
+            HookPosition::PostCreate,
+            HookPosition::PreRetrieve,
+            HookPosition::PostRetrieve,
+            HookPosition::PreUpdate,
+            HookPosition::PostUpdate,
+            HookPosition::PreDelete,
+            HookPosition::PostDelete,
+            HookPosition::PreMove,
+            HookPosition::PostMove,
+        ]
+    }
+
+    /// The name of the position, as used in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            HookPosition::PreCreate    => "pre-create",
+            HookPosition::PostCreate   => "post-create",
+            HookPosition::PreRetrieve  => "pre-retrieve",
+            HookPosition::PostRetrieve => "post-retrieve",
+            HookPosition::PreUpdate    => "pre-update",
+            HookPosition::PostUpdate   => "post-update",
+            HookPosition::PreDelete    => "pre-delete",
+            HookPosition::PostDelete   => "post-delete",
+            HookPosition::PreMove      => "pre-move",
+            HookPosition::PostMove     => "post-move",
+        }
+    }
+
+}
+
+impl FromStr for HookPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HookPosition> {
+        HookPosition::all()
+            .iter()
+            .find(|p| p.as_str() == s)
+            .cloned()
+            .ok_or_else(|| format_err!("Unknown hook position: {}", s))
+    }
+}
+
+impl Display for HookPosition {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+/// What a hook gets to see of the store operation
+pub struct HookArgs<'a> {
+    pub position: HookPosition,
+
+    /// The id of the entry the operation works on. For moves, this is the old id.
+    pub id: &'a StoreId,
+
+    /// The entry, if the operation has one at this position
+    ///
+    /// This is available for `PostCreate`, `PostRetrieve`, `PreUpdate` and `PostUpdate`, as well
+    /// as for `PreDelete` and `PreMove` if the entry exists.
+    /// Hooks at `PreUpdate` may alter the entry before it is written. Changes at `PreDelete` and
+    /// `PreMove` are discarded.
+    pub entry: Option<&'a mut Entry>,
+
+    /// The new id of the entry, for `PreMove` and `PostMove`
+    pub new_id: Option<&'a StoreId>,
+}
+
+/// A hook, which is run at one or more `HookPosition`s
+pub trait Hook : Debug + Send + Sync {
+
+    /// A name for the hook, used in error messages
+    fn name(&self) -> &str;
+
+    /// Run the hook
+    ///
+    /// Returning an error makes the store operation fail. For "pre" positions, the operation is
+    /// not executed then.
+    fn execute(&self, args: &mut HookArgs) -> Result<()>;
+}
+
+/// A hook which runs an external command
+///
+/// The command is run by `sh -c`, so it may use quoting. It is called with the id of the entry as
+/// last argument (and the new id after that, for moves). The entry is passed on stdin, if there is one. Additionally, the environment variables
+/// `IMAG_HOOK_POSITION`, `IMAG_STORE_PATH` and `IMAG_STORE_ID` are set. A non-zero exit status
+/// makes the hook fail.
+pub struct CommandHook {
+    command: String,
+    store_path: PathBuf,
+}
+
+impl CommandHook {
+    pub fn new(command: String, store_path: PathBuf) -> CommandHook {
+        CommandHook { command, store_path }
+    }
+}
+
+impl Debug for CommandHook {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        write!(fmt, "CommandHook({})", self.command)
+    }
+}
+
+impl Hook for CommandHook {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn execute(&self, args: &mut HookArgs) -> Result<()> {
+        if self.command.trim().is_empty() {
+            return Err(format_err!("Empty hook command at {}", args.position))
+        }
+
+        // The ids are passed to the shell as positional parameters, which "$@" appends to the
+        // command, and `$0` is the command itself
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.command)
+            .arg(args.id.to_str()?)
+            .env("IMAG_HOOK_POSITION", args.position.as_str())
+            .env("IMAG_STORE_PATH", &self.store_path)
+            .env("IMAG_STORE_ID", args.id.to_str()?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(new_id) = args.new_id {
+            command.arg(new_id.to_str()?);
+        }
+
+        debug!("Running hook {:?} at {}", command, args.position);
+        let mut child = command
+            .spawn()
+            .context(format_err!("Failed to start hook: {}", self.command))?;
+
+        let mut stdin = child.stdin.take().ok_or_else(|| format_err!("No stdin for hook: {}", self.command))?;
+        let writer = match args.entry {
+            // Write from another thread, as the hook might not read all of its input before
+            // writing to stdout, which we only read in wait_with_output()
+            Some(ref entry) => {
+                let input = entry.to_str()?;
+                Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+            },
+            None => None, // stdin is closed here
+        };
+
+        let output = child
+            .wait_with_output()
+            .context(format_err!("Failed to wait for hook: {}", self.command))?;
+
+        if let Some(writer) = writer {
+            let written = writer
+                .join()
+                .map_err(|_| format_err!("Failed to pass entry to hook: {}", self.command))?;
+
+            if let Err(e) = written {
+                // hooks do not have to read the entry
+                if e.kind() != ::std::io::ErrorKind::BrokenPipe {
+                    return Err(Error::from(e)).context(format_err!("Failed to pass entry to hook: {}", self.command)).map_err(Error::from)
+                }
+            }
+        }
+
+        trace!("Hook stdout: {}", String::from_utf8_lossy(&output.stdout));
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format_err!("Hook '{}' exited with {}: {}",
+                            self.command,
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+}
+
+/// Get the hooks from the `store.hooks` section of the configuration
+pub(crate) fn config_hooks(config: &Option<Value>, store_path: &PathBuf)
+    -> Result<Vec<(HookPosition, Box<dyn Hook>)>>
+{
+    let key       = "store.hooks";
+    let mut hooks = vec![];
+
+    let table = match *config {
+        None => return Ok(hooks),
+        Some(ref config) => match config.read(key).context(format_err!("Error reading '{}' in configuration", key))? {
+            None                        => return Ok(hooks),
+            Some(Value::Table(t))       => t,
+            Some(_)                     => return Err(format_err!("Type error in configuration: '{}' must be a table", key)),
+        },
+    };
+
+    for (name, commands) in table.iter() {
+        let position = HookPosition::from_str(name)
+            .context(format_err!("Error in configuration at '{}'", key))?;
+
+        let commands = match *commands {
+            Value::String(ref s) => vec![s.clone()],
+            Value::Array(ref a)  => a
+                .iter()
+                .map(|v| v.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| format_err!("Type error in configuration: '{}.{}' must be an array of strings", key, name))?,
+            _ => return Err(format_err!("Type error in configuration: '{}.{}' must be an array of strings", key, name)),
+        };
+
+        for command in commands {
+            debug!("Configured hook at {}: {}", position, command);
+            hooks.push((position, Box::new(CommandHook::new(command, store_path.clone())) as Box<dyn Hook>));
+        }
+    }
+
+    Ok(hooks)
+}
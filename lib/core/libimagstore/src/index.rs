@@ -0,0 +1,472 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Secondary index over header fields
+//!
+//! The index maps `header path -> value -> ids` for a set of header paths, so that entries can be
+//! looked up by (for example) their tags without reading every entry in the store.
+//!
+//! The index is persisted as JSON in the reserved `.index` directory of the store. It is loaded
+//! lazily and written back when the store is dropped (or `Store::flush_index()` is called). When
+//! the index is modified for the first time, the file on disk is removed, so that a crashing
+//! process never leaves an outdated index behind. If there is no index on disk, it is rebuilt on
+//! the first lookup.
+//!
+//! Several processes can use the store at the same time, each with its own copy of the index. To
+//! find out whether another process changed the store, every process writes a new, unique
+//! "generation" to the `generation` file in the index directory after every operation (or
+//! transaction) which changed entries. An index file is only used if it was written with the current generation, and a process only
+//! writes its index if no other process changed the store since it loaded (or rebuilt) the index.
+//! If it notices that another process changed the store, its index is rebuilt on the next lookup.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+use serde_json::Value as JsonValue;
+
+use crate::file_abstraction::FileAbstraction;
+use crate::storeid::StoreId;
+
+/// The reserved directory in the store where the index lives
+pub(crate) const INDEX_DIR : &str = ".index";
+
+/// The name of the index file. Contains the format version.
+const INDEX_FILE : &str = "v2.json";
+
+/// The name of the file with the current generation of the store
+const GENERATION_FILE : &str = "generation";
+
+/// Counter to make the generations written by one process unique
+static GENERATION_COUNTER : AtomicUsize = AtomicUsize::new(0);
+
+/// The header path -> value -> ids map of the index
+type IndexMap = BTreeMap<String, BTreeMap<String, BTreeSet<String>>>;
+
+/// The header paths which are always indexed
+pub(crate) const DEFAULT_INDEXED_PATHS : &[&str] = &[
+    "tag.values",
+    "category.value",
+    "links.internal",
+    crate::attachment::HEADER_PATH,
+];
+
+/// Get the header paths to index from the configuration
+///
+/// These are the `DEFAULT_INDEXED_PATHS` plus the paths from the `store.index.headers` array.
+pub(crate) fn config_indexed_paths(config: &Option<Value>) -> Result<Vec<String>> {
+    let key = "store.index.headers";
+    let mut paths : Vec<String> = DEFAULT_INDEXED_PATHS.iter().map(|s| String::from(*s)).collect();
+
+    if let Some(ref config) = *config {
+        let configured = config
+            .read(key)
+            .context(format_err!("Error reading '{}' in configuration", key))?;
+
+        match configured {
+            None => {},
+            Some(Value::Array(ref ary)) => for v in ary {
+                match *v {
+                    Value::String(ref s) => if !paths.contains(s) {
+                        paths.push(s.clone());
+                    },
+                    _ => return Err(format_err!("Type error in configuration: '{}' must be an array of strings", key)),
+                }
+            },
+            Some(_) => return Err(format_err!("Type error in configuration: '{}' must be an array of strings", key)),
+        }
+    }
+
+    Ok(paths)
+}
+
+#[derive(Debug)]
+pub(crate) struct Index {
+    /// header path -> value -> ids
+    map: IndexMap,
+
+    /// id -> (header path, value), for removing an id from the index without knowing its old
+    /// header
+    reverse: HashMap<String, Vec<(String, String)>>,
+
+    /// Whether the index contains all entries of the store.
+    ///
+    /// If not, it has to be rebuilt before it can be used for lookups. Until then, it is not
+    /// maintained.
+    complete: bool,
+
+    /// Whether the index was altered since it was loaded
+    dirty: bool,
+
+    /// Whether there is an index file in the backend
+    on_disk: bool,
+
+    /// Whether the index is written to the backend
+    persistent: bool,
+
+    /// Whether the index takes part in detecting changes by other processes via the generation
+    shared: bool,
+
+    /// The generation of the store the index is up to date with
+    generation: String,
+
+    /// Whether this process changed entries since it last wrote a new generation
+    changed: bool,
+}
+
+impl Index {
+
+    /// The path of the index file in the store at `store_base`
+    pub(crate) fn file_path(store_base: &PathBuf) -> PathBuf {
+        store_base.join(INDEX_DIR).join(INDEX_FILE)
+    }
+
+    /// Load the index from the backend
+    ///
+    /// If there is no index on disk, the index on disk is outdated or it was built for other
+    /// header paths than `paths`, an incomplete index is returned.
+    pub(crate) fn load(store_base: &PathBuf, paths: &[String], backend: &Arc<dyn FileAbstraction>)
+        -> Result<Index>
+    {
+        let file       = Index::file_path(store_base);
+        let generation = read_generation(store_base, backend)?;
+        let map  = match backend.read_raw(&file)? {
+            None => {
+                debug!("No index found at {}", file.display());
+                None
+            },
+            Some(s) => {
+                let mut persisted : JsonValue = ::serde_json::from_str(&s)
+                    .context(format_err!("Failed to parse index: {}", file.display()))?;
+                let map : IndexMap = ::serde_json::from_value(persisted["index"].take())
+                    .context(format_err!("Failed to parse index: {}", file.display()))?;
+
+                if persisted["generation"].as_str() != Some(generation.as_str()) {
+                    debug!("Index was written before the store was changed, ignoring it");
+                    None
+                } else if map.keys().eq(paths.iter().collect::<BTreeSet<_>>()) {
+                    Some(map)
+                } else {
+                    debug!("Index was built for different header paths, ignoring it");
+                    None
+                }
+            },
+        };
+
+        let complete = map.is_some();
+        let on_disk  = complete;
+        let map = map.unwrap_or_else(|| {
+            paths.iter().map(|p| (p.clone(), BTreeMap::new())).collect()
+        });
+
+        let mut reverse : HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for (path, values) in map.iter() {
+            for (value, ids) in values.iter() {
+                for id in ids.iter() {
+                    reverse.entry(id.clone()).or_default().push((path.clone(), value.clone()));
+                }
+            }
+        }
+
+        Ok(Index {
+            map,
+            reverse,
+            complete,
+            dirty: false,
+            on_disk,
+            persistent: true,
+            shared: true,
+            generation,
+            changed: false,
+        })
+    }
+
+    /// An empty index for `paths` which is never written to the backend
+    ///
+    /// Used if stores are mounted into the store, as they can change without the index noticing.
+    /// If `shared`, changes are still announced to other processes via the generation, which is
+    /// not done if another process (the `imag-server`) maintains the index of the store.
+    pub(crate) fn transient(paths: &[String], shared: bool) -> Index {
+        Index {
+            map: paths.iter().map(|p| (p.clone(), BTreeMap::new())).collect(),
+            reverse: HashMap::new(),
+            complete: false,
+            dirty: false,
+            on_disk: false,
+            persistent: false,
+            shared,
+            generation: String::new(),
+            changed: false,
+        }
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Start building the index from scratch
+    ///
+    /// The index is filled with `Index::insert()` afterwards.
+    pub(crate) fn clear(&mut self, store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        self.touch(store_base, backend)?;
+        if self.shared {
+            self.generation = read_generation(store_base, backend)?;
+        }
+        self.clear_map();
+        self.complete = true;
+        Ok(())
+    }
+
+    fn clear_map(&mut self) {
+        for values in self.map.values_mut() {
+            values.clear();
+        }
+        self.reverse.clear();
+    }
+
+    /// Mark the index as incomplete if another process changed the store since it was built
+    pub(crate) fn check_generation(&mut self, store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>)
+        -> Result<()>
+    {
+        if self.shared && self.complete && read_generation(store_base, backend)? != self.generation {
+            debug!("Store was changed by another process, index has to be rebuilt");
+            self.complete = false;
+            self.clear_map();
+        }
+        Ok(())
+    }
+
+    /// Tell other processes that this process changed entries of the store, if it did
+    ///
+    /// A new generation is written to the backend, so other processes notice that their index is
+    /// outdated. If the generation in the backend is not the one this index has seen last, another
+    /// process changed the store as well, so this index is outdated, too, and marked as
+    /// incomplete.
+    ///
+    /// This is done once after every operation (or transaction), not for every changed entry.
+    pub(crate) fn announce(&mut self, store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        if !(self.shared && self.changed) {
+            return Ok(())
+        }
+
+        self.check_generation(store_base, backend)?;
+        self.generation = new_generation();
+        write_generation(store_base, backend, &self.generation)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Mark the index as altered
+    ///
+    /// The first time this is called, the index on disk is removed, as it is outdated from now on.
+    fn touch(&mut self, store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        if !self.dirty {
+            if self.on_disk {
+                let file = Index::file_path(store_base);
+                trace!("Removing outdated index from disk: {}", file.display());
+                backend.remove_raw(&file)?;
+                self.on_disk = false;
+            }
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Put `id` with the values from `header` into the index, replacing its old values
+    pub(crate) fn update(&mut self,
+                         store_base: &PathBuf,
+                         backend: &Arc<dyn FileAbstraction>,
+                         id: &StoreId,
+                         header: &Value)
+        -> Result<()>
+    {
+        self.changed = true;
+        if !self.complete {
+            return Ok(())
+        }
+
+        self.touch(store_base, backend)?;
+        self.insert(id, header)
+    }
+
+    /// Put `id` with the values from `header` into the index, while it is rebuilt
+    pub(crate) fn insert(&mut self, id: &StoreId, header: &Value) -> Result<()> {
+        let id = id.to_str()?;
+        self.remove_id(&id);
+
+        let mut pairs = vec![];
+        for (path, values) in self.map.iter_mut() {
+            // Attachments are indexed by the hashes of their data
+            let found = if path == crate::attachment::HEADER_PATH {
+                crate::attachment::hashes(header)
+            } else {
+                header
+                    .read(path)
+                    .context(format_err!("Failed to read header at '{}'", path))?
+                    .map(index_values)
+                    .unwrap_or_else(Vec::new)
+            };
+
+            for value in found {
+                trace!("Indexing {}: {} = {}", id, path, value);
+                values.entry(value.clone()).or_default().insert(id.clone());
+                pairs.push((path.clone(), value));
+            }
+        }
+
+        if !pairs.is_empty() {
+            self.reverse.insert(id, pairs);
+        }
+        Ok(())
+    }
+
+    /// Remove `id` from the index
+    pub(crate) fn remove(&mut self,
+                         store_base: &PathBuf,
+                         backend: &Arc<dyn FileAbstraction>,
+                         id: &StoreId)
+        -> Result<()>
+    {
+        self.changed = true;
+        if !self.complete {
+            return Ok(())
+        }
+
+        self.touch(store_base, backend)?;
+        self.remove_id(&id.to_str()?);
+        Ok(())
+    }
+
+    fn remove_id(&mut self, id: &str) {
+        if let Some(pairs) = self.reverse.remove(id) {
+            for (path, value) in pairs {
+                let now_empty = self.map
+                    .get_mut(&path)
+                    .and_then(|values| values.get_mut(&value))
+                    .map(|ids| {
+                        ids.remove(id);
+                        ids.is_empty()
+                    })
+                    .unwrap_or(false);
+
+                if now_empty {
+                    self.map.get_mut(&path).map(|values| values.remove(&value));
+                }
+            }
+        }
+    }
+
+    /// Get the ids which have `value` at header path `path`
+    pub(crate) fn lookup(&self, path: &str, value: &str) -> Result<Vec<StoreId>> {
+        self.map
+            .get(path)
+            .ok_or_else(|| format_err!("Header path is not indexed: {}", path))?
+            .get(value)
+            .map(|ids| ids.iter().map(|id| StoreId::new(PathBuf::from(id))).collect())
+            .unwrap_or_else(|| Ok(vec![]))
+    }
+
+    /// Get the ids which have any value at header path `path`
+    pub(crate) fn ids_with(&self, path: &str) -> Result<Vec<StoreId>> {
+        self.map
+            .get(path)
+            .ok_or_else(|| format_err!("Header path is not indexed: {}", path))?
+            .values()
+            .flat_map(|ids| ids.iter())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|id| StoreId::new(PathBuf::from(id)))
+            .collect()
+    }
+
+    /// Write the index to the backend, if it was altered
+    pub(crate) fn flush(&mut self, store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        if !(self.persistent && self.complete && self.dirty) {
+            trace!("Index not altered, not writing it");
+            return Ok(())
+        }
+
+        if read_generation(store_base, backend)? != self.generation {
+            debug!("Store was changed by another process, not writing the outdated index");
+            return Ok(())
+        }
+
+        let file = Index::file_path(store_base);
+        debug!("Writing index to {}", file.display());
+        let s = ::serde_json::to_string(&json!({
+            "generation": self.generation,
+            "index": self.map,
+        })).map_err(Error::from)?;
+        backend.write_raw(&file, &s)?;
+        self.dirty   = false;
+        self.on_disk = true;
+        Ok(())
+    }
+}
+
+/// Read the current generation of the store, empty if no generation was written yet
+fn read_generation(store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<String> {
+    let file = store_base.join(INDEX_DIR).join(GENERATION_FILE);
+    backend.read_raw(&file).map(Option::unwrap_or_default)
+}
+
+fn write_generation(store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>, generation: &str) -> Result<()> {
+    let file = store_base.join(INDEX_DIR).join(GENERATION_FILE);
+    backend.write_raw(&file, generation)
+}
+
+/// Build a generation which is unique across processes
+fn new_generation() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    format!("{}-{}-{}", ::std::process::id(), nanos, GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Get the values which are indexed for a header value
+///
+/// Scalars are indexed by their string representation, arrays by each of their (scalar)
+/// elements. Tables are not indexed.
+fn index_values(v: &Value) -> Vec<String> {
+    match *v {
+        Value::String(ref s) => vec![s.clone()],
+        Value::Integer(i)    => vec![i.to_string()],
+        Value::Float(f)      => vec![f.to_string()],
+        Value::Boolean(b)    => vec![b.to_string()],
+        Value::Datetime(ref d) => vec![d.to_string()],
+        Value::Array(ref a)  => a.iter().flat_map(|v| match *v {
+            Value::Array(_) | Value::Table(_) => vec![],
+            ref other => index_values(other),
+        }).collect(),
+        Value::Table(_)      => vec![],
+    }
+}
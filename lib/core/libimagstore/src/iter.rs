@@ -144,7 +144,9 @@ use self::get::StoreGetIterator;
 use self::retrieve::StoreRetrieveIterator;
 use crate::file_abstraction::iter::PathIterator;
 use crate::store::Store;
+use crate::store::FileLockEntry;
 use failure::Fallible as Result;
+use rayon::prelude::*;
 
 /// Iterator for iterating over all (or a subset of all) entries
 ///
@@ -247,6 +249,36 @@ impl<'a> Entries<'a> {
         FindStartsWith(self, id_substr)
     }
 
+    /// Call `f` on each entry, in parallel, and collect the results
+    ///
+    /// The ids are collected from the backend first, the entries are then loaded and processed on
+    /// a thread pool. The results are in no particular order. An entry which was deleted in the
+    /// meantime is an error.
+    pub fn par_map<F, R>(self, f: F) -> Result<Vec<R>>
+        where F: Fn(FileLockEntry<'a>) -> Result<R> + Sync + Send,
+              R: Send
+    {
+        let store = self.1;
+        let ids   = self.collect::<Result<Vec<StoreId>>>()?;
+
+        ids.into_par_iter()
+            .map(|id| {
+                store.get(id.clone())?
+                    .ok_or_else(|| format_err!("Entry vanished during iteration: {}", id))
+                    .and_then(&f)
+            })
+            .collect()
+    }
+
+    /// Call `f` on each entry, in parallel
+    ///
+    /// See `Entries::par_map()`.
+    pub fn par_for_each<F>(self, f: F) -> Result<()>
+        where F: Fn(FileLockEntry<'a>) -> Result<()> + Sync + Send
+    {
+        self.par_map(f).map(|_| ())
+    }
+
 }
 
 impl<'a> Iterator for Entries<'a> {
@@ -399,5 +431,75 @@ mod tests {
         assert!(succeeded, "not all entries in iterator start with 'entr'");
     }
 
+    #[test]
+    fn test_store_is_sync() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<Store>();
+    }
+
+    #[test]
+    fn test_entries_par_map() {
+        setup_logging();
+        let store = get_store();
+
+        for n in 0..100 {
+            let mut entry = store.retrieve(PathBuf::from(format!("coll/{}", n))).unwrap();
+            *entry.get_content_mut() = format!("{}", n);
+        }
+
+        let mut numbers = store.entries()
+            .unwrap()
+            .in_collection("coll")
+            .unwrap()
+            .par_map(|entry| entry.get_content().parse::<usize>().map_err(::failure::Error::from))
+            .unwrap();
+        numbers.sort();
+
+        assert_eq!(numbers, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_entries_par_for_each_updates() {
+        setup_logging();
+        let store = get_store();
+
+        for n in 0..100 {
+            let _ = store.retrieve(PathBuf::from(format!("coll/{}", n))).unwrap();
+        }
+
+        store.entries()
+            .unwrap()
+            .par_for_each(|mut entry| {
+                *entry.get_content_mut() = String::from("updated");
+                Ok(())
+            })
+            .unwrap();
+
+        let all_updated = store.entries()
+            .unwrap()
+            .into_get_iter()
+            .all(|e| e.unwrap().unwrap().get_content() == "updated");
+        assert!(all_updated);
+    }
+
+    #[test]
+    fn test_entries_par_map_error() {
+        setup_logging();
+        let store = get_store();
+
+        for n in 0..10 {
+            let _ = store.retrieve(PathBuf::from(format!("coll/{}", n))).unwrap();
+        }
+
+        let result = store.entries()
+            .unwrap()
+            .par_for_each(|entry| if entry.get_location().local().ends_with("5") {
+                Err(format_err!("failed"))
+            } else {
+                Ok(())
+            });
+        assert!(result.is_err());
+    }
+
 }
 
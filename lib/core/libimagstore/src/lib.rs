@@ -45,9 +45,18 @@ extern crate toml;
 extern crate semver;
 extern crate walkdir;
 #[macro_use] extern crate is_match;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate failure;
 extern crate toml_query;
+extern crate chacha20poly1305;
+extern crate argon2;
+extern crate once_cell;
+extern crate chrono;
+extern crate blake2;
+extern crate rayon;
+extern crate serde_yaml;
+extern crate gethostname;
+#[cfg(feature = "sqlite")] extern crate rusqlite;
 
 extern crate libimagerror;
 extern crate libimagutil;
@@ -57,6 +66,24 @@ extern crate libimagutil;
 pub mod storeid;
 pub mod iter;
 pub mod store;
+pub mod migration;
+pub mod hook;
+pub mod encryption;
+pub mod trash;
+pub mod history;
+pub mod archive;
+pub mod header_format;
+pub mod lock;
+pub mod fsck;
+pub mod mount;
+pub mod rpc;
+pub mod attachment;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 mod configuration;
+mod schema;
 mod file_abstraction;
+mod transaction;
+mod index;
+mod content;
 
@@ -0,0 +1,611 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Locking of entries between processes
+//!
+//! While an entry is borrowed (a `FileLockEntry` exists for it), the store holds a lock file for
+//! it in `.locks/<id>.lock`. Only one process can create the lock file, it contains the process
+//! id, host and command line of the process which holds the lock and when the lock was acquired.
+//!
+//! A process which wants to borrow an entry which is locked by another process waits for
+//! `store.locks.timeout` seconds and fails afterwards. With a timeout of `0`, it fails right away.
+//!
+//! Threads of one process do not wait for each other: a lock which is held by the own process is
+//! reported right away, like an entry which is borrowed already.
+//!
+//! A lock is stale if the process which holds it ran on this host and does not run anymore.
+//! Stale locks are broken when the entry is locked the next time. Locks which are held longer
+//! than `store.locks.stale-after` seconds are reported as stale as well, but are not broken.
+//!
+//! The same way, `.locks/.transaction.lock` is held while a transaction runs (see
+//! `Store::transaction()`), so only one process at a time writes the transaction journal.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use toml::Value;
+use toml::map::Map;
+use toml_query::read::TomlValueReadTypeExt;
+use walkdir::WalkDir;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use crate::storeid::StoreId;
+
+/// The reserved directory in the store where the lock files live
+pub(crate) const LOCKS_DIR : &str = ".locks";
+
+const LOCK_EXTENSION : &str = "lock";
+
+/// The name of the lock held while a transaction runs, in `LOCKS_DIR`
+const TRANSACTION_LOCK : &str = ".transaction";
+
+const DATETIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S";
+
+/// How long to wait before checking a lock held by another process again
+const POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+const DEFAULT_TIMEOUT : Duration = Duration::from_secs(10);
+
+/// Counter to make the names of temporary files unique between the threads of a process
+static TMP_COUNTER : AtomicUsize = AtomicUsize::new(0);
+
+/// The lock files which are held (or being acquired) by this process
+///
+/// A lock file with the id of this process which is not in here was left behind by an earlier
+/// process with the same id.
+static HELD : Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+#[derive(Debug, Clone)]
+pub(crate) struct LockConfig {
+    pub(crate) enabled: bool,
+
+    /// How long to wait for a lock held by another process
+    pub(crate) timeout: Duration,
+
+    /// Locks held longer than this are stale, `None` if only locks of dead processes are stale
+    pub(crate) stale_after: Option<::chrono::Duration>,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        LockConfig { enabled: true, timeout: DEFAULT_TIMEOUT, stale_after: None }
+    }
+}
+
+/// Get the lock settings from the `store.locks` section of the configuration
+pub(crate) fn config_locks(config: &Option<Value>) -> Result<LockConfig> {
+    let config = match *config {
+        None           => return Ok(LockConfig::default()),
+        Some(ref conf) => conf,
+    };
+
+    let read_seconds = |key: &str| -> Result<Option<u64>> {
+        config
+            .read_int(key)
+            .context(format_err!("Error reading '{}' in configuration", key))?
+            .map(|secs| if secs < 0 {
+                Err(format_err!("'{}' must not be negative", key))
+            } else {
+                Ok(secs as u64)
+            })
+            .transpose()
+    };
+
+    let enabled = config
+        .read_bool("store.locks.enabled")
+        .context(format_err!("Error reading 'store.locks.enabled' in configuration"))?
+        .unwrap_or(true);
+
+    let timeout = read_seconds("store.locks.timeout")?
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT);
+
+    let stale_after = read_seconds("store.locks.stale-after")?
+        .filter(|secs| *secs > 0) // 0 means "never"
+        .map(|secs| ::chrono::Duration::seconds(secs as i64));
+
+    Ok(LockConfig { enabled, timeout, stale_after })
+}
+
+/// The process which holds the lock on an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    /// The locked entry
+    pub id: StoreId,
+
+    pub pid: u32,
+    pub host: String,
+
+    /// The command line of the process
+    pub command: String,
+
+    /// When the lock was acquired
+    pub acquired: NaiveDateTime,
+}
+
+impl LockHolder {
+
+    /// The current process, as holder of the lock on `id`
+    fn current(id: StoreId) -> LockHolder {
+        LockHolder {
+            id,
+            pid: ::std::process::id(),
+            host: hostname(),
+            command: ::std::env::args().collect::<Vec<_>>().join(" "),
+            acquired: ::chrono::Local::now().naive_local(),
+        }
+    }
+
+    fn to_toml(&self) -> Result<String> {
+        let mut map = Map::new();
+        let _ = map.insert(String::from("pid"), Value::Integer(i64::from(self.pid)));
+        let _ = map.insert(String::from("host"), Value::String(self.host.clone()));
+        let _ = map.insert(String::from("command"), Value::String(self.command.clone()));
+        let _ = map.insert(String::from("acquired"), Value::String(self.acquired.format(DATETIME_FORMAT).to_string()));
+        ::toml::ser::to_string(&Value::Table(map)).map_err(Error::from)
+    }
+
+    fn from_toml(id: StoreId, s: &str) -> Result<LockHolder> {
+        let value = ::toml::de::from_str::<Value>(s)?;
+        let read_str = |key: &str| -> Result<String> {
+            value.read_string(key)?.ok_or_else(|| format_err!("Missing '{}' in lock file", key))
+        };
+
+        let pid = value
+            .read_int("pid")?
+            .and_then(|pid| if pid >= 0 { Some(pid as u32) } else { None })
+            .ok_or_else(|| format_err!("Missing or invalid 'pid' in lock file"))?;
+        let acquired = NaiveDateTime::parse_from_str(&read_str("acquired")?, DATETIME_FORMAT)?;
+
+        Ok(LockHolder { id, pid, host: read_str("host")?, command: read_str("command")?, acquired })
+    }
+
+    /// Whether the process which holds the lock is known to be gone
+    pub fn is_dead(&self) -> bool {
+        process_dead(&self.host, self.pid)
+    }
+
+    /// Whether the lock was written by a process with the id of the current process
+    fn is_current_process(&self) -> bool {
+        self.pid == ::std::process::id() && self.host == hostname()
+    }
+
+    /// Whether the lock is stale, because its holder is dead or it is held for longer than
+    /// `stale_after`
+    pub fn is_stale(&self, stale_after: Option<::chrono::Duration>) -> bool {
+        self.is_dead() || stale_after
+            .map(|max| ::chrono::Local::now().naive_local() - self.acquired > max)
+            .unwrap_or(false)
+    }
+}
+
+/// A lock on an entry, which is released when this object is dropped
+#[derive(Debug)]
+pub(crate) struct EntryLock {
+    path: PathBuf,
+}
+
+impl Drop for EntryLock {
+    fn drop(&mut self) {
+        trace!("Releasing lock {}", self.path.display());
+        if let Err(e) = ::std::fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file {}: {}", self.path.display(), e);
+        }
+        if let Ok(mut held) = HELD.lock() {
+            let _ = held.remove(&self.path);
+        }
+    }
+}
+
+fn held() -> Result<MutexGuard<'static, HashSet<PathBuf>>> {
+    HELD.lock().map_err(|_| Error::from(EM::LockError))
+}
+
+/// The path of the lock file of `id` in the store at `store_base`
+fn lock_path(store_base: &Path, id: &StoreId) -> PathBuf {
+    let mut path = store_base.join(LOCKS_DIR).join(id.local()).into_os_string();
+    path.push(".");
+    path.push(LOCK_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Lock `id` in the store at `store_base`
+///
+/// Waits for `config.timeout` if another process holds the lock, breaking the lock if that process
+/// is dead.
+pub(crate) fn acquire(store_base: &Path, id: &StoreId, config: &LockConfig) -> Result<EntryLock> {
+    acquire_at(lock_path(store_base, id), id, config.timeout)?.map_err(|holder| locked(&holder))
+}
+
+/// Lock the store at `store_base` against transactions of other processes
+///
+/// Waits for `config.timeout` if another process runs a transaction, like `acquire()`.
+pub(crate) fn acquire_transaction(store_base: &Path, config: &LockConfig) -> Result<EntryLock> {
+    let id = StoreId::new(PathBuf::from(TRANSACTION_LOCK))?;
+    acquire_at(lock_path(store_base, &id), &id, config.timeout)?.map_err(|holder| locked(&holder))
+}
+
+/// Lock the store at `store_base` against transactions of other processes, `None` right away if
+/// another process runs a transaction
+pub(crate) fn try_acquire_transaction(store_base: &Path) -> Result<Option<EntryLock>> {
+    let id = StoreId::new(PathBuf::from(TRANSACTION_LOCK))?;
+    acquire_at(lock_path(store_base, &id), &id, Duration::from_secs(0)).map(|res| res.ok())
+}
+
+/// Create the lock file at `path`, waiting for `timeout` if it is held by another process
+///
+/// Returns the holder of the lock if it was not released in time, or right away if this process
+/// holds it.
+fn acquire_at(path: PathBuf, id: &StoreId, timeout: Duration) -> Result<::std::result::Result<EntryLock, LockHolder>> {
+    let current = LockHolder::current(id.clone());
+    let content = current.to_toml()?;
+    if !held()?.insert(path.clone()) {
+        debug!("Lock {} is held by this process", path.display());
+        return Ok(Err(current))
+    }
+
+    let created = create_waiting(&path, id, &content, timeout);
+    if !matches!(created, Ok(None)) {
+        let _ = held()?.remove(&path);
+    }
+
+    match created? {
+        None         => {
+            trace!("Acquired lock {}", path.display());
+            Ok(Ok(EntryLock { path }))
+        },
+        Some(holder) => Ok(Err(holder)),
+    }
+}
+
+/// Create the lock file at `path` with `content`, waiting for `timeout` if it exists
+///
+/// Returns the holder of the lock if it was not released in time.
+fn create_waiting(path: &Path, id: &StoreId, content: &str, timeout: Duration) -> Result<Option<LockHolder>> {
+    let start = Instant::now();
+
+    loop {
+        if create_exclusively(path, content)? {
+            return Ok(None)
+        }
+
+        match read_holder(path, id)? {
+            None => continue, // released in the meantime
+            // The locks of this process are in `HELD`, so this one was left behind
+            Some(ref holder) if holder.is_dead() || holder.is_current_process() => {
+                warn!("Breaking stale lock on {} of process {} ({})", id, holder.pid, holder.command);
+                break_lock(path, holder)?;
+                continue
+            },
+            Some(holder) => if start.elapsed() >= timeout {
+                return Ok(Some(holder))
+            } else {
+                ::std::thread::sleep(POLL_INTERVAL)
+            },
+        }
+    }
+}
+
+/// The error for a lock which is held by `holder`
+fn locked(holder: &LockHolder) -> Error {
+    Error::from(format_err!("{} is locked by process {} on {} since {}: {}",
+                            holder.id,
+                            holder.pid,
+                            holder.host,
+                            holder.acquired.format(DATETIME_FORMAT),
+                            holder.command)
+                .context(EM::LockError))
+}
+
+/// Create the lock file at `path` with `content`, returns false if it exists already
+///
+/// The content is written to a temporary file first, which is then hard linked to `path`, so no
+/// other process ever sees a half-written lock file.
+fn create_exclusively(path: &Path, content: &str) -> Result<bool> {
+    if let Some(parent) = path.parent() {
+        ::std::fs::create_dir_all(parent).context(EM::DirNotCreated)?;
+    }
+
+    let tmp = unique_path(path, "tmp");
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .context(format_err!("Failed to write lock file {}", tmp.display()))?;
+
+    let linked = ::std::fs::hard_link(&tmp, path);
+    let _ = ::std::fs::remove_file(&tmp);
+
+    match linked {
+        Ok(())                                             => Ok(true),
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e)
+            .context(format_err!("Failed to create lock file {}", path.display()))
+            .map_err(Error::from),
+    }
+}
+
+/// Read the holder of the lock at `path`, `None` if there is no lock
+fn read_holder(path: &Path, id: &StoreId) -> Result<Option<LockHolder>> {
+    match ::std::fs::read_to_string(path) {
+        Ok(s) => LockHolder::from_toml(id.clone(), &s)
+            .context(format_err!("Invalid lock file {}, remove it if no imag process is running", path.display()))
+            .map(Some)
+            .map_err(Error::from),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+            .context(format_err!("Failed to read lock file {}", path.display()))
+            .map_err(Error::from),
+    }
+}
+
+/// A path next to `path` which is unique to this process and call, ending in `.<suffix>`
+fn unique_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut unique = path.to_path_buf().into_os_string();
+    unique.push(format!(".{}-{}.{}",
+                        ::std::process::id(),
+                        TMP_COUNTER.fetch_add(1, Ordering::SeqCst),
+                        suffix));
+    PathBuf::from(unique)
+}
+
+/// Remove the lock at `path`, if it is still held by `holder`
+///
+/// Another process might have broken the lock and locked the entry itself in the meantime. So the
+/// lock file is renamed to a unique name first, which only one process can do, and is only
+/// removed if it is still the lock of `holder`. Otherwise, it is put back.
+fn break_lock(path: &Path, holder: &LockHolder) -> Result<()> {
+    let broken = unique_path(path, "broken");
+    match ::std::fs::rename(path, &broken) {
+        Ok(()) => {},
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()), // broken by someone else
+        Err(e) => return Err(format_err!("Failed to break stale lock file {}: {}", path.display(), e)),
+    }
+
+    if read_holder(&broken, &holder.id)?.as_ref() != Some(holder) {
+        debug!("Lock {} was taken by another process in the meantime, restoring it", path.display());
+        // If the entry was locked once more in the meantime, that lock wins
+        let _ = ::std::fs::hard_link(&broken, path);
+    }
+
+    ::std::fs::remove_file(&broken)
+        .context(format_err!("Failed to remove stale lock file {}", broken.display()))
+        .map_err(Error::from)
+}
+
+/// List the locks in the store at `store_base`
+pub(crate) fn list(store_base: &Path) -> Result<Vec<LockHolder>> {
+    let base = store_base.join(LOCKS_DIR);
+    if !base.exists() {
+        return Ok(vec![])
+    }
+
+    let mut holders = vec![];
+    for file in WalkDir::new(&base) {
+        let file = file.context(format_err!("Failed to list locks in {}", base.display()))?;
+        let path = file.path();
+        if !file.file_type().is_file() || path.extension().and_then(|e| e.to_str()) != Some(LOCK_EXTENSION) {
+            continue
+        }
+
+        let id = StoreId::new(path.strip_prefix(&base)?.with_extension(""))?;
+        if id.local() == Path::new(TRANSACTION_LOCK) {
+            continue
+        }
+
+        if let Some(holder) = read_holder(path, &id)? {
+            holders.push(holder);
+        }
+    }
+
+    holders.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(holders)
+}
+
+pub(crate) fn hostname() -> String {
+    ::gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// Whether the process `pid` on `host` is known to be gone
+pub(crate) fn process_dead(host: &str, pid: u32) -> bool {
+    host == hostname() && !process_running(pid)
+}
+
+#[cfg(target_os = "linux")]
+fn process_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Without procfs we cannot tell, so we assume the process is still running
+#[cfg(not(target_os = "linux"))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn config(timeout: u64) -> LockConfig {
+        LockConfig { enabled: true, timeout: Duration::from_secs(timeout), stale_after: None }
+    }
+
+    #[test]
+    fn test_lock_and_release() {
+        let dir = TempDir::new("imag-store-lock").unwrap();
+        let id  = StoreId::new(PathBuf::from("notes/foo")).unwrap();
+
+        {
+            let _lock = acquire(dir.path(), &id, &config(0)).unwrap();
+            assert!(dir.path().join(".locks/notes/foo.lock").is_file());
+
+            let holders = list(dir.path()).unwrap();
+            assert_eq!(holders.len(), 1);
+            assert_eq!(holders[0].id, id);
+            assert_eq!(holders[0].pid, ::std::process::id());
+            assert!(!holders[0].is_stale(None));
+
+            let err = acquire(dir.path(), &id, &config(0)).unwrap_err();
+            assert!(err.iter_chain().any(|e| e.to_string().contains("is locked by process")));
+        }
+
+        assert!(list(dir.path()).unwrap().is_empty());
+        let _ = acquire(dir.path(), &id, &config(0)).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dead_holder_is_broken() {
+        let dir    = TempDir::new("imag-store-lock").unwrap();
+        let id     = StoreId::new(PathBuf::from("foo")).unwrap();
+        let mut holder = LockHolder::current(id.clone());
+        holder.pid = u32::MAX; // no such process
+
+        let path = lock_path(dir.path(), &id);
+        assert!(create_exclusively(&path, &holder.to_toml().unwrap()).unwrap());
+        assert!(list(dir.path()).unwrap()[0].is_stale(None));
+
+        let _lock = acquire(dir.path(), &id, &config(0)).unwrap();
+        assert_eq!(list(dir.path()).unwrap()[0].pid, ::std::process::id());
+    }
+
+    #[test]
+    fn test_held_by_this_process() {
+        let dir = TempDir::new("imag-store-lock").unwrap();
+        let id  = StoreId::new(PathBuf::from("foo")).unwrap();
+
+        let _lock = acquire(dir.path(), &id, &config(10)).unwrap();
+
+        // another thread of this process does not wait for the timeout
+        let start = Instant::now();
+        let path  = dir.path().to_path_buf();
+        let other = id.clone();
+        let err   = ::std::thread::spawn(move || acquire(&path, &other, &config(10)).map(|_| ()))
+            .join()
+            .unwrap()
+            .unwrap_err();
+        assert!(err.iter_chain().any(|e| e.to_string().contains("is locked by process")));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_left_behind_by_this_process_is_broken() {
+        let dir  = TempDir::new("imag-store-lock").unwrap();
+        let id   = StoreId::new(PathBuf::from("foo")).unwrap();
+        let path = lock_path(dir.path(), &id);
+
+        // for example from an earlier process which had the same id
+        assert!(create_exclusively(&path, &LockHolder::current(id.clone()).to_toml().unwrap()).unwrap());
+
+        let _lock = acquire(dir.path(), &id, &config(10)).unwrap();
+        assert_eq!(list(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_break_lock_keeps_new_holder() {
+        let dir    = TempDir::new("imag-store-lock").unwrap();
+        let id     = StoreId::new(PathBuf::from("foo")).unwrap();
+        let mut stale = LockHolder::current(id.clone());
+        stale.pid = u32::MAX;
+
+        // the stale lock was broken and the entry was locked again by someone else
+        let path = lock_path(dir.path(), &id);
+        assert!(create_exclusively(&path, &LockHolder::current(id.clone()).to_toml().unwrap()).unwrap());
+        let current = read_holder(&path, &id).unwrap().unwrap();
+
+        break_lock(&path, &stale).unwrap();
+        assert_eq!(read_holder(&path, &id).unwrap(), Some(current.clone()));
+
+        break_lock(&path, &current).unwrap();
+        assert_eq!(read_holder(&path, &id).unwrap(), None);
+        assert_eq!(::std::fs::read_dir(dir.path().join(LOCKS_DIR)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_transaction_lock() {
+        let dir = TempDir::new("imag-store-lock").unwrap();
+
+        {
+            let _lock = acquire_transaction(dir.path(), &config(0)).unwrap();
+            assert!(try_acquire_transaction(dir.path()).unwrap().is_none());
+            assert!(acquire_transaction(dir.path(), &config(0)).is_err());
+            assert!(list(dir.path()).unwrap().is_empty());
+        }
+
+        assert!(try_acquire_transaction(dir.path()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_create_exclusively_from_threads() {
+        let dir  = TempDir::new("imag-store-lock").unwrap();
+        let path = dir.path().join("foo.lock");
+
+        let created = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                ::std::thread::spawn(move || create_exclusively(&path, "content").unwrap())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|created| *created)
+            .count();
+
+        assert_eq!(created, 1);
+        assert_eq!(::std::fs::read_to_string(&path).unwrap(), "content");
+        assert_eq!(::std::fs::read_dir(dir.path()).unwrap().count(), 1); // no temporary files left
+    }
+
+    #[test]
+    fn test_config() {
+        let config = ::toml::de::from_str(r#"
+        [store.locks]
+            timeout = 0
+            stale-after = 3600
+        "#).unwrap();
+        let config = config_locks(&Some(config)).unwrap();
+        assert!(config.enabled);
+        assert_eq!(config.timeout, Duration::from_secs(0));
+        assert_eq!(config.stale_after, Some(::chrono::Duration::hours(1)));
+
+        assert_eq!(config_locks(&None).unwrap().timeout, DEFAULT_TIMEOUT);
+    }
+}
@@ -0,0 +1,356 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Migrations of entries from older header layouts
+//!
+//! Each entry carries the version of imag it was written with in `imag.version`. If a library
+//! changes the layout of its header section, it provides a `Migration` for the version the change
+//! was released with. All migrations with a version newer than the version of an entry are
+//! applied to that entry (in order of their versions), afterwards `imag.version` of the entry is
+//! set to the version of the newest applied migration.
+//!
+//! Each entry is written at once, after all migrations were applied to it. Hence, if a migration
+//! run is interrupted, every entry is either completely migrated or not at all, and running the
+//! migrations again continues where the interrupted run stopped.
+
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Error as FmtError;
+
+use semver::Version;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use toml_query::insert::TomlValueInsertExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::store::Entry;
+use crate::store::Store;
+use crate::storeid::StoreId;
+
+/// A transformation of entries from an older header layout to a newer one
+pub trait Migration {
+
+    /// A short, human readable description of the migration, used for reporting
+    fn name(&self) -> &'static str;
+
+    /// The version of imag which introduced the new header layout
+    ///
+    /// The migration is applied to all entries with an older `imag.version`.
+    fn version(&self) -> &'static str;
+
+    /// Migrate the entry
+    ///
+    /// This is called for every entry with an older `imag.version`, so implementations have to
+    /// check themselves whether the entry is one they are responsible for.
+    ///
+    /// Returns whether the entry was altered.
+    fn migrate(&self, entry: &mut Entry) -> Result<bool>;
+}
+
+/// A set of migrations, ordered by their versions
+#[derive(Default)]
+pub struct MigrationRegistry(Vec<(Version, Box<dyn Migration>)>);
+
+impl Debug for MigrationRegistry {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        let names = self.0.iter().map(|&(ref v, ref m)| format!("{} ({})", m.name(), v)).collect::<Vec<_>>();
+        write!(fmt, "MigrationRegistry({:?})", names)
+    }
+}
+
+impl MigrationRegistry {
+
+    /// Register a migration
+    ///
+    /// Fails if the version of the migration is not a valid version string.
+    pub fn register(&mut self, migration: Box<dyn Migration>) -> Result<()> {
+        let version = Version::parse(migration.version())
+            .context(format_err!("Invalid version of migration '{}': {}", migration.name(), migration.version()))?;
+
+        debug!("Registering migration '{}' ({})", migration.name(), version);
+
+        // keep the registry sorted, migrations with the same version stay in registration order
+        let pos = self.0.iter().position(|&(ref v, _)| *v > version).unwrap_or_else(|| self.0.len());
+        self.0.insert(pos, (version, migration));
+        Ok(())
+    }
+
+    /// Register a list of migrations, as returned by the `migrations()` function of a library
+    pub fn register_all<I>(&mut self, migrations: I) -> Result<()>
+        where I: IntoIterator<Item = Box<dyn Migration>>
+    {
+        migrations.into_iter().map(|m| self.register(m)).collect::<Result<Vec<_>>>().map(|_| ())
+    }
+
+    /// The number of registered migrations
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Apply all pending migrations to `entry`
+    ///
+    /// Returns `None` if no migration is pending for the entry. Otherwise, `imag.version` of the
+    /// entry is updated and the old and the new version are returned, together with the names of
+    /// the migrations which altered the entry.
+    pub fn migrate_entry(&self, entry: &mut Entry) -> Result<Option<MigratedEntry>> {
+        let from = entry_version(entry)?;
+        let pending = self.0.iter().filter(|&&(ref v, _)| *v > from).collect::<Vec<_>>();
+
+        let to = match pending.last() {
+            None => {
+                trace!("No migration pending for {}", entry.get_location());
+                return Ok(None)
+            },
+            Some(&&(ref v, _)) => v.clone(),
+        };
+
+        let mut applied = vec![];
+        for &&(ref version, ref migration) in pending.iter() {
+            trace!("Applying migration '{}' ({}) to {}", migration.name(), version, entry.get_location());
+            let altered = migration
+                .migrate(entry)
+                .context(format_err!("Migration '{}' failed for {}", migration.name(), entry.get_location()))?;
+
+            if altered {
+                applied.push(migration.name());
+            }
+        }
+
+        let _ = entry
+            .get_header_mut()
+            .insert("imag.version", Value::String(to.to_string()))
+            .context(format_err!("Failed to set version of {}", entry.get_location()))?;
+
+        Ok(Some(MigratedEntry { id: entry.get_location().clone(), from, to, applied }))
+    }
+
+    /// Run the migrations on all entries in the store
+    ///
+    /// If `dry_run` is true, nothing is written, the report shows what would be done.
+    ///
+    /// Entries which cannot be migrated do not abort the run, they are listed in the report.
+    pub fn run(&self, store: &Store, dry_run: bool) -> Result<MigrationReport> {
+        info!("Running {} migrations{}", self.len(), if dry_run { " (dry run)" } else { "" });
+
+        // collect the ids first, as entries are rewritten while we go
+        let ids = store.entries()?.into_storeid_iter().collect::<Result<Vec<StoreId>>>()?;
+        let mut report = MigrationReport::default();
+
+        for id in ids {
+            match self.run_for(store, id.clone(), dry_run) {
+                Ok(Some(migrated)) => report.migrated.push(migrated),
+                Ok(None)           => report.unchanged += 1,
+                Err(e)             => {
+                    debug!("Migrating {} failed: {}", id, e);
+                    report.failed.push((id, e));
+                },
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn run_for(&self, store: &Store, id: StoreId, dry_run: bool) -> Result<Option<MigratedEntry>> {
+        let mut entry = store.get_copy(id.clone())?;
+
+        match self.migrate_entry(&mut entry)? {
+            None => Ok(None),
+            Some(migrated) => {
+                if !dry_run {
+                    debug!("Writing migrated entry {}", id);
+                    let mut fle = store.retrieve(id)?;
+                    *fle = entry;
+                    store.update(&mut fle)?;
+                }
+                Ok(Some(migrated))
+            }
+        }
+    }
+}
+
+/// An entry which was migrated
+#[derive(Debug)]
+pub struct MigratedEntry {
+    pub id: StoreId,
+
+    /// The version of the entry before the migration
+    pub from: Version,
+
+    /// The version of the entry after the migration
+    pub to: Version,
+
+    /// The names of the migrations which altered the entry
+    ///
+    /// If this is empty, only the version of the entry was updated.
+    pub applied: Vec<&'static str>,
+}
+
+/// The result of a migration run
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub migrated: Vec<MigratedEntry>,
+    pub failed: Vec<(StoreId, Error)>,
+
+    /// The number of entries which were up to date already
+    pub unchanged: usize,
+}
+
+/// The version of `entry`, entries without `imag.version` are treated as version 0.0.0
+fn entry_version(entry: &Entry) -> Result<Version> {
+    let version = match entry
+        .get_header()
+        .read_string("imag.version")
+        .context(format_err!("Failed to read version of {}", entry.get_location()))?
+    {
+        Some(version) => version,
+        None => {
+            debug!("No version in header of {}, treating it as 0.0.0", entry.get_location());
+            return Ok(Version::new(0, 0, 0))
+        },
+    };
+
+    Version::parse(&version)
+        .context(format_err!("Invalid version of {}: {}", entry.get_location(), version))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml::Value;
+    use toml_query::insert::TomlValueInsertExt;
+    use toml_query::read::TomlValueReadTypeExt;
+    use failure::Fallible as Result;
+
+    use super::*;
+    use crate::store::Entry;
+    use crate::store::Store;
+
+    struct AddFlag(&'static str, &'static str);
+
+    impl Migration for AddFlag {
+        fn name(&self) -> &'static str {
+            "add flag"
+        }
+
+        fn version(&self) -> &'static str {
+            self.0
+        }
+
+        fn migrate(&self, entry: &mut Entry) -> Result<bool> {
+            let _ = entry.get_header_mut().insert(self.1, Value::Boolean(true))?;
+            Ok(true)
+        }
+    }
+
+    fn get_store() -> Store {
+        Store::new_inmemory(PathBuf::from("/"), &None).unwrap()
+    }
+
+    fn create_with_version(store: &Store, id: &str, version: &str) {
+        let mut entry = store.create(PathBuf::from(id)).unwrap();
+        let _ = entry.get_header_mut().insert("imag.version", Value::String(String::from(version))).unwrap();
+    }
+
+    fn version_of(store: &Store, id: &str) -> String {
+        store.get_copy(PathBuf::from(id)).unwrap().get_header().read_string("imag.version").unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_registry_is_sorted() {
+        let mut registry = MigrationRegistry::default();
+        registry.register(Box::new(AddFlag("0.3.0", "test.b"))).unwrap();
+        registry.register(Box::new(AddFlag("0.2.0", "test.a"))).unwrap();
+        registry.register(Box::new(AddFlag("0.3.0", "test.c"))).unwrap();
+
+        let versions = registry.0.iter().map(|&(ref v, _)| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(versions, vec!["0.2.0", "0.3.0", "0.3.0"]);
+        assert!(registry.register(Box::new(AddFlag("nonsense", "test.d"))).is_err());
+    }
+
+    #[test]
+    fn test_only_pending_migrations_are_applied() {
+        let store = get_store();
+        create_with_version(&store, "old", "0.1.0");
+        create_with_version(&store, "mid", "0.2.0");
+        create_with_version(&store, "new", "0.3.0");
+
+        let mut registry = MigrationRegistry::default();
+        registry.register(Box::new(AddFlag("0.2.0", "test.a"))).unwrap();
+        registry.register(Box::new(AddFlag("0.3.0", "test.b"))).unwrap();
+
+        let report = registry.run(&store, false).unwrap();
+        assert_eq!(report.migrated.len(), 2);
+        assert_eq!(report.unchanged, 1);
+        assert!(report.failed.is_empty());
+
+        let old = store.get_copy(PathBuf::from("old")).unwrap();
+        assert_eq!(old.get_header().read_bool("test.a").unwrap(), Some(true));
+        assert_eq!(old.get_header().read_bool("test.b").unwrap(), Some(true));
+
+        let mid = store.get_copy(PathBuf::from("mid")).unwrap();
+        assert_eq!(mid.get_header().read_bool("test.a").unwrap(), None);
+        assert_eq!(mid.get_header().read_bool("test.b").unwrap(), Some(true));
+
+        assert_eq!(version_of(&store, "old"), "0.3.0");
+        assert_eq!(version_of(&store, "mid"), "0.3.0");
+
+        // a second run has nothing to do anymore
+        let report = registry.run(&store, false).unwrap();
+        assert!(report.migrated.is_empty());
+        assert_eq!(report.unchanged, 3);
+    }
+
+    #[test]
+    fn test_dry_run_does_not_write() {
+        let store = get_store();
+        create_with_version(&store, "old", "0.1.0");
+
+        let mut registry = MigrationRegistry::default();
+        registry.register(Box::new(AddFlag("0.2.0", "test.a"))).unwrap();
+
+        let report = registry.run(&store, true).unwrap();
+        assert_eq!(report.migrated.len(), 1);
+        assert_eq!(report.migrated[0].applied, vec!["add flag"]);
+        assert_eq!(version_of(&store, "old"), "0.1.0");
+    }
+
+    #[test]
+    fn test_entry_without_version_is_migrated() {
+        use toml_query::delete::TomlValueDeleteExt;
+
+        let mut entry = Entry::new(StoreId::new(PathBuf::from("unversioned")).unwrap());
+        let _ = entry.get_header_mut().delete("imag.version").unwrap();
+
+        let mut registry = MigrationRegistry::default();
+        registry.register(Box::new(AddFlag("0.2.0", "test.a"))).unwrap();
+
+        let migrated = registry.migrate_entry(&mut entry).unwrap().unwrap();
+        assert_eq!(migrated.from.to_string(), "0.0.0");
+        assert_eq!(migrated.to.to_string(), "0.2.0");
+        assert_eq!(entry.get_header().read_bool("test.a").unwrap(), Some(true));
+    }
+}
@@ -0,0 +1,155 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Read-only stores mounted into the store
+//!
+//! The `store.mounts` section of the configuration maps prefixes to the paths of other stores:
+//!
+//! ```toml
+//! [store.mounts]
+//! team = "/srv/imag/team-store"
+//! ```
+//!
+//! The entries of a mounted store are seen with the prefix in front of their id, `wiki/foo` in the
+//! store above is `team/wiki/foo`. `Store::get()`, `Store::entries()` and everything built on
+//! them see the entries of the store and of all mounted stores. Links to mounted entries are
+//! stored with the prefixed id, like links to any other entry.
+//!
+//! Mounted stores are never written to. Changing a mounted entry writes the changed entry to the
+//! store itself, where it shadows the mounted one. Mounted entries cannot be deleted or moved.
+//! Only filesystem stores can be mounted.
+
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+
+/// A store which is mounted read-only under a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// The prefix of the ids of the entries of the mounted store
+    pub prefix: PathBuf,
+
+    /// Where the mounted store is
+    pub path: PathBuf,
+}
+
+impl Mount {
+
+    /// Build a mount, checking that the prefix is a valid id and the mounted store exists
+    pub fn new(prefix: &str, path: PathBuf) -> Result<Mount> {
+        let prefix = PathBuf::from(prefix);
+        let valid  = prefix.components().count() > 0 && prefix.components().all(|c| match c {
+            Component::Normal(name) => !name.to_str().map(|s| s.starts_with('.')).unwrap_or(true),
+            _                       => false,
+        });
+
+        if !valid {
+            return Err(format_err!("Invalid mount prefix: '{}'", prefix.display()))
+        }
+
+        if !path.is_dir() {
+            return Err(format_err!("Mounted store does not exist: {}", path.display()))
+        }
+
+        Ok(Mount { prefix, path })
+    }
+
+    /// The path in the mounted store for `path` in the store at `store_base`, if `path` is below
+    /// the prefix of this mount
+    pub(crate) fn resolve(&self, store_base: &Path, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(store_base.join(&self.prefix))
+            .ok()
+            .map(|rest| self.path.join(rest))
+    }
+}
+
+/// Get the mounted stores from the `store.mounts` section of the configuration
+pub(crate) fn config_mounts(config: &Option<Value>) -> Result<Vec<Mount>> {
+    let mounts = match *config {
+        None           => return Ok(vec![]),
+        Some(ref conf) => match conf.read("store.mounts").context(format_err!("Error reading 'store.mounts' in configuration"))? {
+            None                      => return Ok(vec![]),
+            Some(Value::Table(table)) => table,
+            Some(_)                   => return Err(format_err!("'store.mounts' must be a table")),
+        },
+    };
+
+    let mounts = mounts
+        .iter()
+        .map(|(prefix, path)| {
+            let path = path
+                .as_str()
+                .map(PathBuf::from)
+                .ok_or_else(|| format_err!("Path of mount '{}' must be a string", prefix))?;
+            Mount::new(prefix, path)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for a in mounts.iter() {
+        if let Some(b) = mounts.iter().find(|b| *b != a && b.prefix.starts_with(&a.prefix)) {
+            return Err(format_err!("Mounts '{}' and '{}' overlap", a.prefix.display(), b.prefix.display()))
+        }
+    }
+
+    Ok(mounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let dir    = TempDir::new("imag-store-mount").unwrap();
+        let config = ::toml::de::from_str(&format!(r#"
+        [store.mounts]
+            team = "{}"
+        "#, dir.path().display())).unwrap();
+
+        let mounts = config_mounts(&Some(config)).unwrap();
+        assert_eq!(mounts, vec![Mount { prefix: PathBuf::from("team"), path: dir.path().to_path_buf() }]);
+
+        let resolved = mounts[0].resolve(Path::new("/store"), Path::new("/store/team/wiki/foo"));
+        assert_eq!(resolved, Some(dir.path().join("wiki/foo")));
+        assert_eq!(mounts[0].resolve(Path::new("/store"), Path::new("/store/notes/foo")), None);
+
+        assert!(config_mounts(&None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_mounts() {
+        let dir  = TempDir::new("imag-store-mount").unwrap();
+        let path = dir.path().to_path_buf();
+
+        assert!(Mount::new("team", path.clone()).is_ok());
+        assert!(Mount::new("team/wiki", path.clone()).is_ok());
+        assert!(Mount::new("", path.clone()).is_err());
+        assert!(Mount::new(".team", path.clone()).is_err());
+        assert!(Mount::new("../team", path.clone()).is_err());
+        assert!(Mount::new("/team", path.clone()).is_err());
+        assert!(Mount::new("team", path.join("missing")).is_err());
+    }
+}
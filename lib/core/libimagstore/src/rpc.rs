@@ -0,0 +1,213 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! The protocol of `imag-server`
+//!
+//! `imag-server` owns the store and serves it over a Unix domain socket, by default
+//! `.imag-server.sock` in the store directory (configurable with `store.server.socket`).
+//! Requests and responses are JSON-RPC 2.0 objects, one per line.
+//!
+//! Entries are transferred as their complete text (header and content), ids relative to the
+//! store. The methods are:
+//!
+//! | Method              | Parameters                              | Result                          |
+//! |---------------------|-----------------------------------------|---------------------------------|
+//! | `create`            | `id`, optionally `entry`                | the text of the new entry       |
+//! | `get`               | `id`                                    | the text of the entry or `null` |
+//! | `exists`            | `id`                                    | `true` or `false`               |
+//! | `update`            | `id`, `entry`                           | `null`, creates the entry       |
+//! | `delete`            | `id`                                    | `null`                          |
+//! | `move`              | `from`, `to`                            | `null`                          |
+//! | `query`             | optionally `collection`, `header`, `value` | the ids of the matching entries |
+//! | `links`             | `id`                                    | `[{ "id": ..., "kind": ... }]`  |
+//! | `attachment.read`   | `hash`                                  | the hex encoded data or `null`  |
+//! | `attachment.write`  | `data`, hex encoded                     | the hash of the data            |
+//! | `shutdown`          |                                         | `null`, the server stops        |
+//!
+//! `query` without parameters returns all entries. With `header`, only entries which have a value
+//! at this header path are returned, with `value` as well only those where the header value (or
+//! one of its elements, for arrays) equals `value`.
+//!
+//! The `Client` is used by the runtime to access the store through a running server, see
+//! `Store::new_remote()`.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::err_msg;
+use serde_json::Value as JsonValue;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+
+/// The name of the socket in the store directory, if `store.server.socket` is not set
+pub const DEFAULT_SOCKET : &str = ".imag-server.sock";
+
+/// The request is not valid JSON
+pub const PARSE_ERROR      : i64 = -32700;
+/// The request is not a valid JSON-RPC request
+pub const INVALID_REQUEST  : i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND : i64 = -32601;
+/// The parameters of the method are missing or have the wrong type
+pub const INVALID_PARAMS   : i64 = -32602;
+/// The method failed
+pub const INTERNAL_ERROR   : i64 = -32603;
+
+/// The path of the socket of the server for the store at `store_base`
+///
+/// Relative paths in `store.server.socket` are relative to the store.
+pub fn socket_path(store_base: &Path, config: &Option<Value>) -> Result<PathBuf> {
+    let configured = match *config {
+        None             => None,
+        Some(ref config) => config
+            .read_string("store.server.socket")
+            .context("Error reading 'store.server.socket' in configuration")?,
+    };
+
+    Ok(store_base.join(configured.as_deref().unwrap_or(DEFAULT_SOCKET)))
+}
+
+/// Encode the data of an attachment for a request or response, as hex string
+pub fn encode_data(data: &[u8]) -> String {
+    crate::encryption::to_hex(data)
+}
+
+/// Decode the data of an attachment from a request or response
+pub fn decode_data(data: &str) -> Result<Vec<u8>> {
+    crate::encryption::from_hex(data)
+}
+
+/// Build a request object
+pub fn request(id: u64, method: &str, params: JsonValue) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+/// Build the response object for a successful request
+pub fn success(id: JsonValue, result: JsonValue) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// Build the response object for a failed request
+pub fn error(id: JsonValue, code: i64, message: &str) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// A connection to `imag-server`
+#[derive(Debug)]
+pub struct Client {
+    stream:  BufReader<UnixStream>,
+    next_id: u64,
+}
+
+impl Client {
+
+    /// Connect to the server listening on `socket`
+    pub fn connect(socket: &Path) -> Result<Client> {
+        let stream = UnixStream::connect(socket)
+            .context(format_err!("Cannot connect to imag-server at {}", socket.display()))?;
+        Ok(Client { stream: BufReader::new(stream), next_id: 0 })
+    }
+
+    /// Call `method` with `params` and wait for the result
+    ///
+    /// Errors returned by the server are turned into errors.
+    pub fn call(&mut self, method: &str, params: JsonValue) -> Result<JsonValue> {
+        self.next_id += 1;
+        let id = self.next_id;
+        trace!("Calling {} ({}): {}", method, id, params);
+
+        let mut line = ::serde_json::to_string(&request(id, method, params))?;
+        line.push('\n');
+        self.stream.get_mut().write_all(line.as_bytes()).context("Error sending request to imag-server")?;
+
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).context("Error reading response from imag-server")? == 0 {
+            return Err(err_msg("imag-server closed the connection"))
+        }
+
+        let mut response : JsonValue = ::serde_json::from_str(&line)
+            .context("Invalid response from imag-server")?;
+        if response.get("id").and_then(JsonValue::as_u64) != Some(id) {
+            return Err(format_err!("Response from imag-server does not match request {}", id))
+        }
+
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(JsonValue::as_str).unwrap_or("unknown error");
+            return Err(format_err!("imag-server: {}", message))
+        }
+
+        Ok(response.get_mut("result").map(JsonValue::take).unwrap_or(JsonValue::Null))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_path() {
+        let base = PathBuf::from("/home/user/.imag/store");
+        assert_eq!(socket_path(&base, &None).unwrap(), base.join(".imag-server.sock"));
+
+        let config = ::toml::from_str("[store.server]\nsocket = \"/run/imag.sock\"").unwrap();
+        assert_eq!(socket_path(&base, &Some(config)).unwrap(), PathBuf::from("/run/imag.sock"));
+    }
+
+    #[test]
+    fn test_call() {
+        use std::os::unix::net::UnixListener;
+
+        let dir      = ::tempdir::TempDir::new("imag-rpc").unwrap();
+        let socket   = dir.path().join("sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let server = ::std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream  = BufReader::new(stream);
+            for answer in [json!(["notes/a"]), JsonValue::Null].iter() {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                let request : JsonValue = ::serde_json::from_str(&line).unwrap();
+                let response = if *answer == JsonValue::Null {
+                    error(request["id"].clone(), METHOD_NOT_FOUND, "Unknown method: frobnicate")
+                } else {
+                    assert_eq!(request["method"], "query");
+                    success(request["id"].clone(), answer.clone())
+                };
+                writeln!(stream.get_mut(), "{}", response).unwrap();
+            }
+        });
+
+        let mut client = Client::connect(&socket).unwrap();
+        assert_eq!(client.call("query", json!({})).unwrap(), json!(["notes/a"]));
+        let e = client.call("frobnicate", json!({})).unwrap_err();
+        assert_eq!(e.to_string(), "imag-server: Unknown method: frobnicate");
+        server.join().unwrap();
+    }
+
+}
@@ -0,0 +1,356 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Schemas for the headers of entries
+//!
+//! The `store.schema` section of the configuration declares which header fields the entries
+//! with a certain id must have:
+//!
+//! ```toml
+//! [store.schema]
+//! mode = "strict"
+//!
+//! [store.schema.entries."todo/*"]
+//! "todo.uuid"   = "string"
+//! "todo.status" = { type = "string", values = [ "pending", "done", "deleted" ] }
+//! "todo.due"    = { type = "datetime", required = false }
+//! ```
+//!
+//! The keys of `store.schema.entries` are glob patterns which are matched against the ids of the
+//! entries, `*` does not match `/`, `**` does. Each field is either a type (`string`, `integer`,
+//! `float`, `boolean`, `datetime`, `array` or `table`) or a table with an optional `type`, an
+//! optional list of allowed `values` and `required` (`true` by default).
+//!
+//! Entries are checked when they are created (except for missing fields) and when they are
+//! written. In `strict` mode, entries which do not match the schema are not written, in `lax`
+//! mode (the default) a warning is printed.
+
+use glob::MatchOptions;
+use glob::Pattern;
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use toml_query::read::TomlValueReadTypeExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::store::Entry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SchemaMode {
+    /// Only warn about entries which do not match the schema
+    Lax,
+
+    /// Refuse to write entries which do not match the schema
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Datetime,
+    Array,
+    Table,
+}
+
+impl FieldType {
+    fn from_name(name: &str) -> Result<FieldType> {
+        match name {
+            "string"   => Ok(FieldType::String),
+            "integer"  => Ok(FieldType::Integer),
+            "float"    => Ok(FieldType::Float),
+            "boolean"  => Ok(FieldType::Boolean),
+            "datetime" => Ok(FieldType::Datetime),
+            "array"    => Ok(FieldType::Array),
+            "table"    => Ok(FieldType::Table),
+            other      => Err(format_err!("Unknown type in schema: {}", other)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::String   => "string",
+            FieldType::Integer  => "integer",
+            FieldType::Float    => "float",
+            FieldType::Boolean  => "boolean",
+            FieldType::Datetime => "datetime",
+            FieldType::Array    => "array",
+            FieldType::Table    => "table",
+        }
+    }
+
+    fn of(value: &Value) -> FieldType {
+        match *value {
+            Value::String(_)   => FieldType::String,
+            Value::Integer(_)  => FieldType::Integer,
+            Value::Float(_)    => FieldType::Float,
+            Value::Boolean(_)  => FieldType::Boolean,
+            Value::Datetime(_) => FieldType::Datetime,
+            Value::Array(_)    => FieldType::Array,
+            Value::Table(_)    => FieldType::Table,
+        }
+    }
+}
+
+/// The declaration of one header field
+#[derive(Debug, Clone)]
+struct Field {
+    path: String,
+    kind: Option<FieldType>,
+    required: bool,
+
+    /// The values the field may have, `None` if any value is allowed
+    values: Option<Vec<Value>>,
+}
+
+impl Field {
+    fn from_config(path: &str, spec: &Value) -> Result<Field> {
+        let path = String::from(path);
+        match *spec {
+            Value::String(ref name) => Ok(Field {
+                path,
+                kind: Some(FieldType::from_name(name)?),
+                required: true,
+                values: None,
+            }),
+
+            Value::Table(_) => Ok(Field {
+                kind: spec.read_string("type")?.map(|t| FieldType::from_name(&t)).transpose()?,
+                required: spec.read_bool("required")?.unwrap_or(true),
+                values: match spec.read("values")? {
+                    None                       => None,
+                    Some(Value::Array(values)) => Some(values.clone()),
+                    Some(_) => return Err(format_err!("'values' of '{}' in schema must be an array", path)),
+                },
+                path,
+            }),
+
+            _ => Err(format_err!("Invalid schema for '{}', expected a type or a table", path)),
+        }
+    }
+
+    /// Find out how `header` violates this declaration, if it does
+    ///
+    /// Missing fields are only a violation if `require` is set.
+    fn violation(&self, header: &Value, require: bool) -> Result<Option<String>> {
+        let value = match header.read(&self.path)? {
+            Some(value) => value,
+            None if self.required && require => return Ok(Some(format!("'{}' is missing", self.path))),
+            None => return Ok(None),
+        };
+
+        if let Some(kind) = self.kind {
+            let actual = FieldType::of(value);
+            if actual != kind {
+                return Ok(Some(format!("'{}' must be a {}, not a {}", self.path, kind.name(), actual.name())))
+            }
+        }
+
+        if let Some(ref values) = self.values {
+            if !values.contains(value) {
+                let allowed = values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+                return Ok(Some(format!("'{}' must be one of {}, not {}", self.path, allowed, value)))
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    fields: Vec<Field>,
+}
+
+/// The schema of the store, from `store.schema`
+#[derive(Debug, Clone)]
+pub(crate) struct Schema {
+    mode: SchemaMode,
+    rules: Vec<Rule>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Schema { mode: SchemaMode::Lax, rules: vec![] }
+    }
+}
+
+impl Schema {
+
+    /// Find the ways the header of `entry` violates the schema
+    pub(crate) fn violations(&self, entry: &Entry) -> Result<Vec<String>> {
+        self.find_violations(entry, true)
+    }
+
+    fn find_violations(&self, entry: &Entry, require: bool) -> Result<Vec<String>> {
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        let mut violations = vec![];
+
+        for rule in self.rules.iter().filter(|r| r.pattern.matches_path_with(entry.get_location().local(), options)) {
+            for field in rule.fields.iter() {
+                if let Some(violation) = field.violation(entry.get_header(), require)? {
+                    violations.push(violation);
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
+    /// Check `entry` before it is written
+    ///
+    /// Fails in strict mode if the entry violates the schema, only warns in lax mode.
+    pub(crate) fn check(&self, entry: &Entry) -> Result<()> {
+        self.check_violations(entry, true)
+    }
+
+    /// Check a newly created `entry`
+    ///
+    /// Like `Schema::check()`, but required fields may still be missing, as the caller did not
+    /// have a chance to set them yet. They are checked when the entry is written.
+    pub(crate) fn check_created(&self, entry: &Entry) -> Result<()> {
+        self.check_violations(entry, false)
+    }
+
+    fn check_violations(&self, entry: &Entry, require: bool) -> Result<()> {
+        if self.rules.is_empty() {
+            return Ok(())
+        }
+
+        let violations = self.find_violations(entry, require)?;
+        if violations.is_empty() {
+            return Ok(())
+        }
+
+        match self.mode {
+            SchemaMode::Strict => Err(format_err!("Header of {} does not match the schema: {}",
+                                                  entry.get_location(),
+                                                  violations.join("; "))),
+            SchemaMode::Lax => {
+                for violation in violations {
+                    warn!("Header of {} does not match the schema: {}", entry.get_location(), violation);
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Get the schema from the `store.schema` section of the configuration
+pub(crate) fn config_schema(config: &Option<Value>) -> Result<Schema> {
+    let schema = match *config {
+        None           => return Ok(Schema::default()),
+        Some(ref conf) => match conf.read("store.schema").context(format_err!("Error reading 'store.schema' in configuration"))? {
+            None         => return Ok(Schema::default()),
+            Some(schema) => schema,
+        },
+    };
+
+    let mode = match schema.read_string("mode")?.as_deref() {
+        None | Some("lax") => SchemaMode::Lax,
+        Some("strict")     => SchemaMode::Strict,
+        Some(other)        => return Err(format_err!("Unknown 'store.schema.mode': {}", other)),
+    };
+
+    let rules = match schema.read("entries")? {
+        None                      => vec![],
+        Some(Value::Table(table)) => table
+            .iter()
+            .map(|(pattern, fields)| {
+                let fields = fields
+                    .as_table()
+                    .ok_or_else(|| format_err!("Schema for '{}' must be a table", pattern))?
+                    .iter()
+                    .map(|(path, spec)| Field::from_config(path, spec))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let pattern = Pattern::new(pattern)
+                    .context(format_err!("Invalid pattern in 'store.schema.entries': {}", pattern))
+                    .map_err(Error::from)?;
+
+                Ok(Rule { pattern, fields })
+            })
+            .collect::<Result<Vec<_>>>()
+            .context(format_err!("Error reading 'store.schema.entries' in configuration"))?,
+        Some(_) => return Err(format_err!("'store.schema.entries' must be a table")),
+    };
+
+    Ok(Schema { mode, rules })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml_query::insert::TomlValueInsertExt;
+
+    use super::*;
+
+    fn schema(mode: &str) -> Schema {
+        let config = ::toml::de::from_str(&format!(r#"
+        [store.schema]
+            mode = "{}"
+
+        [store.schema.entries."todo/*"]
+            "todo.uuid"   = "string"
+            "todo.status" = {{ type = "string", values = [ "pending", "done" ] }}
+            "todo.due"    = {{ type = "datetime", required = false }}
+        "#, mode)).unwrap();
+        config_schema(&Some(config)).unwrap()
+    }
+
+    fn todo(id: &str, status: Value) -> Entry {
+        let mut entry = Entry::new(crate::storeid::StoreId::new(PathBuf::from(id)).unwrap());
+        let _ = entry.get_header_mut().insert("todo.uuid", Value::String(String::from("1234"))).unwrap();
+        let _ = entry.get_header_mut().insert("todo.status", status).unwrap();
+        entry
+    }
+
+    #[test]
+    fn test_violations() {
+        let schema = schema("strict");
+
+        assert!(schema.violations(&todo("todo/a", Value::String(String::from("done")))).unwrap().is_empty());
+
+        let violations = schema.violations(&todo("todo/a", Value::String(String::from("dnoe")))).unwrap();
+        assert_eq!(violations, vec![String::from(r#"'todo.status' must be one of "pending", "done", not "dnoe""#)]);
+
+        let violations = schema.violations(&todo("todo/a", Value::Integer(1))).unwrap();
+        assert_eq!(violations, vec![String::from("'todo.status' must be a string, not a integer")]);
+
+        // the pattern does not match
+        assert!(schema.violations(&todo("notes/a", Value::Integer(1))).unwrap().is_empty());
+        assert!(schema.violations(&todo("todo/a/b", Value::Integer(1))).unwrap().is_empty());
+
+        let missing = Entry::new(crate::storeid::StoreId::new(PathBuf::from("todo/b")).unwrap());
+        assert_eq!(schema.violations(&missing).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_modes() {
+        let entry = todo("todo/a", Value::Integer(1));
+        assert!(schema("strict").check(&entry).is_err());
+        assert!(schema("lax").check(&entry).is_ok());
+        assert!(Schema::default().check(&entry).is_ok());
+    }
+}
@@ -0,0 +1,494 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+
+//! Storing the store in a single SQLite database
+//!
+//! With `store.backend = "sqlite"`, all files of the store (the entries, but also the index, the
+//! history, the trash and the data of attachments) are rows in the database `.store.sqlite` in
+//! the store directory. This avoids many small files, which are slow to synchronize and use up
+//! inodes.
+//!
+//! An existing store can be converted with `convert_to_sqlite()` and back with
+//! `convert_to_filesystem()`.
+//!
+//! This module is only available if libimagstore is compiled with the `sqlite` feature.
+
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Error as FmtError;
+use std::fs::read;
+use std::fs::read_dir;
+use std::fs::read_to_string;
+use std::fs::remove_dir;
+use std::fs::remove_file;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+use walkdir::WalkDir;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagerror::errors::ErrorMsg as EM;
+
+use crate::file_abstraction::fs::write_atomically;
+use crate::file_abstraction::iter::is_visible_below;
+use crate::attachment::hash_of_path;
+
+/// The name of the database file in the store directory
+pub const DATABASE_FILE : &str = ".store.sqlite";
+
+const SCHEMA : &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS files (
+        path    TEXT PRIMARY KEY NOT NULL,
+        content TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS blobs (
+        path    TEXT PRIMARY KEY NOT NULL,
+        content BLOB NOT NULL,
+        written INTEGER NOT NULL DEFAULT 0
+    );
+";
+
+/// Databases created before the blobs had a `written` column (the seconds since the epoch) get it
+const ADD_WRITTEN : &str = "ALTER TABLE blobs ADD COLUMN written INTEGER NOT NULL DEFAULT 0";
+
+/// The database of a store
+///
+/// Files are identified by their path relative to the store directory. Text files are in the
+/// `files` table, binary files (the data of attachments) in the `blobs` table.
+#[derive(Clone)]
+pub(crate) struct Database {
+    store_path: PathBuf,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Debug for Database {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), FmtError> {
+        write!(fmt, "Database({})", self.store_path.join(DATABASE_FILE).display())
+    }
+}
+
+impl Database {
+
+    /// Open (or create) the database of the store at `store_path`
+    pub(crate) fn open(store_path: &Path) -> Result<Database> {
+        let db_path = store_path.join(DATABASE_FILE);
+        debug!("Opening database: {}", db_path.display());
+
+        let conn = Connection::open(&db_path)
+            .context(format_err!("Failed to open database: {}", db_path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .context(format_err!("Failed to initialize database: {}", db_path.display()))?;
+
+        let has_written = conn
+            .prepare("SELECT name FROM pragma_table_info('blobs')")?
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .collect::<::std::result::Result<Vec<_>, _>>()?
+            .iter()
+            .any(|column| column == "written");
+        if !has_written {
+            let _ = conn.execute(ADD_WRITTEN, params![])
+                .context(format_err!("Failed to upgrade database: {}", db_path.display()))?;
+        }
+
+        Ok(Database { store_path: store_path.to_path_buf(), conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn.lock().map_err(|_| Error::from(EM::LockError))
+    }
+
+    /// The key of `path` in the database
+    fn key(&self, path: &Path) -> Result<String> {
+        path.strip_prefix(&self.store_path)
+            .map_err(|_| format_err!("Path is not in the store: {}", path.display()))?
+            .to_str()
+            .map(String::from)
+            .ok_or_else(|| format_err!("Path is not valid UTF-8: {}", path.display()))
+    }
+
+    pub(crate) fn read(&self, path: &Path) -> Result<Option<String>> {
+        let key = self.key(path)?;
+        self.lock()?
+            .query_row("SELECT content FROM files WHERE path = ?1", params![key], |row| row.get(0))
+            .optional()
+            .context(format_err!("Failed to read {} from database", key))
+            .map_err(Error::from)
+    }
+
+    pub(crate) fn write(&self, path: &Path, content: &str) -> Result<()> {
+        let key = self.key(path)?;
+        trace!("Writing {} to database", key);
+        self.lock()?
+            .execute("INSERT OR REPLACE INTO files (path, content) VALUES (?1, ?2)", params![key, content])
+            .context(format_err!("Failed to write {} to database", key))?;
+        Ok(())
+    }
+
+    pub(crate) fn read_bytes(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        let key = self.key(path)?;
+        self.lock()?
+            .query_row("SELECT content FROM blobs WHERE path = ?1", params![key], |row| row.get(0))
+            .optional()
+            .context(format_err!("Failed to read {} from database", key))
+            .map_err(Error::from)
+    }
+
+    pub(crate) fn write_bytes(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let key = self.key(path)?;
+        trace!("Writing binary {} to database", key);
+        let written = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        self.lock()?
+            .execute("INSERT OR REPLACE INTO blobs (path, content, written) VALUES (?1, ?2, ?3)",
+                     params![key, content, written])
+            .context(format_err!("Failed to write {} to database", key))?;
+        Ok(())
+    }
+
+    /// When the binary file at `path` was written, `None` if it does not exist
+    pub(crate) fn modified(&self, path: &Path) -> Result<Option<SystemTime>> {
+        let key = self.key(path)?;
+        self.lock()?
+            .query_row("SELECT written FROM blobs WHERE path = ?1", params![key], |row| row.get::<_, i64>(0))
+            .optional()
+            .context(format_err!("Failed to read {} from database", key))
+            .map(|written| written.map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)))
+            .map_err(Error::from)
+    }
+
+    /// Remove the file (text or binary) at `path`, which fails if it does not exist
+    pub(crate) fn remove(&self, path: &Path) -> Result<()> {
+        let key  = self.key(path)?;
+        let conn = self.lock()?;
+        let n    = conn
+            .execute("DELETE FROM files WHERE path = ?1", params![key])
+            .and_then(|n| conn.execute("DELETE FROM blobs WHERE path = ?1", params![key]).map(|m| n + m))
+            .context(format_err!("Failed to remove {} from database", key))?;
+
+        if n == 0 {
+            Err(Error::from(EM::FileNotFound)).context(format_err!("{}", key)).map_err(Error::from)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Copy the file (text or binary) at `from` to `to`
+    pub(crate) fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (self.key(from)?, self.key(to)?);
+        let conn       = self.lock()?;
+        let n          = conn
+            .execute("INSERT OR REPLACE INTO files (path, content) SELECT ?2, content FROM files WHERE path = ?1",
+                     params![from, to])
+            .and_then(|n| {
+                conn.execute("INSERT OR REPLACE INTO blobs (path, content, written) SELECT ?2, content, written FROM blobs WHERE path = ?1",
+                             params![from, to])
+                    .map(|m| n + m)
+            })
+            .context(format_err!("Failed to copy {} to {} in database", from, to))?;
+
+        if n == 0 {
+            Err(Error::from(EM::FileNotFound)).context(format_err!("{}", from)).map_err(Error::from)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rename the file (text or binary) at `from` to `to`, replacing `to` if it exists
+    pub(crate) fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (self.key(from)?, self.key(to)?);
+        let mut conn   = self.lock()?;
+        let tx         = conn.transaction()?;
+
+        let mut n = 0;
+        for table in &["files", "blobs"] {
+            let _ = tx.execute(&format!("DELETE FROM {} WHERE path = ?1 AND ?1 != ?2", table), params![to, from])?;
+            n += tx.execute(&format!("UPDATE {} SET path = ?2 WHERE path = ?1", table), params![from, to])?;
+        }
+        if n == 0 {
+            return Err(Error::from(EM::FileNotFound)).context(format_err!("{}", from)).map_err(Error::from)
+        }
+
+        tx.commit().context(format_err!("Failed to rename {} to {} in database", from, to))?;
+        Ok(())
+    }
+
+    /// Whether there is a file (text or binary) at `path`
+    pub(crate) fn is_file(&self, path: &Path) -> Result<bool> {
+        let key = self.key(path)?;
+        self.lock()?
+            .query_row("SELECT EXISTS (SELECT 1 FROM files WHERE path = ?1) OR EXISTS (SELECT 1 FROM blobs WHERE path = ?1)",
+                       params![key],
+                       |row| row.get(0))
+            .context(format_err!("Failed to query database for {}", key))
+            .map_err(Error::from)
+    }
+
+    /// Whether there is a file at `path` or below it
+    pub(crate) fn exists(&self, path: &Path) -> Result<bool> {
+        if path == self.store_path {
+            return Ok(true)
+        }
+
+        let key = self.key(path)?;
+        self.lock()?
+            .query_row("SELECT EXISTS (SELECT 1 FROM files WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/')",
+                       params![key],
+                       |row| row.get(0))
+            .context(format_err!("Failed to query database for {}", key))
+            .map_err(Error::from)
+    }
+
+    /// All files below `base` which are no internal files of `root` (the store or the trash), as
+    /// absolute paths
+    pub(crate) fn paths_below(&self, base: &Path, root: &Path) -> Result<Vec<PathBuf>> {
+        let key = self.key(base)?;
+        let key = if key.is_empty() { key } else { key + "/" };
+
+        let conn      = self.lock()?;
+        let mut stmt  = conn.prepare("SELECT path FROM files WHERE substr(path, 1, length(?1)) = ?1 ORDER BY path")?;
+        let paths     = stmt
+            .query_map(params![key], |row| row.get::<_, String>(0))?
+            .map(|path| path.map(|p| self.store_path.join(p)).map_err(Error::from))
+            .filter(|path| path.as_ref().map(|p| is_visible_below(p, root)).unwrap_or(true))
+            .collect::<Result<Vec<_>>>()
+            .context(format_err!("Failed to list files in database"))?;
+        Ok(paths)
+    }
+
+    /// All files in the database, as absolute paths with their content
+    fn all(&self) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let conn      = self.lock()?;
+        let mut stmt  = conn.prepare("SELECT path, CAST(content AS BLOB) FROM files UNION ALL SELECT path, content FROM blobs ORDER BY path")?;
+        let files     = stmt
+            .query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+            .map(|file| file.map(|(p, c)| (self.store_path.join(p), c)).map_err(Error::from))
+            .collect::<Result<Vec<_>>>()
+            .context(format_err!("Failed to read files from database"))?;
+        Ok(files)
+    }
+}
+
+/// Whether the store at `store_path` has a database
+pub fn has_database(store_path: &Path) -> bool {
+    store_path.join(DATABASE_FILE).is_file()
+}
+
+/// Copy all files of the store at `store_path` into its database
+///
+/// The git repository of the store is not copied. The data of attachments is copied as it is,
+/// all other files must be text files. If `remove_source` is set, the files are removed
+/// after they were copied. Returns the copied files.
+pub fn convert_to_sqlite(store_path: &Path, remove_source: bool) -> Result<Vec<PathBuf>> {
+    let db    = Database::open(store_path)?;
+    let files = WalkDir::new(store_path)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| !is_database_file(e.file_name().to_str()) && e.file_name() != ".git")
+        .filter(|e| e.as_ref().map(|e| e.file_type().is_file()).unwrap_or(true))
+        .map(|e| e.map(|e| e.into_path()).context(format_err!("Error in Walkdir")).map_err(Error::from))
+        .collect::<Result<Vec<PathBuf>>>()?;
+
+    {
+        let mut conn = db.lock()?;
+        let tx       = conn.transaction()?;
+        for file in files.iter() {
+            if hash_of_path(store_path, file).is_some() {
+                let content = read(file).context(format_err!("Failed to read {}", file.display()))?;
+                let _ = tx.execute("INSERT OR REPLACE INTO blobs (path, content) VALUES (?1, ?2)",
+                                   params![db.key(file)?, content])?;
+                continue
+            }
+
+            let content = read_to_string(file)
+                .context(format_err!("Failed to read {}, if the store is encrypted, decrypt it first", file.display()))?;
+            let _ = tx.execute("INSERT OR REPLACE INTO files (path, content) VALUES (?1, ?2)",
+                               params![db.key(file)?, content])?;
+        }
+        tx.commit().context(format_err!("Failed to write files to database"))?;
+    }
+
+    if remove_source {
+        for file in files.iter() {
+            trace!("Removing {}", file.display());
+            remove_file(file).context(EM::FileNotRemoved)?;
+        }
+        remove_empty_dirs(store_path)?;
+    }
+
+    Ok(files)
+}
+
+/// Write all files from the database of the store at `store_path` to the filesystem
+///
+/// Existing files are overwritten. If `remove_source` is set, the database is removed afterwards.
+/// Returns the written files.
+pub fn convert_to_filesystem(store_path: &Path, remove_source: bool) -> Result<Vec<PathBuf>> {
+    if !has_database(store_path) {
+        return Err(format_err!("No database in store: {}", store_path.display()))
+    }
+
+    let files = Database::open(store_path)?.all()?;
+    for (path, content) in files.iter() {
+        trace!("Writing {}", path.display());
+        write_atomically(path, content)?;
+    }
+
+    if remove_source {
+        for name in &[DATABASE_FILE, ".store.sqlite-wal", ".store.sqlite-shm"] {
+            let path = store_path.join(name);
+            if path.exists() {
+                remove_file(&path).context(EM::FileNotRemoved)?;
+            }
+        }
+    }
+
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+fn is_database_file(name: Option<&str>) -> bool {
+    name.map(|n| n.starts_with(DATABASE_FILE)).unwrap_or(false)
+}
+
+/// Remove all empty directories below `path`
+fn remove_empty_dirs(path: &Path) -> Result<()> {
+    for dir in WalkDir::new(path).min_depth(1).contents_first(true) {
+        let dir = dir.context(format_err!("Error in Walkdir"))?;
+        if dir.file_type().is_dir() && read_dir(dir.path()).context(EM::IO)?.next().is_none() {
+            remove_dir(dir.path()).context(EM::FileNotRemoved)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::store::Store;
+    use crate::storeid::IntoStoreId;
+
+    fn config() -> Option<::toml::Value> {
+        ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+            backend = "sqlite"
+        "#).ok()
+    }
+
+    #[test]
+    fn test_store_in_database() {
+        let dir = TempDir::new("imag-store-sqlite").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &config()).unwrap();
+            for id in &["notes/a", "notes/b", "todo/a"] {
+                let mut entry = store.create(PathBuf::from(id)).unwrap();
+                *entry.get_content_mut() = format!("content of {}", id);
+            }
+            store.move_by_id(PathBuf::from("todo/a").into_storeid().unwrap(),
+                             PathBuf::from("todo/b").into_storeid().unwrap()).unwrap();
+            store.delete(PathBuf::from("notes/b")).unwrap();
+        }
+
+        // entries are locked with files, which are removed again
+        let files = read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|f| f != crate::lock::LOCKS_DIR)
+            .collect::<Vec<_>>();
+        assert!(files.iter().all(|f| f.to_str().unwrap().starts_with(DATABASE_FILE)), "Files in store: {:?}", files);
+
+        let store   = Store::new(dir.path().to_path_buf(), &config()).unwrap();
+        let mut ids = store.entries().unwrap().map(|id| id.unwrap().to_str().unwrap()).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["notes/a", "todo/b"]);
+        assert_eq!(store.entries().unwrap().in_collection("todo").unwrap().count(), 1);
+        assert_eq!(store.get_copy(PathBuf::from("todo/b")).unwrap().get_content(), "content of todo/a");
+        assert!(store.get(PathBuf::from("notes/b")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let dir = TempDir::new("imag-store-sqlite").unwrap();
+        let db  = Database::open(dir.path()).unwrap();
+        let a   = dir.path().join(".attachments/a");
+        let b   = dir.path().join(".attachments/b");
+        let c   = dir.path().join(".attachments/c");
+
+        db.write_bytes(&a, &[0x00, 0xff]).unwrap();
+        assert!(db.is_file(&a).unwrap());
+        assert!(db.modified(&a).unwrap().is_some());
+        assert!(db.modified(&b).unwrap().is_none());
+
+        db.copy(&a, &b).unwrap();
+        db.rename(&a, &c).unwrap();
+        assert!(!db.is_file(&a).unwrap());
+        assert_eq!(db.read_bytes(&b).unwrap(), Some(vec![0x00, 0xff]));
+        assert_eq!(db.read_bytes(&c).unwrap(), Some(vec![0x00, 0xff]));
+        assert!(db.rename(&a, &c).is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let dir = TempDir::new("imag-store-sqlite").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            let mut entry = store.create(PathBuf::from("notes/a")).unwrap();
+            *entry.get_content_mut() = String::from("a");
+            let _ = store.attach(&mut entry, "scan.png", "image/png", &[0x89, 0x50, 0xff]).unwrap();
+        }
+
+        let converted = convert_to_sqlite(dir.path(), true).unwrap();
+        assert!(converted.contains(&dir.path().join("notes/a")));
+        assert!(!dir.path().join("notes").exists());
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &config()).unwrap();
+            let entry = store.get_copy(PathBuf::from("notes/a")).unwrap();
+            assert_eq!(entry.get_content(), "a");
+            assert_eq!(store.attachment_data(&entry.attachments().unwrap()[0]).unwrap(), vec![0x89, 0x50, 0xff]);
+        }
+
+        let converted = convert_to_filesystem(dir.path(), true).unwrap();
+        assert!(converted.contains(&dir.path().join("notes/a")));
+        assert!(!has_database(dir.path()));
+
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        assert_eq!(store.get_copy(PathBuf::from("notes/a")).unwrap().get_content(), "a");
+    }
+}
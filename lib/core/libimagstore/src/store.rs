@@ -17,12 +17,22 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::ops::Drop;
 use std::path::PathBuf;
 use std::result::Result as RResult;
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
 use std::io::Read;
 use std::ops::Deref;
 use std::ops::DerefMut;
@@ -40,12 +50,40 @@ use failure::ResultExt;
 use failure::err_msg;
 use failure::Error;
 
-use crate::storeid::{IntoStoreId, StoreId};
+use crate::storeid::{IntoStoreId, StoreId, StoreIdIterator};
 use crate::iter::Entries;
 use crate::file_abstraction::FileAbstraction;
 use crate::file_abstraction::FileAbstractionInstance;
+use crate::file_abstraction::encrypted::EncryptedFileAbstraction;
 use crate::file_abstraction::fs::FSFileAbstraction;
+use crate::file_abstraction::overlay::OverlayFileAbstraction;
+use crate::file_abstraction::remote::RemoteFileAbstraction;
+use crate::file_abstraction::git::GitFileAbstraction;
 use crate::file_abstraction::inmemory::InMemoryFileAbstraction;
+#[cfg(feature = "sqlite")]
+use crate::file_abstraction::sqlite::SqliteFileAbstraction;
+use crate::transaction::Journal;
+use crate::index::Index;
+use crate::hook::Hook;
+use crate::hook::HookArgs;
+use crate::hook::HookPosition;
+use crate::trash::TrashConfig;
+use crate::trash::TrashedEntry;
+use crate::history::History;
+use crate::history::HistoryConfig;
+use crate::history::Revision;
+use crate::content::ContentLoader;
+use crate::content::LazyContent;
+use crate::header_format::HeaderFormat;
+use crate::lock::EntryLock;
+use crate::lock::LockConfig;
+use crate::lock::LockHolder;
+use crate::fsck::Finding;
+use crate::fsck::Problem;
+use crate::schema::Schema;
+use crate::mount::Mount;
+use crate::rpc::Client;
+use crate::attachment::Attachment;
 
 use libimagutil::debug_result::*;
 
@@ -53,7 +91,11 @@ use libimagutil::debug_result::*;
 #[derive(Debug, PartialEq)]
 enum StoreEntryStatus {
     Present,
-    Borrowed
+    Borrowed,
+
+    /// The entry is borrowed, but a failed transaction restored the file under it, so the
+    /// `FileLockEntry` must not be written back
+    RolledBack,
 }
 
 /// A store entry, depending on the option type it is either borrowed currently
@@ -64,6 +106,13 @@ struct StoreEntry {
     store_base: PathBuf, // small sacrefice over lifetimes on the Store type
     file: Box<dyn FileAbstractionInstance>,
     status: StoreEntryStatus,
+
+    /// The hashes of the attachments of the entry as it was read, to find the attachments which
+    /// are removed when it is written
+    attachments: Vec<String>,
+
+    /// The hashes of the attachments which were attached to the entry, but not written yet
+    unwritten_attachments: Vec<String>,
 }
 
 impl StoreEntry {
@@ -71,32 +120,41 @@ impl StoreEntry {
     fn new(store_base: PathBuf, id: StoreId, backend: &Arc<dyn FileAbstraction>) -> Result<StoreEntry> {
         let pb = id.clone().with_base(&store_base).into_pathbuf()?;
 
-        #[cfg(feature = "fs-lock")]
-        {
-            open_file(pb.clone())
-                .and_then(|f| f.lock_exclusive())
-                .with_context(|| EM::IO)?;
-        }
-
         Ok(StoreEntry {
             id,
             store_base,
             file: backend.new_instance(pb),
             status: StoreEntryStatus::Present,
+            attachments: vec![],
+            unwritten_attachments: vec![],
         })
     }
 
     /// The entry is currently borrowed, meaning that some thread is currently
     /// mutating it
     fn is_borrowed(&self) -> bool {
-        self.status == StoreEntryStatus::Borrowed
+        self.status != StoreEntryStatus::Present
+    }
+
+    /// The entry is borrowed, but must not be written back because a transaction was rolled back
+    fn is_rolled_back(&self) -> bool {
+        self.status == StoreEntryStatus::RolledBack
     }
 
-    fn get_entry(&mut self) -> Result<Entry> {
+    /// Get the entry, or a new one with a header in `format` if it does not exist
+    fn get_entry(&mut self, format: HeaderFormat) -> Result<Entry> {
         if !self.is_borrowed() {
             match self.file.get_file_content(self.id.clone().with_base(&self.store_base))? {
-                Some(file) => Ok(file),
-                None       => Ok(Entry::new(self.id.clone()))
+                Some(file) => {
+                    self.attachments = crate::attachment::hashes(file.get_header());
+                    Ok(file)
+                },
+                None       => {
+                    self.attachments = vec![];
+                    let mut entry = Entry::new(self.id.clone());
+                    entry.set_header_format(format);
+                    Ok(entry)
+                },
             }
         } else {
             Err(format_err!("EntryAlreadyBorrowed: {}", self.id))
@@ -104,7 +162,7 @@ impl StoreEntry {
     }
 
     fn write_entry(&mut self, entry: &Entry) -> Result<()> {
-        if self.is_borrowed() {
+        if self.status == StoreEntryStatus::Borrowed {
             assert_eq!(self.id, entry.location);
             trace!("Writing entry...");
             self.file
@@ -116,17 +174,127 @@ impl StoreEntry {
     }
 }
 
-#[cfg(feature = "fs-lock")]
-impl Drop for StoreEntry {
+/// The number of shards of the internal cache
+const CACHE_SHARDS : usize = 64;
+
+type CacheShard = HashMap<StoreId, StoreEntry>;
+
+/// The internal cache of the store
+///
+/// The cache is split into shards which are locked independently, so operations on different
+/// entries do not have to wait for each other.
+#[derive(Debug)]
+struct EntryCache(Vec<RwLock<CacheShard>>);
+
+impl EntryCache {
+
+    fn new() -> EntryCache {
+        EntryCache((0..CACHE_SHARDS).map(|_| RwLock::new(HashMap::new())).collect())
+    }
+
+    fn shard_index(&self, id: &StoreId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        (hasher.finish() as usize) % self.0.len()
+    }
+
+    /// Lock the shard of `id` for reading
+    fn read(&self, id: &StoreId) -> Result<RwLockReadGuard<'_, CacheShard>> {
+        self.0[self.shard_index(id)].read().map_err(|_| Error::from(EM::LockError))
+    }
+
+    /// Lock the shard of `id` for writing
+    fn write(&self, id: &StoreId) -> Result<RwLockWriteGuard<'_, CacheShard>> {
+        self.0[self.shard_index(id)].write().map_err(|_| Error::from(EM::LockError))
+    }
+
+    /// Lock the shards of all `ids` for writing
+    ///
+    /// Shards are always locked in the same order, so this cannot deadlock.
+    fn write_many(&self, ids: &[&StoreId]) -> Result<LockedShards<'_>> {
+        let mut indices = ids.iter().map(|id| self.shard_index(id)).collect::<Vec<_>>();
+        indices.sort();
+        indices.dedup();
+        self.lock_shards(indices)
+    }
+
+    /// Lock all shards for writing
+    fn write_all(&self) -> Result<LockedShards<'_>> {
+        self.lock_shards((0..self.0.len()).collect())
+    }
+
+    fn lock_shards(&self, indices: Vec<usize>) -> Result<LockedShards<'_>> {
+        let guards = indices
+            .into_iter()
+            .map(|i| self.0[i].write().map(|g| (i, g)).map_err(|_| Error::from(EM::LockError)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(LockedShards { cache: self, guards })
+    }
+
+    /// The number of cached entries
+    fn len(&self) -> Result<usize> {
+        self.0.iter().try_fold(0, |sum, shard| {
+            shard.read().map(|s| sum + s.len()).map_err(|_| Error::from(EM::LockError))
+        })
+    }
+
+    /// The hashes of the attachments the cached entries refer to, written or not
+    fn attachments(&self) -> Result<BTreeSet<String>> {
+        let mut hashes = BTreeSet::new();
+        for shard in self.0.iter() {
+            for se in shard.read().map_err(|_| Error::from(EM::LockError))?.values() {
+                hashes.extend(se.attachments.iter().cloned());
+                hashes.extend(se.unwritten_attachments.iter().cloned());
+            }
+        }
+        Ok(hashes)
+    }
 
-    fn drop(self) {
-        self.get_entry()
-            .and_then(|entry| open_file(entry.get_location().clone()))
-            .and_then(|f| f.unlock())
+    /// The capacity of all shards
+    fn capacity(&self) -> Result<usize> {
+        self.0.iter().try_fold(0, |sum, shard| {
+            shard.read().map(|s| sum + s.capacity()).map_err(|_| Error::from(EM::LockError))
+        })
     }
+}
 
+/// Several shards of the cache, locked for writing
+struct LockedShards<'a> {
+    cache: &'a EntryCache,
+    guards: Vec<(usize, RwLockWriteGuard<'a, CacheShard>)>,
 }
 
+impl<'a> LockedShards<'a> {
+
+    fn shard(&mut self, id: &StoreId) -> &mut CacheShard {
+        let index = self.cache.shard_index(id);
+        self.guards
+            .iter_mut()
+            .find(|(i, _)| *i == index)
+            .map(|(_, guard)| &mut **guard)
+            .expect("Shard of id is not locked") // only used with the ids the shards were locked for
+    }
+
+    fn contains_key(&mut self, id: &StoreId) -> bool {
+        self.shard(id).contains_key(id)
+    }
+
+    fn remove(&mut self, id: &StoreId) -> Option<StoreEntry> {
+        self.shard(id).remove(id)
+    }
+
+    fn retain<F: FnMut(&StoreId, &mut StoreEntry) -> bool>(&mut self, mut f: F) {
+        for (_, guard) in self.guards.iter_mut() {
+            guard.retain(|id, se| f(id, se));
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for (_, guard) in self.guards.iter_mut() {
+            guard.shrink_to_fit();
+        }
+    }
+}
 
 /// The Store itself, through this object one can interact with IMAG's entries
 pub struct Store {
@@ -135,16 +303,54 @@ pub struct Store {
     ///
     /// Internal Path->File cache map
     ///
-    /// Caches the files, so they remain flock()ed
-    ///
-    /// Could be optimized for a threadsafe HashMap
+    /// Caches the files and whether they are borrowed
     ///
-    entries: Arc<RwLock<HashMap<StoreId, StoreEntry>>>,
+    entries: EntryCache,
 
     /// The backend to use
     ///
     /// This provides the filesystem-operation functions (or pretends to)
     backend: Arc<dyn FileAbstraction>,
+
+    /// The journal of the currently running transaction, if any
+    journal: Mutex<Option<Journal>>,
+
+    /// The secondary index, loaded on first use
+    index: Mutex<Option<Index>>,
+
+    /// The header paths which are indexed
+    index_paths: Vec<String>,
+
+    /// Hooks, in the order they are run
+    hooks: RwLock<Vec<(HookPosition, Arc<dyn Hook>)>>,
+
+    trash: TrashConfig,
+
+    /// Whether the trash was purged according to `store.trash.max-age` already
+    trash_purged: AtomicBool,
+
+    history: HistoryConfig,
+
+    /// The format of the header of new entries
+    header_format: HeaderFormat,
+
+    locks: LockConfig,
+
+    /// The collections entries are expected in, for `Store::fsck()`
+    collections: Vec<String>,
+
+    /// The schema headers are checked against when writing
+    schema: Schema,
+
+    /// The read-only stores mounted into this store
+    mounts: Vec<Mount>,
+
+    /// Whether the store is accessed through `imag-server`
+    remote: bool,
+
+    /// Hashes of attachments which entries do not refer to anymore, their data is removed at the
+    /// end of the operation (or transaction) if no other entry refers to them
+    released_attachments: Mutex<Vec<String>>,
 }
 
 impl Store {
@@ -159,13 +365,31 @@ impl Store {
     ///
     /// If the path exists and is a file, the operation is aborted as well, an error is returned.
     ///
+    /// The backend is selected with the `store.backend` setting: `"filesystem"` (the default),
+    /// `"git"`, which commits all changes to a git repository in `location`, `"encrypted"`,
+    /// which encrypts all files (see the `encryption` module), or `"sqlite"`, which stores all
+    /// files in a single database (see the `sqlite` module, needs the `sqlite` feature).
+    ///
     /// # Return values
     ///
     /// - On success: Store object
     ///
     pub fn new(location: PathBuf, store_config: &Option<Value>) -> Result<Store> {
-        let backend = Arc::new(FSFileAbstraction::default());
-        Store::new_with_backend(location, store_config, backend)
+        use crate::configuration::config_store_backend;
+
+        let backend : Arc<dyn FileAbstraction> = match config_store_backend(store_config)?.as_deref() {
+            None | Some("filesystem") => Arc::new(FSFileAbstraction::default()),
+            Some("git")               => Arc::new(GitFileAbstraction::from_config(location.clone(), store_config)?),
+            Some("encrypted")         => Arc::new(EncryptedFileAbstraction::from_config(location.clone(), store_config)?),
+            #[cfg(feature = "sqlite")]
+            Some("sqlite")            => Arc::new(SqliteFileAbstraction::from_config(location.clone(), store_config)?),
+            #[cfg(not(feature = "sqlite"))]
+            Some("sqlite")            => return Err(format_err!("The sqlite store backend is not available, imag was compiled without the 'sqlite' feature")),
+            Some(other)               => return Err(format_err!("Unknown store backend: {}", other)),
+        };
+        let store = Store::new_with_backend(location, store_config, backend)?;
+        store.recover_transaction()?;
+        Ok(store)
     }
 
     /// Create the store with an in-memory filesystem
@@ -179,6 +403,28 @@ impl Store {
         Self::new_with_backend(location, store_config, backend)
     }
 
+    /// Open the store in `location` through the `imag-server` connected to with `client`
+    ///
+    /// All entries are read and written by the server, which also runs the hooks, checks the
+    /// schema, keeps the history and the index and moves deleted entries to the trash. So these
+    /// are disabled in this store, as are the mounts. The index is built in memory if it is used.
+    pub fn new_remote(location: PathBuf, store_config: &Option<Value>, client: Client) -> Result<Store> {
+        debug!("Accessing store {} through imag-server", location.display());
+        let mut config = store_config.clone();
+        if let Some(store) = config.as_mut().and_then(|c| c.get_mut("store")).and_then(Value::as_table_mut) {
+            for key in ["mounts", "hooks", "schema"].iter() {
+                let _ = store.remove(*key);
+            }
+        }
+
+        let backend   = Arc::new(RemoteFileAbstraction::new(location.clone(), client));
+        let mut store = Store::new_with_backend(location, &config, backend)?;
+        store.trash.enabled   = false;
+        store.history.enabled = false;
+        store.remote          = true;
+        Ok(store)
+    }
+
     /// Create a Store object as descripbed in `Store::new()` documentation, but with an alternative
     /// backend implementation.
     ///
@@ -206,10 +452,59 @@ impl Store {
             return Err(format_err!("StorePathExists: {}", location.display()));
         }
 
+        let index_paths = crate::index::config_indexed_paths(store_config)?;
+        debug!("Indexed header paths: {:?}", index_paths);
+
+        let trash = crate::trash::config_trash(store_config)?;
+        debug!("Trash: {:?}", trash);
+
+        let history = crate::history::config_history(store_config)?;
+        debug!("History: {:?}", history);
+
+        let header_format = crate::header_format::config_header_format(store_config)?;
+        debug!("Header format: {}", header_format);
+
+        let mut locks = crate::lock::config_locks(store_config)?;
+        locks.enabled = locks.enabled && backend.is_shared();
+        debug!("Locks: {:?}", locks);
+
+        let collections = crate::fsck::config_collections(store_config)?;
+        debug!("Collections: {:?}", collections);
+
+        let schema = crate::schema::config_schema(store_config)?;
+        debug!("Schema: {:?}", schema);
+
+        let mounts = crate::mount::config_mounts(store_config)?;
+        debug!("Mounts: {:?}", mounts);
+        let backend : Arc<dyn FileAbstraction> = if mounts.is_empty() {
+            backend
+        } else {
+            Arc::new(OverlayFileAbstraction::new(location.clone(), backend, mounts.clone()))
+        };
+
+        let hooks = crate::hook::config_hooks(store_config, &location)?
+            .into_iter()
+            .map(|(position, hook)| (position, Arc::from(hook)))
+            .collect();
+
         let store = Store {
             location: location.clone(),
-            entries: Arc::new(RwLock::new(HashMap::new())),
+            entries: EntryCache::new(),
             backend,
+            journal: Mutex::new(None),
+            index: Mutex::new(None),
+            index_paths,
+            hooks: RwLock::new(hooks),
+            trash,
+            trash_purged: AtomicBool::new(false),
+            history,
+            header_format,
+            locks,
+            collections,
+            schema,
+            mounts,
+            remote: false,
+            released_attachments: Mutex::new(vec![]),
         };
 
         debug!("Store building succeeded");
@@ -238,11 +533,18 @@ impl Store {
             return Err(format_err!("EntryAlreadyExists: {}", id));
         }
 
+        self.run_hooks(HookPosition::PreCreate, &id, None, None)?;
+
+        let lock = self.lock_entry(&id).context(format_err!("CreateCallError: {}", id))?;
+        if lock.is_some() && self.exists(id.clone())? {
+            debug!("Entry was created by another process: {:?}", id);
+            return Err(format_err!("EntryAlreadyExists: {}", id));
+        }
+
         {
             let mut hsmap = self
                 .entries
-                .write()
-                .map_err(|_| Error::from(EM::LockError))
+                .write(&id)
                 .context(format_err!("CreateCallError: {}", id))?;
 
             if hsmap.contains_key(&id) {
@@ -259,9 +561,21 @@ impl Store {
             });
         }
 
+        let mut entry = Entry::new(id.clone());
+        entry.set_header_format(self.header_format);
+        let checked = self
+            .run_hooks(HookPosition::PostCreate, &id, Some(&mut entry), None)
+            .and_then(|_| self.schema.check_created(&entry));
+
+        if let Err(e) = checked {
+            // Nothing was written yet, so we simply forget about the entry
+            let _ = self.entries.write(&id)?.remove(&id);
+            return Err(e)
+        }
+
         debug!("Constructing FileLockEntry: '{}'", id);
 
-        Ok(FileLockEntry::new(self, Entry::new(id)))
+        Ok(FileLockEntry::new(self, entry, lock))
     }
 
     /// Borrow a given Entry. When the `FileLockEntry` is either `update`d or
@@ -277,21 +591,29 @@ impl Store {
     pub fn retrieve<'a, S: IntoStoreId>(&'a self, id: S) -> Result<FileLockEntry<'a>> {
         let id = id.into_storeid()?;
         debug!("Retrieving id: '{}'", id);
-        let entry = self
+        self.run_hooks(HookPosition::PreRetrieve, &id, None, None)?;
+
+        let lock = self.lock_entry(&id).context(format_err!("RetrieveCallError: {}", id))?;
+
+        let mut entry = self
             .entries
-            .write()
-            .map_err(|_| Error::from(EM::LockError))
+            .write(&id)
             .and_then(|mut es| {
                 let new_se = StoreEntry::new(self.path().clone(), id.clone(), &self.backend)?;
                 let se = es.entry(id.clone()).or_insert(new_se);
-                let entry = se.get_entry();
+                let entry = se.get_entry(self.header_format);
                 se.status = StoreEntryStatus::Borrowed;
                 entry
             })
             .context(format_err!("RetrieveCallError: {}", id))?;
 
+        if let Err(e) = self.run_hooks(HookPosition::PostRetrieve, &id, Some(&mut entry), None) {
+            self.set_present(&id)?;
+            return Err(e)
+        }
+
         debug!("Constructing FileLockEntry: '{}'", id);
-        Ok(FileLockEntry::new(self, entry))
+        Ok(FileLockEntry::new(self, entry, lock))
     }
 
     /// Get an entry from the store if it exists.
@@ -343,8 +665,32 @@ impl Store {
     /// it is not public.
     ///
     fn _update<'a>(&'a self, entry: &mut FileLockEntry<'a>, modify_presence: bool) -> Result<()> {
-        let mut hsmap = self.entries.write()
-            .map_err(|_| Error::from(EM::LockError))?;
+        let id = entry.location.clone();
+        if let Err(e) = self.run_hooks(HookPosition::PreUpdate, &id, Some(&mut entry.entry), None) {
+            if modify_presence {
+                self.set_present(&id)?;
+            }
+            return Err(e)
+        }
+
+        if self._write(entry, modify_presence)? {
+            self.finish_batch()?;
+            self.run_hooks(HookPosition::PostUpdate, &id, Some(&mut entry.entry), None)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the entry, without running hooks
+    ///
+    /// Returns whether the entry was written, which is not the case if it was rolled back by a
+    /// transaction.
+    fn _write<'a>(&'a self, entry: &mut FileLockEntry<'a>, modify_presence: bool) -> Result<bool> {
+        // The entry is borrowed, so nobody else writes it. Recording it before the cache is locked
+        // keeps other threads from waiting on the journal while they access the cache.
+        self.record_in_journal(&entry.location)?;
+
+        let mut hsmap = self.entries.write(&entry.location)?;
 
         let se = hsmap.get_mut(&entry.location).ok_or_else(|| {
             EM::EntryNotFound(entry.location.local_display_string())
@@ -352,19 +698,118 @@ impl Store {
 
         assert!(se.is_borrowed(), "Tried to update a non borrowed entry.");
 
+        if se.is_rolled_back() {
+            if modify_presence {
+                debug!("Not writing {}, it was rolled back by a transaction", entry.get_location());
+                se.status = StoreEntryStatus::Present;
+                return Ok(false)
+            } else {
+                return Err(format_err!("Entry was rolled back by a failed transaction: {}",
+                                       entry.get_location()))
+            }
+        }
+
         debug!("Verifying Entry");
         entry.entry.verify()?;
+        if let Err(e) = self.schema.check(&entry.entry) {
+            if modify_presence {
+                se.status = StoreEntryStatus::Present;
+            }
+            return Err(e)
+        }
+
+        let old = if self.history.enabled {
+            let path = entry.location.clone().with_base(self.path()).into_pathbuf()?;
+            if self.backend.exists(&path)? {
+                self.backend
+                    .new_instance(path)
+                    .get_file_content(entry.location.clone().with_base(self.path()))?
+            } else {
+                None
+            }
+        } else {
+            None
+        };
 
         debug!("Writing Entry");
         se.write_entry(&entry.entry)?;
         trace!("Entry written");
+
+        let attachments = crate::attachment::hashes(&entry.entry.header);
+        let released    = se.attachments
+            .iter()
+            .filter(|hash| !attachments.contains(hash))
+            .cloned()
+            .collect();
+        se.attachments = attachments;
+        se.unwritten_attachments.clear();
+        self.release_attachments(released)?;
+
+        if let Some(old) = old {
+            self.record_history(&entry.location, &old, &entry.entry)?;
+        }
+
         if modify_presence {
             debug!("Modifying presence of {} -> Present", entry.get_location());
             se.status = StoreEntryStatus::Present;
         }
+        drop(hsmap);
+
+        let (location, header) = (&entry.entry.location, &entry.entry.header);
+        self.with_index(|index| index.update(self.path(), &self.backend, location, header))?;
 
         trace!("Entry updated successfully");
-        Ok(())
+        Ok(true)
+    }
+
+    /// Record in the history of `id` that `old` was replaced by `new`
+    fn record_history(&self, id: &StoreId, old: &Entry, new: &Entry) -> Result<()> {
+        let (old, new) = (old.to_str()?, new.to_str()?);
+        if old == new {
+            trace!("No change, not recording history of {}", id);
+            return Ok(())
+        }
+
+        let mut history = History::load(self.path(), id, &self.backend)?
+            .unwrap_or_else(|| History::new(old.clone()));
+        history.record(&old, &new, ::chrono::Local::now().naive_local(), self.history.max_revisions);
+
+        self.record_raw_in_journal(&History::path(self.path(), id))?;
+        history.save(self.path(), id, &self.backend)
+    }
+
+    /// The earlier versions of the entry `id`, oldest first
+    ///
+    /// A version is recorded each time `Store::update()` changes the entry, if `store.history.enabled`
+    /// is not set to `false`.
+    pub fn history(&self, id: StoreId) -> Result<Vec<Revision>> {
+        History::load(self.path(), &id, &self.backend)
+            .map(|h| h.map(|h| h.revisions().to_vec()).unwrap_or_default())
+    }
+
+    /// Get the entry `id` as it was at revision `number`
+    pub fn get_revision(&self, id: StoreId, number: usize) -> Result<Entry> {
+        let history = History::load(self.path(), &id, &self.backend)?
+            .ok_or_else(|| format_err!("No history for {}", id))?;
+        let content = history.content_at(number)?;
+        Entry::from_str(id.clone(), &content)
+            .context(format_err!("Failed to parse revision {} of {}", number, id))
+            .map_err(Error::from)
+    }
+
+    /// Revert the entry `id` to revision `number`
+    ///
+    /// The entry is written with `Store::update()`, so the version which is replaced by this is
+    /// recorded in the history as well and reverting can be undone.
+    pub fn revert(&self, id: StoreId, number: usize) -> Result<()> {
+        debug!("Reverting {} to revision {}", id, number);
+        let revision  = self.get_revision(id.clone(), number)?;
+        let mut entry = self.get(id.clone())?
+            .ok_or_else(|| format_err!("Cannot revert {}, entry does not exist", id))?;
+
+        *entry.get_header_mut() = revision.get_header().clone();
+        entry.set_content(revision.get_content().clone());
+        self.update(&mut entry)
     }
 
     /// Flush the store internal cache
@@ -380,20 +825,8 @@ impl Store {
     pub fn flush_cache(&self) -> Result<()> {
         // We borrow this early so that between the aggregation of the flushables and the actual
         // flush, there is no borrowing from the store.
-        let mut hsmap = self.entries.write()
-            .map_err(|_| Error::from(EM::LockError))?;
-        let mut to_flush = vec![];
-
-        for (storeid, se) in hsmap.deref() {
-            if !se.is_borrowed() {
-                to_flush.push(storeid.clone());
-            }
-        }
-
-        for id in to_flush {
-            let _ = hsmap.remove(&id);
-        }
-
+        let mut hsmap = self.entries.write_all()?;
+        hsmap.retain(|_, se| se.is_borrowed());
         hsmap.shrink_to_fit();
 
         Ok(())
@@ -401,14 +834,12 @@ impl Store {
 
     /// The number of elements in the internal cache
     pub fn cache_size(&self) -> Result<usize> {
-        let hsmap = self.entries.read().map_err(|_| Error::from(EM::LockError))?;
-        Ok(hsmap.iter().count())
+        self.entries.len()
     }
 
     /// The size of the internal cache
     pub fn cache_capacity(&self) -> Result<usize> {
-        let hsmap = self.entries.read().map_err(|_| Error::from(EM::LockError))?;
-        Ok(hsmap.capacity())
+        self.entries.capacity()
     }
 
     // Get a copy of a given entry, this cannot be used to mutate the one on disk
@@ -420,8 +851,7 @@ impl Store {
     pub fn get_copy<S: IntoStoreId>(&self, id: S) -> Result<Entry> {
         let id = id.into_storeid()?;
         debug!("Retrieving copy of '{}'", id);
-        let entries = self.entries.write()
-            .map_err(|_| Error::from(EM::LockError))
+        let entries = self.entries.write(&id)
             .context(format_err!("RetrieveCopyCallError: {}", id))?;
 
         // if the entry is currently modified by the user, we cannot drop it
@@ -431,7 +861,41 @@ impl Store {
                 .map_err(Error::from)
         }
 
-        StoreEntry::new(self.path().clone(), id, &self.backend)?.get_entry()
+        StoreEntry::new(self.path().clone(), id, &self.backend)?.get_entry(self.header_format)
+    }
+
+    /// Get a copy of a given entry with only its header read
+    ///
+    /// The content is read from the backend when it is accessed for the first time, so this is
+    /// cheaper than `Store::get_copy()` for code which only looks at the header. Like a copy, the
+    /// returned entry cannot be used to mutate the one on disk.
+    ///
+    /// # Return value
+    ///
+    /// On success: Entry, or None if the entry does not exist
+    ///
+    pub fn get_header_only<S: IntoStoreId>(&self, id: S) -> Result<Option<HeaderOnlyEntry>> {
+        let id = id.into_storeid()?;
+        debug!("Retrieving header of '{}'", id);
+
+        // if the entry is currently modified by the user, the file might be outdated
+        let borrowed = self.entries
+            .read(&id)
+            .context(format_err!("RetrieveHeaderCallError: {}", id))?
+            .get(&id)
+            .map(|e| e.is_borrowed())
+            .unwrap_or(false);
+
+        if borrowed {
+            return Err(EM::IdLocked)
+                .context(format_err!("RetrieveHeaderCallError: {}", id))
+                .map_err(Error::from)
+        }
+
+        let pb = id.clone().with_base(self.path()).into_pathbuf()?;
+        self.backend
+            .new_instance(pb)
+            .get_file_header(id.with_base(self.path()))
     }
 
     /// Delete an entry and the corrosponding file on disk
@@ -444,6 +908,8 @@ impl Store {
         let id = id.into_storeid()?;
 
         debug!("Deleting id: '{}'", id);
+        self.run_hooks(HookPosition::PreDelete, &id, None, None)?;
+        let _lock = self.lock_entry(&id).context(format_err!("DeleteCallError: {}", id))?;
 
         // Small optimization: We need the pathbuf for deleting, but when calling
         // StoreId::exists(), a PathBuf object gets allocated. So we simply get a
@@ -451,11 +917,16 @@ impl Store {
         // delete the filesystem file.
         let pb = id.clone().with_base(self.path()).into_pathbuf()?;
 
+        if self.backend.is_read_only(&pb)? {
+            return Err(format_err!("Cannot delete {}, it is in a read-only mounted store", id))
+                .context(format_err!("DeleteCallError: {}", id))
+                .map_err(Error::from)
+        }
+
         {
             let mut entries = self
                 .entries
-                .write()
-                .map_err(|_| Error::from(EM::LockError))
+                .write(&id)
                 .context(format_err!("DeleteCallError: {}", id))?;
 
             let do_remove = match entries.get(&id) {
@@ -490,16 +961,306 @@ impl Store {
         }
 
         debug!("Seems like {:?} is on the FS", pb);
-        self
-            .backend
-            .remove_file(&pb)
-            .context(EM::FileError)
-            .context(format_err!("DeleteCallError: {}", id))?;
+        if self.trash.enabled {
+            self.move_to_trash(&id, &pb).context(format_err!("DeleteCallError: {}", id))?;
+        } else {
+            self.record_in_journal(&id)?;
+            let attachments = self.backend
+                .new_instance(pb.clone())
+                .get_file_header(id.clone().with_base(self.path()))?
+                .map(|entry| crate::attachment::hashes(entry.get_header()))
+                .unwrap_or_default();
+
+            self
+                .backend
+                .remove_file(&pb)
+                .context(EM::FileError)
+                .context(format_err!("DeleteCallError: {}", id))?;
+            self.release_attachments(attachments)?;
+
+            self.record_raw_in_journal(&History::path(self.path(), &id))?;
+            History::remove(self.path(), &id, &self.backend)?;
+        }
+
+        self.with_index(|index| index.remove(self.path(), &self.backend, &id))?;
 
         debug!("Deleted");
+        self.finish_batch()?;
+        self.run_hooks(HookPosition::PostDelete, &id, None, None)
+    }
+
+    /// Move the file of `id` to the trash
+    fn move_to_trash(&self, id: &StoreId, pb: &PathBuf) -> Result<()> {
+        let trashed  = TrashedEntry::new(id.clone(), ::chrono::Local::now().naive_local())?;
+        let trash_pb = trashed.trash_id.clone().with_base(self.path()).into_pathbuf()?;
+        debug!("Moving {} to trash: {}", id, trashed.trash_id);
+
+        let mut entry = self.backend
+            .new_instance(pb.clone())
+            .get_file_content(id.clone().with_base(self.path()))?
+            .ok_or_else(|| Error::from(EM::FileNotFound))?;
+        trashed.mark(&mut entry)?;
+
+        self.record_in_journal(&trashed.trash_id)?;
+        self.record_in_journal(id)?;
+        self.backend.new_instance(trash_pb).write_file_content(&entry)?;
+        self.backend.remove_file(pb).context(EM::FileError)?;
+        self.move_history(id, &trashed.trash_id)?;
+
+        if let Some(max_age) = self.trash.max_age {
+            if !self.trash_purged.swap(true, Ordering::SeqCst) {
+                let purged = self.trash_purge(Some(max_age))?;
+                debug!("Purged {} entries older than {} days from trash", purged.len(), max_age.num_days());
+            }
+        }
         Ok(())
     }
 
+    /// Check all files in the store for problems, see the `fsck` module
+    ///
+    /// The files are read from the backend directly, so borrowed entries are checked as well.
+    pub fn fsck(&self) -> Result<Vec<Finding>> {
+        let mut findings = vec![];
+
+        for id in self.entries()?.into_storeid_iter() {
+            let id   = id?;
+            let path = id.clone().with_base(self.path()).into_pathbuf()?;
+            debug!("Checking {}", id);
+
+            let problems = match self.backend.new_instance(path).get_file_content(id.clone().with_base(self.path())) {
+                Ok(Some(entry)) => {
+                    let mut problems = entry.header_problems()?;
+                    problems.extend(self.schema.violations(&entry)?.into_iter().map(Problem::SchemaViolation));
+                    problems.extend(self.attachment_problems(&entry));
+                    problems
+                },
+                Ok(None)        => continue, // removed in the meantime
+                Err(e)          => {
+                    let chain = e.iter_chain().map(|c| c.to_string()).collect::<Vec<_>>();
+                    vec![Problem::Unparseable(chain.join(": "))]
+                },
+            };
+
+            findings.extend(problems.into_iter().map(|problem| Finding { id: id.clone(), problem }));
+
+            // entries of mounted stores are in a collection of the mounted store
+            let unmounted = self.mounts
+                .iter()
+                .filter_map(|m| id.local().strip_prefix(&m.prefix).ok())
+                .next()
+                .map(|rest| StoreId::new(rest.to_path_buf()))
+                .transpose()?
+                .unwrap_or_else(|| id.clone());
+
+            if !crate::fsck::in_collection(&unmounted, &self.collections) {
+                findings.push(Finding { id, problem: Problem::UnknownCollection });
+            }
+        }
+
+        Ok(findings)
+    }
+
+    /// Check that the data of all attachments of `entry` is there and not damaged
+    fn attachment_problems(&self, entry: &Entry) -> Vec<Problem> {
+        match entry.attachments() {
+            Ok(attachments) => attachments
+                .iter()
+                .filter_map(|a| self.attachment_data(a).err())
+                .map(|e| Problem::BrokenAttachment(e.to_string()))
+                .collect(),
+            Err(e) => vec![Problem::BrokenAttachment(e.to_string())],
+        }
+    }
+
+    /// Repair a problem found by `Store::fsck()`
+    ///
+    /// A missing `imag` section or version is set to the version of this imag. A file which
+    /// cannot be parsed gets a new header and its complete old text (including the broken header)
+    /// becomes the content of the entry. The other problems cannot be repaired.
+    pub fn repair(&self, finding: &Finding) -> Result<()> {
+        let id = &finding.id;
+        debug!("Repairing {}: {}", id, finding.problem);
+
+        match finding.problem {
+            Problem::MissingMainSection | Problem::MissingVersion => {
+                let mut entry = self.get(id.clone())?
+                    .ok_or_else(|| format_err!("Entry does not exist: {}", id))?;
+                crate::fsck::repair_version(entry.get_header_mut())?;
+                self.update(&mut entry)
+            },
+
+            Problem::Unparseable(_) => {
+                let _lock = self.lock_entry(id)?;
+                if self.entries.read(id)?.get(id).map(StoreEntry::is_borrowed).unwrap_or(false) {
+                    return Err(Error::from(EM::IdLocked))
+                }
+
+                let path = id.clone().with_base(self.path()).into_pathbuf()?;
+                let raw  = self.backend
+                    .read_raw(&path)?
+                    .ok_or_else(|| Error::from(EM::FileNotFound))?;
+
+                let mut entry = Entry::new(id.clone());
+                entry.set_header_format(self.header_format);
+                entry.set_content(raw);
+
+                self.record_in_journal(id)?;
+                self.backend.new_instance(path).write_file_content(&entry)?;
+                self.reindex(id)?;
+                self.finish_batch()
+            },
+
+            ref problem => Err(format_err!("Cannot repair {}: {}", id, problem)),
+        }
+    }
+
+    /// The age after which trashed entries are purged, from `store.trash.max-age`
+    pub fn trash_max_age(&self) -> Option<::chrono::Duration> {
+        self.trash.max_age
+    }
+
+    /// List the entries in the trash, oldest first
+    pub fn trash_list(&self) -> Result<Vec<TrashedEntry>> {
+        crate::trash::list(self.path(), &self.backend)
+    }
+
+    /// Restore the most recently trashed entry with the id `id`
+    ///
+    /// Fails if there is no such entry in the trash or if an entry with that id exists.
+    pub fn trash_restore(&self, id: StoreId) -> Result<()> {
+        let trashed = self.trash_list()?
+            .into_iter()
+            .rev()
+            .find(|t| t.id == id)
+            .ok_or_else(|| format_err!("Not in trash: {}", id))?;
+
+        if self.exists(id.clone())? {
+            return Err(format_err!("Cannot restore {}, entry exists", id))
+        }
+
+        debug!("Restoring {} from {}", id, trashed.trash_id);
+        let pb       = id.clone().with_base(self.path()).into_pathbuf()?;
+        let trash_pb = trashed.trash_id.clone().with_base(self.path()).into_pathbuf()?;
+
+        let mut entry = self.backend
+            .new_instance(trash_pb.clone())
+            .get_file_content(trashed.trash_id.clone().with_base(self.path()))?
+            .ok_or_else(|| Error::from(EM::FileNotFound))?;
+        crate::trash::unmark(&mut entry)?;
+        entry.location = id.clone();
+
+        self.record_in_journal(&id)?;
+        self.record_in_journal(&trashed.trash_id)?;
+        self.backend.new_instance(pb).write_file_content(&entry)?;
+        self.backend.remove_file(&trash_pb).context(EM::FileError)?;
+        self.move_history(&trashed.trash_id, &id)?;
+
+        self.with_index(|index| index.update(self.path(), &self.backend, &id, &entry.header))?;
+        self.finish_batch()
+    }
+
+    /// Remove entries from the trash for good
+    ///
+    /// If `older_than` is given, only entries which were deleted longer ago than that are
+    /// removed, otherwise all of them. Returns the removed entries.
+    pub fn trash_purge(&self, older_than: Option<::chrono::Duration>) -> Result<Vec<TrashedEntry>> {
+        let now    = ::chrono::Local::now().naive_local();
+        let purged = self.trash_list()?
+            .into_iter()
+            .filter(|t| older_than.map(|age| t.deleted_at < now - age).unwrap_or(true))
+            .collect::<Vec<_>>();
+
+        for trashed in purged.iter() {
+            debug!("Purging from trash: {}", trashed.trash_id);
+            let trash_pb = trashed.trash_id.clone().with_base(self.path()).into_pathbuf()?;
+            self.record_in_journal(&trashed.trash_id)?;
+            let attachments = self.backend
+                .new_instance(trash_pb.clone())
+                .get_file_header(trashed.trash_id.clone().with_base(self.path()))?
+                .map(|entry| crate::attachment::hashes(entry.get_header()))
+                .unwrap_or_default();
+
+            self.backend.remove_file(&trash_pb).context(EM::FileError)?;
+            self.release_attachments(attachments)?;
+
+            self.record_raw_in_journal(&History::path(self.path(), &trashed.trash_id))?;
+            History::remove(self.path(), &trashed.trash_id, &self.backend)?;
+        }
+
+        self.finish_batch()?;
+        Ok(purged)
+    }
+
+    /// Attach `data` to `entry` as `name`, see the `attachment` module
+    ///
+    /// The data is written to the store right away, the header of `entry` is changed and has to
+    /// be written with `Store::update()`. Fails if the entry has an attachment named `name`.
+    pub fn attach(&self, entry: &mut Entry, name: &str, mime: &str, data: &[u8]) -> Result<Attachment> {
+        let mut attachments = entry.attachments()?;
+        if attachments.iter().any(|a| a.name == name) {
+            return Err(format_err!("{} has an attachment named '{}' already", entry.get_location(), name))
+        }
+
+        let attachment = Attachment::new(String::from(name), String::from(mime), data);
+        debug!("Attaching {} to {} ({})", name, entry.get_location(), attachment.hash);
+
+        // Until the entry is written, only the cache knows that the data is in use
+        if let Some(se) = self.entries.write(entry.get_location())?.get_mut(entry.get_location()) {
+            se.unwritten_attachments.push(attachment.hash.clone());
+        }
+        let _ = self.write_attachment_data(data).context(format_err!("Failed to store attachment {}", name))?;
+
+        attachments.push(attachment.clone());
+        crate::attachment::write(entry.get_header_mut(), &attachments)?;
+        Ok(attachment)
+    }
+
+    /// Remove the attachment `name` from `entry`
+    ///
+    /// Only the header of `entry` is changed, the data is removed when the entry is written with
+    /// `Store::update()` and no other entry refers to it.
+    pub fn detach(&self, entry: &mut Entry, name: &str) -> Result<Attachment> {
+        let mut attachments = entry.attachments()?;
+        let position = attachments
+            .iter()
+            .position(|a| a.name == name)
+            .ok_or_else(|| format_err!("{} has no attachment named '{}'", entry.get_location(), name))?;
+
+        let attachment = attachments.remove(position);
+        debug!("Detaching {} from {}", name, entry.get_location());
+        crate::attachment::write(entry.get_header_mut(), &attachments)?;
+        Ok(attachment)
+    }
+
+    /// Read the data of `attachment`, checking that it is not damaged
+    pub fn attachment_data(&self, attachment: &Attachment) -> Result<Vec<u8>> {
+        let data = self.read_attachment_data(&attachment.hash)?
+            .ok_or_else(|| format_err!("Data of attachment '{}' is missing", attachment.name))?;
+
+        if crate::attachment::hash(&data) != attachment.hash {
+            return Err(format_err!("Data of attachment '{}' is damaged", attachment.name))
+        }
+        Ok(data)
+    }
+
+    /// Read the data stored under `hash`, without checking it
+    ///
+    /// This is for passing the data on as it is, like `imag-server` does, use
+    /// `Store::attachment_data()` otherwise.
+    pub fn read_attachment_data(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        self.backend.read_bytes(&crate::attachment::data_path(self.path(), hash)?)
+    }
+
+    /// Store `data` as the data of an attachment, returns its hash
+    ///
+    /// Data which no entry refers to is not removed automatically, use `Store::attach()` to store
+    /// data for an entry.
+    pub fn write_attachment_data(&self, data: &[u8]) -> Result<String> {
+        let hash = crate::attachment::hash(data);
+        self.backend.write_bytes(&crate::attachment::data_path(self.path(), &hash)?, data)?;
+        Ok(hash)
+    }
+
     /// Save a copy of the Entry in another place
     pub fn save_to(&self, entry: &FileLockEntry, new_id: StoreId) -> Result<()> {
         debug!("Saving '{}' to '{}'", entry.get_location(), new_id);
@@ -518,8 +1279,7 @@ impl Store {
     {
         let hsmap = self
             .entries
-            .write()
-            .map_err(|_| Error::from(EM::LockError))
+            .write(&new_id)
             .context(format_err!("MoveCallError: {} -> {}", entry.get_location(), new_id))?;
 
         if hsmap.contains_key(&new_id) {
@@ -532,6 +1292,12 @@ impl Store {
 
         let old_id_as_path = old_id.clone().with_base(self.path()).into_pathbuf()?;
         let new_id_as_path = new_id.clone().with_base(self.path()).into_pathbuf()?;
+
+        self.record_in_journal(&new_id)?;
+        if remove_old {
+            self.record_in_journal(&old_id)?;
+        }
+
         self.backend
             .copy(&old_id_as_path, &new_id_as_path)
             .and_then(|_| if remove_old {
@@ -541,8 +1307,17 @@ impl Store {
                 Ok(())
             })
             .context(EM::FileError)
-            .context(format_err!("MoveCallError: {} -> {}", old_id, new_id))
-            .map_err(Error::from)
+            .context(format_err!("MoveCallError: {} -> {}", old_id, new_id))?;
+
+        if remove_old {
+            self.move_history(&old_id, &new_id)?;
+        }
+
+        self.reindex(&new_id)?;
+        if remove_old {
+            self.reindex(&old_id)?;
+        }
+        self.finish_batch()
     }
 
     /// Move an entry without loading
@@ -580,10 +1355,11 @@ impl Store {
     ///
     pub fn move_by_id(&self, old_id: StoreId, new_id: StoreId) -> Result<()> {
         debug!("Moving '{}' to '{}'", old_id, new_id);
+        self.run_hooks(HookPosition::PreMove, &old_id, None, Some(&new_id))?;
+        let _locks = (self.lock_entry(&old_id)?, self.lock_entry(&new_id)?);
 
         {
-            let mut hsmap = self.entries.write()
-                .map_err(|_| Error::from(EM::LockError))?;
+            let mut hsmap = self.entries.write_many(&[&old_id, &new_id])?;
 
             if hsmap.contains_key(&new_id) {
                 return Err(format_err!("Entry already exists: {}", new_id));
@@ -597,6 +1373,10 @@ impl Store {
                 return Err(format_err!("Entry does not exist: {}", old_id));
             }
 
+            if self.backend.is_read_only(&old_id_pb)? {
+                return Err(format_err!("Cannot move {}, it is in a read-only mounted store", old_id));
+            }
+
             // if it is borrowed, we really should not rename it, as this might
             // lead to strange errors
             //
@@ -610,6 +1390,9 @@ impl Store {
             }
             debug!("New entry does not yet exist on filesystem. Good.");
 
+            self.record_in_journal(&old_id)?;
+            self.record_in_journal(&new_id)?;
+
             self
                 .backend
                 .rename(&old_id_pb, &new_id_pb)
@@ -622,12 +1405,473 @@ impl Store {
             debug!("Rename worked on filesystem");
         }
 
+        self.move_history(&old_id, &new_id)?;
+
+        self.reindex(&old_id)?;
+        self.reindex(&new_id)?;
+
         debug!("Moved");
-        Ok(())
+        self.finish_batch()?;
+        self.run_hooks(HookPosition::PostMove, &old_id, None, Some(&new_id))
     }
 
-    /// Get _all_ entries in the store (by id as iterator)
-    pub fn entries<'a>(&'a self) -> Result<Entries<'a>> {
+    fn move_history(&self, old_id: &StoreId, new_id: &StoreId) -> Result<()> {
+        self.record_raw_in_journal(&History::path(self.path(), old_id))?;
+        self.record_raw_in_journal(&History::path(self.path(), new_id))?;
+        History::rename(self.path(), old_id, new_id, &self.backend)
+    }
+
+    /// Run several store operations as one transaction
+    ///
+    /// All `create`/`update`/`delete`/`move_by_id`/`save_*` calls which write to the backend while
+    /// `f` runs are recorded in a journal. If `f` returns an error, every touched entry is restored
+    /// to the state it had before the transaction started: written entries get their old content
+    /// back, created entries are removed again, deleted entries are re-created and moved entries
+    /// are moved back. The error from `f` is returned afterwards.
+    ///
+    /// Because `FileLockEntry` objects write themselves back when they are dropped, entries should
+    /// be `Store::update()`d explicitly inside the transaction, so that errors are propagated.
+    /// Entries which are still borrowed when a rollback happens are not written back when their
+    /// `FileLockEntry` is dropped later on.
+    ///
+    /// # Note
+    ///
+    /// The transaction is store-wide: Operations from other threads which happen while `f` runs
+    /// are recorded as well. Calling `transaction()` while a transaction is already running does
+    /// not start a new one, `f` simply becomes part of the running transaction.
+    ///
+    /// Transactions of other processes are waited for like locked entries (`store.locks.timeout`).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    ///     store.transaction(|store| {
+    ///         let mut a = store.retrieve(id_a)?;
+    ///         let mut b = store.retrieve(id_b)?;
+    ///         a.add_link(&mut b)?;
+    ///         store.update(&mut a)?;
+    ///         store.update(&mut b)
+    ///     })?;
+    /// ```
+    ///
+    pub fn transaction<F, R>(&self, f: F) -> Result<R>
+        where F: FnOnce(&Store) -> Result<R>
+    {
+        {
+            let mut journal = self.journal.lock().map_err(|_| Error::from(EM::LockError))?;
+            if journal.is_some() {
+                debug!("Transaction already running, joining it");
+                drop(journal);
+                return f(self)
+            }
+
+            debug!("Starting transaction");
+            let lock = if self.locks.enabled {
+                Some(crate::lock::acquire_transaction(self.path(), &self.locks)?)
+            } else {
+                None
+            };
+
+            // The journal of a crashed transaction would be overwritten otherwise
+            self.recover_journal()?;
+            *journal = Some(Journal::new(self.path(), lock));
+        }
+
+        let result = f(self);
+
+        let journal = self.journal
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?
+            .take()
+            .ok_or_else(|| err_msg("Transaction journal vanished"))?;
+
+        match result {
+            Ok(r) => {
+                debug!("Transaction finished, {} entries touched", journal.len());
+                journal.finish(&self.backend)?;
+                self.announce_changes()?;
+                self.remove_released_attachments()?;
+                self.backend.batch_finished()?;
+                Ok(r)
+            },
+            Err(e) => {
+                debug!("Transaction failed, rolling back {} entries", journal.len());
+                self.released_attachments.lock().map_err(|_| Error::from(EM::LockError))?.clear();
+                self.rollback(journal)
+                    .context(format_err!("Rollback failed after error: {}", e))?;
+                self.announce_changes()?;
+                self.backend.batch_finished()?;
+                Err(e)
+            },
+        }
+    }
+
+    /// Roll back the transaction of a process which died while the transaction was running
+    ///
+    /// Nothing is done while another process runs a transaction.
+    pub(crate) fn recover_transaction(&self) -> Result<()> {
+        let _lock = if self.locks.enabled {
+            match crate::lock::try_acquire_transaction(self.path())? {
+                Some(lock) => Some(lock),
+                None       => {
+                    debug!("Another process runs a transaction, not recovering");
+                    return Ok(())
+                },
+            }
+        } else {
+            None
+        };
+
+        self.recover_journal()
+    }
+
+    /// Roll back the journal of a crashed transaction, the transaction lock must be held
+    fn recover_journal(&self) -> Result<()> {
+        let ids = Journal::recover(self.path(), &self.backend)
+            .context("Cannot roll back unfinished transaction")?;
+
+        if !ids.is_empty() {
+            ids.iter().map(|id| self.reindex(id)).collect::<Result<Vec<_>>>()?;
+            self.announce_changes()?;
+            self.backend.batch_finished()?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, journal: Journal) -> Result<()> {
+        let ids = journal.ids().cloned().collect::<Vec<_>>();
+        {
+            for id in journal.ids() {
+                let mut hsmap = self.entries.write(id)?;
+                let remove = match hsmap.get_mut(id) {
+                    Some(ref mut se) if se.is_borrowed() => {
+                        se.status = StoreEntryStatus::RolledBack;
+                        false
+                    },
+                    Some(_) => true,
+                    None    => false,
+                };
+
+                if remove {
+                    let _ = hsmap.remove(id);
+                }
+            }
+        }
+
+        journal.rollback(&self.backend)?;
+        ids.iter().map(|id| self.reindex(id)).collect::<Result<Vec<_>>>().map(|_| ())
+    }
+
+    /// Register a hook which is run at `position`
+    ///
+    /// Hooks are run in the order they were registered, after the hooks from the configuration.
+    pub fn register_hook(&self, position: HookPosition, hook: Box<dyn Hook>) -> Result<()> {
+        debug!("Registering hook '{}' at {}", hook.name(), position);
+        self.hooks
+            .write()
+            .map_err(|_| Error::from(EM::LockError))?
+            .push((position, Arc::from(hook)));
+        Ok(())
+    }
+
+    /// Run all hooks registered for `position`
+    ///
+    /// Stops at the first failing hook.
+    fn run_hooks(&self,
+                 position: HookPosition,
+                 id: &StoreId,
+                 entry: Option<&mut Entry>,
+                 new_id: Option<&StoreId>)
+        -> Result<()>
+    {
+        // Do not hold the lock while the hooks run, they might use the store themselves
+        let hooks = self.hooks
+            .read()
+            .map_err(|_| Error::from(EM::LockError))?
+            .iter()
+            .filter(|&&(ref p, _)| *p == position)
+            .map(|&(_, ref hook)| hook.clone())
+            .collect::<Vec<_>>();
+
+        if hooks.is_empty() {
+            return Ok(())
+        }
+
+        // Hooks before deleting or moving an entry get a copy of the entry as it is stored
+        let mut stored = match position {
+            HookPosition::PreDelete | HookPosition::PreMove if entry.is_none() => self.read_for_hook(id)?,
+            _ => None,
+        };
+        let mut entry = entry.or_else(|| stored.as_mut());
+
+        for hook in hooks {
+            trace!("Running hook '{}' at {} for {}", hook.name(), position, id);
+            let mut args = HookArgs {
+                position,
+                id,
+                entry: entry.as_mut().map(|e| &mut **e),
+                new_id,
+            };
+
+            hook.execute(&mut args)
+                .context(format_err!("Hook '{}' failed at {} for {}", hook.name(), position, id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the entry `id` from the backend, if it exists
+    fn read_for_hook(&self, id: &StoreId) -> Result<Option<Entry>> {
+        let path = id.clone().with_base(self.path()).into_pathbuf()?;
+        if self.backend.exists(&path)? {
+            self.backend.new_instance(path).get_file_content(id.clone().with_base(self.path()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Mark a cached entry as not borrowed anymore
+    fn set_present(&self, id: &StoreId) -> Result<()> {
+        if let Some(se) = self.entries.write(id)?.get_mut(id) {
+            se.status = StoreEntryStatus::Present;
+        }
+        Ok(())
+    }
+
+    /// Tell the backend that an operation is complete, unless it is part of a transaction
+    fn finish_batch(&self) -> Result<()> {
+        if self.journal.lock().map_err(|_| Error::from(EM::LockError))?.is_some() {
+            return Ok(())
+        }
+        self.announce_changes()?;
+        self.remove_released_attachments()?;
+        self.backend.batch_finished()
+    }
+
+    /// Remember that an entry does not refer to the attachments with the hashes `hashes` anymore
+    ///
+    /// Through `imag-server`, the server takes care of the attachments.
+    fn release_attachments(&self, hashes: Vec<String>) -> Result<()> {
+        if !hashes.is_empty() && !self.remote {
+            trace!("Released attachments: {:?}", hashes);
+            self.released_attachments
+                .lock()
+                .map_err(|_| Error::from(EM::LockError))?
+                .extend(hashes);
+        }
+        Ok(())
+    }
+
+    /// Remove the data of the released attachments which no entry, also in the trash, refers to
+    fn remove_released_attachments(&self) -> Result<()> {
+        let mut released = ::std::mem::replace(&mut *self.released_attachments
+            .lock()
+            .map_err(|_| Error::from(EM::LockError))?, Vec::new());
+        if released.is_empty() {
+            return Ok(())
+        }
+        released.sort();
+        released.dedup();
+
+        // The entries in the cache might refer to attachments which are not written yet
+        let cached = self.entries.attachments()?;
+        let now    = SystemTime::now();
+        let mut unused = vec![];
+        for hash in released {
+            if cached.contains(&hash) {
+                continue
+            }
+
+            // The index is rebuilt first if another process changed the store
+            let ids = self.with_complete_index(|index| index.lookup(crate::attachment::HEADER_PATH, &hash))?;
+            if !ids.is_empty() {
+                continue
+            }
+
+            // Another process might have attached the data to an entry it did not write yet
+            let path    = crate::attachment::data_path(self.path(), &hash)?;
+            let written = self.backend.modified(&path)?;
+            if written.map(|w| now.duration_since(w).map(|age| age < crate::attachment::GRACE_PERIOD).unwrap_or(true)).unwrap_or(false) {
+                debug!("Keeping data of attachment {}, it was written recently", hash);
+                continue
+            }
+
+            unused.push(hash);
+        }
+
+        let mut released = unused;
+        if released.is_empty() {
+            return Ok(())
+        }
+
+        // Trashed entries are not in the index
+        for id in self.trash_list()?.into_iter().map(|t| t.trash_id) {
+            let path = id.clone().with_base(self.path()).into_pathbuf()?;
+            match self.backend.new_instance(path).get_file_header(id.clone().with_base(self.path())) {
+                Ok(Some(entry)) => {
+                    let hashes = crate::attachment::hashes(entry.get_header());
+                    released.retain(|hash| !hashes.contains(hash));
+                },
+                Ok(None) => {},
+                Err(e)   => {
+                    warn!("Keeping released attachments, cannot read {}: {}", id, e);
+                    return Ok(())
+                },
+            }
+
+            if released.is_empty() {
+                return Ok(())
+            }
+        }
+
+        for hash in released {
+            debug!("Removing data of attachment {}", hash);
+            if let Err(e) = self.backend.remove_raw(&crate::attachment::data_path(self.path(), &hash)?) {
+                warn!("Failed to remove data of attachment {}: {}", hash, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the state of `id` in the journal, if a transaction is running
+    fn record_in_journal(&self, id: &StoreId) -> Result<()> {
+        let mut journal = self.journal.lock().map_err(|_| Error::from(EM::LockError))?;
+        if let Some(ref mut journal) = *journal {
+            journal.record(self.path(), id, &self.backend)?;
+        }
+        Ok(())
+    }
+
+    /// Record the content of the raw file at `path` in the journal, if a transaction is running
+    fn record_raw_in_journal(&self, path: &PathBuf) -> Result<()> {
+        let mut journal = self.journal.lock().map_err(|_| Error::from(EM::LockError))?;
+        if let Some(ref mut journal) = *journal {
+            journal.record_raw(path, &self.backend)?;
+        }
+        Ok(())
+    }
+
+    /// Get the ids of all entries which have `value` in the header at `header_path`
+    ///
+    /// If the header value is an array, an entry is found if `value` is one of its elements. Non-string
+    /// header values are compared by their string representation.
+    ///
+    /// Only header paths which are indexed can be looked up (see `Store::indexed_header_paths()`),
+    /// other paths result in an error. If there is no index yet, it is built first.
+    pub fn index_lookup(&self, header_path: &str, value: &str) -> Result<StoreIdIterator> {
+        debug!("Index lookup: {} = {}", header_path, value);
+        self.with_complete_index(|index| index.lookup(header_path, value))
+            .map(|ids| StoreIdIterator::new(Box::new(ids.into_iter().map(Ok))))
+    }
+
+    /// Get the ids of all entries which have any value in the header at `header_path`
+    ///
+    /// See `Store::index_lookup()`.
+    pub fn index_ids_with(&self, header_path: &str) -> Result<StoreIdIterator> {
+        debug!("Index lookup: {} = *", header_path);
+        self.with_complete_index(|index| index.ids_with(header_path))
+            .map(|ids| StoreIdIterator::new(Box::new(ids.into_iter().map(Ok))))
+    }
+
+    /// The header paths which are indexed
+    ///
+    /// These are `tag.values`, `category.value` and `links.internal` as well as the paths
+    /// configured in `store.index.headers`.
+    pub fn indexed_header_paths(&self) -> &[String] {
+        &self.index_paths
+    }
+
+    /// Rebuild the index by reading all entries in the store
+    ///
+    /// This is necessary if the store was altered without using imag. Entries which are currently
+    /// borrowed are indexed with their state on disk.
+    pub fn rebuild_index(&self) -> Result<()> {
+        self.with_index(|index| {
+            self.rebuild_index_into(index)?;
+            index.flush(self.path(), &self.backend)
+        })
+    }
+
+    fn rebuild_index_into(&self, index: &mut Index) -> Result<()> {
+        info!("Rebuilding index, this might take a while");
+        index.clear(self.path(), &self.backend)?;
+
+        for id in self.entries()?.into_storeid_iter() {
+            let id   = id?;
+            let path = id.clone().with_base(self.path()).into_pathbuf()?;
+
+            if let Some(entry) = self.backend.new_instance(path).get_file_content(id.clone().with_base(self.path()))? {
+                index.insert(&id, entry.get_header())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the index to disk, if it was altered
+    ///
+    /// This is done automatically when the `Store` is dropped.
+    pub fn flush_index(&self) -> Result<()> {
+        match *self.index.lock().map_err(|_| Error::from(EM::LockError))? {
+            Some(ref mut index) => index.flush(self.path(), &self.backend),
+            None => Ok(()),
+        }
+    }
+
+    /// Write a new generation if entries were changed, see `Index::announce()`
+    fn announce_changes(&self) -> Result<()> {
+        match *self.index.lock().map_err(|_| Error::from(EM::LockError))? {
+            Some(ref mut index) => index.announce(self.path(), &self.backend),
+            None => Ok(()),
+        }
+    }
+
+    /// Run `f` on the index, loading it first if necessary
+    fn with_index<F, R>(&self, f: F) -> Result<R>
+        where F: FnOnce(&mut Index) -> Result<R>
+    {
+        let mut index = self.index.lock().map_err(|_| Error::from(EM::LockError))?;
+        if index.is_none() {
+            *index = Some(if self.mounts.is_empty() && !self.remote {
+                Index::load(self.path(), &self.index_paths, &self.backend)?
+            } else {
+                Index::transient(&self.index_paths, !self.remote)
+            });
+        }
+
+        f(index.as_mut().unwrap()) // just set
+    }
+
+    /// Run `f` on the index, building it first if it is not complete
+    fn with_complete_index<F, R>(&self, f: F) -> Result<R>
+        where F: FnOnce(&mut Index) -> Result<R>
+    {
+        self.with_index(|index| {
+            index.check_generation(self.path(), &self.backend)?;
+            if !index.is_complete() {
+                self.rebuild_index_into(index)?;
+            }
+            f(index)
+        })
+    }
+
+    /// Update the index for `id` with the state in the backend
+    fn reindex(&self, id: &StoreId) -> Result<()> {
+        let path = id.clone().with_base(self.path()).into_pathbuf()?;
+        let entry = if self.backend.exists(&path)? {
+            self.backend.new_instance(path).get_file_content(id.clone().with_base(self.path()))?
+        } else {
+            None
+        };
+
+        self.with_index(|index| match entry {
+            Some(ref entry) => index.update(self.path(), &self.backend, id, entry.get_header()),
+            None            => index.remove(self.path(), &self.backend, id),
+        })
+    }
+
+    /// Get _all_ entries in the store (by id as iterator)
+    pub fn entries<'a>(&'a self) -> Result<Entries<'a>> {
         trace!("Building 'Entries' iterator");
         self.backend
             .pathes_recursively(self.path().clone(), self.path(), self.backend.clone())
@@ -638,9 +1882,8 @@ impl Store {
     pub fn exists(&self, id: StoreId) -> Result<bool> {
         let cache_has_entry = |id: &StoreId|
             self.entries
-                .read()
+                .read(id)
                 .map(|map| map.contains_key(id))
-                .map_err(|_| Error::from(EM::LockError))
                 .context(format_err!("CreateCallError: {}", id));
 
         let backend_has_entry = |id: StoreId|
@@ -649,10 +1892,70 @@ impl Store {
         Ok(cache_has_entry(&id)? || backend_has_entry(id)?)
     }
 
+    /// Whether the store is accessed through `imag-server`, see `Store::new_remote()`
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
     /// Gets the path where this store is on the disk
     pub fn path(&self) -> &PathBuf {
         &self.location
     }
+
+    /// The format of the header of new entries, from `store.header-format`
+    pub fn header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    /// Lock `id` against other processes, if locking is enabled
+    ///
+    /// Fails right away if the entry is borrowed in this process, instead of waiting for the lock
+    /// we hold ourselves.
+    fn lock_entry(&self, id: &StoreId) -> Result<Option<EntryLock>> {
+        if !self.locks.enabled {
+            return Ok(None)
+        }
+
+        if self.entries.read(id)?.get(id).map(StoreEntry::is_borrowed).unwrap_or(false) {
+            return Err(format_err!("EntryAlreadyBorrowed: {}", id))
+        }
+
+        crate::lock::acquire(self.path(), id, &self.locks).map(Some)
+    }
+
+    /// List the entries which are locked by a process, because it borrowed them
+    pub fn locks(&self) -> Result<Vec<LockHolder>> {
+        crate::lock::list(self.path())
+    }
+
+    /// The read-only stores mounted into this store, see the `mount` module
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Whether the lock of `holder` is stale, see the `lock` module
+    pub fn is_stale_lock(&self, holder: &LockHolder) -> bool {
+        holder.is_stale(self.locks.stale_after)
+    }
+}
+
+impl Drop for Store {
+
+    /// Writes the index back to disk and lets the backend finish its session, errors are only
+    /// logged
+    fn drop(&mut self) {
+        use libimagerror::trace::trace_error;
+
+        trace!("Dropping store, flushing index");
+        if let Err(e) = self.announce_changes().and_then(|_| self.flush_index()) {
+            trace_error(&e);
+        }
+
+        if let Err(e) = self.backend.session_finished() {
+            trace_error(&e);
+        }
+    }
+
 }
 
 impl Debug for Store {
@@ -667,6 +1970,9 @@ impl Debug for Store {
 pub struct FileLockEntry<'a> {
     store: &'a Store,
     entry: Entry,
+
+    /// Released after the entry was written back when the `FileLockEntry` is dropped
+    _lock: Option<EntryLock>,
 }
 
 impl<'a> FileLockEntry<'a, > {
@@ -674,8 +1980,8 @@ impl<'a> FileLockEntry<'a, > {
     /// Create a new FileLockEntry based on a `Entry` object.
     ///
     /// Only for internal use.
-    fn new(store: &'a Store, entry: Entry) -> FileLockEntry<'a> {
-        FileLockEntry { store, entry }
+    fn new(store: &'a Store, entry: Entry, lock: Option<EntryLock>) -> FileLockEntry<'a> {
+        FileLockEntry { store, entry, _lock: lock }
     }
 }
 
@@ -744,6 +2050,7 @@ pub type EntryContent = String;
 pub struct Entry {
     location: StoreId,
     header: Value,
+    header_format: HeaderFormat,
     content: EntryContent,
 }
 
@@ -757,7 +2064,8 @@ impl Entry {
         Entry {
             location: loc,
             header: Entry::default_header(),
-            content: EntryContent::new()
+            header_format: HeaderFormat::default(),
+            content: EntryContent::new(),
         }
     }
 
@@ -803,16 +2111,17 @@ impl Entry {
     /// This errors if
     ///
     /// - String cannot be matched on regex to find header and content
-    /// - Header cannot be parsed into a TOML object
+    /// - Header cannot be parsed as TOML, YAML or JSON object
     ///
     pub fn from_str<S: IntoStoreId>(loc: S, s: &str) -> Result<Entry> {
         use crate::util::entry_buffer_to_header_content;
 
-        let (header, content) = entry_buffer_to_header_content(s)?;
+        let (header, header_format, content) = entry_buffer_to_header_content(s)?;
 
         Ok(Entry {
             location: loc.into_storeid()?,
             header,
+            header_format,
             content,
         })
     }
@@ -823,9 +2132,7 @@ impl Entry {
     /// disk).
     pub fn to_str(&self) -> Result<String> {
         Ok(format!("---\n{header}---\n{content}",
-                   header  = ::toml::ser::to_string_pretty(&self.header)
-                       .map_err(Error::from)
-                       .context(err_msg("TOML Error"))?,
+                   header  = self.header_format.serialize(&self.header)?,
                    content = self.content))
     }
 
@@ -844,6 +2151,16 @@ impl Entry {
         &mut self.header
     }
 
+    /// Get the format the header is written in
+    pub fn get_header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    /// Set the format the header is written in
+    pub fn set_header_format(&mut self, format: HeaderFormat) {
+        self.header_format = format;
+    }
+
     /// Get the content of the Entry
     pub fn get_content(&self) -> &EntryContent {
         &self.content
@@ -863,12 +2180,23 @@ impl Entry {
     ///
     /// If an error is returned, the contents of neither the header nor the content are modified.
     pub fn replace_from_buffer(&mut self, buf: &str) -> Result<()> {
-        let (header, content) = crate::util::entry_buffer_to_header_content(buf)?;
-        self.content          = content;
-        self.header           = header;
+        let (header, header_format, content) = crate::util::entry_buffer_to_header_content(buf)?;
+        self.content                         = content;
+        self.header                          = header;
+        self.header_format                   = header_format;
         Ok(())
     }
 
+    /// The attachments of the entry, see the `attachment` module
+    pub fn attachments(&self) -> Result<Vec<Attachment>> {
+        crate::attachment::read(&self.header)
+    }
+
+    /// Find the problems of the header, an empty list if `Entry::verify()` succeeds
+    pub fn header_problems(&self) -> Result<Vec<Problem>> {
+        crate::fsck::header_problems(&self.header)
+    }
+
     /// Verify the entry.
     ///
     /// Currently, this only verifies the header. This might change in the future.
@@ -897,6 +2225,88 @@ impl PartialEq for Entry {
 
 }
 
+/// An entry of which only the header was read, see `Store::get_header_only()`
+///
+/// The content is read from the backend when it is accessed for the first time. As this can fail,
+/// for example if the file was changed in the meantime, there is no infallible access to it.
+#[derive(Debug, Clone)]
+pub struct HeaderOnlyEntry {
+    location: StoreId,
+    header: Value,
+    header_format: HeaderFormat,
+    content: LazyContent,
+}
+
+impl HeaderOnlyEntry {
+
+    /// Create a new HeaderOnlyEntry, the content is loaded with `loader` on first access
+    pub(crate) fn new<S: IntoStoreId>(loc: S,
+                                      header: Value,
+                                      header_format: HeaderFormat,
+                                      loader: ContentLoader)
+        -> Result<HeaderOnlyEntry>
+    {
+        Ok(HeaderOnlyEntry {
+            location: loc.into_storeid()?,
+            header,
+            header_format,
+            content: LazyContent::deferred(loader),
+        })
+    }
+
+    /// Get the location of the Entry
+    pub fn get_location(&self) -> &StoreId {
+        &self.location
+    }
+
+    /// Get the header of the Entry
+    pub fn get_header(&self) -> &Value {
+        &self.header
+    }
+
+    /// Get the format the header is written in
+    pub fn get_header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    /// Get the content of the Entry, reading it from the backend if necessary
+    pub fn try_get_content(&self) -> Result<&EntryContent> {
+        self.content.try_get()
+    }
+
+    /// Whether the content of the Entry is in memory already
+    pub fn is_content_loaded(&self) -> bool {
+        self.content.is_loaded()
+    }
+
+    /// The attachments of the entry, see the `attachment` module
+    pub fn attachments(&self) -> Result<Vec<Attachment>> {
+        crate::attachment::read(&self.header)
+    }
+
+    /// Turn this into a complete Entry, reading the content from the backend if necessary
+    pub fn into_entry(self) -> Result<Entry> {
+        Ok(Entry {
+            location: self.location,
+            header: self.header,
+            header_format: self.header_format,
+            content: self.content.into_content()?,
+        })
+    }
+
+}
+
+impl From<Entry> for HeaderOnlyEntry {
+    fn from(entry: Entry) -> HeaderOnlyEntry {
+        HeaderOnlyEntry {
+            location: entry.location,
+            header: entry.header,
+            header_format: entry.header_format,
+            content: LazyContent::loaded(entry.content),
+        }
+    }
+}
+
 fn has_only_tables(t: &Value) -> Result<bool> {
     debug!("Verifying that table has only tables");
     match *t {
@@ -1008,7 +2418,7 @@ Hai
         let entry = Entry::from_str(StoreId::new(PathBuf::from("test/foo~1.3")).unwrap(),
                                     TEST_ENTRY).unwrap();
 
-        assert_eq!(entry.content, "Hai");
+        assert_eq!(entry.get_content(), "Hai");
     }
 
     #[test]
@@ -1049,6 +2459,9 @@ mod store_tests {
     use std::path::PathBuf;
     use std::sync::Arc;
 
+    use failure::Fallible as Result;
+    use failure::err_msg;
+
     fn setup_logging() {
         let _ = env_logger::try_init();
     }
@@ -1061,12 +2474,24 @@ mod store_tests {
         Store::new_with_backend(PathBuf::from("/"), &None, backend).unwrap()
     }
 
+    /// A store which removes deleted entries instead of moving them to the trash
+    pub fn get_store_without_trash() -> Store {
+        let config = ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+
+        [store.trash]
+            enabled = false
+        "#).unwrap();
+        Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap()
+    }
+
     #[test]
     fn test_store_instantiation() {
         let store = get_store();
 
         assert_eq!(store.location, PathBuf::from("/"));
-        assert!(store.entries.read().unwrap().is_empty());
+        assert_eq!(store.cache_size().unwrap(), 0);
     }
 
     #[test]
@@ -1144,9 +2569,9 @@ mod store_tests {
         for n in 1..100 {
             let pb = StoreId::new(PathBuf::from(format!("test-{}", n))).unwrap();
 
-            assert!(store.entries.read().unwrap().get(&pb).is_none());
+            assert!(store.entries.read(&pb).unwrap().get(&pb).is_none());
             assert!(store.create(pb.clone()).is_ok());
-            assert!(store.entries.read().unwrap().get(&pb).is_some());
+            assert!(store.entries.read(&pb).unwrap().get(&pb).is_some());
         }
     }
 
@@ -1159,9 +2584,9 @@ mod store_tests {
         for n in 1..100 {
             let pb = StoreId::new(PathBuf::from(format!("test-{}", n))).unwrap();
 
-            assert!(store.entries.read().unwrap().get(&pb).is_none());
+            assert!(store.entries.read(&pb).unwrap().get(&pb).is_none());
             assert!(store.retrieve(pb.clone()).is_ok());
-            assert!(store.entries.read().unwrap().get(&pb).is_some());
+            assert!(store.entries.read(&pb).unwrap().get(&pb).is_some());
         }
     }
 
@@ -1205,7 +2630,7 @@ mod store_tests {
 
                 {
                     debug!("Checking presence: {}", id);
-                    assert!(store.entries.read().unwrap().get(&id).is_none());
+                    assert!(store.entries.read(&id).unwrap().get(&id).is_none());
                 }
 
                 {
@@ -1215,7 +2640,7 @@ mod store_tests {
 
                 {
                     debug!("Checking presence: {}", id);
-                    assert!(store.entries.read().unwrap().get(&id).is_some());
+                    assert!(store.entries.read(&id).unwrap().get(&id).is_some());
                 }
 
                 debug!("Moving: {} -> {}", id, id_mv);
@@ -1224,9 +2649,9 @@ mod store_tests {
 
                 {
                     debug!("Checking presence: {}", id_mv);
-                    assert!(store.entries.read().unwrap().get(&id_mv).is_none()); // entry not in cache yet
+                    assert!(store.entries.read(&id_mv).unwrap().get(&id_mv).is_none()); // entry not in cache yet
                     assert!(store.get(id_mv.clone()).unwrap().is_some()); // get entry from backend
-                    assert!(store.entries.read().unwrap().get(&id_mv).is_some()); // entry in cache
+                    assert!(store.entries.read(&id_mv).unwrap().get(&id_mv).is_some()); // entry in cache
                 }
 
                 let res = store.get(id.clone());
@@ -1279,4 +2704,1214 @@ mod store_tests {
         }
     }
 
+
+    #[test]
+    fn test_transaction_commit() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        let id    = StoreId::new(PathBuf::from("transaction/commit")).unwrap();
+
+        store.transaction(|store| {
+            let mut entry = store.create(id.clone())?;
+            entry.get_content_mut().push_str("content");
+            store.update(&mut entry)
+        }).unwrap();
+
+        let entry = store.get(id).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "content");
+    }
+
+    #[test]
+    fn test_transaction_rollback_create_and_update() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store    = get_store();
+        let existing = StoreId::new(PathBuf::from("transaction/existing")).unwrap();
+        let created  = StoreId::new(PathBuf::from("transaction/created")).unwrap();
+
+        {
+            let mut entry = store.create(existing.clone()).unwrap();
+            entry.get_content_mut().push_str("old");
+        }
+
+        let res : Result<()> = store.transaction(|store| {
+            let mut entry = store.get(existing.clone())?.unwrap();
+            entry.set_content(String::from("new"));
+            store.update(&mut entry)?;
+
+            let mut entry = store.create(created.clone())?;
+            store.update(&mut entry)?;
+
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+
+        assert!(!store.exists(created).unwrap());
+        let entry = store.get(existing).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "old");
+    }
+
+    #[test]
+    fn test_transaction_rollback_delete_and_move() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store   = get_store();
+        let deleted = StoreId::new(PathBuf::from("transaction/deleted")).unwrap();
+        let moved   = StoreId::new(PathBuf::from("transaction/moved")).unwrap();
+        let target  = StoreId::new(PathBuf::from("transaction/target")).unwrap();
+
+        let _ = store.create(deleted.clone()).unwrap();
+        {
+            let mut entry = store.create(moved.clone()).unwrap();
+            entry.get_content_mut().push_str("moved content");
+        }
+
+        let res : Result<()> = store.transaction(|store| {
+            store.delete(deleted.clone())?;
+            store.move_by_id(moved.clone(), target.clone())?;
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+
+        assert!(store.exists(deleted).unwrap());
+        assert!(!store.exists(target).unwrap());
+        let entry = store.get(moved).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "moved content");
+    }
+
+    #[test]
+    fn test_transaction_rollback_borrowed_entry_is_not_written() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        let id    = StoreId::new(PathBuf::from("transaction/borrowed")).unwrap();
+        let _     = store.create(id.clone()).unwrap();
+
+        {
+            let mut outer = store.get(id.clone()).unwrap().unwrap();
+            outer.get_content_mut().push_str("changed");
+
+            let res : Result<()> = store.transaction(|_| {
+                store.update(&mut outer)?;
+                Err(err_msg("Failing on purpose"))
+            });
+            assert!(res.is_err());
+            assert!(store.update(&mut outer).is_err());
+        } // dropping must not write the rolled back content
+
+        let entry = store.get(id).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "");
+    }
+
+    #[test]
+    fn test_transaction_rollback_fs_backend() {
+        use crate::storeid::StoreId;
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir   = TempDir::new("imag-store-transaction").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        let a     = StoreId::new(PathBuf::from("transaction/a")).unwrap();
+        let b     = StoreId::new(PathBuf::from("transaction/b")).unwrap();
+
+        {
+            let mut entry = store.create(a.clone()).unwrap();
+            entry.get_content_mut().push_str("a");
+        }
+
+        let res : Result<()> = store.transaction(|store| {
+            let mut entry = store.get(a.clone())?.unwrap();
+            entry.set_content(String::from("changed"));
+            store.update(&mut entry)?;
+
+            let mut entry = store.create(b.clone())?;
+            store.update(&mut entry)?;
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+
+        let ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(ids, vec![a.clone()]);
+        assert_eq!(store.get(a).unwrap().unwrap().get_content(), "a");
+    }
+
+    #[test]
+    fn test_transaction_recovered_after_crash() {
+        use crate::storeid::StoreId;
+        use crate::transaction::JOURNAL_FILE;
+        use std::fs::{read_to_string, write};
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir     = TempDir::new("imag-store-transaction").unwrap();
+        let journal = dir.path().join(JOURNAL_FILE);
+        let a       = StoreId::new(PathBuf::from("transaction/a")).unwrap();
+        let b       = StoreId::new(PathBuf::from("transaction/b")).unwrap();
+
+        let crashed_journal : String = {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            {
+                let mut entry = store.create(a.clone()).unwrap();
+                entry.get_content_mut().push_str("a");
+            }
+
+            // the journal as the process left it behind when it died in the transaction
+            let crashed_journal = store.transaction(|store| {
+                let mut entry = store.get(a.clone())?.unwrap();
+                entry.set_content(String::from("changed"));
+                store.update(&mut entry)?;
+
+                let mut entry = store.create(b.clone())?;
+                store.update(&mut entry)?;
+                Ok(read_to_string(&journal)?)
+            }).unwrap();
+
+            assert!(!journal.exists());
+            crashed_journal
+        };
+
+        // the journal of a process which still runs is left alone
+        write(&journal, &crashed_journal).unwrap();
+        drop(Store::new(dir.path().to_path_buf(), &None).unwrap());
+        assert!(journal.exists());
+
+        let mut crashed_journal = ::serde_json::from_str::<::serde_json::Value>(&crashed_journal).unwrap();
+        crashed_journal["pid"] = ::serde_json::Value::from(u32::MAX); // no such process
+        write(&journal, crashed_journal.to_string()).unwrap();
+
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        assert!(!journal.exists());
+        let ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(ids, vec![a.clone()]);
+        assert_eq!(store.get(a).unwrap().unwrap().get_content(), "a");
+    }
+
+    #[test]
+    fn test_dot_files_are_entries() {
+        use crate::storeid::StoreId;
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir   = TempDir::new("imag-store-entries").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        for id in ["notes/.todo", "notes/a", "notes/b"].iter() {
+            let _ = store.create(PathBuf::from(id)).unwrap();
+        }
+        store.delete(StoreId::new(PathBuf::from("notes/b")).unwrap()).unwrap(); // into the trash
+        ::std::fs::write(dir.path().join("notes/.a.1-0.imag-staging"), "").unwrap();
+        assert!(dir.path().join(".trash").is_dir());
+
+        let mut ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![
+            StoreId::new(PathBuf::from("notes/.todo")).unwrap(),
+            StoreId::new(PathBuf::from("notes/a")).unwrap(),
+        ]);
+    }
+
+    #[test]
+    fn test_transaction_not_recovered_by_other_store() {
+        use crate::storeid::StoreId;
+        use crate::transaction::JOURNAL_FILE;
+        use tempdir::TempDir;
+        setup_logging();
+
+        let config = ::toml::de::from_str(r#"
+        [store.locks]
+            timeout = 0
+        "#).unwrap();
+        let config  = Some(config);
+        let dir     = TempDir::new("imag-store-transaction").unwrap();
+        let journal = dir.path().join(JOURNAL_FILE);
+        let a       = StoreId::new(PathBuf::from("transaction/a")).unwrap();
+        let b       = StoreId::new(PathBuf::from("transaction/b")).unwrap();
+
+        let store = Store::new(dir.path().to_path_buf(), &config).unwrap();
+        {
+            let mut entry = store.create(a.clone()).unwrap();
+            entry.get_content_mut().push_str("a");
+        }
+
+        store.transaction(|store| {
+            {
+                let mut entry = store.get(a.clone())?.unwrap();
+                entry.set_content(String::from("changed"));
+                store.update(&mut entry)?;
+
+                let mut entry = store.create(b.clone())?;
+                store.update(&mut entry)?;
+            }
+
+            // opening the store elsewhere does not roll back the running transaction
+            let other = Store::new(dir.path().to_path_buf(), &config)?;
+            assert!(journal.exists());
+            assert_eq!(other.get(a.clone())?.unwrap().get_content(), "changed");
+
+            // and a second transaction does not overwrite its journal
+            assert!(other.transaction(|_| Ok(())).is_err());
+            assert!(journal.exists());
+            Ok(())
+        }).unwrap();
+
+        assert!(!journal.exists());
+        let ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(store.get(a).unwrap().unwrap().get_content(), "changed");
+    }
+
+    fn tag_entry(store: &Store, id: &str, tags: &[&str]) {
+        use toml::Value;
+        use toml_query::insert::TomlValueInsertExt;
+
+        let mut entry = store.retrieve(PathBuf::from(id)).unwrap();
+        let tags = tags.iter().map(|t| Value::String(String::from(*t))).collect();
+        let _ = entry.get_header_mut().insert("tag.values", Value::Array(tags)).unwrap();
+        store.update(&mut entry).unwrap();
+    }
+
+    fn lookup(store: &Store, path: &str, value: &str) -> Vec<String> {
+        store.index_lookup(path, value)
+            .unwrap()
+            .map(|id| id.unwrap().to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        setup_logging();
+        let store = get_store();
+
+        tag_entry(&store, "index/a", &["foo", "bar"]);
+        tag_entry(&store, "index/b", &["foo"]);
+        tag_entry(&store, "index/c", &[]);
+
+        assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a", "index/b"]);
+        assert_eq!(lookup(&store, "tag.values", "bar"), vec!["index/a"]);
+        assert!(lookup(&store, "tag.values", "baz").is_empty());
+
+        let with_tags = store.index_ids_with("tag.values")
+            .unwrap()
+            .map(|id| id.unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(with_tags, vec!["index/a", "index/b"]);
+    }
+
+    #[test]
+    fn test_index_lookup_not_indexed() {
+        setup_logging();
+        let store = get_store();
+        assert!(store.index_lookup("not.indexed", "foo").is_err());
+    }
+
+    #[test]
+    fn test_index_is_maintained() {
+        use crate::storeid::StoreId;
+        setup_logging();
+        let store = get_store();
+
+        tag_entry(&store, "index/a", &["foo"]);
+        assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a"]);
+
+        tag_entry(&store, "index/a", &["bar"]);
+        assert!(lookup(&store, "tag.values", "foo").is_empty());
+        assert_eq!(lookup(&store, "tag.values", "bar"), vec!["index/a"]);
+
+        let a = StoreId::new(PathBuf::from("index/a")).unwrap();
+        let b = StoreId::new(PathBuf::from("index/b")).unwrap();
+        store.move_by_id(a, b.clone()).unwrap();
+        assert_eq!(lookup(&store, "tag.values", "bar"), vec!["index/b"]);
+
+        store.delete(b).unwrap();
+        assert!(lookup(&store, "tag.values", "bar").is_empty());
+    }
+
+    #[test]
+    fn test_index_rollback() {
+        setup_logging();
+        let store = get_store();
+
+        tag_entry(&store, "index/a", &["foo"]);
+        assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a"]);
+
+        let res : Result<()> = store.transaction(|store| {
+            tag_entry(store, "index/a", &["bar"]);
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+
+        assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a"]);
+        assert!(lookup(&store, "tag.values", "bar").is_empty());
+    }
+
+    #[test]
+    fn test_index_configured_header_path() {
+        use toml::Value;
+        use toml_query::insert::TomlValueInsertExt;
+        setup_logging();
+
+        let config = ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+            index.headers = [ "todo.status" ]
+        "#).unwrap();
+        let store = Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap();
+
+        {
+            let mut entry = store.create(PathBuf::from("todo/a")).unwrap();
+            let _ = entry.get_header_mut().insert("todo.status", Value::String(String::from("done"))).unwrap();
+        }
+
+        assert_eq!(lookup(&store, "todo.status", "done"), vec!["todo/a"]);
+        assert!(store.indexed_header_paths().contains(&String::from("todo.status")));
+    }
+
+    #[test]
+    fn test_index_persisted_fs_backend() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir = TempDir::new("imag-store-index").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            tag_entry(&store, "index/a", &["foo"]);
+            assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a"]);
+        }
+
+        assert!(dir.path().join(".index").is_dir());
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            tag_entry(&store, "index/b", &["foo"]);
+
+            let ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!(ids.len(), 2);
+        }
+
+        {
+            // altered outside of imag, only visible after rebuilding the index
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            let _ = ::std::fs::remove_file(dir.path().join("index/b")).unwrap();
+            assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a", "index/b"]);
+
+            store.rebuild_index().unwrap();
+            assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a"]);
+        }
+    }
+
+    #[test]
+    fn test_index_concurrent_stores() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir = TempDir::new("imag-store-index").unwrap();
+
+        {
+            // Two processes working on the same store
+            let first  = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            let second = Store::new(dir.path().to_path_buf(), &None).unwrap();
+
+            tag_entry(&first, "index/a", &["foo"]);
+            assert_eq!(lookup(&first, "tag.values", "foo"), vec!["index/a"]);
+
+            tag_entry(&second, "index/b", &["foo"]);
+            assert_eq!(lookup(&second, "tag.values", "foo"), vec!["index/a", "index/b"]);
+
+            // the index of the first store is outdated now
+            assert_eq!(lookup(&first, "tag.values", "foo"), vec!["index/a", "index/b"]);
+
+            tag_entry(&first, "index/c", &["foo"]);
+        }
+
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        assert_eq!(lookup(&store, "tag.values", "foo"), vec!["index/a", "index/b", "index/c"]);
+    }
+
+    #[test]
+    fn test_index_generation_written_once_per_transaction() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir        = TempDir::new("imag-store-index").unwrap();
+        let store      = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        let generation = || ::std::fs::read_to_string(dir.path().join(".index/generation")).ok();
+
+        tag_entry(&store, "index/a", &["foo"]);
+        let before = generation();
+        assert!(before.is_some());
+
+        store.transaction(|store| {
+            tag_entry(store, "index/b", &["foo"]);
+            tag_entry(store, "index/c", &["foo"]);
+            assert_eq!(generation(), before);
+            Ok(())
+        }).unwrap();
+
+        assert!(generation().is_some());
+        assert_ne!(generation(), before);
+    }
+
+    #[derive(Debug)]
+    struct VetoHook;
+
+    impl crate::hook::Hook for VetoHook {
+        fn name(&self) -> &str {
+            "veto"
+        }
+
+        fn execute(&self, args: &mut crate::hook::HookArgs) -> Result<()> {
+            if args.id.local().starts_with("forbidden") {
+                Err(err_msg("Forbidden"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct AutoTagHook;
+
+    impl crate::hook::Hook for AutoTagHook {
+        fn name(&self) -> &str {
+            "autotag"
+        }
+
+        fn execute(&self, args: &mut crate::hook::HookArgs) -> Result<()> {
+            use toml::Value;
+            use toml_query::insert::TomlValueInsertExt;
+
+            if let Some(ref mut entry) = args.entry {
+                let tags = Value::Array(vec![Value::String(String::from("auto"))]);
+                let _ = entry.get_header_mut().insert("tag.values", tags)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pre_hooks_veto_operations() {
+        use crate::hook::HookPosition;
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        for position in &[HookPosition::PreCreate, HookPosition::PreDelete, HookPosition::PreMove] {
+            store.register_hook(*position, Box::new(VetoHook)).unwrap();
+        }
+
+        assert!(store.create(PathBuf::from("forbidden/a")).is_err());
+        assert!(!store.exists(StoreId::new(PathBuf::from("forbidden/a")).unwrap()).unwrap());
+        assert_eq!(store.cache_size().unwrap(), 0);
+
+        let _ = store.create(PathBuf::from("allowed/a")).unwrap();
+        let _ = store.create(PathBuf::from("allowed/b")).unwrap();
+
+        let allowed_b   = StoreId::new(PathBuf::from("allowed/b")).unwrap();
+        let forbidden_b = StoreId::new(PathBuf::from("forbidden/b")).unwrap();
+        assert!(store.move_by_id(allowed_b.clone(), forbidden_b.clone()).is_ok());
+        assert!(store.move_by_id(forbidden_b.clone(), allowed_b.clone()).is_err());
+        assert!(store.exists(forbidden_b.clone()).unwrap());
+
+        assert!(store.delete(forbidden_b.clone()).is_err());
+        assert!(store.exists(forbidden_b).unwrap());
+        assert!(store.delete(PathBuf::from("allowed/a")).is_ok());
+    }
+
+    #[test]
+    fn test_failing_pre_update_hook_releases_entry() {
+        use crate::hook::HookPosition;
+        setup_logging();
+
+        let store = get_store();
+        store.register_hook(HookPosition::PreUpdate, Box::new(VetoHook)).unwrap();
+
+        let mut entry = store.create(PathBuf::from("forbidden/a")).unwrap();
+        assert!(store.update(&mut entry).is_err());
+        drop(entry);
+
+        // the entry is not borrowed anymore and was never written
+        let entry = store.retrieve(PathBuf::from("forbidden/a")).unwrap();
+        assert!(store.get_copy(PathBuf::from("forbidden/a")).is_err());
+        drop(entry);
+    }
+
+    #[test]
+    fn test_pre_update_hook_alters_entry() {
+        use crate::hook::HookPosition;
+        use toml_query::read::TomlValueReadExt;
+        setup_logging();
+
+        let store = get_store();
+        store.register_hook(HookPosition::PreUpdate, Box::new(AutoTagHook)).unwrap();
+
+        {
+            let mut entry = store.create(PathBuf::from("a")).unwrap();
+            store.update(&mut entry).unwrap();
+        }
+
+        let entry = store.get_copy(PathBuf::from("a")).unwrap();
+        let tags  = entry.get_header().read("tag.values").unwrap().cloned();
+        assert_eq!(tags, Some(::toml::Value::Array(vec![::toml::Value::String(String::from("auto"))])));
+        assert_eq!(lookup(&store, "tag.values", "auto"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_command_hooks_from_config() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let config = ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+
+        [store.hooks]
+            pre-create  = "true"
+            pre-delete  = [ "true", "false" ]
+        "#).unwrap();
+        let store = Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap();
+
+        {
+            let _ = store.create(PathBuf::from("a")).unwrap();
+        }
+
+        let err = store.delete(PathBuf::from("a")).unwrap_err();
+        assert!(err.iter_chain().any(|c| c.to_string().contains("Hook 'false' failed at pre-delete")));
+        assert!(store.exists(StoreId::new(PathBuf::from("a")).unwrap()).unwrap());
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingHook(::std::sync::Arc<::std::sync::Mutex<Vec<String>>>);
+
+    impl crate::hook::Hook for RecordingHook {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        fn execute(&self, args: &mut crate::hook::HookArgs) -> Result<()> {
+            let seen = match args.entry {
+                Some(ref entry) => format!("{} {}", args.position, entry.get_content()),
+                None            => format!("{} -", args.position),
+            };
+            self.0.lock().unwrap().push(seen);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pre_delete_and_pre_move_hooks_get_entry() {
+        use crate::hook::HookPosition;
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        let seen  = ::std::sync::Arc::new(::std::sync::Mutex::new(vec![]));
+        for position in &[HookPosition::PreMove, HookPosition::PreDelete] {
+            store.register_hook(*position, Box::new(RecordingHook(seen.clone()))).unwrap();
+        }
+
+        {
+            let mut entry = store.create(PathBuf::from("a")).unwrap();
+            entry.set_content(String::from("content"));
+        }
+
+        let a = StoreId::new(PathBuf::from("a")).unwrap();
+        let b = StoreId::new(PathBuf::from("b")).unwrap();
+        store.move_by_id(a, b.clone()).unwrap();
+        store.delete(b).unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), vec!["pre-move content", "pre-delete content"]);
+    }
+
+    #[test]
+    fn test_command_hook_echoing_large_entry() {
+        setup_logging();
+
+        // `cat` writes the entry to stdout while it is still reading it from stdin
+        let config = ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+
+        [store.hooks]
+            pre-update = "sh -c cat"
+        "#).unwrap();
+        let store = Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap();
+
+        let mut entry = store.create(PathBuf::from("a")).unwrap();
+        entry.set_content("x".repeat(1024 * 1024));
+        store.update(&mut entry).unwrap();
+    }
+
+    #[test]
+    fn test_command_hook_with_quoted_argument() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir    = TempDir::new("imag-store-hooks").unwrap();
+        let output = dir.path().join("args");
+        let config = ::toml::de::from_str(&format!(r#"
+        [store]
+            implicit-create = true
+
+        [store.hooks]
+            post-create = "printf '%s\n' 'imag: entry created' > '{}'"
+        "#, output.display())).unwrap();
+        let store = Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap();
+
+        let _ = store.create(PathBuf::from("a")).unwrap();
+        assert_eq!(::std::fs::read_to_string(&output).unwrap(), "imag: entry created\na\n");
+    }
+
+    #[test]
+    fn test_unknown_hook_position_in_config() {
+        let config = ::toml::de::from_str(r#"
+        [store.hooks]
+            pre-frobnicate = "true"
+        "#).unwrap();
+        assert!(Store::new_inmemory(PathBuf::from("/"), &Some(config)).is_err());
+    }
+
+    fn get_store_with_trash(max_age: i64) -> Store {
+        let config = ::toml::de::from_str(&format!(r#"
+        [store]
+            implicit-create = true
+
+        [store.trash]
+            enabled = true
+            max-age = {}
+        "#, max_age)).unwrap();
+        Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap()
+    }
+
+    #[test]
+    fn test_delete_moves_to_trash() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store_with_trash(0);
+        let a     = StoreId::new(PathBuf::from("trash/a")).unwrap();
+        let b     = StoreId::new(PathBuf::from("trash/b")).unwrap();
+        {
+            let mut entry = store.create(a.clone()).unwrap();
+            entry.set_content(String::from("content of a"));
+        }
+        let _ = store.create(b.clone()).unwrap();
+
+        store.delete(a.clone()).unwrap();
+        assert!(!store.exists(a.clone()).unwrap());
+
+        let ids = store.entries().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(ids, vec![b.clone()]);
+
+        let trashed = store.trash_list().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].id, a);
+
+        store.trash_restore(a.clone()).unwrap();
+        assert!(store.trash_list().unwrap().is_empty());
+
+        let entry = store.get(a.clone()).unwrap().unwrap();
+        assert_eq!(entry.get_content(), "content of a");
+        assert!(entry.get_header().as_table().unwrap().get("trash").is_none());
+    }
+
+    #[test]
+    fn test_restore_does_not_overwrite() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store_with_trash(0);
+        let a     = StoreId::new(PathBuf::from("trash/a")).unwrap();
+        let _     = store.create(a.clone()).unwrap();
+        store.delete(a.clone()).unwrap();
+        let _     = store.create(a.clone()).unwrap();
+
+        assert!(store.trash_restore(a.clone()).is_err());
+        assert_eq!(store.trash_list().unwrap().len(), 1);
+
+        let b = StoreId::new(PathBuf::from("trash/b")).unwrap();
+        assert!(store.trash_restore(b).is_err());
+    }
+
+    #[test]
+    fn test_trash_purge() {
+        use crate::storeid::StoreId;
+        use crate::trash::TrashedEntry;
+        setup_logging();
+
+        let store = get_store_with_trash(30);
+        let old   = StoreId::new(PathBuf::from("trash/old")).unwrap();
+        let new   = StoreId::new(PathBuf::from("trash/new")).unwrap();
+
+        // Fake an entry which was trashed long ago
+        let deleted_at = ::chrono::Local::now().naive_local() - ::chrono::Duration::days(31);
+        let trashed    = TrashedEntry::new(old.clone(), deleted_at).unwrap();
+        let trash_pb   = trashed.trash_id.clone().with_base(store.path()).into_pathbuf().unwrap();
+        let entry      = super::Entry::new(trashed.trash_id.clone());
+        store.backend.new_instance(trash_pb).write_file_content(&entry).unwrap();
+        assert_eq!(store.trash_list().unwrap().len(), 1);
+
+        // The first deletion purges old entries
+        let _ = store.create(new.clone()).unwrap();
+        store.delete(new.clone()).unwrap();
+
+        let trashed = store.trash_list().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].id, new);
+
+        let purged = store.trash_purge(None).unwrap();
+        assert_eq!(purged.len(), 1);
+        assert!(store.trash_list().unwrap().is_empty());
+    }
+
+    fn set_content(store: &Store, id: &str, content: &str) {
+        let mut entry = store.retrieve(PathBuf::from(id)).unwrap();
+        entry.set_content(String::from(content));
+        store.update(&mut entry).unwrap();
+    }
+
+    #[test]
+    fn test_history_and_revert() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        let id    = StoreId::new(PathBuf::from("history/a")).unwrap();
+
+        set_content(&store, "history/a", "first");
+        set_content(&store, "history/a", "second");
+        set_content(&store, "history/a", "second"); // no change, no revision
+        set_content(&store, "history/a", "third");
+
+        let numbers = store.history(id.clone()).unwrap().iter().map(|r| r.number).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1, 2]);
+        assert_eq!(store.get_revision(id.clone(), 1).unwrap().get_content(), "first");
+        assert_eq!(store.get_revision(id.clone(), 2).unwrap().get_content(), "second");
+
+        store.revert(id.clone(), 1).unwrap();
+        assert_eq!(store.get_copy(id.clone()).unwrap().get_content(), "first");
+
+        // reverting is recorded as well
+        assert_eq!(store.history(id.clone()).unwrap().len(), 3);
+        assert_eq!(store.get_revision(id.clone(), 3).unwrap().get_content(), "third");
+    }
+
+    #[test]
+    fn test_history_survives_move() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store_without_trash();
+        let a     = StoreId::new(PathBuf::from("history/a")).unwrap();
+        let b     = StoreId::new(PathBuf::from("history/b")).unwrap();
+
+        set_content(&store, "history/a", "first");
+        set_content(&store, "history/a", "second");
+        store.move_by_id(a.clone(), b.clone()).unwrap();
+
+        assert!(store.history(a).unwrap().is_empty());
+        assert_eq!(store.get_revision(b.clone(), 1).unwrap().get_content(), "first");
+
+        store.delete(b.clone()).unwrap();
+        assert!(store.history(b).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_rollback() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store();
+        let id    = StoreId::new(PathBuf::from("history/a")).unwrap();
+        set_content(&store, "history/a", "first");
+
+        let res : Result<()> = store.transaction(|store| {
+            set_content(store, "history/a", "second");
+            Err(err_msg("Failing on purpose"))
+        });
+        assert!(res.is_err());
+        assert!(store.history(id.clone()).unwrap().is_empty());
+
+        set_content(&store, "history/a", "third");
+        assert_eq!(store.get_revision(id, 1).unwrap().get_content(), "first");
+    }
+
+    #[test]
+    fn test_history_disabled() {
+        use crate::storeid::StoreId;
+
+        let config = ::toml::de::from_str(r#"
+        [store]
+            implicit-create = true
+
+        [store.history]
+            enabled = false
+        "#).unwrap();
+        let store = Store::new_inmemory(PathBuf::from("/"), &Some(config)).unwrap();
+
+        set_content(&store, "history/a", "first");
+        set_content(&store, "history/a", "second");
+        assert!(store.history(StoreId::new(PathBuf::from("history/a")).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_header_only_fs_backend() {
+        use tempdir::TempDir;
+        use toml_query::read::TomlValueReadExt;
+        use toml_query::read::TomlValueReadTypeExt;
+        setup_logging();
+
+        let dir   = TempDir::new("imag-store-header").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        tag_entry(&store, "header/a", &["foo"]);
+        set_content(&store, "header/a", "the content\n---\nwith a separator");
+
+        let entry = store.get_header_only(PathBuf::from("header/a")).unwrap().unwrap();
+        assert!(!entry.is_content_loaded());
+        assert_eq!(entry.get_header().read_string("imag.version").unwrap(),
+                   Some(String::from(env!("CARGO_PKG_VERSION"))));
+        assert!(entry.get_header().read("tag.values").unwrap().is_some());
+
+        assert_eq!(entry.try_get_content().unwrap(), "the content\n---\nwith a separator");
+        assert!(entry.is_content_loaded());
+        assert_eq!(entry.into_entry().unwrap().get_content(), "the content\n---\nwith a separator");
+
+        assert!(store.get_header_only(PathBuf::from("header/b")).unwrap().is_none());
+
+        {
+            let _borrowed = store.get(PathBuf::from("header/a")).unwrap().unwrap();
+            assert!(store.get_header_only(PathBuf::from("header/a")).is_err());
+        }
+
+        // the file is replaced with a longer header, the old offset does not fit anymore
+        let entry = store.get_header_only(PathBuf::from("header/a")).unwrap().unwrap();
+        tag_entry(&store, "header/a", &["foo", "a much longer tag"]);
+        assert!(entry.try_get_content().is_err());
+
+        let entry = store.get_header_only(PathBuf::from("header/a")).unwrap().unwrap();
+        let _ = ::std::fs::remove_file(dir.path().join("header/a")).unwrap();
+        assert!(entry.try_get_content().is_err());
+        assert!(entry.into_entry().is_err());
+    }
+
+    #[test]
+    fn test_get_header_only_inmemory() {
+        setup_logging();
+        let store = get_store();
+        set_content(&store, "header/a", "content");
+
+        let entry = store.get_header_only(PathBuf::from("header/a")).unwrap().unwrap();
+        assert!(entry.is_content_loaded());
+        assert_eq!(entry.try_get_content().unwrap(), "content");
+    }
+
+    #[test]
+    fn test_header_format_fs_backend() {
+        use tempdir::TempDir;
+        use crate::header_format::HeaderFormat;
+        setup_logging();
+
+        let dir    = TempDir::new("imag-store-header-format").unwrap();
+        let config = ::toml::de::from_str(r#"
+        [store]
+            header-format = "yaml"
+        "#).unwrap();
+        let store  = Store::new(dir.path().to_path_buf(), &Some(config)).unwrap();
+        assert_eq!(store.header_format(), HeaderFormat::Yaml);
+
+        set_content(&store, "notes/new", "new");
+        let raw = ::std::fs::read_to_string(dir.path().join("notes/new")).unwrap();
+        assert!(raw.starts_with("---\nimag:\n"), "Not YAML: {}", raw);
+        assert!(raw.ends_with("---\nnew"));
+
+        // existing entries keep their format
+        let toml_entry = format!("---\n[imag]\nversion = \"{}\"\n---\ntoml", env!("CARGO_PKG_VERSION"));
+        let json_entry = format!("---\n{{\"imag\": {{\"version\": \"{}\"}}}}\n---\njson", env!("CARGO_PKG_VERSION"));
+        ::std::fs::write(dir.path().join("notes/toml"), &toml_entry).unwrap();
+        ::std::fs::write(dir.path().join("notes/json"), &json_entry).unwrap();
+
+        set_content(&store, "notes/toml", "toml changed");
+        set_content(&store, "notes/json", "json changed");
+
+        let entry = store.get(PathBuf::from("notes/json")).unwrap().unwrap();
+        assert_eq!(entry.get_header_format(), HeaderFormat::Json);
+        assert_eq!(entry.get_content(), "json changed");
+        drop(entry);
+
+        let raw = ::std::fs::read_to_string(dir.path().join("notes/toml")).unwrap();
+        assert!(raw.starts_with("---\n[imag]\n"), "Not TOML: {}", raw);
+        let raw = ::std::fs::read_to_string(dir.path().join("notes/json")).unwrap();
+        assert!(raw.starts_with("---\n{\n"), "Not JSON: {}", raw);
+    }
+
+    #[test]
+    fn test_locked_entry_fs_backend() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir    = TempDir::new("imag-store-locks").unwrap();
+        let config = ::toml::de::from_str(r#"
+        [store.locks]
+            timeout = 0
+        "#).unwrap();
+        let config = Some(config);
+        let first  = Store::new(dir.path().to_path_buf(), &config).unwrap();
+        let second = Store::new(dir.path().to_path_buf(), &config).unwrap();
+
+        {
+            let entry = first.create(PathBuf::from("notes/locked")).unwrap();
+            assert!(second.retrieve(PathBuf::from("notes/locked")).is_err());
+            assert!(second.delete(PathBuf::from("notes/locked")).is_err());
+
+            let locks = second.locks().unwrap();
+            assert_eq!(locks.len(), 1);
+            assert_eq!(&locks[0].id, entry.get_location());
+            assert!(!second.is_stale_lock(&locks[0]));
+        }
+
+        assert!(second.locks().unwrap().is_empty());
+        assert!(second.retrieve(PathBuf::from("notes/locked")).is_ok());
+        assert!(first.locks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fsck_and_repair() {
+        use tempdir::TempDir;
+        use crate::fsck::Problem;
+        setup_logging();
+
+        let dir   = TempDir::new("imag-store-fsck").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        set_content(&store, "notes/fine", "fine");
+        set_content(&store, "elsewhere/fine", "fine");
+
+        ::std::fs::write(dir.path().join("notes/broken"), "---\n[imag\n---\ntext").unwrap();
+        ::std::fs::write(dir.path().join("notes/old"), "---\n[imag]\n---\nold").unwrap();
+
+        let mut findings = store.fsck().unwrap();
+        findings.sort_by(|a, b| a.id.cmp(&b.id));
+        let problems = findings
+            .iter()
+            .map(|f| (f.id.to_str().unwrap(), f.problem.clone()))
+            .map(|(id, p)| (id, if let Problem::Unparseable(_) = p { Problem::Unparseable(String::new()) } else { p }))
+            .collect::<Vec<_>>();
+        assert_eq!(problems, vec![
+            (String::from("elsewhere/fine"), Problem::UnknownCollection),
+            (String::from("notes/broken"), Problem::Unparseable(String::new())),
+            (String::from("notes/old"), Problem::MissingVersion),
+        ]);
+
+        assert!(store.repair(&findings[0]).is_err());
+        store.repair(&findings[1]).unwrap();
+        store.repair(&findings[2]).unwrap();
+        assert_eq!(store.fsck().unwrap().len(), 1);
+
+        let broken = store.get_copy(PathBuf::from("notes/broken")).unwrap();
+        assert_eq!(broken.get_content(), "---\n[imag\n---\ntext");
+        assert_eq!(store.get_copy(PathBuf::from("notes/old")).unwrap().get_content(), "old");
+    }
+
+    #[test]
+    fn test_schema() {
+        use toml::Value;
+        use toml_query::insert::TomlValueInsertExt;
+        use toml_query::read::TomlValueReadTypeExt;
+        use crate::fsck::Problem;
+        setup_logging();
+
+        let schema = |mode: &str| ::toml::de::from_str(&format!(r#"
+        [store.schema]
+            mode = "{}"
+
+        [store.schema.entries."todo/*"]
+            "todo.status" = {{ type = "string", values = [ "pending", "done" ] }}
+        "#, mode)).ok();
+
+        let store = Store::new_inmemory(PathBuf::from("/"), &schema("strict")).unwrap();
+        {
+            let mut entry = store.create(PathBuf::from("todo/a")).unwrap();
+            assert!(store.update(&mut entry).is_err());
+
+            let _ = entry.get_header_mut().insert("todo.status", Value::String(String::from("done"))).unwrap();
+            store.update(&mut entry).unwrap();
+
+            let _ = entry.get_header_mut().insert("todo.status", Value::String(String::from("dnoe"))).unwrap();
+            assert!(store.update(&mut entry).is_err());
+        }
+        assert_eq!(store.get_copy(PathBuf::from("todo/a")).unwrap().get_header().read_string("todo.status").unwrap(),
+                   Some(String::from("done")));
+
+        let store = Store::new_inmemory(PathBuf::from("/"), &schema("lax")).unwrap();
+        {
+            let mut entry = store.create(PathBuf::from("todo/a")).unwrap();
+            store.update(&mut entry).unwrap();
+        }
+        let findings = store.fsck().unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].problem, Problem::SchemaViolation(String::from("'todo.status' is missing")));
+
+        // Entries are checked when they are created, except for missing fields
+        let config = ::toml::de::from_str(r#"
+        [store.schema]
+            mode = "strict"
+
+        [store.schema.entries."versioned/*"]
+            "imag.version" = "integer"
+        "#).ok();
+        let store = Store::new_inmemory(PathBuf::from("/"), &config).unwrap();
+        for _ in 0..2 {
+            // The refused entry is forgotten, so the second attempt fails for the same reason
+            let e = store.create(PathBuf::from("versioned/a")).err().unwrap();
+            assert!(e.to_string().contains("does not match the schema"), "{}", e);
+        }
+        assert!(store.create(PathBuf::from("todo/a")).is_ok());
+    }
+
+    #[test]
+    fn test_history_moves_with_trash() {
+        use crate::storeid::StoreId;
+        setup_logging();
+
+        let store = get_store_with_trash(0);
+        let a     = StoreId::new(PathBuf::from("history/a")).unwrap();
+
+        set_content(&store, "history/a", "first");
+        set_content(&store, "history/a", "second");
+        store.delete(a.clone()).unwrap();
+        assert!(store.history(a.clone()).unwrap().is_empty());
+
+        store.trash_restore(a.clone()).unwrap();
+        assert_eq!(store.get_revision(a.clone(), 1).unwrap().get_content(), "first");
+
+        store.delete(a.clone()).unwrap();
+        let trashed = store.trash_list().unwrap().pop().unwrap();
+        assert_eq!(store.history(trashed.trash_id.clone()).unwrap().len(), 1);
+
+        let _ = store.trash_purge(None).unwrap();
+        assert!(store.history(trashed.trash_id).unwrap().is_empty());
+        assert!(store.history(a).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_attachments() {
+        use crate::fsck::Problem;
+        setup_logging();
+
+        let store = get_store_without_trash();
+        let hash  = {
+            let mut a = store.create(PathBuf::from("diary/a")).unwrap();
+            let mut b = store.create(PathBuf::from("todo/b")).unwrap();
+            let attachment = store.attach(&mut a, "receipt.pdf", "application/pdf", b"%PDF-1.4").unwrap();
+            let _          = store.attach(&mut b, "receipt.pdf", "application/pdf", b"%PDF-1.4").unwrap();
+            assert!(store.attach(&mut a, "receipt.pdf", "application/pdf", b"other").is_err());
+            store.update(&mut a).unwrap();
+            store.update(&mut b).unwrap();
+            attachment.hash
+        };
+        let path = crate::attachment::data_path(store.path(), &hash).unwrap();
+
+        let a = store.get_copy(PathBuf::from("diary/a")).unwrap();
+        let attachments = a.attachments().unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].size, 8);
+        assert_eq!(store.attachment_data(&attachments[0]).unwrap(), b"%PDF-1.4");
+
+        // the data is kept as long as an entry refers to it
+        {
+            let mut a = store.retrieve(PathBuf::from("diary/a")).unwrap();
+            let _     = store.detach(&mut a, "receipt.pdf").unwrap();
+            store.update(&mut a).unwrap();
+            assert!(a.attachments().unwrap().is_empty());
+        }
+        assert!(store.backend.read_bytes(&path).unwrap().is_some());
+
+        store.delete(PathBuf::from("todo/b")).unwrap();
+        assert!(store.backend.read_bytes(&path).unwrap().is_none());
+
+        // a missing attachment is found by fsck
+        {
+            let mut a = store.retrieve(PathBuf::from("diary/a")).unwrap();
+            let _     = store.attach(&mut a, "scan.png", "image/png", &[0x89, 0x50]).unwrap();
+            store.update(&mut a).unwrap();
+            let hash = a.attachments().unwrap()[0].hash.clone();
+            store.backend.remove_raw(&crate::attachment::data_path(store.path(), &hash).unwrap()).unwrap();
+        }
+        let findings = store.fsck().unwrap();
+        assert_eq!(findings.len(), 1);
+        assert!(is_match!(findings[0].problem, Problem::BrokenAttachment(_)));
+    }
+
+    #[test]
+    fn test_unwritten_attachments_are_kept() {
+        setup_logging();
+
+        let store = get_store_without_trash();
+        let data  = b"%PDF-1.4";
+        let path  = crate::attachment::data_path(store.path(), &crate::attachment::hash(data)).unwrap();
+        {
+            let mut a = store.create(PathBuf::from("diary/a")).unwrap();
+            let _     = store.attach(&mut a, "receipt.pdf", "application/pdf", data).unwrap();
+            store.update(&mut a).unwrap();
+        }
+
+        {
+            // b refers to the data, but is not written when a releases it
+            let mut b = store.create(PathBuf::from("todo/b")).unwrap();
+            let _     = store.attach(&mut b, "receipt.pdf", "application/pdf", data).unwrap();
+
+            let mut a = store.retrieve(PathBuf::from("diary/a")).unwrap();
+            let _     = store.detach(&mut a, "receipt.pdf").unwrap();
+            store.update(&mut a).unwrap();
+            assert!(store.backend.read_bytes(&path).unwrap().is_some());
+        }
+
+        store.delete(PathBuf::from("todo/b")).unwrap();
+        assert!(store.backend.read_bytes(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recent_attachments_are_kept() {
+        use tempdir::TempDir;
+        setup_logging();
+
+        let dir   = TempDir::new("imag-store-attachments").unwrap();
+        let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+        let hash  = {
+            let mut a = store.create(PathBuf::from("diary/a")).unwrap();
+            let attachment = store.attach(&mut a, "scan.png", "image/png", &[0x89, 0x50]).unwrap();
+            store.update(&mut a).unwrap();
+            let _ = store.detach(&mut a, "scan.png").unwrap();
+            store.update(&mut a).unwrap();
+            attachment.hash
+        };
+
+        // another process might be about to write an entry with this attachment
+        let path = crate::attachment::data_path(store.path(), &hash).unwrap();
+        assert!(store.backend.read_bytes(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_attachments_in_trash() {
+        setup_logging();
+
+        let store = get_store_with_trash(0);
+        let hash  = {
+            let mut entry  = store.create(PathBuf::from("diary/a")).unwrap();
+            let attachment = store.attach(&mut entry, "scan.png", "image/png", &[0x89, 0x50]).unwrap();
+            store.update(&mut entry).unwrap();
+            attachment.hash
+        };
+        let path = crate::attachment::data_path(store.path(), &hash).unwrap();
+
+        store.delete(PathBuf::from("diary/a")).unwrap();
+        assert!(store.backend.read_bytes(&path).unwrap().is_some());
+
+        let _ = store.trash_purge(None).unwrap();
+        assert!(store.backend.read_bytes(&path).unwrap().is_none());
+    }
+
 }
@@ -0,0 +1,282 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Journal for store transactions
+//!
+//! While a transaction is running (see `Store::transaction()`), the store records the state each
+//! path had _before_ it was touched for the first time. If the transaction fails, the journal is
+//! used to write the original state back through the backend, which is why this works for every
+//! `FileAbstraction` implementation.
+//!
+//! The journal is also written to the `.journal.json` file in the store before anything is changed,
+//! and removed when the transaction is finished. If the process dies in the middle of a
+//! transaction, the file is still there when the store is opened the next time, and the
+//! transaction is rolled back then (see `Journal::recover()`).
+//!
+//! The journal records the process id and host of its owner, and is only rolled back if that
+//! process is gone. While a transaction runs, the store holds the transaction lock (see the
+//! `lock` module), so the transactions of several processes do not write the journal at the same
+//! time.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+use serde_json::Value as JsonValue;
+
+use crate::store::Entry;
+use crate::storeid::StoreId;
+use crate::storeid::StoreIdWithBase;
+use crate::file_abstraction::FileAbstraction;
+use crate::lock::EntryLock;
+
+/// The state of one path before the transaction touched it
+#[derive(Debug)]
+struct JournalEntry {
+    id: StoreId,
+    path: PathBuf,
+
+    /// `None` if the file did not exist before the transaction
+    original: Option<Entry>,
+}
+
+/// The file the journal of the running transaction is kept in, relative to the store
+pub(crate) const JOURNAL_FILE : &str = ".journal.json";
+
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// Where the journal is persisted
+    file: PathBuf,
+
+    entries: Vec<JournalEntry>,
+
+    /// Raw files (like history files) and their content before the transaction
+    raw: Vec<(PathBuf, Option<String>)>,
+
+    /// The transaction lock, released when the journal is finished or rolled back
+    _lock: Option<EntryLock>,
+}
+
+impl Journal {
+
+    pub(crate) fn new(store_base: &PathBuf, lock: Option<EntryLock>) -> Journal {
+        Journal {
+            file: store_base.join(JOURNAL_FILE),
+            entries: vec![],
+            raw: vec![],
+            _lock: lock,
+        }
+    }
+
+    /// Roll back the transaction a crashed process left behind in the store, if there is one
+    ///
+    /// The journal of a process which still runs is left alone. Journals which do not record
+    /// their owner are rolled back.
+    ///
+    /// Returns the ids of the entries which were restored.
+    pub(crate) fn recover(store_base: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<Vec<StoreId>> {
+        let mut journal = Journal::new(store_base, None);
+        let persisted = match backend.read_raw(&journal.file)? {
+            None    => return Ok(vec![]),
+            Some(s) => ::serde_json::from_str::<JsonValue>(&s)
+                .context(format_err!("Cannot parse the transaction journal {}", journal.file.display()))?,
+        };
+
+        let host = persisted.get("host").and_then(JsonValue::as_str);
+        let pid  = persisted.get("pid").and_then(JsonValue::as_u64);
+        if let (Some(host), Some(pid)) = (host, pid) {
+            if !crate::lock::process_dead(host, pid as u32) {
+                debug!("Transaction of process {} on {} is still running", pid, host);
+                return Ok(vec![])
+            }
+        }
+
+        warn!("Found the journal of an unfinished transaction, rolling it back");
+        let original = |v: &JsonValue| v.get("original").and_then(JsonValue::as_str).map(String::from);
+        let path     = |v: &JsonValue, key: &str| v
+            .get(key)
+            .and_then(JsonValue::as_str)
+            .map(PathBuf::from)
+            .ok_or_else(|| format_err!("Invalid transaction journal, '{}' missing", key));
+
+        for je in persisted.get("entries").and_then(JsonValue::as_array).into_iter().flatten() {
+            let id   = StoreId::new(path(je, "id")?)?;
+            let path = id.clone().with_base(store_base).into_pathbuf()?;
+            let original = original(je).map(|s| Entry::from_str(id.clone(), &s)).transpose()?;
+            journal.entries.push(JournalEntry { id, path, original });
+        }
+
+        for raw in persisted.get("raw").and_then(JsonValue::as_array).into_iter().flatten() {
+            journal.raw.push((store_base.join(path(raw, "path")?), original(raw)));
+        }
+
+        let ids = journal.ids().cloned().collect();
+        journal.rollback(backend)?;
+        Ok(ids)
+    }
+
+    /// Write the journal to the journal file
+    fn persist(&self, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        let store_base = self.file.parent().map(PathBuf::from).unwrap_or_default();
+        let entries = self.entries
+            .iter()
+            .map(|je| Ok(json!({
+                "id": je.id.local_display_string(),
+                "original": je.original.as_ref().map(Entry::to_str).transpose()?,
+            })))
+            .collect::<Result<Vec<_>>>()?;
+        let raw = self.raw
+            .iter()
+            .map(|(path, original)| json!({
+                "path": path.strip_prefix(&store_base).unwrap_or(path).display().to_string(),
+                "original": original,
+            }))
+            .collect::<Vec<_>>();
+
+        let persisted = json!({
+            "pid": ::std::process::id(),
+            "host": crate::lock::hostname(),
+            "entries": entries,
+            "raw": raw,
+        });
+        backend
+            .write_raw(&self.file, &persisted.to_string())
+            .context("Cannot write the transaction journal")
+            .map_err(Error::from)
+    }
+
+    /// Remove the journal file, the transaction is finished
+    pub(crate) fn finish(self, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        remove_journal_file(&self.file, backend)
+    }
+
+    /// Record the current state of `id`, if it was not recorded already
+    ///
+    /// Only the first call for an id has an effect, as only the state from before the transaction
+    /// is of interest.
+    pub(crate) fn record(&mut self,
+                         store_base: &PathBuf,
+                         id: &StoreId,
+                         backend: &Arc<dyn FileAbstraction>)
+        -> Result<()>
+    {
+        if self.entries.iter().any(|je| je.id == *id) {
+            trace!("Already recorded in journal: {}", id);
+            return Ok(())
+        }
+
+        let path = id.clone().with_base(store_base).into_pathbuf()?;
+        let original = if backend.exists(&path)? {
+            let id_with_base = StoreIdWithBase::from_full_path(store_base, path.clone())?;
+            backend
+                .new_instance(path.clone())
+                .get_file_content(id_with_base)
+                .context(format_err!("Failed to record original state of {} in journal", id))?
+        } else {
+            None
+        };
+
+        debug!("Recording in journal: {} (existed: {})", id, original.is_some());
+        self.entries.push(JournalEntry { id: id.clone(), path, original });
+        self.persist(backend)
+    }
+
+    /// Record the current content of the raw file at `path`, if it was not recorded already
+    pub(crate) fn record_raw(&mut self, path: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        if self.raw.iter().any(|(p, _)| p == path) {
+            return Ok(())
+        }
+
+        let original = backend.read_raw(path)?;
+        debug!("Recording raw file in journal: {} (existed: {})", path.display(), original.is_some());
+        self.raw.push((path.clone(), original));
+        self.persist(backend)
+    }
+
+    /// The ids touched during the transaction
+    pub(crate) fn ids(&self) -> impl Iterator<Item = &StoreId> {
+        self.entries.iter().map(|je| &je.id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Restore the recorded state of all touched paths
+    ///
+    /// Restoring is done in reverse order of recording. All paths are tried, even if restoring one
+    /// of them fails, so that as much as possible is rolled back. The first error is returned.
+    /// The journal file is only removed if everything was restored.
+    pub(crate) fn rollback(self, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+        let mut first_error : Option<Error> = None;
+
+        for (path, original) in self.raw.into_iter().rev() {
+            debug!("Rolling back: {}", path.display());
+            let res = match original {
+                Some(content) => backend.write_raw(&path, &content),
+                None          => backend.read_raw(&path).and_then(|current| if current.is_some() {
+                    backend.remove_raw(&path)
+                } else {
+                    Ok(())
+                }),
+            };
+
+            if let Err(e) = res {
+                error!("Rolling back {} failed: {}", path.display(), e);
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+
+        for je in self.entries.into_iter().rev() {
+            debug!("Rolling back: {}", je.id);
+            let res = match je.original {
+                Some(entry) => backend.new_instance(je.path).write_file_content(&entry),
+                None        => backend.exists(&je.path).and_then(|exists| if exists {
+                    backend.remove_file(&je.path)
+                } else {
+                    Ok(())
+                }),
+            };
+
+            if let Err(e) = res {
+                error!("Rolling back {} failed: {}", je.id, e);
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+
+        match first_error {
+            None    => remove_journal_file(&self.file, backend),
+            Some(e) => Err(e),
+        }
+    }
+
+}
+
+fn remove_journal_file(file: &PathBuf, backend: &Arc<dyn FileAbstraction>) -> Result<()> {
+    if backend.read_raw(file)?.is_some() {
+        backend.remove_raw(file)?;
+    }
+    Ok(())
+}
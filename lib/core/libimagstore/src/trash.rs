@@ -0,0 +1,200 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! The trash of the store
+//!
+//! If `store.trash.enabled` is set, `Store::delete()` does not remove entries but moves them to
+//! `.trash/<time of deletion>/<id>`. As the directory is hidden, `Store::entries()` does not
+//! yield trashed entries. The id and the time of deletion are also put in the `trash` section of
+//! the header of the trashed entry.
+//!
+//! Trashed entries can be restored with `Store::trash_restore()`. Entries which are older than
+//! `store.trash.max-age` days are purged automatically.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use toml::Value;
+use toml_query::read::TomlValueReadTypeExt;
+use toml_query::insert::TomlValueInsertExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use crate::file_abstraction::FileAbstraction;
+use crate::store::Entry;
+use crate::storeid::StoreId;
+
+/// The reserved directory in the store where trashed entries live
+pub(crate) const TRASH_DIR : &str = ".trash";
+
+/// The format of the directory name for one deletion
+const KEY_FORMAT : &str = "%Y%m%dT%H%M%S%.9f";
+
+const DATETIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Clone)]
+pub(crate) struct TrashConfig {
+    pub(crate) enabled: bool,
+
+    /// Trashed entries older than this are purged automatically, `None` if they are kept forever
+    pub(crate) max_age: Option<Duration>,
+}
+
+impl Default for TrashConfig {
+    /// Deleted entries are moved to the trash and kept there forever
+    fn default() -> TrashConfig {
+        TrashConfig { enabled: true, max_age: None }
+    }
+}
+
+/// Get the trash settings from the `store.trash` section of the configuration
+pub(crate) fn config_trash(config: &Option<Value>) -> Result<TrashConfig> {
+    let config = match *config {
+        None           => return Ok(TrashConfig::default()),
+        Some(ref conf) => conf,
+    };
+
+    let enabled = config
+        .read_bool("store.trash.enabled")
+        .context(format_err!("Error reading 'store.trash.enabled' in configuration"))?
+        .unwrap_or(true);
+
+    let max_age = config
+        .read_int("store.trash.max-age")
+        .context(format_err!("Error reading 'store.trash.max-age' in configuration"))?
+        .map(|days| if days < 0 {
+            Err(format_err!("'store.trash.max-age' must not be negative"))
+        } else {
+            Ok(Duration::days(days))
+        })
+        .transpose()?
+        .filter(|age| age.num_days() > 0); // 0 means "keep forever"
+
+    Ok(TrashConfig { enabled, max_age })
+}
+
+/// An entry in the trash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedEntry {
+    /// The id the entry had before it was deleted
+    pub id: StoreId,
+
+    /// When the entry was deleted
+    pub deleted_at: NaiveDateTime,
+
+    /// Where the entry lives in the trash
+    pub(crate) trash_id: StoreId,
+}
+
+impl TrashedEntry {
+
+    /// Build the trash location for `id`, deleted at `now`
+    pub(crate) fn new(id: StoreId, now: NaiveDateTime) -> Result<TrashedEntry> {
+        let trash_id = StoreId::new(PathBuf::from(TRASH_DIR)
+                                    .join(now.format(KEY_FORMAT).to_string())
+                                    .join(id.local()))?;
+        Ok(TrashedEntry { id, deleted_at: now, trash_id })
+    }
+
+    /// Parse a path from the trash directory (relative to it) into a `TrashedEntry`
+    fn from_trash_path(path: PathBuf) -> Result<TrashedEntry> {
+        let mut components = path.components();
+        let key = components
+            .next()
+            .and_then(|c| c.as_os_str().to_str())
+            .ok_or_else(|| format_err!("Invalid path in trash: {}", path.display()))?;
+
+        let deleted_at = NaiveDateTime::parse_from_str(key, KEY_FORMAT)
+            .context(format_err!("Invalid path in trash: {}", path.display()))?;
+        let id       = StoreId::new(components.as_path().to_path_buf())?;
+        let trash_id = StoreId::new(PathBuf::from(TRASH_DIR).join(&path))?;
+
+        Ok(TrashedEntry { id, deleted_at, trash_id })
+    }
+
+    /// Put the deletion metadata into the header of the entry
+    pub(crate) fn mark(&self, entry: &mut Entry) -> Result<()> {
+        let header = entry.get_header_mut();
+        let _ = header.insert("trash.id", Value::String(self.id.to_str()?))?;
+        let _ = header.insert("trash.deleted", Value::String(self.deleted_at.format(DATETIME_FORMAT).to_string()))?;
+        Ok(())
+    }
+}
+
+/// Remove the deletion metadata from the header of the entry
+pub(crate) fn unmark(entry: &mut Entry) -> Result<()> {
+    if let Some(header) = entry.get_header_mut().as_table_mut() {
+        let _ = header.remove("trash");
+    }
+    Ok(())
+}
+
+/// List the trashed entries of the store at `store_base`, oldest first
+pub(crate) fn list(store_base: &Path, backend: &Arc<dyn FileAbstraction>) -> Result<Vec<TrashedEntry>> {
+    let trash_base  = store_base.join(TRASH_DIR);
+    let mut trashed = backend
+        .pathes_recursively(trash_base.clone(), &trash_base, backend.clone())?
+        .map(|id| id.and_then(|id| TrashedEntry::from_trash_path(id.without_base().local().clone())))
+        .collect::<Result<Vec<_>>>()
+        .context(format_err!("Failed to list trash"))
+        .map_err(Error::from)?;
+
+    trashed.sort_by(|a, b| a.deleted_at.cmp(&b.deleted_at).then_with(|| a.id.cmp(&b.id)));
+    Ok(trashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_trash_path_roundtrip() {
+        let now     = NaiveDate::from_ymd_opt(2019, 10, 17).unwrap().and_hms_nano_opt(12, 13, 14, 15).unwrap();
+        let id      = StoreId::new(PathBuf::from("notes/foo")).unwrap();
+        let trashed = TrashedEntry::new(id, now).unwrap();
+
+        assert_eq!(trashed.trash_id.local(), &PathBuf::from(".trash/20191017T121314.000000015/notes/foo"));
+
+        let parsed = TrashedEntry::from_trash_path(trashed.trash_id.local().strip_prefix(TRASH_DIR).unwrap().to_path_buf()).unwrap();
+        assert_eq!(parsed, trashed);
+    }
+
+    #[test]
+    fn test_config() {
+        let config = ::toml::de::from_str(r#"
+        [store.trash]
+            enabled = true
+            max-age = 30
+        "#).unwrap();
+        let config = config_trash(&Some(config)).unwrap();
+        assert!(config.enabled);
+        assert_eq!(config.max_age, Some(Duration::days(30)));
+
+        assert!(config_trash(&None).unwrap().enabled);
+        assert_eq!(config_trash(&None).unwrap().max_age, None);
+    }
+}
@@ -18,6 +18,7 @@
 //
 
 use std::fmt::Write;
+use std::io::BufRead;
 
 use toml::Value;
 use failure::Fallible as Result;
@@ -25,6 +26,8 @@ use failure::ResultExt;
 
 use libimagerror::errors::ErrorMsg as EM;
 
+use crate::header_format::HeaderFormat;
+
 #[cfg(feature = "early-panic")]
 #[macro_export]
 macro_rules! if_cfg_panic {
@@ -41,7 +44,8 @@ macro_rules! if_cfg_panic {
     ($fmt:expr, $($arg:tt)+) => { };
 }
 
-pub fn entry_buffer_to_header_content(buf: &str) -> Result<(Value, String)> {
+/// Split an entry into its header, the format of the header and its content
+pub fn entry_buffer_to_header_content(buf: &str) -> Result<(Value, HeaderFormat, String)> {
 
     debug!("Building entry from string");
     let mut header          = String::new();
@@ -63,8 +67,33 @@ pub fn entry_buffer_to_header_content(buf: &str) -> Result<(Value, String)> {
         }
     }
 
-    let h = ::toml::de::from_str(&header).context(EM::TomlDeserError)?;
-    Ok((h, content))
+    let (h, format) = HeaderFormat::detect_and_parse(&header)?;
+    Ok((h, format, content))
+}
+
+/// Read only the header of an entry
+///
+/// Returns the header, its format and the offset of the content in bytes. The reader is not
+/// consumed further than the end of the header.
+pub fn read_header<R: BufRead>(reader: &mut R) -> Result<(Value, HeaderFormat, u64)> {
+    debug!("Reading header of entry");
+    let mut header = String::new();
+    let mut line   = String::new();
+    let mut offset = reader.read_line(&mut line).context(EM::IO)? as u64; // the first line is "---"
+
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line).context(EM::IO)?;
+        offset += n as u64;
+
+        if n == 0 || line.trim_end_matches('\n') == "---" {
+            break;
+        }
+        header.push_str(&line);
+    }
+
+    let (h, format) = HeaderFormat::detect_and_parse(&header)?;
+    Ok((h, format, offset))
 }
 
 #[cfg(test)]
@@ -72,6 +101,7 @@ mod test {
     extern crate env_logger;
 
     use super::entry_buffer_to_header_content;
+    use super::read_header;
 
     fn setup_logging() {
         let _ = env_logger::try_init();
@@ -97,7 +127,7 @@ version = '{version}'
         let res = entry_buffer_to_header_content(&file);
 
         assert!(res.is_ok());
-        let (_, res_content) = res.unwrap();
+        let (_, _, res_content) = res.unwrap();
         assert_eq!(res_content, content)
     }
 
@@ -112,7 +142,7 @@ version = '{version}'
         let res  = entry_buffer_to_header_content(&file);
 
         assert!(res.is_ok());
-        let (_, res_content) = res.unwrap();
+        let (_, _, res_content) = res.unwrap();
         debug!("CONTENT: <<<{}>>>", res_content);
         assert_eq!(res_content, content)
     }
@@ -129,7 +159,7 @@ barbar
         let res  = entry_buffer_to_header_content(&file);
 
         assert!(res.is_ok());
-        let (_, res_content) = res.unwrap();
+        let (_, _, res_content) = res.unwrap();
         assert_eq!(res_content, content)
     }
 
@@ -147,7 +177,7 @@ barbar
         let res  = entry_buffer_to_header_content(&file);
 
         assert!(res.is_ok());
-        let (_, res_content) = res.unwrap();
+        let (_, _, res_content) = res.unwrap();
         assert_eq!(res_content, content)
     }
 
@@ -166,9 +196,31 @@ barbar
         let res  = entry_buffer_to_header_content(&file);
 
         assert!(res.is_ok());
-        let (_, res_content) = res.unwrap();
+        let (_, _, res_content) = res.unwrap();
         assert_eq!(res_content, content)
     }
 
+    #[test]
+    fn test_read_header() {
+        let content = "Hai\n---\nbarbar\n";
+        let file    = mkfile(content);
+
+        let (eager_header, _, _) = entry_buffer_to_header_content(&file).unwrap();
+        let (header, _, offset)  = read_header(&mut file.as_bytes()).unwrap();
+
+        assert_eq!(header, eager_header);
+        assert_eq!(&file[offset as usize..], content);
+    }
+
+    #[test]
+    fn test_read_header_without_content() {
+        let file = format!("---\n[imag]\nversion = '{}'\n---", env!("CARGO_PKG_VERSION"));
+
+        let (header, _, offset) = read_header(&mut file.as_bytes()).unwrap();
+
+        assert!(header.get("imag").is_some());
+        assert_eq!(offset as usize, file.len());
+    }
+
 }
 
@@ -98,6 +98,7 @@ impl<'a> EventStore<'a> for Store {
                     .make_ref(p.as_ref(), basepath_name.as_ref(), refconfig, force)?;
                 let _ = entry.get_header_mut().insert("calendar.event.uid", uid_header)?;
                 let _ = entry.set_isflag::<IsEvent>()?;
+                self.update(&mut entry)?;
                 Ok(entry)
             })
             .collect())
@@ -20,10 +20,28 @@
 use toml::Value;
 
 use libimagrt::runtime::Runtime;
+use libimagrt::schema::ConfigKey;
+use libimagrt::schema::ValueKind;
 
 use toml_query::read::TomlValueReadExt;
 use toml_query::read::TomlValueReadTypeExt;
 
+/// The settings read by libimagdiary and imag-diary
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "diary.default_diary",
+        kind: ValueKind::String,
+        required: false,
+        description: "The diary to use if none is specified",
+    },
+    ConfigKey {
+        path: "diary.diaries.*.timed",
+        kind: ValueKind::String,
+        required: false,
+        description: "Whether new entries in the diary are 'daily', 'hourly', 'minutely' or 'secondly'",
+    },
+];
+
 pub fn get_default_diary_name(rt: &Runtime) -> Option<String> {
     get_diary_config_section(rt)
         .and_then(|config| {
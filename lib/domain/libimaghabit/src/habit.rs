@@ -368,6 +368,9 @@ pub mod builder {
                 entry.get_header_mut().insert("habit.template.until", Value::String(until))?;
             }
 
+            // Write the header right away, so violations of the schema of the store are reported
+            store.update(&mut entry)?;
+
             debug!("Success: Created entry in store and set headers");
             Ok(entry)
         }
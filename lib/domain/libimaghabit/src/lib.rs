@@ -54,6 +54,7 @@ module_entry_path_mod!("habit");
 pub mod habit;
 pub mod instance;
 pub mod iter;
+pub mod migration;
 pub mod store;
 pub mod util;
 
@@ -0,0 +1,120 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+//! Migrations for the header layout of habits, see `libimagstore::migration`
+
+use libimagstore::store::Entry;
+use libimagstore::migration::Migration;
+use libimagentryutil::isa::Is;
+
+use failure::Fallible as Result;
+
+use crate::habit::IsHabitTemplate;
+use crate::instance::IsHabitInstance;
+use crate::util::IsHabitCheck;
+
+/// All migrations of this library
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(HabitKindFlags),
+    ]
+}
+
+/// Habits were introduced in 0.5.0, the kind flags (`habit.template.is_habit_template` and
+/// `habit.instance.is_habit_instance`) in 0.6.0. Habits from 0.5.x get them set.
+struct HabitKindFlags;
+
+impl Migration for HabitKindFlags {
+    fn name(&self) -> &'static str {
+        "libimaghabit: add kind flags to habit templates and instances"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.6.0"
+    }
+
+    fn migrate(&self, entry: &mut Entry) -> Result<bool> {
+        if entry.get_location().is_habit_template() && !entry.is::<IsHabitTemplate>()? {
+            debug!("Setting habit template flag on {}", entry.get_location());
+            entry.set_isflag::<IsHabitTemplate>()?;
+            Ok(true)
+        } else if entry.get_location().is_habit_instance() && !entry.is::<IsHabitInstance>()? {
+            debug!("Setting habit instance flag on {}", entry.get_location());
+            entry.set_isflag::<IsHabitInstance>()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml_query::delete::TomlValueDeleteExt;
+
+    use libimagstore::store::Entry;
+    use libimagstore::storeid::StoreId;
+    use libimagstore::migration::Migration;
+    use libimagstore::migration::MigrationRegistry;
+    use libimagentryutil::isa::Is;
+
+    use super::HabitKindFlags;
+    use crate::habit::IsHabitTemplate;
+    use crate::instance::IsHabitInstance;
+
+    fn entry(id: &str) -> Entry {
+        Entry::new(StoreId::new(PathBuf::from(id)).unwrap())
+    }
+
+    #[test]
+    fn test_old_habits_get_kind_flags() {
+        let mut template = entry("habit/template/exercise");
+        assert!(HabitKindFlags.migrate(&mut template).unwrap());
+        assert!(template.is::<IsHabitTemplate>().unwrap());
+        assert!(!HabitKindFlags.migrate(&mut template).unwrap());
+
+        let mut instance = entry("habit/instance/exercise-2019-01-01");
+        assert!(HabitKindFlags.migrate(&mut instance).unwrap());
+        assert!(instance.is::<IsHabitInstance>().unwrap());
+        assert!(!HabitKindFlags.migrate(&mut instance).unwrap());
+    }
+
+    #[test]
+    fn test_other_entries_are_not_touched() {
+        let mut other = entry("notes/habit");
+        assert!(!HabitKindFlags.migrate(&mut other).unwrap());
+        assert!(!other.is::<IsHabitTemplate>().unwrap());
+    }
+
+    #[test]
+    fn test_habit_without_version_is_migrated() {
+        let mut template = entry("habit/template/exercise");
+        let _ = template.get_header_mut().delete("imag.version").unwrap();
+
+        let mut registry = MigrationRegistry::default();
+        registry.register_all(super::migrations()).unwrap();
+
+        let migrated = registry.migrate_entry(&mut template).unwrap().unwrap();
+        assert_eq!(migrated.from.to_string(), "0.0.0");
+        assert_eq!(migrated.applied, vec![HabitKindFlags.name()]);
+        assert!(template.is::<IsHabitTemplate>().unwrap());
+    }
+}
@@ -19,6 +19,55 @@
 
 use std::path::PathBuf;
 
+use libimagrt::schema::ConfigKey;
+use libimagrt::schema::ValueKind;
+
+/// The settings read by libimagmail and imag-mail
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "mail.ref_collection_name",
+        kind: ValueKind::String,
+        required: true,
+        description: "The name of the ref collection (in 'ref.basepathes') mails are referenced in",
+    },
+    ConfigKey {
+        path: "mail.default_account",
+        kind: ValueKind::String,
+        required: false,
+        description: "The name of the account to use if none is specified",
+    },
+    ConfigKey {
+        path: "mail.accounts",
+        kind: ValueKind::Array,
+        required: false,
+        description: "The mail accounts, with name, outgoingbox, draftbox, sentbox and maildirroot each",
+    },
+    ConfigKey {
+        path: "mail.fetchcommand",
+        kind: ValueKind::Table,
+        required: false,
+        description: "The command to fetch mails with, as command, env and args",
+    },
+    ConfigKey {
+        path: "mail.postfetchcommand",
+        kind: ValueKind::Table,
+        required: false,
+        description: "The command to run after fetching mails, as command, env and args",
+    },
+    ConfigKey {
+        path: "mail.sendcommand",
+        kind: ValueKind::Table,
+        required: false,
+        description: "The command to send mails with, as command, env and args",
+    },
+    ConfigKey {
+        path: "mail.postsendcommand",
+        kind: ValueKind::Table,
+        required: false,
+        description: "The command to run after sending mails, as command, env and args",
+    },
+];
+
 /// A struct representing a full mail configuration, required for working with this library
 ///
 /// For convenience reasons, this implements Serialize and Deserialize, so it can be fetched from a
@@ -47,6 +47,7 @@ extern crate serde;
 #[macro_use] extern crate serde_derive;
 
 extern crate libimagerror;
+extern crate libimagrt;
 #[macro_use] extern crate libimagstore;
 extern crate libimagentryref;
 #[macro_use] extern crate libimagentryutil;
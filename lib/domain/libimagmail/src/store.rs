@@ -78,6 +78,7 @@ impl<'a> MailStore<'a> for Store {
             .get_header_mut()
             .insert("mail.message-id", Value::String(message_id))?;
 
+        self.update(&mut entry)?;
         Ok(entry)
     }
 
@@ -97,6 +97,7 @@ impl<'a> TimeTrackStore<'a> for Store {
             .and_then(|mut fle| {
                 fle.set_isflag::<IsTimeTracking>().map(|_| fle)
             })
+            .and_then(|mut fle| self.update(&mut fle).map(|_| fle))
     }
 
     fn create_timetracking(&'a self, start: &NDT, end: &NDT, ts: &TTT) -> Result<FileLockEntry<'a>> {
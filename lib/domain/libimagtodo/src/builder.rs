@@ -89,6 +89,9 @@ impl TodoBuilder {
         let _ = entry.get_header_mut().insert_serialized("todo", header)?;
         let _ = entry.set_isflag::<IsTodo>()?;
 
+        // Write the header right away, so violations of the schema of the store are reported
+        store.update(&mut entry)?;
+
         Ok(entry)
     }
 
@@ -37,6 +37,7 @@ extern crate libimagutil;
 pub mod builder;
 pub mod entry;
 pub mod iter;
+pub mod migration;
 pub mod priority;
 pub mod status;
 pub mod store;
@@ -0,0 +1,111 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+//! Migrations for the header layout of todos, see `libimagstore::migration`
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use toml_query::insert::TomlValueInsertExt;
+
+use libimagstore::store::Entry;
+use libimagstore::migration::Migration;
+use libimagentryutil::isa::Is;
+
+use failure::Fallible as Result;
+
+use crate::entry::IsTodo;
+use crate::status::Status;
+
+/// All migrations of this library
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(TodoStatusInStore),
+    ]
+}
+
+/// Before 0.10.0, todos were managed by taskwarrior and the store only held `todo.uuid`.
+/// Since then, the status lives in the store as well, and todos carry the `todo.is_todo` flag.
+///
+/// The status of old todos is not known to imag, so they are marked as pending.
+struct TodoStatusInStore;
+
+impl Migration for TodoStatusInStore {
+    fn name(&self) -> &'static str {
+        "libimagtodo: add status and kind flag to todos"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.10.0"
+    }
+
+    fn migrate(&self, entry: &mut Entry) -> Result<bool> {
+        if !entry.get_location().is_in_collection(&["todo"]) {
+            return Ok(false)
+        }
+
+        if entry.get_header().read("todo.uuid")?.is_none() || entry.get_header().read("todo.status")?.is_some() {
+            return Ok(false)
+        }
+
+        debug!("Adding status to {}", entry.get_location());
+        let status = Value::String(String::from(Status::Pending.as_str()));
+        let _ = entry.get_header_mut().insert("todo.status", status)?;
+        entry.set_isflag::<IsTodo>()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml::Value;
+    use toml_query::read::TomlValueReadTypeExt;
+    use toml_query::insert::TomlValueInsertExt;
+
+    use libimagstore::store::Entry;
+    use libimagstore::storeid::StoreId;
+    use libimagstore::migration::Migration;
+
+    use super::TodoStatusInStore;
+    use crate::entry::Todo;
+
+    #[test]
+    fn test_old_todo_gets_status() {
+        let id = StoreId::new(PathBuf::from("todo/1f1a9e83-3ad2-4ec1-9ab4-a6d1b1f1d6b1")).unwrap();
+        let mut entry = Entry::new(id);
+        let uuid = Value::String(String::from("1f1a9e83-3ad2-4ec1-9ab4-a6d1b1f1d6b1"));
+        let _ = entry.get_header_mut().insert("todo.uuid", uuid).unwrap();
+
+        assert!(TodoStatusInStore.migrate(&mut entry).unwrap());
+        assert!(entry.is_todo().unwrap());
+        assert_eq!(entry.get_header().read_string("todo.status").unwrap(), Some(String::from("pending")));
+
+        assert!(!TodoStatusInStore.migrate(&mut entry).unwrap());
+    }
+
+    #[test]
+    fn test_other_entries_are_not_touched() {
+        let id = StoreId::new(PathBuf::from("notes/todo")).unwrap();
+        let mut entry = Entry::new(id);
+        let uuid = Value::String(String::from("1f1a9e83-3ad2-4ec1-9ab4-a6d1b1f1d6b1"));
+        let _ = entry.get_header_mut().insert("todo.uuid", uuid).unwrap();
+
+        assert!(!TodoStatusInStore.migrate(&mut entry).unwrap());
+    }
+}
@@ -21,8 +21,6 @@ use libimagentryutil::isa::Is;
 use libimagentryutil::isa::IsKindHeaderPathProvider;
 use libimagstore::store::Entry;
 use libimagstore::store::Store;
-use libimagstore::storeid::StoreIdIterator;
-use libimagentrylink::linkable::Linkable;
 
 use toml_query::read::TomlValueReadTypeExt;
 
@@ -55,11 +53,13 @@ impl Category for Entry {
             .ok_or_else(|| err_msg("Category name missing"))
     }
 
+    /// Get the entries which are in this category
+    ///
+    /// The entries are looked up in the index of the store (`category.value` is always indexed).
     fn get_entries<'a>(&self, store: &'a Store) -> Result<CategoryEntryIterator<'a>> {
-        trace!("Getting linked entries for category '{:?}'", self.get_location());
-        let sit  = self.links()?.map(|l| l.get_store_id().clone()).map(Ok);
-        let sit  = StoreIdIterator::new(Box::new(sit));
+        trace!("Getting entries for category '{:?}'", self.get_location());
         let name = self.get_name()?;
+        let sit  = store.index_lookup("category.value", &name)?;
         Ok(CategoryEntryIterator::new(store, sit, name))
     }
 }
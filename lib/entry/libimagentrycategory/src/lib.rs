@@ -48,6 +48,7 @@ extern crate libimagerror;
 extern crate libimagutil;
 #[macro_use] extern crate libimagentryutil;
 extern crate libimagentrylink;
+#[cfg(test)] extern crate tempdir;
 
 pub mod category;
 pub mod entry;
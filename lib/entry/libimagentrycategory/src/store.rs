@@ -204,6 +204,70 @@ mod tests {
             None        => panic!("Header field not present"),
         }
     }
+
+    fn set_category(store: &Store, id: &str, category_name: &str) {
+        use crate::entry::EntryCategory;
+
+        let mut entry = store.retrieve(PathBuf::from(id)).unwrap();
+        entry.set_category_checked(store, category_name).unwrap();
+        store.update(&mut entry).unwrap();
+    }
+
+    fn category_entries(store: &Store, category_name: &str) -> Vec<String> {
+        use crate::category::Category;
+
+        let category = store.get_category_by_name(category_name).unwrap().unwrap();
+        category.get_entries(store)
+            .unwrap()
+            .map(|e| e.unwrap().get_location().to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_get_entries_without_index() {
+        let _ = env_logger::try_init();
+        let store = get_store();
+        let _ = store.create_category("examplecategory").unwrap();
+        let _ = store.create_category("othercategory").unwrap();
+
+        set_category(&store, "entry/a", "examplecategory");
+        set_category(&store, "entry/b", "othercategory");
+        set_category(&store, "entry/c", "examplecategory");
+
+        assert_eq!(category_entries(&store, "examplecategory"), vec!["entry/a", "entry/c"]);
+        assert_eq!(category_entries(&store, "othercategory"), vec!["entry/b"]);
+    }
+
+    #[test]
+    fn test_get_entries_with_stale_index() {
+        use tempdir::TempDir;
+        let _ = env_logger::try_init();
+
+        let dir = TempDir::new("imag-category").unwrap();
+
+        {
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            let _ = store.create_category("examplecategory").unwrap();
+            set_category(&store, "entry/a", "examplecategory");
+            assert_eq!(category_entries(&store, "examplecategory"), vec!["entry/a"]);
+
+            // another process changes the store in the meantime
+            {
+                let other = Store::new(dir.path().to_path_buf(), &None).unwrap();
+                set_category(&other, "entry/b", "examplecategory");
+            }
+
+            assert_eq!(category_entries(&store, "examplecategory"), vec!["entry/a", "entry/b"]);
+        }
+
+        {
+            // altered outside of imag, only visible after rebuilding the index
+            ::std::fs::remove_file(dir.path().join("entry/b")).unwrap();
+            let store = Store::new(dir.path().to_path_buf(), &None).unwrap();
+            store.rebuild_index().unwrap();
+            assert_eq!(category_entries(&store, "examplecategory"), vec!["entry/a"]);
+        }
+    }
 }
 
 #[inline]
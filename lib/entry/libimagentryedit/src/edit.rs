@@ -56,9 +56,10 @@ impl Edit for Entry {
 impl EditHeader for Entry {
 
     fn edit_header(&mut self, rt: &Runtime) -> Result<()> {
-        let mut header = ::toml::ser::to_string_pretty(self.get_header())?;
+        let format     = self.get_header_format();
+        let mut header = format.serialize(self.get_header())?;
         edit_in_tmpfile(rt, &mut header)?;
-        let header     = ::toml::de::from_str(&header)?;
+        let header     = format.parse(&header)?;
         *self.get_header_mut() = header;
         Ok(())
     }
@@ -0,0 +1,78 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
+use libimagstore::storeid::StoreIdIterator;
+
+use failure::Fallible as Result;
+
+/// Extension on the Store for finding the entries which link to an entry
+pub trait StoreBacklinksExt {
+
+    /// Get the ids of all entries which have an internal link to `id`
+    ///
+    /// This uses the index of the store, so no entry has to be read. As internal links are
+    /// bidirectional, the result is equal to the links of `id` itself if the store is consistent
+    /// (see `StoreLinkConsistentExt`).
+    fn entries_linking_to(&self, id: &StoreId) -> Result<StoreIdIterator>;
+}
+
+impl StoreBacklinksExt for Store {
+    fn entries_linking_to(&self, id: &StoreId) -> Result<StoreIdIterator> {
+        debug!("Looking up backlinks of {}", id);
+        self.index_lookup("links.internal", &id.to_str()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use libimagstore::store::Store;
+    use libimagstore::storeid::StoreId;
+
+    use super::StoreBacklinksExt;
+    use crate::linkable::Linkable;
+
+    fn setup_logging() {
+        let _ = ::env_logger::try_init();
+    }
+
+    #[test]
+    fn test_entries_linking_to() {
+        setup_logging();
+        let store = Store::new_inmemory(PathBuf::from("/"), &None).unwrap();
+
+        {
+            let mut a = store.create(PathBuf::from("a")).unwrap();
+            let mut b = store.create(PathBuf::from("b")).unwrap();
+            let mut c = store.create(PathBuf::from("c")).unwrap();
+            a.add_link(&mut c).unwrap();
+            b.add_link(&mut c).unwrap();
+        }
+
+        let c = StoreId::new(PathBuf::from("c")).unwrap();
+        let ids = store.entries_linking_to(&c)
+            .unwrap()
+            .map(|id| id.unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+}
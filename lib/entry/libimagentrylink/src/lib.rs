@@ -62,4 +62,6 @@ pub mod iter;
 pub mod linkable;
 pub mod link;
 pub mod storecheck;
+pub mod backlinks;
+pub mod migration;
 
@@ -23,6 +23,7 @@ use libimagstore::storeid::StoreId;
 use libimagstore::store::Entry;
 use libimagstore::store::Store;
 
+use toml::Value;
 use toml_query::read::Partial;
 use toml_query::read::TomlValueReadExt;
 use toml_query::insert::TomlValueInsertExt;
@@ -67,10 +68,10 @@ pub trait Linkable {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct LinkPartial {
-    internal: Option<Vec<String>>,
-    from: Option<Vec<String>>,
-    to: Option<Vec<String>>,
+pub(crate) struct LinkPartial {
+    pub(crate) internal: Option<Vec<String>>,
+    pub(crate) from: Option<Vec<String>>,
+    pub(crate) to: Option<Vec<String>>,
 }
 
 impl Default for LinkPartial {
@@ -278,9 +279,9 @@ impl Linkable for Entry {
 
     /// Check whether an entry is linked to another entry
     fn is_linked_to(&self, other: &Entry) -> Result<bool> {
-        let left_partial  = get_link_partial(self)?
+        let left_partial  = get_link_partial(self.get_header())?
             .ok_or_else(|| format_err!("Cannot read links from {}", self.get_location()))?;
-        let right_partial = get_link_partial(&other)?
+        let right_partial = get_link_partial(other.get_header())?
             .ok_or_else(|| format_err!("Cannot read links from {}", other.get_location()))?;
 
         let left_id       = self.get_location();
@@ -321,12 +322,12 @@ fn alter_linking<F>(left: &mut Entry, right: &mut Entry, f: F) -> Result<()>
     where F: FnOnce(LinkPartial, LinkPartial) -> Result<(LinkPartial, LinkPartial)>
 {
     debug!("Altering linkage of {:?} and {:?}", left, right);
-    let get_partial = |e| -> Result<_> {
-        Ok(get_link_partial(e)?.unwrap_or_else(LinkPartial::default))
+    let get_partial = |e: &Entry| -> Result<_> {
+        Ok(get_link_partial(e.get_header())?.unwrap_or_else(LinkPartial::default))
     };
 
-    let left_partial : LinkPartial = get_partial(&left)?;
-    let right_partial : LinkPartial = get_partial(&right)?;
+    let left_partial : LinkPartial = get_partial(left)?;
+    let right_partial : LinkPartial = get_partial(right)?;
 
     trace!("Partial left before: {:?}", left_partial);
     trace!("Partial right before: {:?}", right_partial);
@@ -343,9 +344,9 @@ fn alter_linking<F>(left: &mut Entry, right: &mut Entry, f: F) -> Result<()>
     Ok(())
 }
 
-fn get_link_partial(entry: &Entry) -> Result<Option<LinkPartial>> {
+pub(crate) fn get_link_partial(header: &Value) -> Result<Option<LinkPartial>> {
     use failure::Error;
-    entry.get_header().read_partial::<LinkPartial>().map_err(Error::from)
+    header.read_partial::<LinkPartial>().map_err(Error::from)
 }
 
 
@@ -0,0 +1,113 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+//! Migrations for the header layout of links, see `libimagstore::migration`
+
+use toml::Value;
+use toml_query::read::TomlValueReadExt;
+use toml_query::insert::TomlValueInsertExt;
+
+use libimagstore::store::Entry;
+use libimagstore::migration::Migration;
+
+use failure::Fallible as Result;
+
+/// All migrations of this library
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(LinksOutOfImagNamespace),
+    ]
+}
+
+/// Before 0.3.0, links were stored in `imag.links`, which is reserved for the store itself.
+/// They live in `links.internal` since then.
+struct LinksOutOfImagNamespace;
+
+impl Migration for LinksOutOfImagNamespace {
+    fn name(&self) -> &'static str {
+        "libimagentrylink: move links from 'imag.links' to 'links.internal'"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.3.0"
+    }
+
+    fn migrate(&self, entry: &mut Entry) -> Result<bool> {
+        // toml-query does not delete non-empty arrays, so we remove it from the table ourselves
+        let old = entry
+            .get_header_mut()
+            .get_mut("imag")
+            .and_then(Value::as_table_mut)
+            .and_then(|imag| imag.remove("links"));
+
+        let old = match old {
+            None                  => return Ok(false),
+            Some(Value::Array(a)) => a,
+            Some(other)           => return Err(format_err!("Expected array at 'imag.links' in {}, found: {:?}", entry.get_location(), other)),
+        };
+
+        let mut links = match entry.get_header().read("links.internal")? {
+            None                       => vec![],
+            Some(&Value::Array(ref a)) => a.clone(),
+            Some(other)                => return Err(format_err!("Expected array at 'links.internal' in {}, found: {:?}", entry.get_location(), other)),
+        };
+
+        for link in old {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+
+        debug!("Moving links of {} to 'links.internal'", entry.get_location());
+        let _ = entry.get_header_mut().insert("links.internal", Value::Array(links))?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml::Value;
+    use toml_query::read::TomlValueReadExt;
+    use toml_query::insert::TomlValueInsertExt;
+
+    use libimagstore::store::Entry;
+    use libimagstore::storeid::StoreId;
+    use libimagstore::migration::Migration;
+
+    use super::LinksOutOfImagNamespace;
+
+    #[test]
+    fn test_links_are_moved_out_of_imag_namespace() {
+        let mut entry = Entry::new(StoreId::new(PathBuf::from("a")).unwrap());
+        let old = Value::Array(vec![Value::String(String::from("b")), Value::String(String::from("c"))]);
+        let _ = entry.get_header_mut().insert("imag.links", old).unwrap();
+        let new = Value::Array(vec![Value::String(String::from("c"))]);
+        let _ = entry.get_header_mut().insert("links.internal", new).unwrap();
+
+        assert!(LinksOutOfImagNamespace.migrate(&mut entry).unwrap());
+        assert!(entry.get_header().read("imag.links").unwrap().is_none());
+
+        let expected = Value::Array(vec![Value::String(String::from("c")), Value::String(String::from("b"))]);
+        assert_eq!(entry.get_header().read("links.internal").unwrap(), Some(&expected));
+
+        // nothing left to do
+        assert!(!LinksOutOfImagNamespace.migrate(&mut entry).unwrap());
+    }
+}
@@ -17,12 +17,19 @@
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
 //
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::path::PathBuf;
 
+use libimagstore::store::Entry;
 use libimagstore::store::Store;
 use libimagstore::storeid::StoreId;
 use libimagutil::debug_result::DebugResult;
 
+use toml_query::insert::TomlValueInsertExt;
 use failure::ResultExt;
 use failure::Fallible as Result;
 use failure::Error;
@@ -30,8 +37,102 @@ use failure::err_msg;
 
 use crate::linkable::*;
 
+/// The list in the `links` section of the header a link is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// `links.internal`, the target links back in `links.internal`
+    Internal,
+
+    /// `links.to`, the target links back in `links.from`
+    To,
+
+    /// `links.from`, the target links back in `links.to`
+    From,
+}
+
+impl LinkKind {
+
+    /// The kind of link the target of a link of this kind has to have back
+    pub fn reverse(self) -> LinkKind {
+        match self {
+            LinkKind::Internal => LinkKind::Internal,
+            LinkKind::To       => LinkKind::From,
+            LinkKind::From     => LinkKind::To,
+        }
+    }
+
+    fn list(self, partial: &LinkPartial) -> Option<&Vec<String>> {
+        match self {
+            LinkKind::Internal => partial.internal.as_ref(),
+            LinkKind::To       => partial.to.as_ref(),
+            LinkKind::From     => partial.from.as_ref(),
+        }
+    }
+
+    fn list_mut(self, partial: &mut LinkPartial) -> &mut Vec<String> {
+        match self {
+            LinkKind::Internal => partial.internal.get_or_insert_with(Vec::new),
+            LinkKind::To       => partial.to.get_or_insert_with(Vec::new),
+            LinkKind::From     => partial.from.get_or_insert_with(Vec::new),
+        }
+    }
+}
+
+impl Display for LinkKind {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            LinkKind::Internal => write!(fmt, "links.internal"),
+            LinkKind::To       => write!(fmt, "links.to"),
+            LinkKind::From     => write!(fmt, "links.from"),
+        }
+    }
+}
+
+/// A broken link, found by `StoreLinkConsistentExt::link_problems()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkProblem {
+    /// `source` links to `target`, which does not exist
+    Dangling { source: StoreId, target: StoreId, kind: LinkKind },
+
+    /// `source` links to `target`, but `target` does not link back
+    OneDirectional { source: StoreId, target: StoreId, kind: LinkKind },
+}
+
+impl LinkProblem {
+
+    /// The entry which has the broken link
+    pub fn source(&self) -> &StoreId {
+        match *self {
+            LinkProblem::Dangling { ref source, .. }       => source,
+            LinkProblem::OneDirectional { ref source, .. } => source,
+        }
+    }
+}
+
+impl Display for LinkProblem {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            LinkProblem::Dangling { ref target, kind, .. } =>
+                write!(fmt, "Dead link in {} to {}", kind, target),
+            LinkProblem::OneDirectional { ref target, kind, .. } =>
+                write!(fmt, "Link in {} to {} which does not link back in {}", kind, target, kind.reverse()),
+        }
+    }
+}
+
 pub trait StoreLinkConsistentExt {
     fn check_link_consistency(&self) -> Result<()>;
+
+    /// Find all dangling and one-directional links in the store
+    ///
+    /// Entries which cannot be read are skipped, `Store::fsck()` reports them.
+    fn link_problems(&self) -> Result<Vec<LinkProblem>>;
+
+    /// Repair a problem found by `link_problems()`
+    ///
+    /// A dangling link is removed, the missing link back is added to the target of a
+    /// one-directional link.
+    fn repair_link_problem(&self, problem: &LinkProblem) -> Result<()>;
 }
 
 impl StoreLinkConsistentExt for Store {
@@ -169,5 +270,134 @@ impl StoreLinkConsistentExt for Store {
             })
             .map(|_| ())
     }
+
+    fn link_problems(&self) -> Result<Vec<LinkProblem>> {
+        let mut network : BTreeMap<StoreId, LinkPartial> = BTreeMap::new();
+        for id in self.entries()?.into_storeid_iter() {
+            let id = id?;
+            let partial = self
+                .get_header_only(id.clone())
+                .and_then(|entry| entry.map(|e| get_link_partial(e.get_header())).transpose());
+
+            match partial {
+                Ok(partial) => {
+                    let _ = network.insert(id, partial.flatten().unwrap_or_else(LinkPartial::default));
+                },
+                Err(e) => warn!("Not checking links of {}: {}", id, e),
+            }
+        }
+
+        let parse = |links: Option<&Vec<String>>| -> Result<Vec<StoreId>> {
+            links.into_iter().flatten().map(|l| StoreId::new(PathBuf::from(l))).collect()
+        };
+
+        let mut problems = vec![];
+        for (source, partial) in network.iter() {
+            for kind in [LinkKind::Internal, LinkKind::To, LinkKind::From].iter().cloned() {
+                for target in parse(kind.list(partial))? {
+                    let problem = match network.get(&target) {
+                        Some(target_partial) => if parse(kind.reverse().list(target_partial))?.contains(source) {
+                            continue
+                        } else {
+                            LinkProblem::OneDirectional { source: source.clone(), target, kind }
+                        },
+                        None => if self.exists(target.clone())? {
+                            continue // not readable, we warned above
+                        } else {
+                            LinkProblem::Dangling { source: source.clone(), target, kind }
+                        },
+                    };
+
+                    debug!("Link problem in {}: {}", source, problem);
+                    problems.push(problem);
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    fn repair_link_problem(&self, problem: &LinkProblem) -> Result<()> {
+        match *problem {
+            LinkProblem::Dangling { ref source, ref target, kind } => {
+                let mut entry = self.get(source.clone())?
+                    .ok_or_else(|| format_err!("Entry does not exist: {}", source))?;
+                alter_link_list(&mut entry, kind, |links| {
+                    links.retain(|l| StoreId::new(PathBuf::from(l)).map(|id| id != *target).unwrap_or(true))
+                })?;
+                self.update(&mut entry)
+            },
+
+            LinkProblem::OneDirectional { ref source, ref target, kind } => {
+                let source_str = source.to_str()?;
+                let mut entry  = self.get(target.clone())?
+                    .ok_or_else(|| format_err!("Entry does not exist: {}", target))?;
+                alter_link_list(&mut entry, kind.reverse(), |links| {
+                    links.push(source_str);
+                    links.sort_unstable();
+                    links.dedup();
+                })?;
+                self.update(&mut entry)
+            },
+        }
+    }
+}
+
+/// Alter the list of links of `kind` in the header of `entry`
+fn alter_link_list<F>(entry: &mut Entry, kind: LinkKind, f: F) -> Result<()>
+    where F: FnOnce(&mut Vec<String>)
+{
+    let mut partial = get_link_partial(entry.get_header())?.unwrap_or_else(LinkPartial::default);
+    f(kind.list_mut(&mut partial));
+    entry.get_header_mut().insert_serialized("links", partial)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use libimagstore::store::Store;
+    use libimagstore::storeid::StoreId;
+    use toml::Value;
+    use toml_query::insert::TomlValueInsertExt;
+
+    use super::*;
+    use crate::linkable::Linkable;
+
+    #[test]
+    fn test_link_problems() {
+        let _ = ::env_logger::try_init();
+        let store = Store::new_inmemory(PathBuf::from("/"), &None).unwrap();
+
+        {
+            let mut a = store.create(PathBuf::from("a")).unwrap();
+            let mut b = store.create(PathBuf::from("b")).unwrap();
+            let mut c = store.create(PathBuf::from("c")).unwrap();
+            a.add_link(&mut b).unwrap();
+            a.add_link_to(&mut c).unwrap();
+
+            let _ = b.get_header_mut().insert("links.internal", Value::Array(vec![])).unwrap();
+            let dead = Value::Array(vec![Value::String(String::from("a")), Value::String(String::from("gone"))]);
+            let _ = c.get_header_mut().insert("links.from", dead).unwrap();
+        }
+
+        assert!(store.check_link_consistency().is_err());
+
+        let id = |s: &str| StoreId::new(PathBuf::from(s)).unwrap();
+        let problems = store.link_problems().unwrap();
+        assert_eq!(problems, vec![
+            LinkProblem::OneDirectional { source: id("a"), target: id("b"), kind: LinkKind::Internal },
+            LinkProblem::Dangling { source: id("c"), target: id("gone"), kind: LinkKind::From },
+        ]);
+
+        for problem in problems.iter() {
+            store.repair_link_problem(problem).unwrap();
+        }
+
+        assert!(store.link_problems().unwrap().is_empty());
+        let b = store.get(PathBuf::from("b")).unwrap().unwrap();
+        assert!(b.links().unwrap().any(|l| *l.get_store_id() == id("a")));
+    }
 }
 
@@ -25,6 +25,8 @@ use std::ops::Deref;
 use libimagentryutil::isa::Is;
 use libimagentryutil::isa::IsKindHeaderPathProvider;
 use libimagerror::errors::ErrorMsg as EM;
+use libimagrt::schema::ConfigKey;
+use libimagrt::schema::ValueKind;
 
 use toml::Value;
 use toml::map::Map;
@@ -74,6 +76,22 @@ impl<'a> Partial<'a> for Config {
     type Output = Self;
 }
 
+/// The settings read by libimagentryref
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "ref.basepathes",
+        kind: ValueKind::Table,
+        required: true,
+        description: "The collections of referenced files, as 'name = path' pairs",
+    },
+    ConfigKey {
+        path: "ref.basepathes.*",
+        kind: ValueKind::String,
+        required: false,
+        description: "The path of a collection of referenced files",
+    },
+];
+
 provide_kindflag_path!(pub IsRef, "ref.is_ref");
 
 /// Fassade module
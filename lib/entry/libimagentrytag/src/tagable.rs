@@ -18,6 +18,7 @@
 //
 
 use itertools::Itertools;
+use toml::Value;
 
 use libimagstore::store::Entry;
 use libimagerror::errors::ErrorMsg as EM;
@@ -54,20 +55,25 @@ impl<'a> Partial<'a> for TagHeader {
     type Output                  = Self;
 }
 
+/// Read the tags from an entry header, for entries read with `Store::get_header_only()`
+pub fn header_tags(header: &Value) -> Result<Vec<Tag>> {
+    header
+        .read_partial::<TagHeader>()?
+        .map(|header| {
+            header.values
+                .iter()
+                .map(|val| is_tag_str(val))
+                .collect::<Result<_>>()?;
+
+            Ok(header.values)
+        })
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
 impl Tagable for Entry {
 
     fn get_tags(&self) -> Result<Vec<Tag>> {
-        self.get_header()
-            .read_partial::<TagHeader>()?
-            .map(|header| {
-                header.values
-                    .iter()
-                    .map(|val| is_tag_str(val))
-                    .collect::<Result<_>>()?;
-
-                Ok(header.values)
-            })
-            .unwrap_or_else(|| Ok(vec![]))
+        header_tags(self.get_header())
     }
 
     fn set_tags(&mut self, ts: &[Tag]) -> Result<()> {
@@ -0,0 +1,71 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Two-way synchronization of stores
+//!
+//! `sync::sync()` synchronizes two stores, for example a store on a laptop with a copy on a USB
+//! drive. For each entry, the `state::SyncState` records a hash and the header of the entry after
+//! the last synchronization, so it is known which side changed the entry since then:
+//!
+//! * Entries which changed on one side only are copied to (or deleted on) the other side.
+//! * Entries which changed on both sides are merged: the headers with a three-way merge (see the
+//!   `merge` module), the content if only one side changed it.
+//! * If that is not possible, the version of the other store is put into a sidecar entry next to
+//!   the entry, `<id>.sync-conflict-<time>`, and the local version is kept on both sides.
+//!
+//! The state is kept in the local store, so each store which is synchronized with another one has
+//! its own state for it.
+
+#![forbid(unsafe_code)]
+
+#![deny(
+    dead_code,
+    non_camel_case_types,
+    non_snake_case,
+    path_statements,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_allocation,
+    unused_import_braces,
+    unused_imports,
+    unused_must_use,
+    unused_mut,
+    unused_qualifications,
+    while_true,
+)]
+
+#[macro_use] extern crate log;
+extern crate toml;
+extern crate toml_query;
+extern crate chrono;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate sha1;
+extern crate hex;
+#[macro_use] extern crate is_match;
+#[macro_use] extern crate failure;
+
+#[cfg(test)] extern crate tempdir;
+
+extern crate libimagstore;
+
+pub mod merge;
+pub mod state;
+pub mod sync;
@@ -0,0 +1,164 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Three-way merge of entry headers
+//!
+//! Values which were changed on one side only take the changed value. Tables are merged key by
+//! key. Arrays are merged as sets: elements added on either side are added, elements removed on
+//! either side are removed. This is what imag uses arrays for, `links.internal` or `tag.values`
+//! for example, so two sides linking the same entry to different entries is not a conflict.
+
+use toml::Value;
+use toml::map::Map;
+
+/// The result of a three-way merge
+#[derive(Debug, Clone, PartialEq)]
+pub enum Merge {
+    /// The merged value
+    Merged(Value),
+
+    /// The paths of the values which were changed differently on both sides
+    Conflict(Vec<String>),
+}
+
+/// Merge the headers `local` and `remote`, which were both changed from `base`
+///
+/// `base` is `None` if there is no common ancestor, for example because the entry was created on
+/// both sides independently.
+pub fn merge_headers(base: Option<&Value>, local: &Value, remote: &Value) -> Merge {
+    let mut conflicts = vec![];
+    let merged = merge(String::new(), base, Some(local), Some(remote), &mut conflicts);
+
+    if conflicts.is_empty() {
+        Merge::Merged(merged.unwrap_or_else(|| Value::Table(Map::new())))
+    } else {
+        Merge::Conflict(conflicts)
+    }
+}
+
+fn merge(path: String,
+         base: Option<&Value>,
+         local: Option<&Value>,
+         remote: Option<&Value>,
+         conflicts: &mut Vec<String>)
+    -> Option<Value>
+{
+    if local == remote || remote == base {
+        return local.cloned()
+    }
+    if local == base {
+        return remote.cloned()
+    }
+
+    match (base, local, remote) {
+        (None, Some(&Value::Table(ref l)), Some(&Value::Table(ref r))) |
+        (Some(&Value::Table(_)), Some(&Value::Table(ref l)), Some(&Value::Table(ref r))) => {
+            let empty = Map::new();
+            let b     = base.and_then(Value::as_table).unwrap_or(&empty);
+
+            let mut keys = l.keys().chain(r.keys()).chain(b.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+
+            let merged = keys
+                .into_iter()
+                .filter_map(|key| {
+                    let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    merge(path, b.get(key), l.get(key), r.get(key), conflicts).map(|v| (key.clone(), v))
+                })
+                .collect();
+
+            Some(Value::Table(merged))
+        },
+
+        (None, Some(&Value::Array(ref l)), Some(&Value::Array(ref r))) |
+        (Some(&Value::Array(_)), Some(&Value::Array(ref l)), Some(&Value::Array(ref r))) => {
+            let empty = vec![];
+            let b     = base.and_then(Value::as_array).unwrap_or(&empty);
+            let kept  = |v: &&Value| !b.contains(v) || (l.contains(v) && r.contains(v));
+
+            let mut merged : Vec<Value> = vec![];
+            for value in l.iter().chain(r.iter()).filter(kept) {
+                if !merged.contains(value) {
+                    merged.push(value.clone());
+                }
+            }
+
+            Some(Value::Array(merged))
+        },
+
+        _ => {
+            conflicts.push(path);
+            local.cloned()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(s: &str) -> Value {
+        ::toml::de::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_one_side_changed() {
+        let base   = header("[imag]\nversion = \"0.10.0\"\n[todo]\nstatus = \"pending\"");
+        let local  = header("[imag]\nversion = \"0.10.0\"\n[todo]\nstatus = \"done\"");
+        let remote = header("[imag]\nversion = \"0.10.0\"\n[todo]\nstatus = \"pending\"\n[tag]\nvalues = [\"a\"]");
+
+        let merged = header("[imag]\nversion = \"0.10.0\"\n[todo]\nstatus = \"done\"\n[tag]\nvalues = [\"a\"]");
+        assert_eq!(merge_headers(Some(&base), &local, &remote), Merge::Merged(merged));
+    }
+
+    #[test]
+    fn test_arrays_merge_as_sets() {
+        let base   = header("[links]\ninternal = [\"a\", \"b\", \"c\"]");
+        let local  = header("[links]\ninternal = [\"a\", \"c\", \"d\"]");
+        let remote = header("[links]\ninternal = [\"e\", \"a\", \"b\"]");
+
+        let merged = header("[links]\ninternal = [\"a\", \"d\", \"e\"]");
+        assert_eq!(merge_headers(Some(&base), &local, &remote), Merge::Merged(merged));
+
+        // without a common ancestor, nothing is removed
+        let merged = header("[links]\ninternal = [\"a\", \"c\", \"d\", \"e\", \"b\"]");
+        assert_eq!(merge_headers(None, &local, &remote), Merge::Merged(merged));
+    }
+
+    #[test]
+    fn test_removed_on_one_side() {
+        let base   = header("[todo]\nstatus = \"pending\"\ndue = \"2019-10-17\"");
+        let local  = header("[todo]\nstatus = \"pending\"");
+        let remote = header("[todo]\nstatus = \"done\"\ndue = \"2019-10-17\"");
+
+        let merged = header("[todo]\nstatus = \"done\"");
+        assert_eq!(merge_headers(Some(&base), &local, &remote), Merge::Merged(merged));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let base   = header("[todo]\nstatus = \"pending\"\npriority = 1");
+        let local  = header("[todo]\nstatus = \"done\"\npriority = 2");
+        let remote = header("[todo]\nstatus = \"deleted\"\npriority = 2");
+
+        let conflicts = vec![String::from("todo.status")];
+        assert_eq!(merge_headers(Some(&base), &local, &remote), Merge::Conflict(conflicts));
+    }
+}
@@ -0,0 +1,186 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! The state of the entries after the last synchronization
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use sha1::{Sha1, Digest};
+use toml::Value;
+use failure::Fallible as Result;
+use failure::ResultExt;
+use failure::Error;
+
+use libimagstore::store::Entry;
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
+
+/// The directory in the store where the states are kept
+pub const SYNC_DIR : &str = ".sync";
+
+/// The state of one entry after the last synchronization
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryState {
+    /// The hash of the header and the content
+    pub hash: String,
+
+    /// The hash of the content only
+    pub content_hash: String,
+
+    /// The header, as TOML, which is the common ancestor for merging the headers
+    pub header: String,
+}
+
+impl EntryState {
+
+    pub fn of(entry: &Entry) -> Result<EntryState> {
+        let header = ::toml::ser::to_string(entry.get_header())
+            .context(format_err!("Failed to serialize header of {}", entry.get_location()))?;
+        let content = entry.get_content();
+
+        Ok(EntryState {
+            hash: hash(&[header.as_str(), content]),
+            content_hash: hash(&[content]),
+            header,
+        })
+    }
+
+    /// The header as a TOML value
+    pub fn header(&self) -> Result<Value> {
+        ::toml::de::from_str(&self.header)
+            .context(format_err!("Failed to parse header in sync state"))
+            .map_err(Error::from)
+    }
+}
+
+fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.input(part.as_bytes());
+        hasher.input(b"\0");
+    }
+    hex::encode(hasher.result())
+}
+
+/// The state of all entries after the last synchronization with one other store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    entries: BTreeMap<String, EntryState>,
+}
+
+impl SyncState {
+
+    /// Where the state of the synchronization named `name` of `store` is kept
+    pub fn path(store: &Store, name: &str) -> PathBuf {
+        store.path().join(SYNC_DIR).join(format!("{}.json", name))
+    }
+
+    /// Load the state from `path`, an empty state if it does not exist
+    pub fn load(path: &Path) -> Result<SyncState> {
+        if !path.exists() {
+            debug!("No sync state at {}", path.display());
+            return Ok(SyncState::default())
+        }
+
+        let s = ::std::fs::read_to_string(path)
+            .context(format_err!("Failed to read sync state: {}", path.display()))?;
+        ::serde_json::from_str(&s)
+            .context(format_err!("Failed to parse sync state: {}", path.display()))
+            .map_err(Error::from)
+    }
+
+    /// Save the state to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            ::std::fs::create_dir_all(parent)
+                .context(format_err!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let s       = ::serde_json::to_string(self)?;
+        let staging = path.with_extension("json.staging");
+        ::std::fs::write(&staging, s)
+            .context(format_err!("Failed to write sync state: {}", staging.display()))?;
+        ::std::fs::rename(&staging, path)
+            .context(format_err!("Failed to write sync state: {}", path.display()))
+            .map_err(Error::from)
+    }
+
+    pub fn get(&self, id: &StoreId) -> Result<Option<&EntryState>> {
+        Ok(self.entries.get(&id.to_str()?))
+    }
+
+    /// Set the state of `id`, `None` if the entry does not exist on both sides
+    pub fn set(&mut self, id: &StoreId, state: Option<EntryState>) -> Result<()> {
+        let key = id.to_str()?;
+        match state {
+            Some(state) => { let _ = self.entries.insert(key, state); },
+            None        => { let _ = self.entries.remove(&key); },
+        }
+        Ok(())
+    }
+
+    /// The ids of all entries with a state
+    pub fn ids(&self) -> Result<Vec<StoreId>> {
+        self.entries.keys().map(|id| StoreId::new(PathBuf::from(id))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let dir   = TempDir::new("imag-sync-state").unwrap();
+        let path  = dir.path().join(SYNC_DIR).join("usb.json");
+        let id    = StoreId::new(PathBuf::from("notes/foo")).unwrap();
+        let mut entry = Entry::new(id.clone());
+        entry.set_content(String::from("foo"));
+
+        assert_eq!(SyncState::load(&path).unwrap(), SyncState::default());
+
+        let mut state = SyncState::default();
+        state.set(&id, Some(EntryState::of(&entry).unwrap())).unwrap();
+        state.save(&path).unwrap();
+
+        let loaded = SyncState::load(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.get(&id).unwrap().unwrap().header().unwrap(), *entry.get_header());
+        assert_eq!(loaded.ids().unwrap(), vec![id]);
+    }
+
+    #[test]
+    fn test_hash_changes() {
+        let id    = StoreId::new(PathBuf::from("notes/foo")).unwrap();
+        let mut entry = Entry::new(id);
+        let before = EntryState::of(&entry).unwrap();
+
+        entry.set_content(String::from("changed"));
+        let after = EntryState::of(&entry).unwrap();
+        assert_ne!(before.hash, after.hash);
+        assert_ne!(before.content_hash, after.content_hash);
+        assert_eq!(before.header, after.header);
+    }
+}
@@ -0,0 +1,441 @@
+//
+// imag - the personal information management suite for the commandline
+// Copyright (C) 2015-2019 Matthias Beyer <mail@beyermatthias.de> and contributors
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; version
+// 2.1 of the License.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License along with this library; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301  USA
+//
+
+//! Synchronization of two stores
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::path::PathBuf;
+
+use chrono::Local;
+use toml::Value;
+use toml_query::insert::TomlValueInsertExt;
+use failure::Fallible as Result;
+use failure::ResultExt;
+
+use libimagstore::store::Entry;
+use libimagstore::store::Store;
+use libimagstore::storeid::StoreId;
+
+use crate::merge::Merge;
+use crate::merge::merge_headers;
+use crate::state::EntryState;
+use crate::state::SyncState;
+
+/// A change made by `sync()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The entry was created or changed in the other store and copied into the local store
+    ToLocal(StoreId),
+
+    /// The entry was created or changed in the local store and copied into the other store
+    ToRemote(StoreId),
+
+    /// The entry was deleted in the other store and is deleted in the local store
+    DeletedLocal(StoreId),
+
+    /// The entry was deleted in the local store and is deleted in the other store
+    DeletedRemote(StoreId),
+
+    /// The entry was changed in both stores and the changes were merged
+    Merged(StoreId),
+
+    /// The entry was deleted in one store but changed in the other, so it was restored
+    Restored(StoreId),
+
+    /// The entry was changed in both stores in ways which cannot be merged
+    ///
+    /// The local version is kept in both stores, the version of the other store is in `sidecar`.
+    Conflict {
+        id: StoreId,
+        sidecar: StoreId,
+        reason: String,
+    },
+}
+
+impl Change {
+    pub fn id(&self) -> &StoreId {
+        match *self {
+            Change::ToLocal(ref id)          |
+            Change::ToRemote(ref id)         |
+            Change::DeletedLocal(ref id)     |
+            Change::DeletedRemote(ref id)    |
+            Change::Merged(ref id)           |
+            Change::Restored(ref id)         |
+            Change::Conflict { ref id, .. } => id,
+        }
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        is_match!(*self, Change::Conflict { .. })
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Change::ToLocal(ref id)       => write!(fmt, "{}: copied from the other store", id),
+            Change::ToRemote(ref id)      => write!(fmt, "{}: copied to the other store", id),
+            Change::DeletedLocal(ref id)  => write!(fmt, "{}: deleted, it was deleted in the other store", id),
+            Change::DeletedRemote(ref id) => write!(fmt, "{}: deleted in the other store", id),
+            Change::Merged(ref id)        => write!(fmt, "{}: merged the changes of both stores", id),
+            Change::Restored(ref id)      => write!(fmt, "{}: restored, it was deleted in one store but changed in the other", id),
+            Change::Conflict { ref id, ref sidecar, ref reason } =>
+                write!(fmt, "{}: conflict, {}. The version of the other store is in {}", id, reason, sidecar),
+        }
+    }
+}
+
+/// Synchronize the stores `local` and `remote`
+///
+/// `state` is the state after the last synchronization of the two stores, it is updated to the
+/// state after this one. With `dry_run`, the stores are not changed, but the changes which would
+/// be made are returned.
+///
+/// Entries of stores which are mounted into one of the stores are not synchronized.
+pub fn sync(local: &Store, remote: &Store, state: &mut SyncState, dry_run: bool) -> Result<Vec<Change>> {
+    let is_mounted = |id: &StoreId| local
+        .mounts()
+        .iter()
+        .chain(remote.mounts().iter())
+        .any(|m| id.local().starts_with(&m.prefix));
+
+    let mut ids = BTreeSet::new();
+    for store in [local, remote].iter() {
+        for id in store.entries()?.into_storeid_iter() {
+            let _ = ids.insert(id?);
+        }
+    }
+    ids.extend(state.ids()?);
+
+    let mut changes = vec![];
+    for id in ids.into_iter().filter(|id| !is_mounted(id)) {
+        trace!("Synchronizing {}", id);
+        if let Some(change) = sync_entry(local, remote, state, &id, dry_run).context(format_err!("Failed to synchronize {}", id))? {
+            debug!("{}", change);
+            changes.push(change);
+        }
+    }
+
+    Ok(changes)
+}
+
+fn sync_entry(local: &Store, remote: &Store, state: &mut SyncState, id: &StoreId, dry_run: bool)
+    -> Result<Option<Change>>
+{
+    let l  = read(local, id)?;
+    let r  = read(remote, id)?;
+    let ls = l.as_ref().map(EntryState::of).transpose()?;
+    let rs = r.as_ref().map(EntryState::of).transpose()?;
+    let base = state.get(id)?.cloned();
+
+    let hash = |s: &Option<EntryState>| s.as_ref().map(|s| s.hash.clone());
+    let (lh, rh, bh) = (hash(&ls), hash(&rs), hash(&base));
+
+    if lh == rh {
+        trace!("{} is the same in both stores", id);
+        state.set(id, ls)?;
+        return Ok(None)
+    }
+
+    let change = if rh == bh {
+        let change = match l {
+            Some(ref l) => write(remote, l, dry_run).map(|_| Change::ToRemote(id.clone()))?,
+            None        => delete(remote, id, dry_run).map(|_| Change::DeletedRemote(id.clone()))?,
+        };
+        state.set(id, ls)?;
+        change
+    } else if lh == bh {
+        let change = match r {
+            Some(ref r) => write(local, r, dry_run).map(|_| Change::ToLocal(id.clone()))?,
+            None        => delete(local, id, dry_run).map(|_| Change::DeletedLocal(id.clone()))?,
+        };
+        state.set(id, rs)?;
+        change
+    } else {
+        match (l, r, ls, rs) {
+            (Some(l), Some(r), Some(ls), Some(rs)) => {
+                return merge_entry(local, remote, state, (l, ls), (r, rs), base, dry_run).map(Some)
+            },
+            (Some(l), None, ls, _) => {
+                write(remote, &l, dry_run)?;
+                state.set(id, ls)?;
+                Change::Restored(id.clone())
+            },
+            (None, Some(r), _, rs) => {
+                write(local, &r, dry_run)?;
+                state.set(id, rs)?;
+                Change::Restored(id.clone())
+            },
+            _ => return Ok(None), // not possible, both would be the same
+        }
+    };
+
+    Ok(Some(change))
+}
+
+/// Merge an entry which was changed in both stores, or create a sidecar entry if that fails
+fn merge_entry(local: &Store,
+               remote: &Store,
+               state: &mut SyncState,
+               (l, ls): (Entry, EntryState),
+               (r, rs): (Entry, EntryState),
+               base: Option<EntryState>,
+               dry_run: bool)
+    -> Result<Change>
+{
+    let id          = l.get_location().clone();
+    let base_header = base.as_ref().map(EntryState::header).transpose()?;
+    let header      = merge_headers(base_header.as_ref(), l.get_header(), r.get_header());
+
+    let content = if ls.content_hash == rs.content_hash {
+        Some(l.get_content())
+    } else {
+        match base {
+            Some(ref b) if b.content_hash == ls.content_hash => Some(r.get_content()),
+            Some(ref b) if b.content_hash == rs.content_hash => Some(l.get_content()),
+            _ => None,
+        }
+    };
+
+    if let (Merge::Merged(header), Some(content)) = (&header, content) {
+        let mut merged = Entry::new(id.clone());
+        *merged.get_header_mut() = header.clone();
+        merged.set_content(content.clone());
+
+        if merged != l {
+            write(local, &merged, dry_run)?;
+        }
+        if merged != r {
+            write(remote, &merged, dry_run)?;
+        }
+        state.set(&id, Some(EntryState::of(&merged)?))?;
+        return Ok(Change::Merged(id))
+    }
+
+    let reason = match (header, content) {
+        (Merge::Conflict(paths), None) => format!("the content and {} were changed in both stores", paths.join(", ")),
+        (Merge::Conflict(paths), _)    => format!("{} were changed in both stores", paths.join(", ")),
+        (_, _)                         => String::from("the content was changed in both stores"),
+    };
+
+    let sidecar = sidecar(&id, &r)?;
+    write(local, &sidecar, dry_run)?;
+    write(remote, &sidecar, dry_run)?;
+    write(remote, &l, dry_run)?;
+    state.set(sidecar.get_location(), Some(EntryState::of(&sidecar)?))?;
+    state.set(&id, Some(ls))?;
+
+    Ok(Change::Conflict { id, sidecar: sidecar.get_location().clone(), reason })
+}
+
+/// Build the sidecar entry for a conflict on `id`, with `entry` as its header and content
+fn sidecar(id: &StoreId, entry: &Entry) -> Result<Entry> {
+    let now = Local::now().format("%Y%m%dT%H%M%S");
+    let sidecar_id = StoreId::new(PathBuf::from(format!("{}.sync-conflict-{}", id.to_str()?, now)))?;
+
+    let mut sidecar = Entry::new(sidecar_id);
+    *sidecar.get_header_mut() = entry.get_header().clone();
+    let _ = sidecar.get_header_mut().insert("sync.conflict-of", Value::String(id.to_str()?))?;
+    sidecar.set_content(entry.get_content().clone());
+    Ok(sidecar)
+}
+
+fn read(store: &Store, id: &StoreId) -> Result<Option<Entry>> {
+    if store.exists(id.clone())? {
+        store.get_copy(id.clone()).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Write `entry` into `store`, creating it if it does not exist
+fn write(store: &Store, entry: &Entry, dry_run: bool) -> Result<()> {
+    if dry_run {
+        return Ok(())
+    }
+
+    let id = entry.get_location().clone();
+    let mut fle = match store.get(id.clone())? {
+        Some(fle) => fle,
+        None      => store.create(id)?,
+    };
+    *fle.get_header_mut() = entry.get_header().clone();
+    fle.set_content(entry.get_content().clone());
+    store.update(&mut fle)
+}
+
+fn delete(store: &Store, id: &StoreId, dry_run: bool) -> Result<()> {
+    if dry_run {
+        return Ok(())
+    }
+    store.delete(id.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml_query::read::TomlValueReadExt;
+    use toml_query::read::TomlValueReadTypeExt;
+
+    use super::*;
+
+    fn store() -> Store {
+        Store::new_inmemory(PathBuf::from("/"), &None).unwrap()
+    }
+
+    fn set(store: &Store, id: &str, header: &[(&str, Value)], content: &str) {
+        let mut entry = store.retrieve(PathBuf::from(id)).unwrap();
+        for &(path, ref value) in header {
+            let _ = entry.get_header_mut().insert(path, value.clone()).unwrap();
+        }
+        entry.set_content(String::from(content));
+        store.update(&mut entry).unwrap();
+    }
+
+    fn content(store: &Store, id: &str) -> Option<String> {
+        read(store, &StoreId::new(PathBuf::from(id)).unwrap())
+            .unwrap()
+            .map(|e| e.get_content().clone())
+    }
+
+    fn links(store: &Store, id: &str) -> Vec<String> {
+        store.get_copy(PathBuf::from(id))
+            .unwrap()
+            .get_header()
+            .read("links.internal")
+            .unwrap()
+            .and_then(Value::as_array)
+            .map(|a| a.iter().filter_map(Value::as_str).map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    fn strings(s: &[&str]) -> Value {
+        Value::Array(s.iter().map(|s| Value::String(String::from(*s))).collect())
+    }
+
+    fn id(s: &str) -> StoreId {
+        StoreId::new(PathBuf::from(s)).unwrap()
+    }
+
+    #[test]
+    fn test_one_sided_changes() {
+        let (local, remote) = (store(), store());
+        let mut state = SyncState::default();
+
+        set(&local, "notes/a", &[], "a");
+        set(&remote, "notes/b", &[], "b");
+        let changes = sync(&local, &remote, &mut state, false).unwrap();
+        assert_eq!(changes, vec![Change::ToRemote(id("notes/a")), Change::ToLocal(id("notes/b"))]);
+        assert_eq!(content(&remote, "notes/a"), Some(String::from("a")));
+        assert_eq!(content(&local, "notes/b"), Some(String::from("b")));
+
+        assert!(sync(&local, &remote, &mut state, false).unwrap().is_empty());
+
+        set(&remote, "notes/a", &[], "a, changed");
+        local.delete(PathBuf::from("notes/b")).unwrap();
+        let changes = sync(&local, &remote, &mut state, false).unwrap();
+        assert_eq!(changes, vec![Change::ToLocal(id("notes/a")), Change::DeletedRemote(id("notes/b"))]);
+        assert_eq!(content(&local, "notes/a"), Some(String::from("a, changed")));
+        assert_eq!(content(&remote, "notes/b"), None);
+        assert!(state.get(&id("notes/b")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let (local, remote) = (store(), store());
+        let mut state = SyncState::default();
+
+        set(&local, "notes/a", &[], "a");
+        let changes = sync(&local, &remote, &mut state, true).unwrap();
+        assert_eq!(changes, vec![Change::ToRemote(id("notes/a"))]);
+        assert_eq!(content(&remote, "notes/a"), None);
+    }
+
+    #[test]
+    fn test_merge_links() {
+        let (local, remote) = (store(), store());
+        let mut state = SyncState::default();
+
+        set(&local, "wiki/a", &[("links.internal", strings(&["wiki/b"]))], "a");
+        let _ = sync(&local, &remote, &mut state, false).unwrap();
+
+        set(&local, "wiki/a", &[("links.internal", strings(&["wiki/b", "wiki/c"]))], "a");
+        set(&remote, "wiki/a", &[("links.internal", strings(&["wiki/d"]))], "a, changed");
+
+        let changes = sync(&local, &remote, &mut state, false).unwrap();
+        assert_eq!(changes, vec![Change::Merged(id("wiki/a"))]);
+
+        for store in [&local, &remote].iter() {
+            assert_eq!(links(store, "wiki/a"), vec!["wiki/c", "wiki/d"]);
+            assert_eq!(content(store, "wiki/a"), Some(String::from("a, changed")));
+        }
+        assert!(sync(&local, &remote, &mut state, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_conflict() {
+        let (local, remote) = (store(), store());
+        let mut state = SyncState::default();
+
+        set(&local, "notes/a", &[], "a");
+        let _ = sync(&local, &remote, &mut state, false).unwrap();
+
+        set(&local, "notes/a", &[], "local a");
+        set(&remote, "notes/a", &[], "remote a");
+
+        let changes = sync(&local, &remote, &mut state, false).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_conflict());
+
+        let sidecar = match changes[0] {
+            Change::Conflict { ref sidecar, .. } => sidecar.to_str().unwrap(),
+            _ => unreachable!(),
+        };
+        assert!(sidecar.starts_with("notes/a.sync-conflict-"));
+
+        for store in [&local, &remote].iter() {
+            assert_eq!(content(store, "notes/a"), Some(String::from("local a")));
+            assert_eq!(content(store, &sidecar), Some(String::from("remote a")));
+
+            let conflict_of = store.get_copy(PathBuf::from(&sidecar)).unwrap().get_header().read_string("sync.conflict-of").unwrap();
+            assert_eq!(conflict_of, Some(String::from("notes/a")));
+        }
+        assert!(sync(&local, &remote, &mut state, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deleted_and_changed() {
+        let (local, remote) = (store(), store());
+        let mut state = SyncState::default();
+
+        set(&local, "notes/a", &[], "a");
+        let _ = sync(&local, &remote, &mut state, false).unwrap();
+
+        local.delete(PathBuf::from("notes/a")).unwrap();
+        set(&remote, "notes/a", &[], "a, changed");
+
+        let changes = sync(&local, &remote, &mut state, false).unwrap();
+        assert_eq!(changes, vec![Change::Restored(id("notes/a"))]);
+        assert_eq!(content(&local, "notes/a"), Some(String::from("a, changed")));
+    }
+}
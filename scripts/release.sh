@@ -14,6 +14,7 @@ CRATES=(
     ./lib/core/libimagerror
     ./lib/core/libimagstore
     ./lib/etc/libimaginteraction
+    ./lib/etc/libimagsync
     ./lib/core/libimagrt
     ./lib/entry/libimagentrylink
     ./lib/entry/libimagentryurl
@@ -52,10 +53,13 @@ CRATES=(
     ./bin/domain/imag-wiki
     ./bin/core/imag-markdown
     ./bin/core/imag-ref
+    ./bin/core/imag-attach
     ./bin/core/imag-gps
     ./bin/core/imag-diagnostics
     ./bin/core/imag-mv
+    ./bin/core/imag-server
     ./bin/core/imag-store
+    ./bin/core/imag-sync
     ./bin/core/imag-tag
     ./bin/core/imag-grep
     ./bin/core/imag-annotate
@@ -68,6 +72,7 @@ CRATES=(
     ./bin/core/imag-git
     ./bin/core/imag-category
     ./bin/core/imag-header
+    ./bin/core/imag-config
     ./bin/core/imag-create
     ./bin/core/imag
 )